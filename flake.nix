@@ -165,6 +165,8 @@
           installPhase = ''
             mkdir -p $out/bin
             cp target/release/wg-ondemand $out/bin/
+            mkdir -p $out/share/dbus-1/system.d
+            cp dbus/io.github.vly.WgOndemand.conf $out/share/dbus-1/system.d/
           '';
 
           meta = with pkgs.lib; {
@@ -222,6 +224,9 @@
                 };
               };
 
+              # Allow the daemon to own its bus name for state change signals
+              services.dbus.packages = [ cfg.package ];
+
               # Ensure NetworkManager is enabled
               services.networkmanager.enable = mkDefault true;
 
@@ -43,6 +43,21 @@ fi
 echo "Installing systemd service..."
 install -m 644 systemd/wg-ondemand.service /etc/systemd/system/wg-ondemand.service
 
+# Install D-Bus policy (lets the daemon publish state change signals)
+echo "Installing D-Bus policy..."
+install -m 644 dbus/io.github.vly.WgOndemand.conf /etc/dbus-1/system.d/io.github.vly.WgOndemand.conf
+
+# Install shell completions
+echo "Installing shell completions..."
+mkdir -p /usr/local/share/bash-completion/completions /usr/local/share/zsh/site-functions \
+    /usr/local/share/fish/vendor_completions.d
+for ctl in "" --ctl; do
+    name=wg-ondemand${ctl:+-ctl}
+    target/release/wg-ondemand completions bash $ctl > /usr/local/share/bash-completion/completions/$name
+    target/release/wg-ondemand completions zsh $ctl > /usr/local/share/zsh/site-functions/_$name
+    target/release/wg-ondemand completions fish $ctl > /usr/local/share/fish/vendor_completions.d/$name.fish
+done
+
 # Reload systemd
 echo "Reloading systemd daemon..."
 systemctl daemon-reload
@@ -99,6 +99,7 @@ fi
 # Install systemd service
 info "Installing systemd service..."
 install -m 644 systemd/wg-ondemand.service /etc/systemd/system/wg-ondemand.service
+install -m 644 dbus/io.github.vly.WgOndemand.conf /etc/dbus-1/system.d/io.github.vly.WgOndemand.conf
 systemctl daemon-reload
 
 success "Installation complete!"
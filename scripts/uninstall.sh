@@ -29,6 +29,12 @@ if [ -f /etc/systemd/system/wg-ondemand.service ]; then
     systemctl daemon-reload
 fi
 
+# Remove D-Bus policy
+if [ -f /etc/dbus-1/system.d/io.github.vly.WgOndemand.conf ]; then
+    echo "Removing D-Bus policy..."
+    rm -f /etc/dbus-1/system.d/io.github.vly.WgOndemand.conf
+fi
+
 # Remove binaries
 if [ -f /usr/local/bin/wg-ondemand ]; then
     echo "Removing binaries..."
@@ -37,6 +43,14 @@ if [ -f /usr/local/bin/wg-ondemand ]; then
     rm -f /usr/local/bin/wg-ondemand-ctl
 fi
 
+# Remove shell completions
+echo "Removing shell completions..."
+for name in wg-ondemand wg-ondemand-ctl; do
+    rm -f /usr/local/share/bash-completion/completions/$name
+    rm -f /usr/local/share/zsh/site-functions/_$name
+    rm -f /usr/local/share/fish/vendor_completions.d/$name.fish
+done
+
 # Remove shared scripts
 if [ -d /usr/local/share/wg-ondemand ]; then
     echo "Removing helper scripts..."
@@ -1,72 +1,10 @@
 #!/usr/bin/env bash
 # Waybar module for wg-ondemand
 # Outputs JSON for waybar custom module
+#
+# Thin wrapper around `wg-ondemand-ctl status --waybar`, kept for existing
+# waybar configs that exec this script directly.
 
-# Get status from wg-ondemand-ctl
-status_json=$(wg-ondemand-ctl status --json 2>/dev/null || echo '{"service_status":"unknown","tunnel_state":"unknown","ssid":""}')
-
-# Parse JSON (simple grep-based parsing for bash)
-status=$(echo "$status_json" | grep -o '"service_status": "[^"]*"' | cut -d'"' -f4 || echo "unknown")
-tunnel_state=$(echo "$status_json" | grep -o '"tunnel_state": "[^"]*"' | cut -d'"' -f4 || echo "unknown")
-ssid=$(echo "$status_json" | grep -o '"ssid": "[^"]*"' | cut -d'"' -f4 || echo "")
-
-# Initialize variables
-text=""
-tooltip=""
-class=""
-
-case "$status" in
-    active)
-        case "$tunnel_state" in
-            connected)
-                text="󰖂 VPN"
-                tooltip="WireGuard: Connected"
-                if [[ -n "$ssid" ]]; then
-                    tooltip="$tooltip - SSID: $ssid"
-                fi
-                class="connected"
-                ;;
-            monitoring)
-                text="󰀂 Mon"
-                tooltip="WireGuard: Monitoring"
-                if [[ -n "$ssid" ]]; then
-                    tooltip="$tooltip - SSID: $ssid"
-                fi
-                class="monitoring"
-                ;;
-            idle|inactive|deactivating|activating)
-                text="󰀃 Idle"
-                tooltip="WireGuard: Idle"
-                class="idle"
-                ;;
-            *)
-                text="󰀄 ?"
-                tooltip="WireGuard: Unknown"
-                class="unknown"
-                ;;
-        esac
-        ;;
-    inactive)
-        text="󰅛 Off"
-        tooltip="WireGuard: Stopped"
-        class="inactive"
-        ;;
-    disabled)
-        text="󰅙 Dis"
-        tooltip="WireGuard: Disabled"
-        class="disabled"
-        ;;
-    *)
-        text="󰀄 ?"
-        tooltip="WireGuard: Unknown"
-        class="unknown"
-        ;;
-esac
-
-# Output JSON for waybar (ensure variables are set)
-text="${text:-?}"
-tooltip="${tooltip:-Unknown}"
-class="${class:-unknown}"
-status="${status:-unknown}"
-
-printf '{"text":"%s","tooltip":"%s","class":"%s","alt":"%s"}\n' "$text" "$tooltip" "$class" "$status"
+if ! wg-ondemand-ctl status --waybar 2>/dev/null; then
+    printf '{"text":"%s","tooltip":"%s","class":"%s","alt":"%s"}\n' "󰀄 ?" "WireGuard: Unknown" "unknown" "unknown"
+fi
@@ -0,0 +1,65 @@
+//! Types shared by the eBPF program and the daemon
+//!
+//! Both sides depend on this crate, so the layout of the ring buffer events and
+//! the map indices can't drift apart. It is `no_std` unless the `user` feature
+//! is enabled, which adds helpers for the daemon.
+
+#![cfg_attr(not(feature = "user"), no_std)]
+
+/// Event structure for eBPF → userspace communication
+/// Must be #[repr(C)] for ABI compatibility with eBPF
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct TrafficEvent {
+    /// Kernel timestamp in nanoseconds
+    pub timestamp: u64,
+    /// Destination IPv4 address as a host byte order integer (192.168.1.1 is
+    /// `0xC0A80101`), as converted from the packet header by the classifier
+    pub dest_ip: u32,
+    /// Destination port
+    pub dest_port: u16,
+    /// IP protocol (IPPROTO_TCP, IPPROTO_UDP, etc.)
+    pub protocol: u8,
+    /// Padding for alignment
+    pub _padding: u8,
+}
+
+// The ring buffer reader relies on this exact size
+const _: () = assert!(core::mem::size_of::<TrafficEvent>() == 16);
+const _: () = assert!(core::mem::align_of::<TrafficEvent>() == 8);
+
+#[cfg(feature = "user")]
+impl TrafficEvent {
+    /// Destination as `a.b.c.d:port/proto`
+    pub fn destination(&self) -> String {
+        let ip = std::net::Ipv4Addr::from(self.dest_ip.to_be_bytes());
+        let proto = match self.protocol {
+            1 => "icmp".to_string(),
+            6 => "tcp".to_string(),
+            17 => "udp".to_string(),
+            other => other.to_string(),
+        };
+        format!("{}:{}/{}", ip, self.dest_port, proto)
+    }
+}
+
+/// SETTINGS index of the kill switch flag
+pub const SETTING_DROP_MATCHED: u32 = 0;
+
+/// SETTINGS index of the WireGuard interface index
+pub const SETTING_TUNNEL_IFINDEX: u32 = 1;
+
+/// SETTINGS index of the WireGuard firewall mark
+pub const SETTING_TUNNEL_MARK: u32 = 2;
+
+/// SETTINGS index of the source filter flag
+pub const SETTING_SOURCE_FILTER: u32 = 3;
+
+/// WG_BYTES index for received bytes
+pub const WG_BYTES_RX: u32 = 0;
+
+/// WG_BYTES index for sent bytes
+pub const WG_BYTES_TX: u32 = 1;
+
+/// Network and mask of empty SUBNETS and SOURCES slots
+pub const EMPTY_SENTINEL: u32 = 0xFFFFFFFF;
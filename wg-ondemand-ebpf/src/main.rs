@@ -2,18 +2,22 @@
 #![no_main]
 
 use aya_ebpf::{
-    bindings::TC_ACT_OK,
+    bindings::{TC_ACT_OK, TC_ACT_SHOT},
     macros::{classifier, map},
-    maps::{Array, RingBuf},
+    maps::{Array, HashMap, PerCpuArray, RingBuf},
     programs::TcContext,
 };
 use aya_log_ebpf::info;
 use network_types::{
-    eth::{EthHdr, EtherType},
+    eth::EthHdr,
     ip::{IpProto, Ipv4Hdr},
     tcp::TcpHdr,
     udp::UdpHdr,
 };
+use wg_ondemand_common::{
+    TrafficEvent, EMPTY_SENTINEL, SETTING_DROP_MATCHED, SETTING_SOURCE_FILTER,
+    SETTING_TUNNEL_IFINDEX, SETTING_TUNNEL_MARK, WG_BYTES_RX, WG_BYTES_TX,
+};
 
 /// Ringbuf for sending events to userspace
 /// 16KB = 1024 events, provides 10x safety margin for realistic traffic bursts
@@ -26,15 +30,50 @@ static EVENTS: RingBuf = RingBuf::with_byte_size(16 * 1024, 0);
 #[map]
 static SUBNETS: Array<[u32; 2]> = Array::with_max_entries(16, 0);
 
-/// Event structure matching userspace definition
-#[repr(C)]
-struct TrafficEvent {
-    timestamp: u64,
-    dest_ip: u32,
-    dest_port: u16,
-    protocol: u8,
-    _padding: u8,
-}
+/// Destinations inside the subnets that never trigger: the tunnel's own peer
+/// endpoints (site-to-site setups), keyed by IPv4 address in host byte order
+#[map]
+static EXCLUDED: HashMap<u32, u8> = HashMap::with_max_entries(16, 0);
+
+/// Source ranges allowed to trigger when the source filter is enabled
+/// Same layout and empty-slot sentinel as SUBNETS
+#[map]
+static SOURCES: Array<[u32; 2]> = Array::with_max_entries(16, 0);
+
+/// Runtime settings written by userspace
+/// Index 0: drop matched packets after reporting them (kill switch), if non-zero
+/// Index 1: index of the WireGuard interface, 0 if unknown
+/// Index 2: firewall mark of the WireGuard interface's packets, 0 if none
+/// Index 3: only traffic from SOURCES triggers, if non-zero
+#[map]
+static SETTINGS: Array<u32> = Array::with_max_entries(4, 0);
+
+/// Firewall mark of the daemon's own probes, which are let through
+/// unreported (must match `probe::PROBE_MARK` in userspace)
+const PROBE_MARK: u32 = 0x7767_6f70;
+
+/// Offset of the EtherType field in the Ethernet header
+const ETH_TYPE_OFFSET: usize = 12;
+
+/// EtherType of IPv4 payloads
+const ETH_P_IP: u16 = 0x0800;
+
+/// EtherType of 802.1Q VLAN tags
+const ETH_P_8021Q: u16 = 0x8100;
+
+/// EtherType of 802.1ad (QinQ) service VLAN tags
+const ETH_P_8021AD: u16 = 0x88A8;
+
+/// Length of a VLAN tag: TCI followed by the encapsulated EtherType
+const VLAN_HDR_LEN: usize = 4;
+
+/// VLAN tags skipped before giving up (802.1ad outer + 802.1Q inner)
+const MAX_VLAN_TAGS: usize = 2;
+
+/// Per-CPU byte counters for the WireGuard interface while the tunnel is active
+/// Index 0 counts received (ingress) bytes, index 1 sent (egress) bytes
+#[map]
+static WG_BYTES: PerCpuArray<u64> = PerCpuArray::with_max_entries(2, 0);
 
 #[classifier]
 pub fn wg_ondemand_tc(ctx: TcContext) -> i32 {
@@ -45,17 +84,36 @@ pub fn wg_ondemand_tc(ctx: TcContext) -> i32 {
 }
 
 fn try_wg_ondemand_tc(ctx: TcContext) -> Result<i32, ()> {
-    // Parse Ethernet header
-    let ethhdr: EthHdr = ctx.load(0).map_err(|_| ())?;
+    // The daemon's own probes must neither trigger nor be dropped
+    let (mark, ifindex) = unsafe { ((*ctx.skb.skb).mark, (*ctx.skb.skb).ifindex) };
+    if mark == PROBE_MARK {
+        return Ok(TC_ACT_OK);
+    }
+
+    // Neither must traffic already going through the tunnel: leaving through
+    // the WireGuard interface, or encapsulated by it (overlapping AllowedIPs)
+    if is_tunneled(ifindex, mark) {
+        return Ok(TC_ACT_OK);
+    }
+
+    // Parse Ethernet header, skipping VLAN tags the NIC didn't offload
+    let mut ether_type = u16::from_be(ctx.load(ETH_TYPE_OFFSET).map_err(|_| ())?);
+    let mut l3_offset = EthHdr::LEN;
+    for _ in 0..MAX_VLAN_TAGS {
+        if ether_type != ETH_P_8021Q && ether_type != ETH_P_8021AD {
+            break;
+        }
+        ether_type = u16::from_be(ctx.load(l3_offset + 2).map_err(|_| ())?);
+        l3_offset += VLAN_HDR_LEN;
+    }
 
     // Only process IPv4
-    match ethhdr.ether_type {
-        EtherType::Ipv4 => {}
-        _ => return Ok(TC_ACT_OK),
+    if ether_type != ETH_P_IP {
+        return Ok(TC_ACT_OK);
     }
 
     // Parse IPv4 header
-    let ipv4hdr: Ipv4Hdr = ctx.load(EthHdr::LEN).map_err(|_| ())?;
+    let ipv4hdr: Ipv4Hdr = ctx.load(l3_offset).map_err(|_| ())?;
     let dest_ip = u32::from_be(ipv4hdr.dst_addr);
 
     // Check if destination matches any configured subnet
@@ -63,14 +121,28 @@ fn try_wg_ondemand_tc(ctx: TcContext) -> Result<i32, ()> {
         return Ok(TC_ACT_OK);
     }
 
+    // The tunnel's handshakes to its endpoint would otherwise trigger it again
+    if unsafe { EXCLUDED.get(&dest_ip) }.is_some() {
+        return Ok(TC_ACT_OK);
+    }
+
+    // Forwarded, bridged or container traffic may be excluded by source
+    if SETTINGS
+        .get(SETTING_SOURCE_FILTER)
+        .is_some_and(|flag| *flag != 0)
+        && !in_ranges(&SOURCES, u32::from_be(ipv4hdr.src_addr))
+    {
+        return Ok(TC_ACT_OK);
+    }
+
     // Get destination port based on protocol
     let dest_port = match ipv4hdr.proto {
         IpProto::Tcp => {
-            let tcphdr: TcpHdr = ctx.load(EthHdr::LEN + Ipv4Hdr::LEN).map_err(|_| ())?;
+            let tcphdr: TcpHdr = ctx.load(l3_offset + Ipv4Hdr::LEN).map_err(|_| ())?;
             u16::from_be(tcphdr.dest)
         }
         IpProto::Udp => {
-            let udphdr: UdpHdr = ctx.load(EthHdr::LEN + Ipv4Hdr::LEN).map_err(|_| ())?;
+            let udphdr: UdpHdr = ctx.load(l3_offset + Ipv4Hdr::LEN).map_err(|_| ())?;
             u16::from_be(udphdr.dest)
         }
         _ => 0,
@@ -98,18 +170,58 @@ fn try_wg_ondemand_tc(ctx: TcContext) -> Result<i32, ()> {
         entry.submit(0);
     }
 
+    // Kill switch: the tunnel isn't up yet, so this packet would leave in plaintext
+    if SETTINGS
+        .get(SETTING_DROP_MATCHED)
+        .is_some_and(|flag| *flag != 0)
+    {
+        return Ok(TC_ACT_SHOT);
+    }
+
     Ok(TC_ACT_OK)
 }
 
+/// Count bytes received on the WireGuard interface
+#[classifier]
+pub fn wg_ondemand_count_ingress(ctx: TcContext) -> i32 {
+    count_bytes(&ctx, WG_BYTES_RX);
+    TC_ACT_OK
+}
+
+/// Count bytes sent on the WireGuard interface
+#[classifier]
+pub fn wg_ondemand_count_egress(ctx: TcContext) -> i32 {
+    count_bytes(&ctx, WG_BYTES_TX);
+    TC_ACT_OK
+}
+
+/// Add the packet length to a per-CPU counter (no atomics needed)
+fn count_bytes(ctx: &TcContext, index: u32) {
+    if let Some(counter) = WG_BYTES.get_ptr_mut(index) {
+        unsafe { *counter += u64::from(ctx.len()) };
+    }
+}
+
+/// Check if a packet leaves through the WireGuard interface or carries its mark
+fn is_tunneled(ifindex: u32, mark: u32) -> bool {
+    let setting = |index| SETTINGS.get(index).copied().unwrap_or(0);
+    let tunnel_ifindex = setting(SETTING_TUNNEL_IFINDEX);
+    let tunnel_mark = setting(SETTING_TUNNEL_MARK);
+    (tunnel_ifindex != 0 && ifindex == tunnel_ifindex) || (tunnel_mark != 0 && mark == tunnel_mark)
+}
+
 /// Check if the given IP matches any configured subnet
 fn is_target_subnet(ip: u32) -> bool {
-    // Sentinel value for empty slots: 0xFFFFFFFF/0xFFFFFFFF
-    // This allows 0.0.0.0/0 (match all) to be a valid configuration
-    const EMPTY_SENTINEL: u32 = 0xFFFFFFFF;
+    in_ranges(&SUBNETS, ip)
+}
 
-    // Iterate through configured subnets
+/// Check if the given IP matches any range in a SUBNETS-style map
+fn in_ranges(ranges: &Array<[u32; 2]>, ip: u32) -> bool {
+    // Empty slots hold EMPTY_SENTINEL as network and mask, which allows
+    // 0.0.0.0/0 (match all) to be a valid configuration
+    // Iterate through configured ranges
     for i in 0..16 {
-        if let Some(subnet) = SUBNETS.get(i) {
+        if let Some(subnet) = ranges.get(i) {
             let network = subnet[0];
             let mask = subnet[1];
 
@@ -55,6 +55,11 @@ test -f target/release/wg-ondemand || {
 install -d %{buildroot}%{_bindir}
 install -d %{buildroot}%{_sysconfdir}/wg-ondemand
 install -d %{buildroot}%{_unitdir}
+install -d %{buildroot}%{_tmpfilesdir}
+install -d %{buildroot}%{_datadir}/dbus-1/system.d
+install -d %{buildroot}%{_datadir}/bash-completion/completions
+install -d %{buildroot}%{_datadir}/zsh/site-functions
+install -d %{buildroot}%{_datadir}/fish/vendor_completions.d
 
 # Install binaries
 install -m 755 target/release/wg-ondemand %{buildroot}%{_bindir}/wg-ondemand
@@ -66,9 +71,22 @@ install -m 644 config/wg-ondemand.toml %{buildroot}%{_sysconfdir}/wg-ondemand/co
 
 # Install systemd service
 install -m 644 systemd/wg-ondemand.service %{buildroot}%{_unitdir}/wg-ondemand.service
+install -m 644 systemd/wg-ondemand.tmpfiles %{buildroot}%{_tmpfilesdir}/wg-ondemand.conf
+
+# Install D-Bus policy
+install -m 644 dbus/io.github.vly.WgOndemand.conf %{buildroot}%{_datadir}/dbus-1/system.d/io.github.vly.WgOndemand.conf
+
+# Install shell completions
+for ctl in "" --ctl; do
+    name=wg-ondemand${ctl:+-ctl}
+    target/release/wg-ondemand completions bash $ctl > %{buildroot}%{_datadir}/bash-completion/completions/$name
+    target/release/wg-ondemand completions zsh $ctl > %{buildroot}%{_datadir}/zsh/site-functions/_$name
+    target/release/wg-ondemand completions fish $ctl > %{buildroot}%{_datadir}/fish/vendor_completions.d/$name.fish
+done
 
 %post
 %systemd_post wg-ondemand.service
+%tmpfiles_create wg-ondemand.conf
 
 # Warn user to configure
 cat <<'EOF'
@@ -112,6 +130,14 @@ EOF
 %{_bindir}/wg-ondemand-ctl
 %config(noreplace) %{_sysconfdir}/wg-ondemand/config.toml
 %{_unitdir}/wg-ondemand.service
+%{_tmpfilesdir}/wg-ondemand.conf
+%{_datadir}/dbus-1/system.d/io.github.vly.WgOndemand.conf
+%{_datadir}/bash-completion/completions/wg-ondemand
+%{_datadir}/bash-completion/completions/wg-ondemand-ctl
+%{_datadir}/zsh/site-functions/_wg-ondemand
+%{_datadir}/zsh/site-functions/_wg-ondemand-ctl
+%{_datadir}/fish/vendor_completions.d/wg-ondemand.fish
+%{_datadir}/fish/vendor_completions.d/wg-ondemand-ctl.fish
 
 %changelog
 * Mon Jan 01 2024 Your Name <your.email@example.com> - 0.1.0-1
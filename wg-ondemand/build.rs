@@ -0,0 +1,113 @@
+//! Copies the eBPF object into `OUT_DIR` for `include_bytes_aligned!`
+//!
+//! The object is built separately with `cargo xtask build-ebpf --release` (it
+//! needs nightly and bpf-linker). `WG_ONDEMAND_EBPF_OBJECT` points to a prebuilt
+//! object instead, for out-of-tree builds and packaging.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Name of the object in `OUT_DIR`
+const OBJECT_NAME: &str = "wg-ondemand-ebpf";
+
+/// Crates compiled into the object, relative to the workspace
+const EBPF_SOURCES: [&str; 2] = ["wg-ondemand-ebpf/src", "wg-ondemand-common/src"];
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=WG_ONDEMAND_EBPF_OBJECT");
+    println!("cargo:rerun-if-env-changed=CARGO_TARGET_DIR");
+
+    // Without the eBPF backend there is nothing to embed
+    if env::var_os("CARGO_FEATURE_EBPF").is_none() {
+        return;
+    }
+
+    let object = match env::var_os("WG_ONDEMAND_EBPF_OBJECT") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let Some(workspace) = workspace_dir() else {
+                panic!(
+                    "Building wg-ondemand outside its workspace (e.g. `cargo publish` or a \
+                     vendored crate), where the eBPF object can't be located. Build it in the \
+                     repository with `cargo xtask build-ebpf --release` and set \
+                     WG_ONDEMAND_EBPF_OBJECT to target/bpfel-unknown-none/release/{}, or build \
+                     without the `ebpf` feature (--no-default-features).",
+                    OBJECT_NAME
+                );
+            };
+            let object = env::var_os("CARGO_TARGET_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| workspace.join("target"))
+                .join("bpfel-unknown-none/release")
+                .join(OBJECT_NAME);
+            warn_if_stale(&workspace, &object);
+            object
+        }
+    };
+    println!("cargo:rerun-if-changed={}", object.display());
+
+    if !object.is_file() {
+        panic!(
+            "eBPF object not found at {}. Build it with `cargo xtask build-ebpf --release`, \
+             point WG_ONDEMAND_EBPF_OBJECT to a prebuilt one, or build without the `ebpf` \
+             feature (--no-default-features).",
+            object.display()
+        );
+    }
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join(OBJECT_NAME);
+    if let Err(e) = std::fs::copy(&object, &out) {
+        panic!(
+            "Failed to copy {} to {}: {}",
+            object.display(),
+            out.display(),
+            e
+        );
+    }
+}
+
+/// The workspace this package was built in, if it has the eBPF crate
+fn workspace_dir() -> Option<PathBuf> {
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR")?);
+    let workspace = manifest_dir.parent()?;
+    workspace
+        .join("wg-ondemand-ebpf/Cargo.toml")
+        .is_file()
+        .then(|| workspace.to_path_buf())
+}
+
+/// Warn when the eBPF sources changed after `object` was built
+fn warn_if_stale(workspace: &Path, object: &Path) {
+    let Ok(built) = object.metadata().and_then(|metadata| metadata.modified()) else {
+        return;
+    };
+    for dir in EBPF_SOURCES {
+        let dir = workspace.join(dir);
+        println!("cargo:rerun-if-changed={}", dir.display());
+        if newest_mtime(&dir).is_some_and(|modified| modified > built) {
+            println!(
+                "cargo:warning=The eBPF object is older than {}; rebuild it with \
+                 `cargo xtask build-ebpf --release`",
+                dir.display()
+            );
+            return;
+        }
+    }
+}
+
+/// Latest modification time of the files under `dir`
+fn newest_mtime(dir: &Path) -> Option<SystemTime> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            if metadata.is_dir() {
+                newest_mtime(&entry.path())
+            } else {
+                metadata.modified().ok()
+            }
+        })
+        .max()
+}
@@ -0,0 +1,53 @@
+//! Daemon for `cargo xtask integration-test`
+//!
+//! Runs the daemon with the config given as the only argument as if it were on a
+//! monitored network, since there is no NetworkManager in the test namespace, and
+//! prints each state transition to stdout as `STATE <from> <to>` for the harness
+//! to assert on. Logs go to stderr. SIGTERM shuts it down cleanly.
+
+use anyhow::{Context, Result};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing_subscriber::EnvFilter;
+use wg_ondemand::{
+    config::load_config, daemon::Daemon, network_detector::ScriptedDetector, state_file::state_str,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .context("Usage: netns-daemon <config>")?;
+    let config =
+        load_config(&path).with_context(|| format!("Failed to load config from {:?}", path))?;
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new(&config.general.log_level)),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let detector = ScriptedDetector::new(Some("integration-test".to_string()), Vec::new());
+    let mut daemon = Daemon::with_detector(config, Box::new(detector)).await?;
+
+    let mut events = daemon.events();
+    tokio::spawn(async move {
+        while let Ok(event) = events.recv().await {
+            if event.from != event.to {
+                println!("STATE {} {}", state_str(event.from), state_str(event.to));
+            }
+        }
+    });
+
+    let handle = daemon.handle();
+    let mut sigterm =
+        signal(SignalKind::terminate()).context("Failed to set up SIGTERM handler")?;
+    tokio::spawn(async move {
+        sigterm.recv().await;
+        handle.shutdown();
+    });
+
+    daemon.run().await?;
+    daemon.shutdown().await
+}
@@ -0,0 +1,112 @@
+// Shell completion generation
+
+//! Shell completions
+//!
+//! `wg-ondemand completions <SHELL>` prints a completion script for the daemon's
+//! flags, or with `--ctl` for the `wg-ondemand-ctl` subcommands, for packages to
+//! install. The control script is plain bash, so its command line is described
+//! here by hand; a test keeps it in sync with the script's dispatcher.
+
+use clap::{Arg, ArgAction, Command};
+use std::io::Write;
+
+pub use clap_complete::Shell;
+
+/// Command line of `wg-ondemand-ctl`, only used to generate completions
+pub fn ctl_command() -> Command {
+    let simple = |name: &'static str, about: &'static str| Command::new(name).about(about);
+    Command::new("wg-ondemand-ctl")
+        .about("WireGuard On-Demand control utility")
+        .subcommand(
+            simple("status", "Show service status and recent logs")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Machine-readable output"),
+                )
+                .arg(
+                    Arg::new("waybar")
+                        .long("waybar")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("json")
+                        .help("Output for a waybar custom module"),
+                ),
+        )
+        .subcommand(simple("start", "Start the daemon"))
+        .subcommand(simple("stop", "Stop the daemon"))
+        .subcommand(simple("restart", "Restart the daemon"))
+        .subcommand(simple("enable", "Enable service to start on boot"))
+        .subcommand(simple("disable", "Disable service from starting on boot"))
+        .subcommand(
+            simple("logs", "Show logs").arg(
+                Arg::new("follow")
+                    .short('f')
+                    .long("follow")
+                    .action(ArgAction::SetTrue)
+                    .help("Follow new log entries"),
+            ),
+        )
+        .subcommand(
+            simple("config", "Show config, or edit it").arg(
+                Arg::new("action")
+                    .value_parser(["edit"])
+                    .help("Open the config in $EDITOR"),
+            ),
+        )
+        .subcommand(simple("up", "Activate the tunnel now"))
+        .subcommand(simple("down", "Deactivate the tunnel and pause activation"))
+        .subcommand(simple("history", "Show recent events"))
+        .subcommand(simple("peers", "Show WireGuard peers"))
+        .subcommand(simple("stats", "Show session counters"))
+        .subcommand(simple("watch", "Follow events live"))
+        .subcommand(simple("keep-alive", "Reset the idle timer"))
+        .subcommand(simple("notify", "Show desktop notifications"))
+        .subcommand(simple("install", "Run installation"))
+        .subcommand(simple("uninstall", "Remove wg-ondemand"))
+        .subcommand(simple("version", "Show version information"))
+}
+
+/// Write the completion script of `cmd` for `shell` to `out`
+pub fn generate(shell: Shell, cmd: &mut Command, out: &mut dyn Write) {
+    let name = cmd.get_name().to_string();
+    clap_complete::generate(shell, cmd, name, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ctl_command_matches_script() {
+        let script = include_str!("../../scripts/wg-ondemand-ctl");
+        let dispatcher = script
+            .split_once("case \"${1:-}\" in")
+            .expect("dispatcher not found")
+            .1;
+        let mut commands: Vec<&str> = dispatcher
+            .lines()
+            .filter_map(|line| line.strip_prefix("    ")?.strip_suffix(')'))
+            .filter(|name| name.chars().all(|c| c.is_ascii_lowercase() || c == '-'))
+            .collect();
+        commands.push("help");
+        commands.sort_unstable();
+
+        let cmd = ctl_command();
+        cmd.clone().debug_assert();
+        let mut completed: Vec<&str> = cmd.get_subcommands().map(Command::get_name).collect();
+        // clap adds `help` itself
+        completed.push("help");
+        completed.sort_unstable();
+        assert_eq!(completed, commands);
+    }
+
+    #[test]
+    fn test_generate() {
+        let mut out = Vec::new();
+        generate(Shell::Bash, &mut ctl_command(), &mut out);
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.contains("wg-ondemand-ctl"));
+        assert!(script.contains("keep-alive"));
+    }
+}
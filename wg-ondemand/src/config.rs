@@ -4,23 +4,253 @@
 //!
 //! This module handles loading TOML configuration files and validating
 //! their contents, including CIDR subnet parsing and range checks.
+//!
+//! Fragments in the drop-in directory next to the main file (`config.d` for
+//! `config.toml`) are merged over it in lexical order: tables merge key by key,
+//! any other value replaces the one before it.
 
-use crate::types::Config;
+use crate::dock;
+use crate::endpoint;
+use crate::error::ConfigError;
+use crate::instance::{Instance, MAX_TUNNELS};
+use crate::native_tunnel::{check_native_allowed_ip, parse_allowed_ip};
+use crate::probe::{MAX_TUNNEL_MTU, MIN_TUNNEL_MTU};
+use crate::route_manager::MAX_MONITOR_INTERFACES;
+use crate::traffic_monitor::MAX_SOURCE_RANGES;
+use crate::types::{
+    Config, GatewayFallback, IdleDetection, KillSwitchMode, SourceFilter, TrafficDetection,
+    TunnelConfig,
+};
+use crate::wg_controller::validate_interface_name;
 use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::fs;
 use std::net::Ipv4Addr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// Interval at which WireGuard renews handshakes while traffic flows (seconds)
+const HANDSHAKE_RENEWAL_SECS: u64 = 120;
+
+/// Config values given on the command line, taking precedence over the file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigOverrides {
+    /// Name of the `[tunnel.NAME]` section to run (required if there are several)
+    pub tunnel: Option<String>,
+    /// Replaces `general.wg_interface`
+    pub wg_interface: Option<String>,
+    /// Replaces `general.idle_timeout`
+    pub idle_timeout: Option<u64>,
+    /// Replaces `general.monitor_interface`
+    pub monitor_interface: Option<String>,
+    /// Replaces `subnets.ranges` if not empty
+    pub subnets: Vec<String>,
+}
+
+impl ConfigOverrides {
+    /// Apply the overrides to `config`
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(iface) = &self.wg_interface {
+            config.general.wg_interface = iface.clone();
+        }
+        if let Some(timeout) = self.idle_timeout {
+            config.general.idle_timeout = timeout;
+        }
+        if let Some(iface) = &self.monitor_interface {
+            config.general.monitor_interface = Some(iface.clone());
+            config.general.monitor_interfaces.clear();
+        }
+        if !self.subnets.is_empty() {
+            config.subnets.ranges = self.subnets.clone();
+        }
+    }
+}
 
 /// Load configuration from TOML file
-pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config> {
-    let contents = fs::read_to_string(path.as_ref()).context("Failed to read config file")?;
+pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+    load_config_with_overrides(path, &ConfigOverrides::default())
+}
+
+/// Load configuration from TOML file, applying `overrides` before validation
+pub fn load_config_with_overrides<P: AsRef<Path>>(
+    path: P,
+    overrides: &ConfigOverrides,
+) -> Result<Config, ConfigError> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path).context("Failed to read config file")?;
+    let mut merged: toml::Table =
+        toml::from_str(&contents).context("Failed to parse config file")?;
+
+    for fragment in drop_in_files(path)? {
+        let contents = fs::read_to_string(&fragment)
+            .with_context(|| format!("Failed to read config fragment {:?}", fragment))?;
+        let table: toml::Table = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config fragment {:?}", fragment))?;
+        merge_tables(&mut merged, table);
+    }
 
-    let config: Config = toml::from_str(&contents).context("Failed to parse config file")?;
+    let mut config: Config = toml::Value::Table(merged)
+        .try_into()
+        .context("Failed to parse config file")?;
+    normalize_tunnels(&mut config)?;
+    let selected = select_tunnel(&config, overrides.tunnel.as_deref())?;
+
+    // Tunnels not selected are still checked so mistakes show up early
+    for (name, tunnel) in config.tunnels.iter().filter(|(name, _)| **name != selected) {
+        let mut view = config.clone();
+        apply_tunnel(&mut view, tunnel);
+        validate_config(&view).with_context(|| format!("Invalid tunnel {:?}", name))?;
+    }
+
+    let tunnel = config.tunnels[&selected].clone();
+    apply_tunnel(&mut config, &tunnel);
+    overrides.apply(&mut config);
+    // Instances of the other tunnels run side by side
+    let index = config.tunnels.keys().position(|name| *name == selected);
+    config.instance =
+        Instance::new(overrides.tunnel.as_deref()).with_index(index.unwrap_or_default() as u16);
 
     validate_config(&config)?;
     Ok(config)
 }
 
+/// Compatibility shim for the flat schema: without `[tunnel.NAME]` sections,
+/// `general.wg_interface`, `general.nm_connection` and `[subnets]` form a single
+/// tunnel named after its interface
+fn normalize_tunnels(config: &mut Config) -> Result<()> {
+    if config.tunnels.is_empty() {
+        let tunnel = TunnelConfig {
+            wg_interface: config.general.wg_interface.clone(),
+            nm_connection: config.general.nm_connection.clone(),
+            subnets: config.subnets.ranges.clone(),
+            idle_timeout: None,
+            mode: None,
+        };
+        config.tunnels.insert(tunnel.wg_interface.clone(), tunnel);
+        return Ok(());
+    }
+
+    if !config.general.wg_interface.is_empty()
+        || config.general.nm_connection.is_some()
+        || !config.subnets.ranges.is_empty()
+    {
+        anyhow::bail!(
+            "wg_interface, nm_connection and [subnets] belong in the [tunnel.NAME] sections \
+            when those are used"
+        );
+    }
+
+    if config.tunnels.len() > MAX_TUNNELS {
+        anyhow::bail!("At most {} tunnels can be defined", MAX_TUNNELS);
+    }
+    let mut interfaces = HashMap::new();
+    for (name, tunnel) in &config.tunnels {
+        // Names the directory of the tunnel's runtime state
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!(
+                "Invalid tunnel name {:?}: use letters, digits, '-' and '_'",
+                name
+            );
+        }
+        if let Some(other) = interfaces.insert(&tunnel.wg_interface, name) {
+            anyhow::bail!(
+                "Tunnels {:?} and {:?} both use interface {}",
+                other,
+                name,
+                tunnel.wg_interface
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Name of the tunnel to run: `name`, or the only one defined
+fn select_tunnel(config: &Config, name: Option<&str>) -> Result<String> {
+    match name {
+        Some(name) if config.tunnels.contains_key(name) => Ok(name.to_string()),
+        Some(name) => anyhow::bail!(
+            "No tunnel named {:?} (defined: {})",
+            name,
+            tunnel_names(config)
+        ),
+        None if config.tunnels.len() == 1 => {
+            Ok(config.tunnels.keys().next().cloned().unwrap_or_default())
+        }
+        None => anyhow::bail!(
+            "Several tunnels defined ({}), select one with --tunnel",
+            tunnel_names(config)
+        ),
+    }
+}
+
+fn tunnel_names(config: &Config) -> String {
+    config
+        .tunnels
+        .keys()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Apply a tunnel's settings over `[general]` and `[subnets]`
+fn apply_tunnel(config: &mut Config, tunnel: &TunnelConfig) {
+    config.general.wg_interface = tunnel.wg_interface.clone();
+    config.general.nm_connection = tunnel.nm_connection.clone();
+    config.subnets.ranges = tunnel.subnets.clone();
+    if let Some(timeout) = tunnel.idle_timeout {
+        config.general.idle_timeout = timeout;
+    }
+    if let Some(mode) = tunnel.mode {
+        config.general.mode = mode;
+    }
+}
+
+/// Drop-in directory of a config file (`config.d` for `config.toml`)
+fn drop_in_dir(path: &Path) -> PathBuf {
+    path.with_extension("d")
+}
+
+/// `*.toml` files in the drop-in directory of `path`, in lexical order
+fn drop_in_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let dir = drop_in_dir(path);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read drop-in directory {:?}", dir))
+        }
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("Failed to read drop-in directory {:?}", dir))?
+            .path();
+        if path.extension().is_some_and(|ext| ext == "toml") && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Merge `overlay` into `base`: tables recursively, other values replaced
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
 /// Validate configuration values
 fn validate_config(config: &Config) -> Result<()> {
     // Validate SSID lists
@@ -36,7 +266,7 @@ fn validate_config(config: &Config) -> Result<()> {
 
     // Warn if both lists are empty (monitor on all networks mode)
     if config.general.target_ssids.0.is_empty() && config.general.exclude_ssids.is_empty() {
-        log::warn!(
+        tracing::warn!(
             "No SSID filtering configured (target_ssids and exclude_ssids both empty). \
             Will monitor on ALL networks. IP collision detection will prevent issues \
             when on networks with same subnet as configured ranges."
@@ -53,6 +283,25 @@ fn validate_config(config: &Config) -> Result<()> {
         anyhow::bail!("idle_timeout must be > 0");
     }
 
+    // Validate max_session_secs is reasonable
+    if config.general.max_session_secs == Some(0) {
+        anyhow::bail!("max_session_secs must be > 0 (omit it for unlimited sessions)");
+    }
+
+    // Validate command timeout is reasonable
+    if config.general.command_timeout_secs == 0 {
+        anyhow::bail!("command_timeout_secs must be > 0");
+    }
+
+    // Validate flap detection parameters
+    if config.general.flap_threshold > 0
+        && (config.general.flap_window_secs == 0 || config.general.flap_backoff_secs == 0)
+    {
+        anyhow::bail!(
+            "flap_window_secs and flap_backoff_secs must be > 0 when flap_threshold is set"
+        );
+    }
+
     // Validate subnets list is not empty
     if config.subnets.ranges.is_empty() {
         anyhow::bail!("subnets.ranges cannot be empty");
@@ -71,6 +320,260 @@ fn validate_config(config: &Config) -> Result<()> {
         parse_cidr(subnet).with_context(|| format!("Invalid CIDR: {}", subnet))?;
     }
 
+    // Validate probe target is reachable through the tunnel
+    if let Some(probe) = &config.probe {
+        let host: Ipv4Addr = probe
+            .host
+            .parse()
+            .with_context(|| format!("Invalid probe host: {}", probe.host))?;
+        if !ip_in_subnets(u32::from_be_bytes(host.octets()), &config.subnets.ranges)? {
+            anyhow::bail!(
+                "Probe host {} is not inside any configured subnet range",
+                probe.host
+            );
+        }
+        if probe.timeout_secs == 0 {
+            anyhow::bail!("probe.timeout_secs must be > 0");
+        }
+    }
+
+    for host in &config.general.local_hosts {
+        let ip: Ipv4Addr = host
+            .parse()
+            .with_context(|| format!("Invalid local_hosts entry: {}", host))?;
+        if !ip_in_subnets(u32::from_be_bytes(ip.octets()), &config.subnets.ranges)? {
+            anyhow::bail!(
+                "local_hosts entry {} is not inside any configured subnet range",
+                host
+            );
+        }
+    }
+
+    if let Some(host) = &config.general.presence_probe {
+        let ip: Ipv4Addr = host
+            .parse()
+            .with_context(|| format!("Invalid presence_probe host: {}", host))?;
+        if !ip_in_subnets(u32::from_be_bytes(ip.octets()), &config.subnets.ranges)? {
+            anyhow::bail!(
+                "presence_probe host {} is not inside any configured subnet range",
+                host
+            );
+        }
+    }
+
+    if let Some(fingerprint) = &config.fingerprint {
+        if fingerprint.home_ranges.is_empty() && fingerprint.home_reverse_dns.is_empty() {
+            anyhow::bail!("fingerprint needs home_ranges or home_reverse_dns");
+        }
+        for range in &fingerprint.home_ranges {
+            parse_cidr(range).with_context(|| format!("Invalid fingerprint range: {}", range))?;
+        }
+        if fingerprint.timeout_secs == 0 {
+            anyhow::bail!("fingerprint.timeout_secs must be > 0");
+        }
+        // Only STUN requests carry the mark the kill switch lets through
+        if config.general.kill_switch == KillSwitchMode::All
+            && !fingerprint.url.starts_with("stun:")
+        {
+            anyhow::bail!("fingerprint.url must be a stun: URL with kill_switch = \"all\"");
+        }
+    }
+
+    if let Some(dock) = &config.dock {
+        validate_interface_name(&dock.interface).context("Invalid dock interface")?;
+        if dock.gateway_mac.is_none() && dock.subnet.is_none() {
+            anyhow::bail!("dock needs gateway_mac or subnet");
+        }
+        if let Some(mac) = &dock.gateway_mac {
+            if dock::parse_mac(mac).is_none() {
+                anyhow::bail!("Invalid dock gateway_mac: {}", mac);
+            }
+        }
+        if let Some(subnet) = &dock.subnet {
+            parse_cidr(subnet).with_context(|| format!("Invalid dock subnet: {}", subnet))?;
+        }
+    }
+
+    if let Some(otel) = &config.otel {
+        if !cfg!(feature = "otel") {
+            anyhow::bail!("[otel] needs a build with the `otel` feature");
+        }
+        if !otel.endpoint.starts_with("http://") {
+            anyhow::bail!("otel.endpoint must be an http:// URL: {}", otel.endpoint);
+        }
+        if otel.metrics_interval_secs == 0 {
+            anyhow::bail!("otel.metrics_interval_secs must be > 0");
+        }
+    }
+
+    if config.general.monitor_interface.is_some() && !config.general.monitor_interfaces.is_empty() {
+        anyhow::bail!("Set either monitor_interface or monitor_interfaces, not both");
+    }
+    for iface in &config.general.monitor_interfaces {
+        validate_interface_name(iface)
+            .with_context(|| format!("Invalid monitor interface: {}", iface))?;
+    }
+    if config.general.monitor_interfaces.len() > MAX_MONITOR_INTERFACES {
+        anyhow::bail!(
+            "At most {} monitor_interfaces are supported",
+            MAX_MONITOR_INTERFACES
+        );
+    }
+
+    // Blackholed packets never reach the eBPF classifier, only the netfilter queue
+    if config.general.gateway_fallback == GatewayFallback::Blackhole && !config.general.hold_traffic
+    {
+        anyhow::bail!("gateway_fallback = \"blackhole\" requires hold_traffic = true");
+    }
+
+    match config.general.source_filter {
+        SourceFilter::Ranges if config.general.source_ranges.is_empty() => {
+            anyhow::bail!("source_filter = \"ranges\" requires source_ranges");
+        }
+        SourceFilter::Ranges => {}
+        _ if !config.general.source_ranges.is_empty() => {
+            anyhow::bail!("source_ranges requires source_filter = \"ranges\"");
+        }
+        _ => {}
+    }
+    if config.general.source_ranges.len() > MAX_SOURCE_RANGES {
+        anyhow::bail!("At most {} source_ranges are supported", MAX_SOURCE_RANGES);
+    }
+    for range in &config.general.source_ranges {
+        parse_cidr(range).with_context(|| format!("Invalid source range: {}", range))?;
+    }
+
+    if let Some(mtu) = config.general.mtu {
+        if !(MIN_TUNNEL_MTU..=MAX_TUNNEL_MTU).contains(&mtu) {
+            anyhow::bail!(
+                "mtu must be between {} and {}",
+                MIN_TUNNEL_MTU,
+                MAX_TUNNEL_MTU
+            );
+        }
+    }
+
+    if config.general.idle_check_interval_secs == 0 {
+        anyhow::bail!("idle_check_interval_secs must be > 0");
+    }
+    if config.general.ebpf_poll_interval_ms == 0 {
+        anyhow::bail!("ebpf_poll_interval_ms must be > 0");
+    }
+
+    // Handshakes are only renewed every 2 minutes while traffic flows, and the
+    // renewal is seen at the next idle check
+    let min_handshake_idle_timeout =
+        HANDSHAKE_RENEWAL_SECS + config.general.idle_check_interval_secs;
+    if config.general.idle_detection == IdleDetection::Handshake
+        && config.general.idle_timeout < min_handshake_idle_timeout
+    {
+        anyhow::bail!(
+            "idle_timeout must be at least {}s with idle_detection = \"handshake\"",
+            min_handshake_idle_timeout
+        );
+    }
+
+    if config.general.idle_detection == IdleDetection::Ebpf
+        && !config.general.activity_peers.is_empty()
+    {
+        anyhow::bail!("activity_peers cannot be used with idle_detection = \"ebpf\"");
+    }
+
+    // Scanning sockets can neither drop packets nor count the tunnel's bytes
+    if config.general.traffic_detection == TrafficDetection::Sockets {
+        if config.general.kill_switch != KillSwitchMode::Off {
+            anyhow::bail!("kill_switch cannot be used with traffic_detection = \"sockets\"");
+        }
+        if config.general.idle_detection == IdleDetection::Ebpf {
+            anyhow::bail!(
+                "idle_detection = \"ebpf\" cannot be used with traffic_detection = \"sockets\""
+            );
+        }
+    }
+
+    for peer in &config.general.activity_peers {
+        wireguard_control::Key::from_base64(peer)
+            .map_err(|_| anyhow::anyhow!("Invalid activity_peers public key: {}", peer))?;
+    }
+
+    if config.general.narrow_allowed_ips && config.general.nm_connection.is_some() {
+        anyhow::bail!("narrow_allowed_ips is not supported with nm_connection");
+    }
+
+    // Validate native tunnel definition
+    if let Some(native) = &config.native {
+        if config.general.nm_connection.is_some() {
+            anyhow::bail!("[native] cannot be combined with nm_connection");
+        }
+        if native.config_file.is_some() {
+            if native.private_key_file.is_some()
+                || !native.addresses.is_empty()
+                || !native.peers.is_empty()
+            {
+                anyhow::bail!(
+                    "native.config_file cannot be combined with an inline interface definition"
+                );
+            }
+        } else {
+            if native.private_key_file.is_none() {
+                anyhow::bail!("native.private_key_file is required without native.config_file");
+            }
+            if native.addresses.is_empty() {
+                anyhow::bail!("native.addresses cannot be empty");
+            }
+            for address in &native.addresses {
+                parse_allowed_ip(address).context("Invalid native.addresses entry")?;
+            }
+            if native.peers.is_empty() {
+                anyhow::bail!("native.peers cannot be empty");
+            }
+            for peer in &native.peers {
+                wireguard_control::Key::from_base64(&peer.public_key).map_err(|_| {
+                    anyhow::anyhow!("Invalid native peer public key: {}", peer.public_key)
+                })?;
+                if let Some(address) = &peer.endpoint {
+                    endpoint::validate_address(address)?;
+                }
+                for cidr in &peer.allowed_ips {
+                    check_native_allowed_ip(cidr)?;
+                }
+            }
+        }
+    }
+
+    // wg-quick and nmcli need root
+    if let Some(user) = &config.general.user {
+        if user.is_empty() {
+            anyhow::bail!("user cannot be empty");
+        }
+        if config.native.is_none() {
+            anyhow::bail!("user requires the [native] tunnel backend");
+        }
+    }
+
+    // Validate endpoint failover list
+    if let Some(endpoints) = &config.endpoints {
+        if endpoints.addresses.is_empty() {
+            anyhow::bail!("endpoints.addresses cannot be empty");
+        }
+        for address in &endpoints.addresses {
+            endpoint::validate_address(address)?;
+        }
+        if let Some(peer) = &endpoints.peer {
+            wireguard_control::Key::from_base64(peer)
+                .map_err(|_| anyhow::anyhow!("endpoints.peer is not a valid public key"))?;
+        }
+        if endpoints.addresses.len() > 1
+            && config.general.handshake_timeout_secs == 0
+            && config.probe.is_none()
+        {
+            tracing::warn!(
+                "Multiple endpoints configured without handshake_timeout_secs or [probe]: \
+                failover only happens when bring-up itself fails"
+            );
+        }
+    }
+
     Ok(())
 }
 
@@ -82,7 +585,7 @@ fn validate_config(config: &Config) -> Result<()> {
 ///
 /// # Returns
 /// `true` if the IP is within any subnet, `false` otherwise
-pub fn ip_in_subnets(ip: u32, subnet_cidrs: &[String]) -> Result<bool> {
+pub fn ip_in_subnets(ip: u32, subnet_cidrs: &[String]) -> Result<bool, ConfigError> {
     for cidr in subnet_cidrs {
         let (network, mask) = parse_cidr(cidr)?;
         if (ip & mask) == network {
@@ -94,17 +597,17 @@ pub fn ip_in_subnets(ip: u32, subnet_cidrs: &[String]) -> Result<bool> {
 
 /// Parse CIDR notation into (network, mask) tuple
 /// Returns network address and netmask in network byte order (big endian)
-pub fn parse_cidr(cidr: &str) -> Result<(u32, u32)> {
+pub fn parse_cidr(cidr: &str) -> Result<(u32, u32), ConfigError> {
     let parts: Vec<&str> = cidr.split('/').collect();
     if parts.len() != 2 {
-        anyhow::bail!("Invalid CIDR format (expected X.X.X.X/N)");
+        return Err(anyhow::anyhow!("Invalid CIDR format (expected X.X.X.X/N)").into());
     }
 
     let ip: Ipv4Addr = parts[0].parse().context("Invalid IP address")?;
     let prefix_len: u8 = parts[1].parse().context("Invalid prefix length")?;
 
     if prefix_len > 32 {
-        anyhow::bail!("Prefix length must be <= 32");
+        return Err(anyhow::anyhow!("Prefix length must be <= 32").into());
     }
 
     // Convert IP to u32 (network byte order = big endian)
@@ -126,7 +629,11 @@ pub fn parse_cidr(cidr: &str) -> Result<(u32, u32)> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::SsidList;
+    use crate::types::{
+        DockConfig, FingerprintConfig, IdleDetection, LogTarget, MonitorRouting, NetworkBackend,
+        OtelConfig, SourceFilter, SsidList, TunnelMode,
+    };
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_parse_cidr() {
@@ -157,7 +664,10 @@ mod tests {
 
     #[test]
     fn test_validate_config() {
-        use crate::types::{GeneralConfig, SsidList, SubnetConfig};
+        use crate::types::{
+            EndpointConfig, EndpointSelection, GeneralConfig, NativePeerConfig, NativeTunnelConfig,
+            ProbeConfig, SsidList, SubnetConfig,
+        };
 
         // Valid config with target SSID
         let config = Config {
@@ -167,12 +677,72 @@ mod tests {
                 wg_interface: "wg0".to_string(),
                 nm_connection: None,
                 monitor_interface: None,
+                monitor_interfaces: vec![],
                 idle_timeout: 300,
+                mode: TunnelMode::OnDemand,
+                idle_warning_secs: 60,
+                min_active_secs: 0,
+                activation_delay_ms: 0,
+                max_session_secs: None,
+                cooldown_secs: 0,
+                pause_secs: 900,
+                history_size: 100,
+                event_log: None,
+                event_log_max_bytes: 1024 * 1024,
+                event_log_keep: 5,
+                event_log_max_days: 0,
+                flap_threshold: 0,
+                flap_window_secs: 600,
+                flap_backoff_secs: 60,
+                activation_retries: 3,
+                activation_retry_secs: 2,
+                handshake_timeout_secs: 0,
+                handshake_stale_secs: 0,
+                command_timeout_secs: 30,
+                narrow_allowed_ips: false,
+                persistent_keepalive_secs: None,
+                mtu: None,
+                activity_peers: vec![],
+                ignore_keepalives: true,
+                activity_threshold_bytes: 0,
+                idle_check_interval_secs: 60,
+                ebpf_poll_interval_ms: 1000,
+                idle_detection: IdleDetection::Bytes,
+                traffic_detection: TrafficDetection::Auto,
+                manual_idle_timeout: false,
+                dry_run: false,
+                kill_switch: KillSwitchMode::Off,
+                hold_traffic: false,
+                network_backend: NetworkBackend::NetworkManager,
+                captive_portal_check: true,
+                connection_wait_secs: 30,
+                local_hosts: vec![],
+                presence_probe: None,
+                monitor_routing: MonitorRouting::Table,
+                route_metric: None,
+                gateway_fallback: GatewayFallback::None,
+                source_filter: SourceFilter::Any,
+                source_ranges: vec![],
+                tc_priority: None,
+                tc_handle: None,
+                drop_capabilities: false,
+                user: None,
+                landlock: true,
+                seccomp: true,
                 log_level: "info".to_string(),
+                log_target: LogTarget::Stderr,
             },
             subnets: SubnetConfig {
                 ranges: vec!["192.168.1.0/24".to_string()],
             },
+            tunnels: BTreeMap::new(),
+            instance: Instance::default(),
+            probe: None,
+            endpoints: None,
+            fingerprint: None,
+            dock: None,
+            native: None,
+            otel: None,
         };
         assert!(validate_config(&config).is_ok());
 
@@ -193,6 +763,266 @@ mod tests {
         bad_config.general.idle_timeout = 0;
         assert!(validate_config(&bad_config).is_err());
 
+        // Zero max session
+        let mut bad_config = config.clone();
+        bad_config.general.max_session_secs = Some(0);
+        assert!(validate_config(&bad_config).is_err());
+
+        // Zero command timeout
+        let mut bad_config = config.clone();
+        bad_config.general.command_timeout_secs = 0;
+        assert!(validate_config(&bad_config).is_err());
+
+        // Flap detection without a window
+        let mut bad_config = config.clone();
+        bad_config.general.flap_threshold = 5;
+        bad_config.general.flap_window_secs = 0;
+        assert!(validate_config(&bad_config).is_err());
+
+        // Valid probe
+        let mut probe_config = config.clone();
+        probe_config.probe = Some(ProbeConfig {
+            host: "192.168.1.10".to_string(),
+            port: Some(22),
+            timeout_secs: 5,
+            path_mtu: false,
+        });
+        assert!(validate_config(&probe_config).is_ok());
+
+        // Probe host outside target subnets
+        let mut bad_config = probe_config.clone();
+        bad_config.probe.as_mut().unwrap().host = "10.0.0.1".to_string();
+        assert!(validate_config(&bad_config).is_err());
+
+        // Probe host not an IP address
+        let mut bad_config = probe_config.clone();
+        bad_config.probe.as_mut().unwrap().host = "nas.local".to_string();
+        assert!(validate_config(&bad_config).is_err());
+
+        // Local reachability hosts must be inside the target subnets
+        let mut local_config = config.clone();
+        local_config.general.local_hosts = vec!["192.168.1.1".to_string()];
+        assert!(validate_config(&local_config).is_ok());
+        local_config.general.local_hosts = vec!["10.0.0.1".to_string()];
+        assert!(validate_config(&local_config).is_err());
+        local_config.general.local_hosts = vec!["router.lan".to_string()];
+        assert!(validate_config(&local_config).is_err());
+
+        // Fingerprint
+        let mut fingerprint_config = config.clone();
+        fingerprint_config.fingerprint = Some(FingerprintConfig {
+            url: "https://api.ipify.org".to_string(),
+            home_ranges: vec!["203.0.113.0/24".to_string()],
+            home_reverse_dns: vec![],
+            timeout_secs: 5,
+        });
+        assert!(validate_config(&fingerprint_config).is_ok());
+        fingerprint_config.general.kill_switch = KillSwitchMode::All;
+        assert!(validate_config(&fingerprint_config).is_err());
+        fingerprint_config.fingerprint.as_mut().unwrap().url =
+            "stun:stun.example.com:3478".to_string();
+        assert!(validate_config(&fingerprint_config).is_ok());
+        fingerprint_config.fingerprint.as_mut().unwrap().home_ranges = vec![];
+        assert!(validate_config(&fingerprint_config).is_err());
+
+        // Dock
+        let mut dock_config = config.clone();
+        dock_config.dock = Some(DockConfig {
+            interface: "enp0s13f0u1".to_string(),
+            gateway_mac: Some("aa:bb:cc:00:11:22".to_string()),
+            subnet: None,
+        });
+        assert!(validate_config(&dock_config).is_ok());
+        dock_config.dock.as_mut().unwrap().gateway_mac = Some("aa:bb:cc".to_string());
+        assert!(validate_config(&dock_config).is_err());
+        dock_config.dock.as_mut().unwrap().gateway_mac = None;
+        assert!(validate_config(&dock_config).is_err());
+        dock_config.dock.as_mut().unwrap().subnet = Some("192.168.1.0/24".to_string());
+        assert!(validate_config(&dock_config).is_ok());
+        dock_config.dock.as_mut().unwrap().interface = "bad iface".to_string();
+        assert!(validate_config(&dock_config).is_err());
+
+        // OpenTelemetry export
+        let mut otel_config = config.clone();
+        otel_config.otel = Some(OtelConfig {
+            endpoint: "http://localhost:4318".to_string(),
+            service_name: "wg-ondemand".to_string(),
+            metrics_interval_secs: 60,
+        });
+        assert_eq!(
+            validate_config(&otel_config).is_ok(),
+            cfg!(feature = "otel")
+        );
+        otel_config.otel.as_mut().unwrap().endpoint = "https://otel.example.com".to_string();
+        assert!(validate_config(&otel_config).is_err());
+
+        // Blackhole fallback needs the traffic hold to see the traffic
+        let mut fallback_config = config.clone();
+        fallback_config.general.gateway_fallback = GatewayFallback::Device;
+        assert!(validate_config(&fallback_config).is_ok());
+        fallback_config.general.gateway_fallback = GatewayFallback::Blackhole;
+        assert!(validate_config(&fallback_config).is_err());
+        fallback_config.general.hold_traffic = true;
+        assert!(validate_config(&fallback_config).is_ok());
+
+        // Source filter
+        let mut source_config = config.clone();
+        source_config.general.source_filter = SourceFilter::Local;
+        assert!(validate_config(&source_config).is_ok());
+        source_config.general.source_ranges = vec!["192.168.122.0/24".to_string()];
+        assert!(validate_config(&source_config).is_err());
+        source_config.general.source_filter = SourceFilter::Ranges;
+        assert!(validate_config(&source_config).is_ok());
+        source_config.general.source_ranges = vec!["192.168.122.0".to_string()];
+        assert!(validate_config(&source_config).is_err());
+        source_config.general.source_ranges = vec![];
+        assert!(validate_config(&source_config).is_err());
+
+        // Several monitor interfaces replace monitor_interface
+        let mut uplinks_config = config.clone();
+        uplinks_config.general.monitor_interfaces = vec!["eth0".to_string(), "wlan0".to_string()];
+        assert!(validate_config(&uplinks_config).is_ok());
+        uplinks_config.general.monitor_interface = Some("wlan0".to_string());
+        assert!(validate_config(&uplinks_config).is_err());
+        uplinks_config.general.monitor_interface = None;
+        uplinks_config.general.monitor_interfaces = vec!["eth0; rm".to_string()];
+        assert!(validate_config(&uplinks_config).is_err());
+
+        // Presence probe host must be inside the target subnets
+        let mut presence_config = config.clone();
+        presence_config.general.presence_probe = Some("192.168.1.10".to_string());
+        assert!(validate_config(&presence_config).is_ok());
+        presence_config.general.presence_probe = Some("10.0.0.1".to_string());
+        assert!(validate_config(&presence_config).is_err());
+
+        // Endpoint failover list
+        let mut endpoint_config = config.clone();
+        endpoint_config.endpoints = Some(EndpointConfig {
+            peer: None,
+            addresses: vec!["home.example.com:51820".to_string()],
+            selection: EndpointSelection::Failover,
+        });
+        assert!(validate_config(&endpoint_config).is_ok());
+
+        let mut bad_config = endpoint_config.clone();
+        bad_config.endpoints.as_mut().unwrap().addresses = vec![];
+        assert!(validate_config(&bad_config).is_err());
+
+        let mut bad_config = endpoint_config.clone();
+        bad_config.endpoints.as_mut().unwrap().addresses = vec!["no-port".to_string()];
+        assert!(validate_config(&bad_config).is_err());
+
+        let mut bad_config = endpoint_config.clone();
+        bad_config.endpoints.as_mut().unwrap().peer = Some("not-a-key".to_string());
+        assert!(validate_config(&bad_config).is_err());
+
+        // Tunnel MTU bounds
+        let mut mtu_config = config.clone();
+        mtu_config.general.mtu = Some(1380);
+        assert!(validate_config(&mtu_config).is_ok());
+        mtu_config.general.mtu = Some(500);
+        assert!(validate_config(&mtu_config).is_err());
+
+        // Zero intervals
+        let mut bad_config = config.clone();
+        bad_config.general.idle_check_interval_secs = 0;
+        assert!(validate_config(&bad_config).is_err());
+        let mut bad_config = config.clone();
+        bad_config.general.ebpf_poll_interval_ms = 0;
+        assert!(validate_config(&bad_config).is_err());
+
+        // Handshake idle detection needs a timeout above the renewal interval
+        let mut handshake_config = config.clone();
+        handshake_config.general.idle_detection = IdleDetection::Handshake;
+        assert!(validate_config(&handshake_config).is_ok());
+        handshake_config.general.idle_timeout = 60;
+        assert!(validate_config(&handshake_config).is_err());
+        handshake_config.general.idle_timeout = 150;
+        handshake_config.general.idle_check_interval_secs = 30;
+        assert!(validate_config(&handshake_config).is_ok());
+
+        // Activity peers must be public keys
+        let mut peers_config = config.clone();
+        peers_config.general.activity_peers =
+            vec!["yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=".to_string()];
+        assert!(validate_config(&peers_config).is_ok());
+        // eBPF counters on the interface can't tell peers apart
+        let mut ebpf_config = peers_config.clone();
+        ebpf_config.general.idle_detection = IdleDetection::Ebpf;
+        assert!(validate_config(&ebpf_config).is_err());
+        peers_config.general.activity_peers = vec!["not-a-key".to_string()];
+        assert!(validate_config(&peers_config).is_err());
+
+        // Socket scanning has no kill switch or byte counters
+        let mut sockets_config = config.clone();
+        sockets_config.general.traffic_detection = TrafficDetection::Sockets;
+        assert!(validate_config(&sockets_config).is_ok());
+        sockets_config.general.kill_switch = KillSwitchMode::Subnets;
+        assert!(validate_config(&sockets_config).is_err());
+        sockets_config.general.kill_switch = KillSwitchMode::Off;
+        sockets_config.general.idle_detection = IdleDetection::Ebpf;
+        assert!(validate_config(&sockets_config).is_err());
+
+        // AllowedIPs narrowing leaves NetworkManager-managed routes alone
+        let mut narrow_config = config.clone();
+        narrow_config.general.narrow_allowed_ips = true;
+        assert!(validate_config(&narrow_config).is_ok());
+        narrow_config.general.nm_connection = Some("home-vpn".to_string());
+        assert!(validate_config(&narrow_config).is_err());
+
+        // Native tunnel definition
+        let mut native_config = config.clone();
+        native_config.native = Some(NativeTunnelConfig {
+            config_file: None,
+            private_key_file: Some("/etc/wg-ondemand/wg0.key".into()),
+            addresses: vec!["10.0.0.2/32".to_string()],
+            listen_port: None,
+            mtu: None,
+            peers: vec![NativePeerConfig {
+                public_key: "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=".to_string(),
+                preshared_key_file: None,
+                endpoint: Some("home.example.com:51820".to_string()),
+                allowed_ips: vec!["192.168.1.0/24".to_string()],
+                persistent_keepalive: Some(25),
+            }],
+        });
+        assert!(validate_config(&native_config).is_ok());
+
+        let mut bad_config = native_config.clone();
+        bad_config.general.nm_connection = Some("home-vpn".to_string());
+        assert!(validate_config(&bad_config).is_err());
+
+        let mut bad_config = native_config.clone();
+        bad_config.native.as_mut().unwrap().peers[0].allowed_ips = vec!["0.0.0.0/0".to_string()];
+        assert!(validate_config(&bad_config).is_err());
+
+        let mut bad_config = native_config.clone();
+        bad_config.native.as_mut().unwrap().peers[0].public_key = "bogus".to_string();
+        assert!(validate_config(&bad_config).is_err());
+
+        // Switching user needs the native backend
+        let mut user_config = native_config.clone();
+        user_config.general.user = Some("wg-ondemand".to_string());
+        assert!(validate_config(&user_config).is_ok());
+        user_config.native = None;
+        assert!(validate_config(&user_config).is_err());
+
+        // Native tunnel read from a wg-quick config excludes the inline definition
+        let mut file_config = config.clone();
+        file_config.native = Some(NativeTunnelConfig {
+            config_file: Some("/etc/wireguard/wg0.conf".into()),
+            private_key_file: None,
+            addresses: vec![],
+            listen_port: None,
+            mtu: None,
+            peers: vec![],
+        });
+        assert!(validate_config(&file_config).is_ok());
+
+        let mut bad_config = native_config.clone();
+        bad_config.native.as_mut().unwrap().config_file = Some("/etc/wireguard/wg0.conf".into());
+        assert!(validate_config(&bad_config).is_err());
+
         // Invalid CIDR
         let mut bad_config = config.clone();
         bad_config.subnets.ranges = vec!["invalid".to_string()];
@@ -251,10 +1081,70 @@ mod tests {
                 wg_interface: "wg0".to_string(),
                 nm_connection: None,
                 monitor_interface: None,
+                monitor_interfaces: vec![],
                 idle_timeout: 300,
+                mode: TunnelMode::OnDemand,
+                idle_warning_secs: 60,
+                min_active_secs: 0,
+                activation_delay_ms: 0,
+                max_session_secs: None,
+                cooldown_secs: 0,
+                pause_secs: 900,
+                history_size: 100,
+                event_log: None,
+                event_log_max_bytes: 1024 * 1024,
+                event_log_keep: 5,
+                event_log_max_days: 0,
+                flap_threshold: 0,
+                flap_window_secs: 600,
+                flap_backoff_secs: 60,
+                activation_retries: 3,
+                activation_retry_secs: 2,
+                handshake_timeout_secs: 0,
+                handshake_stale_secs: 0,
+                command_timeout_secs: 30,
+                narrow_allowed_ips: false,
+                persistent_keepalive_secs: None,
+                mtu: None,
+                activity_peers: vec![],
+                ignore_keepalives: true,
+                activity_threshold_bytes: 0,
+                idle_check_interval_secs: 60,
+                ebpf_poll_interval_ms: 1000,
+                idle_detection: IdleDetection::Bytes,
+                traffic_detection: TrafficDetection::Auto,
+                manual_idle_timeout: false,
+                dry_run: false,
+                kill_switch: KillSwitchMode::Off,
+                hold_traffic: false,
+                network_backend: NetworkBackend::NetworkManager,
+                captive_portal_check: true,
+                connection_wait_secs: 30,
+                local_hosts: vec![],
+                presence_probe: None,
+                monitor_routing: MonitorRouting::Table,
+                route_metric: None,
+                gateway_fallback: GatewayFallback::None,
+                source_filter: SourceFilter::Any,
+                source_ranges: vec![],
+                tc_priority: None,
+                tc_handle: None,
+                drop_capabilities: false,
+                user: None,
+                landlock: true,
+                seccomp: true,
                 log_level: "info".to_string(),
+                log_target: LogTarget::Stderr,
             },
             subnets: SubnetConfig { ranges: vec![] },
+            tunnels: BTreeMap::new(),
+            instance: Instance::default(),
+            probe: None,
+            endpoints: None,
+            fingerprint: None,
+            dock: None,
+            native: None,
+            otel: None,
         };
 
         assert!(validate_config(&config).is_err());
@@ -271,12 +1161,72 @@ mod tests {
                 wg_interface: "wg0".to_string(),
                 nm_connection: None,
                 monitor_interface: None,
+                monitor_interfaces: vec![],
                 idle_timeout: 300,
+                mode: TunnelMode::OnDemand,
+                idle_warning_secs: 60,
+                min_active_secs: 0,
+                activation_delay_ms: 0,
+                max_session_secs: None,
+                cooldown_secs: 0,
+                pause_secs: 900,
+                history_size: 100,
+                event_log: None,
+                event_log_max_bytes: 1024 * 1024,
+                event_log_keep: 5,
+                event_log_max_days: 0,
+                flap_threshold: 0,
+                flap_window_secs: 600,
+                flap_backoff_secs: 60,
+                activation_retries: 3,
+                activation_retry_secs: 2,
+                handshake_timeout_secs: 0,
+                handshake_stale_secs: 0,
+                command_timeout_secs: 30,
+                narrow_allowed_ips: false,
+                persistent_keepalive_secs: None,
+                mtu: None,
+                activity_peers: vec![],
+                ignore_keepalives: true,
+                activity_threshold_bytes: 0,
+                idle_check_interval_secs: 60,
+                ebpf_poll_interval_ms: 1000,
+                idle_detection: IdleDetection::Bytes,
+                traffic_detection: TrafficDetection::Auto,
+                manual_idle_timeout: false,
+                dry_run: false,
+                kill_switch: KillSwitchMode::Off,
+                hold_traffic: false,
+                network_backend: NetworkBackend::NetworkManager,
+                captive_portal_check: true,
+                connection_wait_secs: 30,
+                local_hosts: vec![],
+                presence_probe: None,
+                monitor_routing: MonitorRouting::Table,
+                route_metric: None,
+                gateway_fallback: GatewayFallback::None,
+                source_filter: SourceFilter::Any,
+                source_ranges: vec![],
+                tc_priority: None,
+                tc_handle: None,
+                drop_capabilities: false,
+                user: None,
+                landlock: true,
+                seccomp: true,
                 log_level: "info".to_string(),
+                log_target: LogTarget::Stderr,
             },
             subnets: SubnetConfig {
                 ranges: (0..17).map(|i| format!("10.{}.0.0/24", i)).collect(),
             },
+            tunnels: BTreeMap::new(),
+            instance: Instance::default(),
+            probe: None,
+            endpoints: None,
+            fingerprint: None,
+            dock: None,
+            native: None,
+            otel: None,
         };
 
         assert!(validate_config(&config).is_err());
@@ -294,12 +1244,72 @@ mod tests {
                 wg_interface: "wg0".to_string(),
                 nm_connection: None,
                 monitor_interface: None,
+                monitor_interfaces: vec![],
                 idle_timeout: 300,
+                mode: TunnelMode::OnDemand,
+                idle_warning_secs: 60,
+                min_active_secs: 0,
+                activation_delay_ms: 0,
+                max_session_secs: None,
+                cooldown_secs: 0,
+                pause_secs: 900,
+                history_size: 100,
+                event_log: None,
+                event_log_max_bytes: 1024 * 1024,
+                event_log_keep: 5,
+                event_log_max_days: 0,
+                flap_threshold: 0,
+                flap_window_secs: 600,
+                flap_backoff_secs: 60,
+                activation_retries: 3,
+                activation_retry_secs: 2,
+                handshake_timeout_secs: 0,
+                handshake_stale_secs: 0,
+                command_timeout_secs: 30,
+                narrow_allowed_ips: false,
+                persistent_keepalive_secs: None,
+                mtu: None,
+                activity_peers: vec![],
+                ignore_keepalives: true,
+                activity_threshold_bytes: 0,
+                idle_check_interval_secs: 60,
+                ebpf_poll_interval_ms: 1000,
+                idle_detection: IdleDetection::Bytes,
+                traffic_detection: TrafficDetection::Auto,
+                manual_idle_timeout: false,
+                dry_run: false,
+                kill_switch: KillSwitchMode::Off,
+                hold_traffic: false,
+                network_backend: NetworkBackend::NetworkManager,
+                captive_portal_check: true,
+                connection_wait_secs: 30,
+                local_hosts: vec![],
+                presence_probe: None,
+                monitor_routing: MonitorRouting::Table,
+                route_metric: None,
+                gateway_fallback: GatewayFallback::None,
+                source_filter: SourceFilter::Any,
+                source_ranges: vec![],
+                tc_priority: None,
+                tc_handle: None,
+                drop_capabilities: false,
+                user: None,
+                landlock: true,
+                seccomp: true,
                 log_level: "info".to_string(),
+                log_target: LogTarget::Stderr,
             },
             subnets: SubnetConfig {
                 ranges: (0..16).map(|i| format!("10.{}.0.0/24", i)).collect(),
             },
+            tunnels: BTreeMap::new(),
+            instance: Instance::default(),
+            probe: None,
+            endpoints: None,
+            fingerprint: None,
+            dock: None,
+            native: None,
+            otel: None,
         };
 
         assert!(validate_config(&config).is_ok());
@@ -317,8 +1327,60 @@ mod tests {
                 wg_interface: "wg0".to_string(),
                 nm_connection: None,
                 monitor_interface: None,
+                monitor_interfaces: vec![],
                 idle_timeout: 300,
+                mode: TunnelMode::OnDemand,
+                idle_warning_secs: 60,
+                min_active_secs: 0,
+                activation_delay_ms: 0,
+                max_session_secs: None,
+                cooldown_secs: 0,
+                pause_secs: 900,
+                history_size: 100,
+                event_log: None,
+                event_log_max_bytes: 1024 * 1024,
+                event_log_keep: 5,
+                event_log_max_days: 0,
+                flap_threshold: 0,
+                flap_window_secs: 600,
+                flap_backoff_secs: 60,
+                activation_retries: 3,
+                activation_retry_secs: 2,
+                handshake_timeout_secs: 0,
+                handshake_stale_secs: 0,
+                command_timeout_secs: 30,
+                narrow_allowed_ips: false,
+                persistent_keepalive_secs: None,
+                mtu: None,
+                activity_peers: vec![],
+                ignore_keepalives: true,
+                activity_threshold_bytes: 0,
+                idle_check_interval_secs: 60,
+                ebpf_poll_interval_ms: 1000,
+                idle_detection: IdleDetection::Bytes,
+                traffic_detection: TrafficDetection::Auto,
+                manual_idle_timeout: false,
+                dry_run: false,
+                kill_switch: KillSwitchMode::Off,
+                hold_traffic: false,
+                network_backend: NetworkBackend::NetworkManager,
+                captive_portal_check: true,
+                connection_wait_secs: 30,
+                local_hosts: vec![],
+                presence_probe: None,
+                monitor_routing: MonitorRouting::Table,
+                route_metric: None,
+                gateway_fallback: GatewayFallback::None,
+                source_filter: SourceFilter::Any,
+                source_ranges: vec![],
+                tc_priority: None,
+                tc_handle: None,
+                drop_capabilities: false,
+                user: None,
+                landlock: true,
+                seccomp: true,
                 log_level: "info".to_string(),
+                log_target: LogTarget::Stderr,
             },
             subnets: SubnetConfig {
                 ranges: vec![
@@ -326,6 +1388,14 @@ mod tests {
                     "192.168.1.0/24".to_string(), // More specific
                 ],
             },
+            tunnels: BTreeMap::new(),
+            instance: Instance::default(),
+            probe: None,
+            endpoints: None,
+            fingerprint: None,
+            dock: None,
+            native: None,
+            otel: None,
         };
 
         assert!(validate_config(&config).is_ok());
@@ -342,12 +1412,72 @@ mod tests {
                 wg_interface: "wg0".to_string(),
                 nm_connection: None,
                 monitor_interface: None,
+                monitor_interfaces: vec![],
                 idle_timeout: 300,
+                mode: TunnelMode::OnDemand,
+                idle_warning_secs: 60,
+                min_active_secs: 0,
+                activation_delay_ms: 0,
+                max_session_secs: None,
+                cooldown_secs: 0,
+                pause_secs: 900,
+                history_size: 100,
+                event_log: None,
+                event_log_max_bytes: 1024 * 1024,
+                event_log_keep: 5,
+                event_log_max_days: 0,
+                flap_threshold: 0,
+                flap_window_secs: 600,
+                flap_backoff_secs: 60,
+                activation_retries: 3,
+                activation_retry_secs: 2,
+                handshake_timeout_secs: 0,
+                handshake_stale_secs: 0,
+                command_timeout_secs: 30,
+                narrow_allowed_ips: false,
+                persistent_keepalive_secs: None,
+                mtu: None,
+                activity_peers: vec![],
+                ignore_keepalives: true,
+                activity_threshold_bytes: 0,
+                idle_check_interval_secs: 60,
+                ebpf_poll_interval_ms: 1000,
+                idle_detection: IdleDetection::Bytes,
+                traffic_detection: TrafficDetection::Auto,
+                manual_idle_timeout: false,
+                dry_run: false,
+                kill_switch: KillSwitchMode::Off,
+                hold_traffic: false,
+                network_backend: NetworkBackend::NetworkManager,
+                captive_portal_check: true,
+                connection_wait_secs: 30,
+                local_hosts: vec![],
+                presence_probe: None,
+                monitor_routing: MonitorRouting::Table,
+                route_metric: None,
+                gateway_fallback: GatewayFallback::None,
+                source_filter: SourceFilter::Any,
+                source_ranges: vec![],
+                tc_priority: None,
+                tc_handle: None,
+                drop_capabilities: false,
+                user: None,
+                landlock: true,
+                seccomp: true,
                 log_level: "info".to_string(),
+                log_target: LogTarget::Stderr,
             },
             subnets: SubnetConfig {
                 ranges: vec!["192.168.1.0/24".to_string()],
             },
+            tunnels: BTreeMap::new(),
+            instance: Instance::default(),
+            probe: None,
+            endpoints: None,
+            fingerprint: None,
+            dock: None,
+            native: None,
+            otel: None,
         };
 
         // Very small timeout should work
@@ -372,12 +1502,72 @@ mod tests {
                 wg_interface: "".to_string(),
                 nm_connection: None,
                 monitor_interface: None,
+                monitor_interfaces: vec![],
                 idle_timeout: 300,
+                mode: TunnelMode::OnDemand,
+                idle_warning_secs: 60,
+                min_active_secs: 0,
+                activation_delay_ms: 0,
+                max_session_secs: None,
+                cooldown_secs: 0,
+                pause_secs: 900,
+                history_size: 100,
+                event_log: None,
+                event_log_max_bytes: 1024 * 1024,
+                event_log_keep: 5,
+                event_log_max_days: 0,
+                flap_threshold: 0,
+                flap_window_secs: 600,
+                flap_backoff_secs: 60,
+                activation_retries: 3,
+                activation_retry_secs: 2,
+                handshake_timeout_secs: 0,
+                handshake_stale_secs: 0,
+                command_timeout_secs: 30,
+                narrow_allowed_ips: false,
+                persistent_keepalive_secs: None,
+                mtu: None,
+                activity_peers: vec![],
+                ignore_keepalives: true,
+                activity_threshold_bytes: 0,
+                idle_check_interval_secs: 60,
+                ebpf_poll_interval_ms: 1000,
+                idle_detection: IdleDetection::Bytes,
+                traffic_detection: TrafficDetection::Auto,
+                manual_idle_timeout: false,
+                dry_run: false,
+                kill_switch: KillSwitchMode::Off,
+                hold_traffic: false,
+                network_backend: NetworkBackend::NetworkManager,
+                captive_portal_check: true,
+                connection_wait_secs: 30,
+                local_hosts: vec![],
+                presence_probe: None,
+                monitor_routing: MonitorRouting::Table,
+                route_metric: None,
+                gateway_fallback: GatewayFallback::None,
+                source_filter: SourceFilter::Any,
+                source_ranges: vec![],
+                tc_priority: None,
+                tc_handle: None,
+                drop_capabilities: false,
+                user: None,
+                landlock: true,
+                seccomp: true,
                 log_level: "info".to_string(),
+                log_target: LogTarget::Stderr,
             },
             subnets: SubnetConfig {
                 ranges: vec!["192.168.1.0/24".to_string()],
             },
+            tunnels: BTreeMap::new(),
+            instance: Instance::default(),
+            probe: None,
+            endpoints: None,
+            fingerprint: None,
+            dock: None,
+            native: None,
+            otel: None,
         };
 
         assert!(validate_config(&config).is_err());
@@ -394,4 +1584,192 @@ mod tests {
         // Should be 10.0.0.0, not 10.0.0.255
         assert_eq!(network, u32::from_be_bytes([10, 0, 0, 0]));
     }
+
+    #[test]
+    fn test_load_config_with_overrides() {
+        let path =
+            std::env::temp_dir().join(format!("wg-ondemand-overrides-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "[general]\nwg_interface = \"wg0\"\nidle_timeout = 300\n\n[subnets]\nranges = [\"192.168.1.0/24\"]\n",
+        )
+        .unwrap();
+
+        let overrides = ConfigOverrides {
+            tunnel: None,
+            wg_interface: Some("wg1".to_string()),
+            idle_timeout: Some(60),
+            monitor_interface: Some("wlan1".to_string()),
+            subnets: vec!["10.0.0.0/8".to_string(), "172.16.0.0/12".to_string()],
+        };
+        let config = load_config_with_overrides(&path, &overrides).unwrap();
+        assert_eq!(config.general.wg_interface, "wg1");
+        assert_eq!(config.general.idle_timeout, 60);
+        assert_eq!(config.general.monitor_interface.as_deref(), Some("wlan1"));
+        assert_eq!(config.subnets.ranges, overrides.subnets);
+
+        // Overridden values are validated like the file's
+        let invalid = ConfigOverrides {
+            idle_timeout: Some(0),
+            ..Default::default()
+        };
+        assert!(load_config_with_overrides(&path, &invalid).is_err());
+
+        // Without overrides the file is used as is
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.general.wg_interface, "wg0");
+        assert_eq!(config.subnets.ranges, ["192.168.1.0/24"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_config_drop_ins() {
+        let dir = std::env::temp_dir().join(format!("wg-ondemand-dropins-{}", std::process::id()));
+        let path = dir.join("config.toml");
+        fs::create_dir_all(drop_in_dir(&path)).unwrap();
+        fs::write(
+            &path,
+            "[general]\nwg_interface = \"wg0\"\nidle_timeout = 300\n\n[subnets]\nranges = [\"192.168.1.0/24\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("config.d/20-site.toml"),
+            "[general]\nidle_timeout = 600\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("config.d/10-defaults.toml"),
+            "[general]\nidle_timeout = 120\nexclude_ssids = [\"Home\"]\n\n[subnets]\nranges = [\"10.0.0.0/8\"]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("config.d/30-ignored.toml.bak"), "not toml").unwrap();
+
+        // Later fragments win, untouched keys keep the main file's values
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.general.wg_interface, "wg0");
+        assert_eq!(config.general.idle_timeout, 600);
+        assert_eq!(config.general.exclude_ssids, ["Home"]);
+        assert_eq!(config.subnets.ranges, ["10.0.0.0/8"]);
+
+        fs::write(dir.join("config.d/40-broken.toml"), "[general\n").unwrap();
+        let err = load_config(&path).unwrap_err();
+        assert!(format!("{:#}", err).contains("40-broken.toml"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_config_tunnels() {
+        let dir = std::env::temp_dir().join(format!("wg-ondemand-tunnels-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(
+            &path,
+            "[general]\nidle_timeout = 300\n\n\
+             [tunnel.home]\nwg_interface = \"wg0\"\nnm_connection = \"Home\"\nsubnets = [\"192.168.1.0/24\"]\n\n\
+             [tunnel.lab]\nwg_interface = \"wg1\"\nsubnets = [\"10.0.0.0/8\"]\nidle_timeout = 60\nmode = \"manual\"\n",
+        )
+        .unwrap();
+
+        let err = load_config(&path).unwrap_err();
+        assert!(format!("{:#}", err).contains("home, lab"));
+
+        let select = |name: &str| ConfigOverrides {
+            tunnel: Some(name.to_string()),
+            ..Default::default()
+        };
+        let config = load_config_with_overrides(&path, &select("home")).unwrap();
+        assert_eq!(config.general.wg_interface, "wg0");
+        assert_eq!(config.general.nm_connection.as_deref(), Some("Home"));
+        assert_eq!(config.general.idle_timeout, 300);
+        assert_eq!(config.general.mode, TunnelMode::OnDemand);
+        assert_eq!(config.subnets.ranges, ["192.168.1.0/24"]);
+        assert_eq!(config.tunnels.len(), 2);
+
+        let config = load_config_with_overrides(&path, &select("lab")).unwrap();
+        assert_eq!(config.general.wg_interface, "wg1");
+        assert_eq!(config.general.nm_connection, None);
+        assert_eq!(config.general.idle_timeout, 60);
+        assert_eq!(config.general.mode, TunnelMode::Manual);
+        assert_eq!(config.subnets.ranges, ["10.0.0.0/8"]);
+
+        assert!(load_config_with_overrides(&path, &select("office")).is_err());
+
+        // Flat keys cannot be mixed with tunnel sections
+        fs::write(
+            &path,
+            "[general]\nwg_interface = \"wg0\"\n\n[tunnel.lab]\nwg_interface = \"wg1\"\nsubnets = [\"10.0.0.0/8\"]\n",
+        )
+        .unwrap();
+        assert!(load_config(&path).is_err());
+
+        // A flat config is a single tunnel named after its interface
+        fs::write(
+            &path,
+            "[general]\nwg_interface = \"wg0\"\n\n[subnets]\nranges = [\"192.168.1.0/24\"]\n",
+        )
+        .unwrap();
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.tunnels["wg0"].subnets, ["192.168.1.0/24"]);
+        assert_eq!(config.general.wg_interface, "wg0");
+        assert!(load_config_with_overrides(&path, &select("wg0")).is_ok());
+        assert_eq!(config.instance, Instance::default());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tunnel_instances_distinct() {
+        let dir =
+            std::env::temp_dir().join(format!("wg-ondemand-instances-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(
+            &path,
+            "[general]\n\n\
+             [tunnel.home]\nwg_interface = \"wg0\"\nsubnets = [\"192.168.1.0/24\"]\n\n\
+             [tunnel.lab]\nwg_interface = \"wg1\"\nsubnets = [\"10.0.0.0/8\"]\n",
+        )
+        .unwrap();
+        let load = |name: &str| {
+            let overrides = ConfigOverrides {
+                tunnel: Some(name.to_string()),
+                ..Default::default()
+            };
+            load_config_with_overrides(&path, &overrides)
+                .unwrap()
+                .instance
+        };
+        let home = load("home");
+        let lab = load("lab");
+
+        assert_eq!(home.name(), Some("home"));
+        assert_eq!(home.state_file(), Path::new("/run/wg-ondemand/home/state"));
+        assert_eq!(
+            lab.session_file(),
+            Path::new("/var/lib/wg-ondemand/lab/session")
+        );
+        assert_ne!(home.state_file(), lab.state_file());
+        assert_ne!(home.control_socket(), lab.control_socket());
+        assert_ne!(home.session_file(), lab.session_file());
+        assert_ne!(home.stats_file(), lab.stats_file());
+        for slot in 0..MAX_MONITOR_INTERFACES as u32 {
+            assert!(!(0..MAX_MONITOR_INTERFACES as u32)
+                .any(|other| home.monitor_slot(slot) == lab.monitor_slot(other)));
+        }
+        assert_ne!(home.tc_priority(None), lab.tc_priority(None));
+        assert_ne!(home.tc_priority(Some(100)), lab.tc_priority(Some(100)));
+        assert_ne!(
+            home.nft_table(crate::kill_switch::NFT_TABLE),
+            lab.nft_table(crate::kill_switch::NFT_TABLE)
+        );
+        assert_ne!(home.queue_num(), lab.queue_num());
+
+        // The name becomes a directory
+        fs::write(
+            &path,
+            "[general]\n\n[tunnel.\"../etc\"]\nwg_interface = \"wg0\"\nsubnets = [\"192.168.1.0/24\"]\n",
+        )
+        .unwrap();
+        assert!(load_config(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,160 @@
+// Configuration cross-checks
+
+//! Configuration checks
+//!
+//! [`load_config`](crate::config::load_config) only validates the config on its
+//! own. `wg-ondemand --check-config` additionally checks it against the system:
+//! interface names, the NetworkManager connection behind `nm_connection` and the
+//! AllowedIPs of the wg-quick config in use, so mistakes are reported before the
+//! daemon starts rather than on the first activation.
+
+use crate::process;
+use crate::types::Config;
+use crate::wg_controller::validate_interface_name;
+use crate::wg_quick;
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Run all cross-checks on a loaded config, returning the problems found
+pub async fn check(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    let wg_interface = &config.general.wg_interface;
+
+    if let Err(e) = validate_interface_name(wg_interface) {
+        problems.push(format!("wg_interface: {:#}", e));
+    }
+
+    if let Some(iface) = &config.general.monitor_interface {
+        if let Err(e) = validate_interface_name(iface) {
+            problems.push(format!("monitor_interface: {:#}", e));
+        } else if !Path::new("/sys/class/net").join(iface).exists() {
+            problems.push(format!("monitor_interface {} does not exist", iface));
+        }
+    }
+    // The others may be missing while undocked, but the first must exist at start
+    if let Some(iface) = config.general.monitor_interfaces.first() {
+        if !Path::new("/sys/class/net").join(iface).exists() {
+            problems.push(format!("monitor_interfaces: {} does not exist", iface));
+        }
+    }
+
+    if let Some(name) = &config.general.nm_connection {
+        let timeout = Duration::from_secs(config.general.command_timeout_secs);
+        if let Err(problem) = check_nm_connection(name, wg_interface, timeout).await {
+            problems.push(problem);
+        }
+    }
+
+    if let Some(path) = wg_quick::config_in_use(config) {
+        match wg_quick::load(&path) {
+            Ok(wg_config) if !config.general.narrow_allowed_ips => {
+                for subnet in wg_config.uncovered_subnets(&config.subnets.ranges) {
+                    problems.push(format!(
+                        "Target subnet {} is not covered by any peer's AllowedIPs in {:?}",
+                        subnet, path
+                    ));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => problems.push(format!("{:#}", e)),
+        }
+    }
+
+    problems
+}
+
+/// Check that NetworkManager has a WireGuard connection `name` for `wg_interface`
+async fn check_nm_connection(
+    name: &str,
+    wg_interface: &str,
+    timeout: Duration,
+) -> Result<(), String> {
+    let mut cmd = Command::new("nmcli");
+    cmd.args([
+        "-g",
+        "connection.type,connection.interface-name",
+        "connection",
+        "show",
+        name,
+    ]);
+    let output = process::run(cmd, timeout)
+        .await
+        .map_err(|e| format!("Cannot check nm_connection {}: {:#}", name, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "nm_connection {} is not a NetworkManager connection",
+            name
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (conn_type, iface) = parse_nm_connection(&stdout);
+    if conn_type != "wireguard" {
+        return Err(format!(
+            "nm_connection {} is a {} connection, not wireguard",
+            name, conn_type
+        ));
+    }
+    if iface != wg_interface {
+        return Err(format!(
+            "nm_connection {} manages interface {}, but wg_interface is {}",
+            name, iface, wg_interface
+        ));
+    }
+    Ok(())
+}
+
+/// Split `nmcli -g connection.type,connection.interface-name` output into its fields
+fn parse_nm_connection(output: &str) -> (&str, &str) {
+    let mut lines = output.lines().map(str::trim);
+    let conn_type = lines.next().unwrap_or_default();
+    let iface = lines.next().unwrap_or_default();
+    (conn_type, iface)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(extra: &str) -> Config {
+        toml::from_str(&format!(
+            "[general]\nwg_interface = \"wg0\"\n{}\n[subnets]\nranges = [\"192.168.1.0/24\", \"10.1.0.0/16\"]\n",
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_nm_connection() {
+        assert_eq!(
+            parse_nm_connection("wireguard\nwg0\n"),
+            ("wireguard", "wg0")
+        );
+        assert_eq!(parse_nm_connection(""), ("", ""));
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let problems = check(&config("monitor_interface = \"no-such-if0\"")).await;
+        assert!(problems.contains(&"monitor_interface no-such-if0 does not exist".to_string()));
+
+        let path = std::env::temp_dir().join(format!(
+            "wg-ondemand-check-test-{}.conf",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "[Interface]\nPrivateKey = cHJpdmF0ZQ==\n\n[Peer]\nPublicKey = cHVibGlj\nAllowedIPs = 192.168.0.0/16\n",
+        )
+        .unwrap();
+        let native = format!("[native]\nconfig_file = {:?}", path);
+        let problems = check(&config(&native)).await;
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("Target subnet 10.1.0.0/16 is not covered"));
+
+        let narrowed = format!("narrow_allowed_ips = true\n{}", native);
+        assert!(check(&config(&narrowed)).await.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
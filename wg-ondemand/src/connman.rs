@@ -0,0 +1,256 @@
+// ConnMan network monitor via D-Bus
+
+//! Network detection via ConnMan
+//!
+//! Embedded and automotive systems often run ConnMan instead of NetworkManager.
+//! ConnMan lists its services (connections) with the connected ones first, so
+//! the first service in the `ready` or `online` state carries the default route.
+//! If it is a WiFi service, its name is the SSID checked against the
+//! whitelist/blacklist. With the connectivity check enabled, a monitored
+//! network only counts as connected once ConnMan's online check has passed
+//! (state `online`), so captive portals are cleared first.
+
+use crate::error::DbusError;
+use crate::network_detector::{is_monitored_ssid, NetworkDetector, NetworkEvent};
+use anyhow::Context;
+use futures_util::future::BoxFuture;
+use futures_util::stream::StreamExt;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::{proxy, Connection, MessageStream};
+
+/// Signals of the ConnMan manager and services (ServicesChanged, PropertyChanged)
+const CONNMAN_SIGNALS: &str = "type='signal',sender='net.connman'";
+
+/// D-Bus proxy for the ConnMan manager
+#[proxy(
+    interface = "net.connman.Manager",
+    default_service = "net.connman",
+    default_path = "/"
+)]
+trait Manager {
+    /// Get all services with their properties, connected ones first
+    fn get_services(&self) -> zbus::Result<Vec<(OwnedObjectPath, HashMap<String, OwnedValue>)>>;
+}
+
+/// The properties of a ConnMan service used here
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Service {
+    /// Technology: "wifi", "ethernet", "cellular", ...
+    kind: String,
+    /// Network name, the SSID for WiFi
+    name: String,
+    /// "idle", "association", "configuration", "ready", "online", ...
+    state: String,
+}
+
+impl Service {
+    fn from_properties(properties: &HashMap<String, OwnedValue>) -> Self {
+        let string = |key: &str| {
+            properties
+                .get(key)
+                .and_then(|value| <&str>::try_from(&**value).ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        Self {
+            kind: string("Type"),
+            name: string("Name"),
+            state: string("State"),
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        matches!(self.state.as_str(), "ready" | "online")
+    }
+}
+
+/// The default service if it is a WiFi network
+fn default_wifi(services: &[Service]) -> Option<&Service> {
+    services
+        .iter()
+        .find(|service| service.is_connected())
+        .filter(|service| service.kind == "wifi")
+}
+
+/// ConnMan network monitor
+pub struct ConnmanMonitor {
+    target_ssids: Vec<String>,
+    exclude_ssids: Vec<String>,
+    connectivity_check: bool,
+    connection: Connection,
+}
+
+impl ConnmanMonitor {
+    /// Create a new ConnMan monitor
+    ///
+    /// # Arguments
+    /// * `target_ssids` - Whitelist of SSIDs to monitor. If empty, monitors all SSIDs.
+    /// * `exclude_ssids` - Blacklist of SSIDs to exclude. Takes precedence over target_ssids.
+    pub async fn new(
+        target_ssids: Vec<String>,
+        exclude_ssids: Vec<String>,
+    ) -> Result<Self, DbusError> {
+        let connection = Connection::system()
+            .await
+            .context("Failed to connect to system D-Bus")?;
+
+        Ok(Self {
+            target_ssids,
+            exclude_ssids,
+            connectivity_check: false,
+            connection,
+        })
+    }
+
+    /// Only report a monitored network as connected once ConnMan's online
+    /// check has passed
+    pub fn with_connectivity_check(mut self, enabled: bool) -> Self {
+        self.connectivity_check = enabled;
+        self
+    }
+
+    /// The default service if it is a WiFi network
+    async fn current_wifi(&self) -> Result<Option<Service>, DbusError> {
+        let manager = ManagerProxy::new(&self.connection)
+            .await
+            .context("Failed to create ConnMan manager proxy")?;
+        let services: Vec<Service> = manager
+            .get_services()
+            .await
+            .context("Failed to list ConnMan services")?
+            .iter()
+            .map(|(_, properties)| Service::from_properties(properties))
+            .collect();
+        Ok(default_wifi(&services).cloned())
+    }
+
+    /// Get the current SSID
+    pub async fn current_ssid(&self) -> Result<Option<String>, DbusError> {
+        Ok(self.current_wifi().await?.map(|service| service.name))
+    }
+
+    /// Whether the current network is monitored, and whether it may be
+    /// monitored yet (connectivity check)
+    async fn status(&self) -> Result<(bool, bool), DbusError> {
+        let Some(service) = self.current_wifi().await? else {
+            return Ok((false, false));
+        };
+        let is_target = is_monitored_ssid(&service.name, &self.target_ssids, &self.exclude_ssids);
+        let online = !self.connectivity_check || service.state == "online";
+        Ok((is_target, is_target && online))
+    }
+
+    /// Check if connected to a monitored SSID (respecting whitelist/blacklist
+    /// rules and the connectivity check)
+    pub async fn is_connected_to_target(&self) -> Result<bool, DbusError> {
+        Ok(self.status().await?.1)
+    }
+
+    /// Monitor for network changes and send events
+    pub async fn monitor(&self, tx: mpsc::Sender<NetworkEvent>) -> Result<(), DbusError> {
+        let mut signals = MessageStream::for_match_rule(CONNMAN_SIGNALS, &self.connection, None)
+            .await
+            .context("Failed to subscribe to ConnMan signals")?;
+
+        let (is_target, mut was_connected) = self.status().await?;
+        tracing::info!("Starting ConnMan monitor");
+        if was_connected {
+            if let Ok(Some(current)) = self.current_ssid().await {
+                tracing::info!("Already connected to monitored SSID: {}", current);
+            }
+        } else if is_target {
+            tracing::info!("On a monitored SSID that is not online yet, deferring monitoring");
+        }
+
+        while signals.next().await.is_some() {
+            let is_connected = match self.status().await {
+                Ok((_, connected)) => connected,
+                Err(e) => {
+                    tracing::warn!("Failed to check ConnMan services: {}", e);
+                    continue;
+                }
+            };
+
+            if is_connected && !was_connected {
+                let current = self.current_ssid().await.ok().flatten().unwrap_or_default();
+                tracing::info!("Connected to monitored SSID: {}", current);
+                let _ = tx.send(NetworkEvent::ConnectedToTarget(current)).await;
+            } else if !is_connected && was_connected {
+                tracing::info!("Disconnected from monitored SSID");
+                let _ = tx.send(NetworkEvent::Disconnected).await;
+            }
+
+            was_connected = is_connected;
+        }
+
+        Ok(())
+    }
+}
+
+impl NetworkDetector for ConnmanMonitor {
+    fn current_network(&self) -> BoxFuture<'_, anyhow::Result<Option<String>>> {
+        Box::pin(async move { Ok(self.current_ssid().await?) })
+    }
+
+    fn is_connected_to_target(&self) -> BoxFuture<'_, anyhow::Result<bool>> {
+        Box::pin(async move { Ok(ConnmanMonitor::is_connected_to_target(self).await?) })
+    }
+
+    fn monitor(&self, tx: mpsc::Sender<NetworkEvent>) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move { Ok(ConnmanMonitor::monitor(self, tx).await?) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(kind: &str, name: &str, state: &str) -> Service {
+        Service {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            state: state.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_from_properties() {
+        let mut properties = HashMap::new();
+        for (key, value) in [("Type", "wifi"), ("Name", "home"), ("State", "online")] {
+            properties.insert(
+                key.to_string(),
+                OwnedValue::try_from(zbus::zvariant::Value::from(value)).unwrap(),
+            );
+        }
+        properties.insert("Strength".to_string(), OwnedValue::from(70u8));
+        assert_eq!(
+            Service::from_properties(&properties),
+            service("wifi", "home", "online")
+        );
+        assert_eq!(
+            Service::from_properties(&HashMap::new()),
+            Service::default()
+        );
+    }
+
+    #[test]
+    fn test_default_wifi() {
+        let wifi = service("wifi", "home", "ready");
+        let services = vec![
+            wifi.clone(),
+            service("ethernet", "Wired", "ready"),
+            service("wifi", "cafe", "idle"),
+        ];
+        assert_eq!(default_wifi(&services), Some(&wifi));
+
+        // Ethernet carries the default route
+        let services = vec![service("ethernet", "Wired", "online"), wifi];
+        assert_eq!(default_wifi(&services), None);
+
+        // Nothing connected
+        let services = vec![service("wifi", "cafe", "association")];
+        assert_eq!(default_wifi(&services), None);
+    }
+}
@@ -0,0 +1,424 @@
+// Control socket for runtime commands
+
+//! Control socket for runtime commands
+//!
+//! This module exposes a Unix socket that accepts line-based commands
+//! (sent by `wg-ondemand-ctl` via `wg-ondemand control <command>`) and
+//! forwards them to the main event loop. `watch` keeps the connection open and
+//! streams events as they happen.
+
+use crate::history::{HistoryEvent, SharedHistory};
+use crate::stats::SharedStats;
+use crate::wg_controller::{self, format_peers};
+use anyhow::{Context, Result};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, mpsc};
+
+/// Default path of the control socket (see
+/// [`Instance::control_socket`](crate::instance::Instance::control_socket))
+pub const CONTROL_SOCKET: &str = "/run/wg-ondemand/control.sock";
+
+/// Commands accepted on the control socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// Reset the idle timer as if tunnel activity had been observed
+    KeepAlive,
+    /// Activate the tunnel now, bypassing cooldown and debounce
+    Up,
+    /// Deactivate the tunnel now and hold off traffic-triggered activation for a while
+    Down,
+    /// List recent events (answered by the control socket itself)
+    History,
+    /// Show WireGuard peer status (answered by the control socket itself)
+    Peers,
+    /// Show session counters (answered by the control socket itself)
+    Stats,
+    /// Stream events live until the client disconnects (answered by the control
+    /// socket itself)
+    Watch,
+}
+
+impl ControlCommand {
+    /// Parse a command from its wire representation
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.trim() {
+            "keep-alive" => Ok(Self::KeepAlive),
+            "up" => Ok(Self::Up),
+            "down" => Ok(Self::Down),
+            "history" => Ok(Self::History),
+            "peers" => Ok(Self::Peers),
+            "stats" => Ok(Self::Stats),
+            "watch" => Ok(Self::Watch),
+            other => anyhow::bail!("Unknown control command: '{}'", other),
+        }
+    }
+
+    /// Wire representation of the command
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::KeepAlive => "keep-alive",
+            Self::Up => "up",
+            Self::Down => "down",
+            Self::History => "history",
+            Self::Peers => "peers",
+            Self::Stats => "stats",
+            Self::Watch => "watch",
+        }
+    }
+}
+
+/// Listener for the control socket
+pub struct ControlServer {
+    listener: UnixListener,
+    history: Option<SharedHistory>,
+    wg_interface: Option<String>,
+    stats: Option<SharedStats>,
+}
+
+impl ControlServer {
+    /// Bind the control socket, replacing any stale socket from a previous instance
+    ///
+    /// The socket is only accessible by the daemon's user (root).
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("Failed to create control socket directory")?;
+        }
+
+        // Remove stale socket left behind by a previous daemon instance
+        let _ = std::fs::remove_file(path);
+
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind control socket {:?}", path))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .context("Failed to set control socket permissions")?;
+
+        tracing::info!("Control socket listening on {:?}", path);
+        Ok(Self {
+            listener,
+            history: None,
+            wg_interface: None,
+            stats: None,
+        })
+    }
+
+    /// Answer `history` requests from the given event history
+    pub fn with_history(mut self, history: SharedHistory) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Answer `peers` requests by querying the given WireGuard interface
+    pub fn with_peers(mut self, wg_interface: String) -> Self {
+        self.wg_interface = Some(wg_interface);
+        self
+    }
+
+    /// Answer `stats` requests from the given session counters
+    pub fn with_stats(mut self, stats: SharedStats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Accept connections and forward parsed commands to the main loop
+    pub async fn serve(self, tx: mpsc::Sender<ControlCommand>) -> Result<()> {
+        loop {
+            let (stream, _) = self
+                .listener
+                .accept()
+                .await
+                .context("Failed to accept control connection")?;
+
+            let tx = tx.clone();
+            let history = self.history.clone();
+            let wg_interface = self.wg_interface.clone();
+            let stats = self.stats.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_client(stream, tx, history, wg_interface, stats).await {
+                    tracing::warn!("Control connection error: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Read a single command line from a client and reply with "ok", the requested
+/// listing or "error: ..."
+async fn handle_client(
+    stream: UnixStream,
+    tx: mpsc::Sender<ControlCommand>,
+    history: Option<SharedHistory>,
+    wg_interface: Option<String>,
+    stats: Option<SharedStats>,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+
+    let mut line = String::new();
+    BufReader::new(reader)
+        .read_line(&mut line)
+        .await
+        .context("Failed to read control command")?;
+
+    let reply = match ControlCommand::parse(&line) {
+        Ok(ControlCommand::Watch) => match &history {
+            Some(history) => {
+                let live = history
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .subscribe();
+                return stream_events(writer, live).await;
+            }
+            None => "error: event history is disabled\n".to_string(),
+        },
+        Ok(ControlCommand::History) => match &history {
+            Some(history) => {
+                let history = history.lock().unwrap_or_else(|e| e.into_inner());
+                format!("{}\n", history.format())
+            }
+            None => "error: event history is disabled\n".to_string(),
+        },
+        Ok(ControlCommand::Peers) => match &wg_interface {
+            Some(interface) => match wg_controller::query_peers(interface).await {
+                Ok(peers) => format!("{}\n", format_peers(&peers, SystemTime::now())),
+                Err(e) => format!("error: {:#}\n", e),
+            },
+            None => "error: peer status is unavailable\n".to_string(),
+        },
+        Ok(ControlCommand::Stats) => match &stats {
+            Some(stats) => {
+                let stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                format!("{}\n", stats.format(now))
+            }
+            None => "error: statistics are unavailable\n".to_string(),
+        },
+        Ok(cmd) => {
+            tracing::info!("Control command received: {}", cmd.as_str());
+            tx.send(cmd)
+                .await
+                .context("Main loop is not accepting control commands")?;
+            "ok\n".to_string()
+        }
+        Err(e) => format!("error: {}\n", e),
+    };
+
+    writer
+        .write_all(reply.as_bytes())
+        .await
+        .context("Failed to write control reply")?;
+    Ok(())
+}
+
+/// Write events to a `watch` client until it disconnects or the daemon shuts down
+async fn stream_events(
+    mut writer: OwnedWriteHalf,
+    mut live: broadcast::Receiver<HistoryEvent>,
+) -> Result<()> {
+    writer
+        .write_all(b"Watching events, press Ctrl+C to stop\n")
+        .await
+        .context("Failed to write control reply")?;
+    loop {
+        let line = match live.recv().await {
+            Ok(event) => event.format(),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                format!("({} events missed)", missed)
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        if writer
+            .write_all(format!("{}\n", line).as_bytes())
+            .await
+            .is_err()
+        {
+            // Client went away
+            return Ok(());
+        }
+    }
+}
+
+/// Stream events from a running daemon, calling `on_line` for each line
+///
+/// Returns when the daemon closes the connection.
+///
+/// # Errors
+///
+/// Returns an error if the daemon is not reachable or refuses to stream events.
+pub async fn watch<P: AsRef<Path>>(path: P, mut on_line: impl FnMut(&str)) -> Result<()> {
+    let path = path.as_ref();
+    let mut stream = UnixStream::connect(path)
+        .await
+        .with_context(|| format!("Failed to connect to control socket {:?}", path))?;
+    stream
+        .write_all(format!("{}\n", ControlCommand::Watch.as_str()).as_bytes())
+        .await
+        .context("Failed to send control command")?;
+
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .context("Failed to read control reply")?
+    {
+        if let Some(err) = line.strip_prefix("error: ") {
+            anyhow::bail!("{}", err);
+        }
+        on_line(&line);
+    }
+    Ok(())
+}
+
+/// Send a command to a running daemon and return its reply
+///
+/// # Errors
+///
+/// Returns an error if the daemon is not reachable or rejects the command.
+pub async fn send_command<P: AsRef<Path>>(path: P, command: &str) -> Result<String> {
+    let path = path.as_ref();
+    let mut stream = UnixStream::connect(path)
+        .await
+        .with_context(|| format!("Failed to connect to control socket {:?}", path))?;
+
+    stream
+        .write_all(format!("{}\n", command.trim()).as_bytes())
+        .await
+        .context("Failed to send control command")?;
+
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .await
+        .context("Failed to read control reply")?;
+    let reply = reply.trim_end().to_string();
+
+    if let Some(err) = reply.strip_prefix("error: ") {
+        anyhow::bail!("{}", err);
+    }
+    Ok(reply)
+}
+
+/// Remove the control socket on shutdown
+pub fn cleanup<P: AsRef<Path>>(path: P) {
+    let _ = std::fs::remove_file(path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::EventKind;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            ControlCommand::parse("keep-alive\n").unwrap(),
+            ControlCommand::KeepAlive
+        );
+        assert!(ControlCommand::parse("rm -rf /").is_err());
+        assert!(ControlCommand::parse("").is_err());
+    }
+
+    #[test]
+    fn test_command_round_trip() {
+        for cmd in [
+            ControlCommand::KeepAlive,
+            ControlCommand::Up,
+            ControlCommand::Down,
+            ControlCommand::History,
+            ControlCommand::Peers,
+            ControlCommand::Stats,
+            ControlCommand::Watch,
+        ] {
+            assert_eq!(ControlCommand::parse(cmd.as_str()).unwrap(), cmd);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_socket_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("wg-ondemand-test-{}.sock", std::process::id()));
+        let server = ControlServer::bind(&path).unwrap();
+        let (tx, mut rx) = mpsc::channel(1);
+        tokio::spawn(server.serve(tx));
+
+        assert_eq!(send_command(&path, "keep-alive").await.unwrap(), "ok");
+        assert_eq!(rx.recv().await, Some(ControlCommand::KeepAlive));
+
+        assert!(send_command(&path, "bogus").await.is_err());
+        assert!(send_command(&path, "history").await.is_err());
+        assert!(send_command(&path, "peers").await.is_err());
+        assert!(send_command(&path, "stats").await.is_err());
+        assert!(watch(&path, |_| {}).await.is_err());
+
+        cleanup(&path);
+    }
+
+    #[tokio::test]
+    async fn test_socket_history() {
+        let path = std::env::temp_dir().join(format!(
+            "wg-ondemand-history-test-{}.sock",
+            std::process::id()
+        ));
+        let history = SharedHistory::default();
+        history
+            .lock()
+            .unwrap()
+            .record(EventKind::Network, "Connected to HomeWiFi");
+        let server = ControlServer::bind(&path)
+            .unwrap()
+            .with_history(history.clone());
+        let (tx, _rx) = mpsc::channel(1);
+        tokio::spawn(server.serve(tx));
+
+        let reply = send_command(&path, "history").await.unwrap();
+        assert!(reply.ends_with("network      Connected to HomeWiFi"));
+
+        cleanup(&path);
+    }
+
+    #[tokio::test]
+    async fn test_socket_watch() {
+        let path = std::env::temp_dir().join(format!(
+            "wg-ondemand-watch-test-{}.sock",
+            std::process::id()
+        ));
+        let history = SharedHistory::default();
+        let server = ControlServer::bind(&path)
+            .unwrap()
+            .with_history(history.clone());
+        let (tx, _rx) = mpsc::channel(1);
+        tokio::spawn(server.serve(tx));
+
+        let (lines_tx, mut lines) = mpsc::unbounded_channel();
+        let client_path = path.clone();
+        let client = tokio::spawn(async move {
+            watch(&client_path, |line| {
+                let _ = lines_tx.send(line.to_string());
+            })
+            .await
+        });
+
+        // Subscribed once the banner arrives
+        assert!(lines.recv().await.unwrap().starts_with("Watching events"));
+        history
+            .lock()
+            .unwrap()
+            .announce(EventKind::State, "monitoring -> activating");
+        assert!(lines
+            .recv()
+            .await
+            .unwrap()
+            .ends_with("state        monitoring -> activating"));
+
+        client.abort();
+        cleanup(&path);
+    }
+}
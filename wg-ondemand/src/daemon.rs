@@ -0,0 +1,783 @@
+// Daemon orchestration
+
+//! Daemon
+//!
+//! Wires the SSID monitor, eBPF traffic detection, state machine, WireGuard
+//! controller, control socket and D-Bus service together. [`Daemon`] is what the
+//! `wg-ondemand` binary runs; other binaries and integration tests can embed it
+//! the same way:
+//!
+//! ```no_run
+//! # async fn example(config: wg_ondemand::types::Config) -> anyhow::Result<()> {
+//! use wg_ondemand::daemon::Daemon;
+//!
+//! let mut daemon = Daemon::new(config).await?;
+//! let handle = daemon.handle();
+//! tokio::spawn(async move {
+//!     tokio::signal::ctrl_c().await.ok();
+//!     handle.shutdown();
+//! });
+//! daemon.run().await?;
+//! daemon.shutdown().await
+//! # }
+//! ```
+
+use crate::config;
+use crate::connman::ConnmanMonitor;
+use crate::control::ControlCommand;
+use crate::dbus_service::DbusService;
+use crate::dock;
+use crate::endpoint;
+use crate::event_log::EventLog;
+use crate::history::{EventHistory, EventKind, SharedHistory};
+use crate::instance::Instance;
+use crate::kill_switch::KillSwitch;
+use crate::network_detector::{NetworkDetector, NetworkEvent};
+use crate::nfqueue::TrafficHold;
+use crate::route_manager::RouteManager;
+use crate::rtnl::{self, RtnlMonitor};
+use crate::ssid_monitor::SsidMonitor;
+use crate::state::{StateCommand, StateEvent, StateManager};
+use crate::state_file::{self, StateSnapshot};
+use crate::stats::{LatencyStats, LifetimeStats, SharedStats};
+use crate::traffic_monitor::TrafficMonitor;
+use crate::types::{Config, IdleDetection, NetworkBackend, TrafficEvent, TunnelMode, TunnelState};
+use crate::wg_controller::WgController;
+use anyhow::{Context, Result};
+use commands::ActiveSession;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Instant};
+
+mod commands;
+mod idle;
+mod monitoring;
+mod network;
+mod setup;
+mod shutdown;
+mod tunnel;
+
+// Configuration constants for main event loop
+/// Size of the channel buffer for network events (SSID changes)
+const NETWORK_EVENT_CHANNEL_SIZE: usize = 32;
+
+/// Size of the channel buffer for state commands
+const STATE_COMMAND_CHANNEL_SIZE: usize = 32;
+
+/// Size of the channel buffer for control socket commands
+const CONTROL_COMMAND_CHANNEL_SIZE: usize = 8;
+
+/// Interval between checks that the WireGuard interface is still up
+const LINK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Interval between dock checks, catching the gateway's ARP entry showing up
+/// after the carrier
+const DOCK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Interval for flushing lifetime statistics to disk
+const STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Daemon status reported in the state file alongside the state machine state
+#[derive(Default)]
+struct DaemonStatus {
+    /// Current SSID, if connected to a monitored network
+    ssid: Option<String>,
+    /// Time from traffic detection to tunnel up
+    activation_latency: LatencyStats,
+    /// Whether idle deactivation is imminent (within idle_warning_secs)
+    idle_warning: bool,
+    /// Most recent user-facing notice and its Unix timestamp
+    notice: Option<(String, u64)>,
+    /// Most recent peer handshake, refreshed by the idle check while the tunnel is active
+    last_handshake: Option<SystemTime>,
+    /// Counters answered by the control socket, refreshed with the state file
+    stats: SharedStats,
+}
+
+impl DaemonStatus {
+    /// Record a notice for desktop notification tools (`wg-ondemand-ctl notify`)
+    fn set_notice(&mut self, message: String) {
+        self.notice = Some((message, unix_now()));
+    }
+}
+
+/// Current Unix time in seconds
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Append an event to the shared history
+fn record_event(history: &SharedHistory, kind: EventKind, message: impl Into<String>) {
+    history
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .record(kind, message);
+}
+
+/// Log and record a change that a dry run skips
+fn dry_run_action(history: &SharedHistory, action: String) {
+    tracing::info!("Dry run: would {}", action);
+    record_event(history, EventKind::DryRun, format!("Would {}", action));
+}
+
+/// Persist lifetime statistics, adding the tunnel traffic of this daemon run
+fn save_lifetime_stats(
+    instance: &Instance,
+    lifetime: &LifetimeStats,
+    wg_controller: &WgController,
+) {
+    let traffic = wg_controller.traffic();
+    let stats = LifetimeStats {
+        rx_bytes: lifetime.rx_bytes + traffic.total_rx_bytes,
+        tx_bytes: lifetime.tx_bytes + traffic.total_tx_bytes,
+        ..*lifetime
+    };
+    if let Err(e) = stats.save(&instance.stats_file()) {
+        tracing::warn!("Failed to save lifetime statistics: {:#}", e);
+    }
+}
+
+/// Current daemon state, including tunnel traffic totals and idle countdown
+fn state_snapshot<'a>(
+    state_manager: &StateManager,
+    wg_controller: &WgController,
+    status: &'a DaemonStatus,
+) -> StateSnapshot<'a> {
+    let state = state_manager.state();
+    StateSnapshot {
+        state,
+        ssid: status.ssid.as_deref(),
+        traffic: wg_controller.traffic(),
+        activation_latency: status.activation_latency,
+        idle: if state == TunnelState::Active {
+            wg_controller.idle_duration()
+        } else {
+            None
+        },
+        active_for: state_manager.active_for(),
+        manual: state_manager.is_manual(),
+        idle_timeout: state_manager.idle_timeout(),
+        idle_warning: status.idle_warning,
+        notice: status.notice.as_ref().map(|(msg, ts)| (msg.as_str(), *ts)),
+        cooldown: state_manager.cooldown_remaining(),
+        backoff_level: state_manager.backoff_level(),
+        health_restarts: state_manager.health_restarts(),
+        last_handshake: status.last_handshake,
+    }
+}
+
+/// Write the current daemon state to the state file
+fn write_state_file(
+    instance: &Instance,
+    state_manager: &StateManager,
+    wg_controller: &WgController,
+    status: &DaemonStatus,
+) {
+    let snapshot = state_snapshot(state_manager, wg_controller, status);
+    if let Err(e) = state_file::write_state(instance, &snapshot) {
+        tracing::warn!("Failed to write state file: {}", e);
+    }
+
+    let mut stats = status.stats.lock().unwrap_or_else(|e| e.into_inner());
+    stats.session_start = snapshot
+        .active_for
+        .map(|active_for| unix_now().saturating_sub(active_for.as_secs()));
+    stats.session_rx_bytes = snapshot.traffic.session_rx_bytes;
+    stats.session_tx_bytes = snapshot.traffic.session_tx_bytes;
+    stats.activation_latency = status.activation_latency;
+}
+
+/// Next traffic event from the netfilter queue, or never without one
+async fn next_held_event(traffic_hold: &mut Option<TrafficHold>) -> Option<TrafficEvent> {
+    match traffic_hold {
+        Some(traffic_hold) => traffic_hold.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Tell the classifier which traffic already goes through the (new) tunnel
+/// interface or is the tunnel's own, so it isn't taken for demand
+async fn sync_tunnel_identity(
+    traffic_monitor: &mut TrafficMonitor,
+    wg_controller: &WgController,
+    subnets: &[String],
+) {
+    match wg_controller.tunnel_identity().await {
+        Ok((ifindex, fwmark)) => {
+            if let Err(e) = traffic_monitor.set_tunnel(ifindex, fwmark) {
+                tracing::warn!("Failed to pass the tunnel interface to eBPF: {:#}", e);
+            }
+        }
+        Err(e) => tracing::debug!("Tunnel interface not identified: {:#}", e),
+    }
+    // Hostname endpoints are only known once WireGuard resolved them
+    match wg_controller.peer_endpoints().await {
+        Ok(endpoints) => exclude_endpoints(traffic_monitor, &endpoints, subnets),
+        Err(e) => tracing::debug!("Peer endpoints unavailable: {:#}", e),
+    }
+}
+
+/// Let only traffic from the monitored interfaces' own addresses trigger
+/// activation, not forwarded, bridged or container traffic
+async fn sync_local_sources(traffic_monitor: &mut TrafficMonitor, interfaces: &[String]) {
+    let mut sources = Vec::new();
+    for iface in interfaces {
+        match rtnl::interface_addresses(iface).await {
+            Ok(addresses) => sources.extend(
+                addresses
+                    .iter()
+                    .filter(|address| address.ip.is_ipv4())
+                    .map(|address| format!("{}/32", address.ip)),
+            ),
+            Err(e) => tracing::debug!("Failed to list the addresses of {}: {}", iface, e),
+        }
+    }
+    tracing::debug!("Local source addresses: {:?}", sources);
+    if let Err(e) = traffic_monitor.set_source_filter(Some(&sources)) {
+        tracing::warn!("Failed to pass the local addresses to eBPF: {:#}", e);
+    }
+}
+
+/// Keep the tunnel's own packets to peer endpoints inside the subnets
+/// (site-to-site setups) from triggering activation
+fn exclude_endpoints(
+    traffic_monitor: &mut TrafficMonitor,
+    endpoints: &[SocketAddr],
+    subnets: &[String],
+) {
+    for endpoint in endpoints {
+        let SocketAddr::V4(endpoint) = endpoint else {
+            continue;
+        };
+        let ip = *endpoint.ip();
+        if !config::ip_in_subnets(u32::from(ip), subnets).unwrap_or(false) {
+            continue;
+        }
+        match traffic_monitor.exclude_destination(ip) {
+            Ok(true) => tracing::info!(
+                "Peer endpoint {} is inside the target subnets, traffic to it won't trigger activation",
+                ip
+            ),
+            Ok(false) => {}
+            Err(e) => tracing::warn!("Failed to exclude peer endpoint {}: {:#}", ip, e),
+        }
+    }
+}
+
+/// Block traffic outside the tunnel while on a monitored network, allow it otherwise
+async fn sync_kill_switch(kill_switch: &mut Option<KillSwitch>, state: TunnelState) {
+    let Some(kill_switch) = kill_switch else {
+        return;
+    };
+    let result = if state == TunnelState::Inactive {
+        if !kill_switch.is_enabled() {
+            return;
+        }
+        kill_switch.disable().await
+    } else {
+        kill_switch.enable().await
+    };
+    if let Err(e) = result {
+        tracing::error!("Failed to update kill switch: {:#}", e);
+    }
+}
+
+/// Event loop settings derived from the config
+struct Settings {
+    /// How long before an idle deactivation to warn about it
+    idle_warning_window: Duration,
+    /// Interval of the tunnel health check
+    idle_check_interval: Duration,
+    /// How long to wait for a handshake after bring-up
+    handshake_timeout: Duration,
+    /// Handshake age at which an active tunnel is restarted (zero: never)
+    handshake_stale_after: Duration,
+    /// Debounce window of triggering traffic, in nanoseconds
+    activation_delay_ns: u64,
+    /// Timeout of netlink queries and external commands
+    command_timeout: Duration,
+    /// Hosts that answering without the tunnel means being at home
+    local_hosts: Vec<Ipv4Addr>,
+    /// Host whose presence before activating means being at home
+    presence_probe: Option<Ipv4Addr>,
+    /// Whether idle detection reads the eBPF byte counters
+    use_ebpf_counters: bool,
+    /// Whether only explicit commands activate the tunnel
+    manual_mode: bool,
+    /// Whether an auto-detected interface follows the default route to other uplinks
+    follow_default_route: bool,
+}
+
+impl Settings {
+    fn new(config: &Config, ebpf: bool) -> Self {
+        let use_ebpf_counters = config.general.idle_detection == IdleDetection::Ebpf;
+        if use_ebpf_counters && !ebpf {
+            tracing::warn!("eBPF unavailable, idle detection uses WireGuard statistics");
+        }
+        Self {
+            idle_warning_window: Duration::from_secs(config.general.idle_warning_secs),
+            idle_check_interval: Duration::from_secs(config.general.idle_check_interval_secs),
+            handshake_timeout: Duration::from_secs(config.general.handshake_timeout_secs),
+            handshake_stale_after: Duration::from_secs(config.general.handshake_stale_secs),
+            activation_delay_ns: config.general.activation_delay_ms * 1_000_000,
+            command_timeout: Duration::from_secs(config.general.command_timeout_secs),
+            local_hosts: config
+                .general
+                .local_hosts
+                .iter()
+                .filter_map(|host| host.parse().ok())
+                .collect(),
+            presence_probe: config
+                .general
+                .presence_probe
+                .as_ref()
+                .and_then(|host| host.parse().ok()),
+            use_ebpf_counters: use_ebpf_counters && ebpf,
+            manual_mode: config.general.mode == TunnelMode::Manual,
+            follow_default_route: config.general.monitor_interface.is_none()
+                && config.general.monitor_interfaces.is_empty(),
+        }
+    }
+}
+
+/// The wg-ondemand daemon with all its components set up
+///
+/// Created with [`Daemon::new`], driven by [`Daemon::run`] until a shutdown is
+/// requested through a [`DaemonHandle`], then torn down with [`Daemon::shutdown`].
+pub struct Daemon {
+    config: Config,
+    state_manager: StateManager,
+    wg_controller: WgController,
+    traffic_monitor: TrafficMonitor,
+    route_managers: Vec<RouteManager>,
+    monitor_ifaces: Vec<String>,
+    monitor_handle: JoinHandle<Result<()>>,
+    network_rx: mpsc::Receiver<NetworkEvent>,
+    state_tx: mpsc::Sender<StateCommand>,
+    state_rx: mpsc::Receiver<StateCommand>,
+    control_tx: mpsc::Sender<ControlCommand>,
+    control_rx: mpsc::Receiver<ControlCommand>,
+    shutdown: Arc<Notify>,
+    history: SharedHistory,
+    event_log: Option<EventLog>,
+    lifetime: LifetimeStats,
+    session: Option<ActiveSession>,
+    pending_trigger: Option<String>,
+    dbus_service: Option<DbusService>,
+    status: DaemonStatus,
+    activation_trigger_ns: Option<u64>,
+    activation_trigger_dest: Option<String>,
+    dry_run: bool,
+    kill_switch: Option<KillSwitch>,
+    traffic_hold: Option<TrafficHold>,
+    rtnl_monitor: Option<RtnlMonitor>,
+    on_monitored_network: bool,
+    docked: bool,
+    settings: Settings,
+    /// Last seen carrier state of the monitored interfaces (unplugged, docked)
+    link_running: HashMap<String, bool>,
+    last_idle_check: Instant,
+}
+
+/// Cloneable handle for controlling a [`Daemon`] while it runs
+#[derive(Clone)]
+pub struct DaemonHandle {
+    control_tx: mpsc::Sender<ControlCommand>,
+    shutdown: Arc<Notify>,
+}
+
+impl DaemonHandle {
+    /// Send a command as if it came from the control socket
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the daemon has been dropped.
+    pub async fn send(&self, cmd: ControlCommand) -> Result<()> {
+        self.control_tx
+            .send(cmd)
+            .await
+            .context("Daemon is no longer running")
+    }
+
+    /// Ask [`Daemon::run`] to return; a request made before it runs takes effect once it does
+    pub fn shutdown(&self) {
+        self.shutdown.notify_one();
+    }
+}
+
+impl Daemon {
+    /// Set up all components from `config`
+    ///
+    /// Loads the eBPF program, binds the control socket, registers on D-Bus,
+    /// reconciles state left behind by a previous instance and starts the SSID
+    /// monitor. The control socket and D-Bus service are optional and only warned
+    /// about if unavailable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tunnel backend, monitor interface, eBPF program or
+    /// SSID monitor cannot be set up.
+    pub async fn new(config: Config) -> Result<Self> {
+        let target_ssids = config.general.target_ssids.0.clone();
+        let exclude_ssids = config.general.exclude_ssids.clone();
+        let detector: Box<dyn NetworkDetector> = match config.general.network_backend {
+            NetworkBackend::NetworkManager => Box::new(
+                SsidMonitor::new(target_ssids, exclude_ssids)
+                    .await
+                    .context("Failed to create SSID monitor")?
+                    .with_connectivity_check(config.general.captive_portal_check)
+                    .with_connection_wait(Duration::from_secs(config.general.connection_wait_secs)),
+            ),
+            NetworkBackend::Connman => Box::new(
+                ConnmanMonitor::new(target_ssids, exclude_ssids)
+                    .await
+                    .context("Failed to create ConnMan monitor")?
+                    .with_connectivity_check(config.general.captive_portal_check),
+            ),
+        };
+        Self::with_detector(config, detector).await
+    }
+
+    /// Set up all components from `config`, detecting network changes with `detector`
+    /// instead of NetworkManager
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tunnel backend, monitor interface or eBPF program
+    /// cannot be set up.
+    pub async fn with_detector(config: Config, detector: Box<dyn NetworkDetector>) -> Result<Self> {
+        setup::log_settings(&config);
+        let command_timeout = Duration::from_secs(config.general.command_timeout_secs);
+
+        // Initialize components
+        let mut wg_controller = setup::wg_controller(&config)?;
+        let mut state_manager = setup::state_manager(&config);
+        let monitor_ifaces = setup::monitor_interfaces(&config).await?;
+
+        // Load eBPF program or its fallback (includes interface existence validation)
+        let mut traffic_monitor = TrafficMonitor::load(&config, &monitor_ifaces)
+            .context("Failed to load traffic detection")?;
+
+        // Create a route manager per interface for traffic detection
+        let route_managers: Vec<RouteManager> = monitor_ifaces
+            .iter()
+            .zip(0..)
+            .map(|(iface, slot)| setup::monitor_route_manager(&config, iface.clone(), slot))
+            .collect();
+
+        // Channels for communication
+        let (network_tx, network_rx) = mpsc::channel::<NetworkEvent>(NETWORK_EVENT_CHANNEL_SIZE);
+        let (state_tx, state_rx) = mpsc::channel::<StateCommand>(STATE_COMMAND_CHANNEL_SIZE);
+        let (control_tx, control_rx) =
+            mpsc::channel::<ControlCommand>(CONTROL_COMMAND_CHANNEL_SIZE);
+
+        // Recent events, answered directly by the control socket
+        let history: SharedHistory = Arc::new(std::sync::Mutex::new(EventHistory::new(
+            config.general.history_size,
+        )));
+        // Track SSID, latency and notices for state file updates; the session
+        // counters are also answered directly by the control socket
+        let status = DaemonStatus {
+            stats: SharedStats::default(),
+            ..DaemonStatus::default()
+        };
+
+        let dry_run = config.general.dry_run;
+        if dry_run {
+            tracing::warn!("Dry run: routes and the tunnel will not be changed");
+        }
+        let kill_switch = setup::kill_switch(&config, &mut traffic_monitor, dry_run)?;
+        setup::source_filter(&config, &mut traffic_monitor, &monitor_ifaces).await?;
+        let event_log = setup::event_log(&config, dry_run);
+        let lifetime = setup::lifetime_stats(&config.instance.stats_file());
+
+        setup::serve_control_socket(
+            &config.instance.control_socket(),
+            &history,
+            &status,
+            &wg_controller,
+            &control_tx,
+        );
+        let dbus_service = setup::dbus_service(state_manager.state()).await;
+
+        // Check initial SSID and tunnel state before spawning monitor
+        let on_monitored_network = detector.is_connected_to_target().await.unwrap_or(false);
+        // Docked at home, the tunnel isn't needed whatever the WiFi is on
+        let docked = match &config.dock {
+            Some(dock) => dock::is_docked(dock).await,
+            None => false,
+        };
+        if docked {
+            tracing::info!("Docked at home, not monitoring");
+        }
+        let initial_connected = on_monitored_network && !docked;
+        let mut tunnel_already_up = wg_controller.is_up().await;
+        // Endpoints given as addresses; hostnames are added once the tunnel is up
+        let configured_endpoints: Vec<SocketAddr> = endpoint::configured(&config)
+            .iter()
+            .filter_map(|address| address.parse().ok())
+            .collect();
+        exclude_endpoints(
+            &mut traffic_monitor,
+            &configured_endpoints,
+            &config.subnets.ranges,
+        );
+        if tunnel_already_up {
+            sync_tunnel_identity(&mut traffic_monitor, &wg_controller, &config.subnets.ranges)
+                .await;
+        }
+
+        setup::remove_stale_state(
+            &config.instance,
+            &route_managers,
+            kill_switch.is_some(),
+            command_timeout,
+            dry_run,
+        )
+        .await;
+
+        // Pick up where a previous instance left off (restart or crash)
+        if let Some(saved) = state_file::read_state(&config.instance).filter(|_| !dry_run) {
+            let current_ssid = detector.current_network().await.unwrap_or(None);
+            setup::resume_saved_state(
+                &saved,
+                current_ssid.as_deref(),
+                initial_connected,
+                tunnel_already_up,
+                &mut state_manager,
+                &mut wg_controller,
+            )
+            .await;
+            tunnel_already_up = wg_controller.is_up().await;
+        }
+
+        if initial_connected {
+            if tunnel_already_up {
+                tracing::info!(
+                "Already connected to monitored network and tunnel is up, transitioning to Active state"
+            );
+                // State sequence: Inactive -> Monitoring -> Active (tunnel already up)
+                state_tx.send(StateCommand::StartMonitoring).await?;
+                state_tx.send(StateCommand::TunnelAlreadyUp).await?;
+            } else {
+                tracing::info!("Already connected to monitored network, starting monitoring");
+                state_tx.send(StateCommand::StartMonitoring).await?;
+            }
+        }
+
+        // Spawn SSID monitor task
+        // Store the handle so we can monitor it for failures
+        let monitor_handle = tokio::spawn(async move {
+            let result = detector.monitor(network_tx).await;
+            if let Err(e) = &result {
+                tracing::error!("SSID monitor error: {:#}", e);
+            }
+            // Return error to signal failure
+            result
+        });
+
+        let traffic_hold = setup::traffic_hold(&config, state_manager.state(), dry_run).await;
+        let rtnl_monitor = setup::rtnl_monitor();
+
+        // Everything needing more than CAP_NET_ADMIN is loaded or open by now
+        setup::restrict(&config, &mut traffic_monitor)?;
+
+        // Write initial state
+        let _ = state_file::write_state(
+            &config.instance,
+            &StateSnapshot::new(state_manager.state(), None),
+        );
+
+        tracing::info!("Daemon started successfully");
+
+        let settings = Settings::new(&config, traffic_monitor.is_ebpf());
+        Ok(Self {
+            config,
+            state_manager,
+            wg_controller,
+            traffic_monitor,
+            route_managers,
+            monitor_ifaces,
+            monitor_handle,
+            network_rx,
+            state_tx,
+            state_rx,
+            control_tx,
+            control_rx,
+            shutdown: Arc::new(Notify::new()),
+            history,
+            event_log,
+            lifetime,
+            // Session in progress and what started the pending activation, for the session log
+            session: None,
+            pending_trigger: None,
+            dbus_service,
+            status,
+            // Kernel timestamp (CLOCK_MONOTONIC ns) of the traffic event that triggered the
+            // pending activation, used to measure time until the tunnel is up
+            activation_trigger_ns: None,
+            // Destination of that traffic event, for the event history
+            activation_trigger_dest: None,
+            dry_run,
+            kill_switch,
+            traffic_hold,
+            rtnl_monitor,
+            on_monitored_network,
+            docked,
+            settings,
+            link_running: HashMap::new(),
+            last_idle_check: Instant::now(),
+        })
+    }
+
+    /// Subscribe to state machine transitions and actions (see [`StateManager::subscribe`])
+    pub fn events(&self) -> broadcast::Receiver<StateEvent> {
+        self.state_manager.subscribe()
+    }
+
+    /// Handle for requesting a shutdown or sending commands from other tasks
+    pub fn handle(&self) -> DaemonHandle {
+        DaemonHandle {
+            control_tx: self.control_tx.clone(),
+            shutdown: self.shutdown.clone(),
+        }
+    }
+
+    /// Run the event loop until [`DaemonHandle::shutdown`] is called
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SSID monitor or state machine channel dies, in which
+    /// case the daemon should be restarted rather than run again.
+    pub async fn run(&mut self) -> Result<()> {
+        sync_kill_switch(&mut self.kill_switch, self.state_manager.state()).await;
+        self.last_idle_check = Instant::now();
+
+        // Detects tunnels brought down outside the daemon
+        let mut link_timer = interval(LINK_CHECK_INTERVAL);
+
+        // Dock check timer
+        let mut dock_timer = interval(DOCK_CHECK_INTERVAL);
+
+        // Lifetime statistics flush timer
+        let mut stats_timer = interval(STATS_FLUSH_INTERVAL);
+
+        // eBPF event check timer
+        let mut ebpf_timer = interval(
+            self.traffic_monitor
+                .poll_interval(self.config.general.ebpf_poll_interval_ms),
+        );
+
+        loop {
+            let idle_deadline = self.idle_deadline();
+
+            tokio::select! {
+                _ = self.shutdown.notified() => {
+                    tracing::info!("Shutdown requested");
+                    break;
+                }
+
+                // Monitor SSID monitor task for failures (fail-fast approach)
+                monitor_result = &mut self.monitor_handle => {
+                    match monitor_result {
+                        Ok(Ok(())) => {
+                            tracing::error!("SSID monitor task exited unexpectedly");
+                        }
+                        Ok(Err(e)) => {
+                            tracing::error!("SSID monitor task failed: {}", e);
+                        }
+                        Err(e) => {
+                            tracing::error!("SSID monitor task panicked: {}", e);
+                        }
+                    }
+                    anyhow::bail!("SSID monitor task terminated, aborting daemon for systemd restart");
+                }
+
+                // Network events (SSID changes)
+                Some(event) = self.network_rx.recv() => self.on_network_event(event).await?,
+
+                // State commands
+                Some(cmd) = self.state_rx.recv() => self.on_state_command(cmd).await?,
+
+                // Control socket commands
+                Some(cmd) = self.control_rx.recv() => self.on_control_command(cmd).await?,
+
+                // eBPF events (traffic detection) - check periodically
+                _ = ebpf_timer.tick() => {
+                    for event in self.traffic_monitor.read_events() {
+                        self.on_traffic_event(&event).await?;
+                    }
+                }
+
+                // Traffic held in the netfilter queue (instead of seen by eBPF)
+                Some(event) = next_held_event(&mut self.traffic_hold) => {
+                    self.on_traffic_event(&event).await?;
+                }
+
+                // Route, address and link changes
+                Some(event) = network::next_rtnl_event(&mut self.rtnl_monitor) => {
+                    self.on_rtnl_event(event).await?;
+                }
+
+                // Docking and undocking (also seen through link and route events)
+                _ = dock_timer.tick(), if self.config.dock.is_some() => self.check_dock().await?,
+
+                // Link check - notice tunnels brought down externally
+                // (a dry run has no tunnel of its own to track)
+                _ = link_timer.tick(), if !self.dry_run => self.on_link_check().await?,
+
+                // Periodically persist lifetime statistics
+                _ = stats_timer.tick(), if !self.dry_run => {
+                    save_lifetime_stats(&self.config.instance, &self.lifetime, &self.wg_controller);
+                }
+
+                // Idle deadline - check for tunnel inactivity
+                _ = idle::sleep_until(idle_deadline) => self.on_idle_check().await?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Feed a traffic event to the state machine, remembering what triggered activation
+    async fn on_traffic_event(&mut self, event: &TrafficEvent) -> Result<()> {
+        tracing::debug!("Traffic detected: {}", event.destination());
+
+        // In manual mode only explicit commands activate the tunnel
+        if self.settings.manual_mode {
+            return Ok(());
+        }
+
+        // Remember the first event that will trigger activation
+        // (restarting if the debounce window expired without confirmation)
+        if self.state_manager.state() == TunnelState::Monitoring {
+            let delay_ns = self.settings.activation_delay_ns;
+            let window_expired = self
+                .activation_trigger_ns
+                .is_some_and(|t| delay_ns > 0 && event.timestamp.saturating_sub(t) > delay_ns);
+            if self.activation_trigger_ns.is_none() || window_expired {
+                self.activation_trigger_ns = Some(event.timestamp);
+                self.activation_trigger_dest = Some(event.destination());
+            }
+        }
+
+        // Notify state manager (apply backpressure - never silently drop events)
+        // If channel fills, state manager is broken and we should fail-fast
+        if let Err(e) = self
+            .state_tx
+            .send(StateCommand::TrafficDetected(event.timestamp))
+            .await
+        {
+            tracing::error!("State manager channel closed: {}", e);
+            anyhow::bail!("State manager task died unexpectedly");
+        }
+        Ok(())
+    }
+}
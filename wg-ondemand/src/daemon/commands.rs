@@ -0,0 +1,371 @@
+// State machine and control socket commands
+
+//! Feeding commands to the state machine and acting on its transitions:
+//! event history, D-Bus signals, the session log and the resulting action
+
+use super::{
+    dry_run_action, record_event, save_lifetime_stats, sync_kill_switch, unix_now,
+    write_state_file, Daemon,
+};
+use crate::control::ControlCommand;
+use crate::event_log::{EventLog, SessionRecord};
+use crate::history::EventKind;
+use crate::probe;
+use crate::state::{StateAction, StateCommand};
+use crate::state_file;
+use crate::stats;
+use crate::types::TunnelState;
+use crate::wg_controller::WgController;
+use anyhow::Result;
+use std::time::Duration;
+
+/// How long to wait for the presence probe host to answer before activating
+const PRESENCE_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Tunnel session in progress, kept for the session log
+pub struct ActiveSession {
+    /// Unix timestamp when the tunnel came up
+    pub start: u64,
+    /// What brought the tunnel up
+    trigger: String,
+    /// SSID the session started on
+    ssid: Option<String>,
+}
+
+/// Append a finished session to the session log
+pub fn log_session(
+    event_log: &EventLog,
+    session: &ActiveSession,
+    wg_controller: &WgController,
+    reason: &str,
+) {
+    let traffic = wg_controller.traffic();
+    let record = SessionRecord {
+        start: session.start,
+        end: unix_now(),
+        trigger: &session.trigger,
+        ssid: session.ssid.as_deref(),
+        rx_bytes: traffic.session_rx_bytes,
+        tx_bytes: traffic.session_tx_bytes,
+        reason,
+    };
+    if let Err(e) = event_log.append(&record) {
+        tracing::warn!("Failed to write session log: {:#}", e);
+    }
+}
+
+/// Describe a state transition worth keeping in the event history
+///
+/// `trigger` is the destination of the traffic that triggered a pending activation.
+fn transition_event(
+    cmd: StateCommand,
+    before: TunnelState,
+    after: TunnelState,
+    trigger: Option<&str>,
+) -> Option<(EventKind, String)> {
+    if before == after {
+        return None;
+    }
+    let event = match (cmd, after) {
+        (StateCommand::TrafficDetected(_), TunnelState::Activating) => (
+            EventKind::Trigger,
+            format!("Traffic to {}", trigger.unwrap_or("target subnets")),
+        ),
+        (StateCommand::ForceActivate, TunnelState::Activating) => {
+            (EventKind::Trigger, "Manual activation".to_string())
+        }
+        (StateCommand::TunnelUnhealthy, _) => {
+            (EventKind::Error, "Tunnel unhealthy, restarting".to_string())
+        }
+        (StateCommand::HomeNetworkReachable, _) => (
+            EventKind::Network,
+            "Home network reachable, activation skipped".to_string(),
+        ),
+        (StateCommand::ActivationFailed, _) => (
+            EventKind::Error,
+            "Tunnel activation failed, giving up".to_string(),
+        ),
+        (StateCommand::TunnelUp, TunnelState::Active) => {
+            (EventKind::Activated, "Tunnel up".to_string())
+        }
+        (StateCommand::TunnelAlreadyUp, TunnelState::Active) => (
+            EventKind::Activated,
+            "Tunnel already up at startup".to_string(),
+        ),
+        (StateCommand::TunnelUpExternally, TunnelState::Active) => (
+            EventKind::Activated,
+            "Tunnel brought up outside the daemon".to_string(),
+        ),
+        _ if before == TunnelState::Active => (
+            EventKind::Deactivated,
+            format!("Tunnel down: {}", deactivation_reason(cmd)?),
+        ),
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// Why a command took the tunnel out of the Active state
+fn deactivation_reason(cmd: StateCommand) -> Option<&'static str> {
+    Some(match cmd {
+        StateCommand::IdleTimeout => "idle timeout",
+        StateCommand::SessionLimitReached => "maximum session duration reached",
+        StateCommand::StopMonitoring => "left monitored network",
+        StateCommand::ForceDeactivate => "manual deactivation",
+        StateCommand::TunnelLost => "brought down outside the daemon",
+        StateCommand::TunnelUnhealthy => "health check failed",
+        _ => return None,
+    })
+}
+
+/// Short description of what started a session, for the session log
+///
+/// `trigger` is the destination of the traffic that triggered a pending activation.
+fn session_trigger(cmd: StateCommand, trigger: Option<&str>) -> String {
+    match cmd {
+        StateCommand::TrafficDetected(_) => trigger.unwrap_or("traffic").to_string(),
+        StateCommand::ForceActivate => "manual".to_string(),
+        StateCommand::TunnelUnhealthy => "restart".to_string(),
+        StateCommand::TunnelAlreadyUp => "startup".to_string(),
+        StateCommand::TunnelUpExternally => "external".to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+impl Daemon {
+    /// Feed a command to the state machine and carry out the resulting action
+    pub(super) async fn on_state_command(&mut self, cmd: StateCommand) -> Result<()> {
+        let before = self.state_manager.state();
+        let action = self.state_manager.handle_command(cmd);
+        let after = self.state_manager.state();
+        self.announce_transition(cmd, before, after).await;
+        self.track_session(cmd, before, after);
+
+        // A manual activation is honored even on the home network
+        let at_home = match self.settings.presence_probe {
+            Some(host)
+                if action == StateAction::ActivateTunnel && cmd != StateCommand::ForceActivate =>
+            {
+                probe::presence(host, PRESENCE_PROBE_TIMEOUT).await
+            }
+            _ => false,
+        };
+
+        match action {
+            StateAction::AttachEbpf => self.attach_ebpf().await,
+            StateAction::DetachEbpf => self.detach_ebpf().await,
+            StateAction::ActivateTunnel if at_home => {
+                self.state_tx
+                    .send(StateCommand::HomeNetworkReachable)
+                    .await?;
+            }
+            action @ (StateAction::ActivateTunnel | StateAction::RestartTunnel) if self.dry_run => {
+                let verb = if action == StateAction::RestartTunnel {
+                    "restart"
+                } else {
+                    "bring up"
+                };
+                dry_run_action(
+                    &self.history,
+                    format!(
+                        "{} WireGuard tunnel {}",
+                        verb,
+                        self.wg_controller.interface()
+                    ),
+                );
+                self.wg_controller.reset_activity();
+                self.state_tx.send(StateCommand::TunnelUp).await?;
+            }
+            action @ (StateAction::ActivateTunnel | StateAction::RestartTunnel) => {
+                self.activate(action == StateAction::RestartTunnel).await?;
+            }
+            StateAction::ScheduleActivationRetry(delay) => {
+                let state_tx = self.state_tx.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    if let Err(e) = state_tx.send(StateCommand::RetryActivation).await {
+                        tracing::error!("Failed to send activation retry command: {}", e);
+                    }
+                });
+            }
+            StateAction::DeactivateTunnel if self.dry_run => {
+                dry_run_action(
+                    &self.history,
+                    format!(
+                        "bring down WireGuard tunnel {}",
+                        self.wg_controller.interface()
+                    ),
+                );
+                self.state_tx.send(StateCommand::TunnelDown).await?;
+            }
+            StateAction::DeactivateTunnel => self.deactivate().await?,
+            StateAction::None => {}
+        }
+
+        self.settle(cmd, before, after).await;
+        Ok(())
+    }
+
+    /// Record a transition in the event history, switch the kill switch and
+    /// signal it on D-Bus
+    async fn announce_transition(
+        &mut self,
+        cmd: StateCommand,
+        before: TunnelState,
+        after: TunnelState,
+    ) {
+        if let Some((kind, message)) =
+            transition_event(cmd, before, after, self.activation_trigger_dest.as_deref())
+        {
+            record_event(&self.history, kind, message);
+        }
+        if before == after {
+            return;
+        }
+
+        self.history
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .announce(
+                EventKind::State,
+                format!(
+                    "{} -> {}",
+                    state_file::state_str(before),
+                    state_file::state_str(after)
+                ),
+            );
+        sync_kill_switch(&mut self.kill_switch, after).await;
+        if let Some(dbus_service) = &self.dbus_service {
+            if let Err(e) = dbus_service
+                .state_changed(before, after, self.status.ssid.as_deref())
+                .await
+            {
+                tracing::warn!("{:#}", e);
+            }
+        }
+    }
+
+    /// Track sessions for the session log and lifetime statistics
+    fn track_session(&mut self, cmd: StateCommand, before: TunnelState, after: TunnelState) {
+        if after == TunnelState::Activating && before != TunnelState::Activating {
+            self.pending_trigger = Some(session_trigger(
+                cmd,
+                self.activation_trigger_dest.as_deref(),
+            ));
+            if let Some(dest) = self.activation_trigger_dest.as_deref() {
+                self.status
+                    .stats
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .last_trigger = Some(dest.to_string());
+            }
+        }
+        if after == TunnelState::Active && before != TunnelState::Active {
+            // A tunnel found up at startup was counted by the instance that raised it
+            if !matches!(cmd, StateCommand::TunnelAlreadyUp) {
+                self.lifetime.activations += 1;
+                self.status
+                    .stats
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record_activation(stats::local_day(unix_now()));
+            }
+            let active_for = self.state_manager.active_for().unwrap_or_default();
+            self.session = Some(ActiveSession {
+                start: unix_now().saturating_sub(active_for.as_secs()),
+                trigger: self
+                    .pending_trigger
+                    .take()
+                    .unwrap_or_else(|| session_trigger(cmd, None)),
+                ssid: self.status.ssid.clone(),
+            });
+        } else if before == TunnelState::Active && after != TunnelState::Active {
+            if let Some(session) = self.session.take() {
+                self.lifetime.active_secs += unix_now().saturating_sub(session.start);
+                if let Some(event_log) = &self.event_log {
+                    let reason = deactivation_reason(cmd).unwrap_or("unknown");
+                    log_session(event_log, &session, &self.wg_controller, reason);
+                }
+            }
+            if !self.dry_run {
+                save_lifetime_stats(&self.config.instance, &self.lifetime, &self.wg_controller);
+            }
+        }
+    }
+
+    /// Bring the activation trigger, status, traffic hold and state file in line
+    /// with the state a command left the state machine in
+    async fn settle(&mut self, cmd: StateCommand, before: TunnelState, after: TunnelState) {
+        let state = self.state_manager.state();
+
+        // Drop a pending activation trigger once we are no longer working towards Active
+        // (including when bring-up retries are exhausted)
+        let activation_abandoned =
+            matches!(cmd, StateCommand::ActivationFailed) && state != TunnelState::Activating;
+        if activation_abandoned
+            || !matches!(state, TunnelState::Monitoring | TunnelState::Activating)
+        {
+            self.activation_trigger_ns = None;
+            self.activation_trigger_dest = None;
+        }
+
+        if state != TunnelState::Active {
+            self.status.idle_warning = false;
+            self.status.last_handshake = None;
+        } else if before != after && !self.dry_run {
+            self.status.last_handshake = self.wg_controller.latest_handshake().await.ok().flatten();
+        }
+
+        // Release or drop held packets once the actions are done. Without
+        // eBPF attached nothing triggers activation, so nothing is held.
+        if let Some(traffic_hold) = &self.traffic_hold {
+            if state == TunnelState::Monitoring && !self.traffic_monitor.is_attached() {
+                traffic_hold.set_state(TunnelState::Inactive);
+            } else {
+                traffic_hold.set_state(state);
+            }
+        }
+
+        // Write state file after any state transition
+        write_state_file(
+            &self.config.instance,
+            &self.state_manager,
+            &self.wg_controller,
+            &self.status,
+        );
+    }
+
+    /// Handle a command from the control socket
+    pub(super) async fn on_control_command(&mut self, cmd: ControlCommand) -> Result<()> {
+        match cmd {
+            ControlCommand::KeepAlive => {
+                if self.state_manager.state() == TunnelState::Active {
+                    tracing::info!("Keep-alive requested, resetting idle timer");
+                    self.wg_controller.mark_activity();
+                    self.status.idle_warning = false;
+                } else {
+                    tracing::info!("Keep-alive ignored, tunnel is not active");
+                }
+            }
+            ControlCommand::Up => {
+                self.state_tx.send(StateCommand::ForceActivate).await?;
+            }
+            ControlCommand::Down => {
+                self.state_tx.send(StateCommand::ForceDeactivate).await?;
+            }
+            // Answered by the control server itself
+            ControlCommand::History
+            | ControlCommand::Peers
+            | ControlCommand::Stats
+            | ControlCommand::Watch => {}
+        }
+
+        write_state_file(
+            &self.config.instance,
+            &self.state_manager,
+            &self.wg_controller,
+            &self.status,
+        );
+        Ok(())
+    }
+}
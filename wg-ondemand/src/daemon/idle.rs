@@ -0,0 +1,232 @@
+// Idle and health checks of the active tunnel
+
+//! Scheduling and running the idle check: tunnel activity, the idle warning,
+//! the session limit, the idle timeout and the tunnel health check
+
+use super::{write_state_file, Daemon};
+use crate::probe;
+use crate::state::{StateCommand, StateManager};
+use crate::traffic_monitor::TrafficMonitor;
+use crate::types::{ProbeConfig, TunnelState};
+use crate::wg_controller::{self, WgController};
+use anyhow::Result;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Minimum time between two idle checks
+const MIN_IDLE_CHECK_DELAY: Duration = Duration::from_secs(1);
+
+/// Check an active tunnel's handshake age and peer reachability
+///
+/// A handshake is only expected while traffic flows, so an old handshake counts as
+/// stale only if the tunnel has been up and in use within the threshold. The
+/// connectivity probe, if configured, is run as well.
+async fn check_tunnel_health(
+    wg_controller: &WgController,
+    state_manager: &StateManager,
+    stale_after: Duration,
+    probe: Option<&ProbeConfig>,
+) -> bool {
+    let active_for = state_manager.active_for().unwrap_or_default();
+    let idle = wg_controller.idle_duration().unwrap_or_default();
+
+    if active_for > stale_after && idle < stale_after {
+        match wg_controller.handshake_age().await {
+            Ok(age) if wg_controller::handshake_stale(age, stale_after) => {
+                tracing::warn!(
+                    "WireGuard handshake stale ({}) while tunnel is in use",
+                    age.map(|a| format!("{}s ago", a.as_secs()))
+                        .unwrap_or_else(|| "never".to_string())
+                );
+                return false;
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to query handshake age: {}", e),
+        }
+    }
+
+    if let Some(probe) = probe {
+        if let Err(e) = probe::run(probe).await {
+            tracing::warn!("Health check connectivity probe failed: {:#}", e);
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Measure tunnel activity since the last idle check
+///
+/// With eBPF idle detection the byte counters on the WireGuard interface are read
+/// (attaching them first if needed, e.g. for a tunnel that was already up);
+/// otherwise, or if they can't be attached, WireGuard's own statistics are used.
+async fn check_tunnel_activity(
+    wg_controller: &mut WgController,
+    traffic_monitor: &mut TrafficMonitor,
+    use_counters: bool,
+) -> Result<bool> {
+    if use_counters && !traffic_monitor.counters_attached() {
+        if let Err(e) = traffic_monitor.attach_counters(wg_controller.interface()) {
+            tracing::warn!(
+                "eBPF byte counters unavailable, using WireGuard statistics: {:#}",
+                e
+            );
+        }
+    }
+    if use_counters && traffic_monitor.counters_attached() {
+        let (rx, tx) = traffic_monitor.read_counters()?;
+        return Ok(wg_controller.observe_counters(rx, tx));
+    }
+    Ok(wg_controller.check_activity().await?)
+}
+
+/// Compute when the idle check next needs to run, or None while the tunnel is not active
+///
+/// Activity pushes the deadline back, so the daemon only wakes up when the idle
+/// timeout, the idle warning or the session limit could fire, and every
+/// `health_interval` if health checks are enabled.
+fn next_idle_check(
+    state_manager: &StateManager,
+    wg_controller: &WgController,
+    last_check: Instant,
+    warning_window: Duration,
+    health_interval: Option<Duration>,
+) -> Option<Instant> {
+    if state_manager.state() != TunnelState::Active {
+        return None;
+    }
+    let now = Instant::now();
+    // Without recorded activity, count idle time from the last check
+    let idle_for = wg_controller
+        .idle_duration()
+        .unwrap_or_else(|| now.duration_since(last_check));
+    let next = state_manager.next_idle_check(idle_for, warning_window);
+
+    let health = health_interval.map(|interval| last_check + interval);
+    let deadline = match (next.map(|next| now + next), health) {
+        (Some(next), Some(health)) => next.min(health),
+        (next, health) => next.or(health)?,
+    };
+    // Don't spin while a command sent by the previous check is still pending
+    Some(deadline.max(last_check + MIN_IDLE_CHECK_DELAY))
+}
+
+/// Sleep until `deadline`, or forever if there is none
+pub async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+impl Daemon {
+    /// When the idle check next needs to run, or None while the tunnel is not active
+    pub(super) fn idle_deadline(&self) -> Option<Instant> {
+        let health_checks = !self.settings.handshake_stale_after.is_zero();
+        next_idle_check(
+            &self.state_manager,
+            &self.wg_controller,
+            self.last_idle_check,
+            self.settings.idle_warning_window,
+            health_checks.then_some(self.settings.idle_check_interval),
+        )
+    }
+
+    /// Check an active tunnel for inactivity, the session limit and its health
+    pub(super) async fn on_idle_check(&mut self) -> Result<()> {
+        self.last_idle_check = Instant::now();
+        // Only check idle when tunnel is active
+        if self.state_manager.state() != TunnelState::Active {
+            return Ok(());
+        }
+
+        // Check for WireGuard tunnel activity (a dry run has no tunnel to
+        // measure, so it goes idle after the idle timeout)
+        let activity = if self.dry_run {
+            Ok(false)
+        } else {
+            check_tunnel_activity(
+                &mut self.wg_controller,
+                &mut self.traffic_monitor,
+                self.settings.use_ebpf_counters,
+            )
+            .await
+        };
+        match activity {
+            Ok(true) => tracing::debug!("Tunnel activity detected"),
+            Ok(false) => {}
+            Err(e) => tracing::warn!("Failed to check WireGuard activity: {}", e),
+        }
+        if !self.dry_run {
+            match self.wg_controller.latest_handshake().await {
+                Ok(latest) => self.status.last_handshake = latest,
+                Err(e) => tracing::debug!("Failed to query handshake state: {}", e),
+            }
+        }
+
+        // Flag imminent idle deactivation so user-facing tools can offer to keep it up
+        let idle_warning_window = self.settings.idle_warning_window;
+        let idle_duration = self.wg_controller.idle_duration().unwrap_or_default();
+        let idle_timeout = self.state_manager.idle_timeout();
+        let active_for = self.state_manager.active_for().unwrap_or_default();
+        let warn_now = self.state_manager.idle_timeout_applies()
+            && !idle_warning_window.is_zero()
+            && idle_duration + idle_warning_window >= idle_timeout
+            && active_for + idle_warning_window >= self.state_manager.min_active();
+        if warn_now && !self.status.idle_warning {
+            tracing::info!(
+                "Tunnel idle for {}s, deactivating in {}s unless activity resumes",
+                idle_duration.as_secs(),
+                idle_timeout.saturating_sub(idle_duration).as_secs()
+            );
+        }
+        self.status.idle_warning = warn_now;
+
+        // Refresh session traffic totals and idle countdown in the state file
+        write_state_file(
+            &self.config.instance,
+            &self.state_manager,
+            &self.wg_controller,
+            &self.status,
+        );
+
+        // Enforce maximum session duration before considering idleness
+        if self.state_manager.session_limit_reached() {
+            let max_session = self.state_manager.max_session().unwrap_or_default();
+            tracing::info!(
+                "Maximum session duration reached ({}s)",
+                max_session.as_secs()
+            );
+            self.status.set_notice(format!(
+                "Maximum session duration ({}s) reached, tunnel deactivated",
+                max_session.as_secs()
+            ));
+            self.state_tx
+                .send(StateCommand::SessionLimitReached)
+                .await?;
+        } else if let Some(idle_duration) = self.wg_controller.idle_duration() {
+            // Check if idle timeout reached
+            if idle_duration > idle_timeout && self.state_manager.idle_timeout_applies() {
+                tracing::info!(
+                    "Idle timeout reached ({:.0}s of {:.0}s)",
+                    idle_duration.as_secs_f32(),
+                    idle_timeout.as_secs_f32()
+                );
+                // Trigger deactivation via state manager
+                self.state_tx.send(StateCommand::IdleTimeout).await?;
+            } else if !self.settings.handshake_stale_after.is_zero()
+                && !self.dry_run
+                && !check_tunnel_health(
+                    &self.wg_controller,
+                    &self.state_manager,
+                    self.settings.handshake_stale_after,
+                    self.config.probe.as_ref(),
+                )
+                .await
+            {
+                self.state_tx.send(StateCommand::TunnelUnhealthy).await?;
+            }
+        }
+        Ok(())
+    }
+}
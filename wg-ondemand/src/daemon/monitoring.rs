@@ -0,0 +1,188 @@
+// Attaching traffic detection to the monitored interfaces
+
+//! Attaching the eBPF program and monitoring routes to the monitored
+//! interfaces, unless their addresses overlap the target subnets or the
+//! subnets are reachable without the tunnel
+
+use super::{dry_run_action, sync_local_sources, Daemon};
+use crate::config;
+use crate::fingerprint::{self, Location};
+use crate::probe;
+use crate::rtnl;
+use crate::types::{FingerprintConfig, SourceFilter};
+use anyhow::{Context, Result};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+/// How long to wait for a `local_hosts` entry to answer without the tunnel
+const LOCAL_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Get the IPv4 address assigned to a network interface
+/// Returns the IP as u32 in network byte order (big endian), or None if no IPv4 address assigned
+async fn get_interface_ip(interface: &str) -> Result<Option<u32>> {
+    let addresses = rtnl::interface_addresses(interface)
+        .await
+        .context("Failed to get interface addresses")?;
+
+    Ok(addresses.iter().find_map(|address| match address.ip {
+        IpAddr::V4(ip) => Some(u32::from_be_bytes(ip.octets())),
+        IpAddr::V6(_) => None,
+    }))
+}
+
+/// Whether the target subnets are reachable without the tunnel, because one of
+/// `local_hosts` answers or the `fingerprint` identifies the home network
+async fn reachable_locally(
+    local_hosts: &[Ipv4Addr],
+    fingerprint: Option<&FingerprintConfig>,
+) -> bool {
+    if let Some(host) = probe::first_reachable(local_hosts, LOCAL_CHECK_TIMEOUT).await {
+        tracing::info!("Target host {} answers without the tunnel", host);
+        return true;
+    }
+    match fingerprint.map(fingerprint::check) {
+        Some(check) => match check.await {
+            Ok(location) => location == Location::Home,
+            Err(e) => {
+                tracing::warn!("Network fingerprint check failed: {:#}", e);
+                false
+            }
+        },
+        None => false,
+    }
+}
+
+/// Whether the addresses of `iface` leave it safe to attach to, warning if not
+async fn attachable(iface: &str, subnets: &[String]) -> bool {
+    // Check if local IP conflicts with configured subnets
+    match get_interface_ip(iface).await {
+        Ok(Some(local_ip)) => {
+            // Check if local IP is within any configured subnet
+            match config::ip_in_subnets(local_ip, subnets) {
+                Ok(true) => {
+                    let ip_bytes = local_ip.to_be_bytes();
+                    tracing::warn!(
+                        "Local IP {}.{}.{}.{} on {} conflicts with configured subnet ranges. \
+                        Skipping eBPF attachment to avoid routing loops. \
+                        This network appears to use the same IP range as your home network.",
+                        ip_bytes[0],
+                        ip_bytes[1],
+                        ip_bytes[2],
+                        ip_bytes[3],
+                        iface
+                    );
+                    // Don't attach eBPF - would cause routing issues
+                    false
+                }
+                // Safe to attach - local IP doesn't conflict
+                Ok(false) => true,
+                Err(e) => {
+                    tracing::error!("Failed to check IP subnet overlap: {}", e);
+                    false
+                }
+            }
+        }
+        Ok(None) => {
+            // Address and default route events bring it in
+            tracing::info!(
+                "Interface {} has no IPv4 address yet, attaching once it gets one",
+                iface
+            );
+            false
+        }
+        Err(e) => {
+            tracing::error!("Failed to get interface IP: {}", e);
+            false
+        }
+    }
+}
+
+impl Daemon {
+    /// Attach the eBPF program and add monitoring routes on every monitored
+    /// interface that doesn't have them yet and is safe to attach to
+    pub(super) async fn attach_ebpf(&mut self) {
+        // Each uplink is checked on its own: one may sit on a network
+        // using the home ranges while the other doesn't
+        let mut eligible = Vec::new();
+        for (slot, iface) in self.monitor_ifaces.iter().enumerate() {
+            // Attached before its gateway was known: only the routes are missing
+            if self.traffic_monitor.is_attached_to(iface)
+                && (self.dry_run || self.route_managers[slot].has_active_routes())
+            {
+                continue;
+            }
+            if attachable(iface, &self.config.subnets.ranges).await {
+                eligible.push(slot);
+            }
+        }
+
+        if !eligible.is_empty() && self.config.general.source_filter == SourceFilter::Local {
+            sync_local_sources(&mut self.traffic_monitor, &self.monitor_ifaces).await;
+        }
+
+        // The subnets may be reachable without the tunnel (at home)
+        if !eligible.is_empty()
+            && reachable_locally(&self.settings.local_hosts, self.config.fingerprint.as_ref()).await
+        {
+            tracing::info!("Skipping eBPF attachment: the subnets are reachable locally");
+            return;
+        }
+
+        for slot in eligible {
+            let iface = &self.monitor_ifaces[slot];
+            tracing::info!(
+                "Action: Attaching eBPF program to {} and adding monitoring routes",
+                iface
+            );
+
+            // Add monitoring routes first
+            if self.route_managers[slot].has_active_routes() {
+                // Added on an earlier attempt
+            } else if self.dry_run {
+                dry_run_action(
+                    &self.history,
+                    format!(
+                        "add monitoring routes for {} on {}",
+                        self.config.subnets.ranges.join(", "),
+                        iface
+                    ),
+                );
+            } else if let Err(e) = self.route_managers[slot]
+                .add_routes(&self.config.subnets.ranges)
+                .await
+            {
+                tracing::error!("Failed to add monitoring routes: {}", e);
+            }
+
+            // Then attach eBPF
+            if self.traffic_monitor.is_attached_to(iface) {
+                // Attached on an earlier attempt
+            } else if let Err(e) = self.traffic_monitor.attach(iface) {
+                tracing::error!("Failed to attach eBPF: {}", e);
+            } else {
+                tracing::info!("eBPF program attached and monitoring traffic");
+            }
+        }
+    }
+
+    /// Detach the eBPF program and remove the monitoring routes
+    pub(super) async fn detach_ebpf(&mut self) {
+        tracing::info!("Action: Detaching eBPF program and removing monitoring routes");
+
+        // Detach eBPF first
+        if let Err(e) = self.traffic_monitor.detach() {
+            tracing::error!("Failed to detach eBPF: {}", e);
+        }
+
+        // Then remove routes
+        if self.dry_run {
+            dry_run_action(&self.history, "remove monitoring routes".to_string());
+        } else {
+            for route_manager in self.route_managers.iter_mut() {
+                if let Err(e) = route_manager.remove_routes().await {
+                    tracing::error!("Failed to remove monitoring routes: {}", e);
+                }
+            }
+        }
+    }
+}
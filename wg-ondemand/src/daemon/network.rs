@@ -0,0 +1,358 @@
+// Network, route and link events
+
+//! Following the network the daemon is on: SSID changes, docking, the
+//! monitored interfaces coming and going, route and address changes, and the
+//! tunnel interface being changed outside the daemon
+
+use super::setup::monitor_route_manager;
+use super::{record_event, sync_local_sources, Daemon};
+use crate::dock;
+use crate::history::EventKind;
+use crate::network_detector::NetworkEvent;
+use crate::route_manager;
+use crate::rtnl::{self, RtnlEvent, RtnlMonitor};
+use crate::state::StateCommand;
+use crate::types::{SourceFilter, TunnelState};
+use crate::wg_controller;
+use anyhow::Result;
+use std::time::Duration;
+
+/// The interface now carrying the default route, if it is another one than
+/// `current` (and not the tunnel itself)
+async fn default_route_moved(current: &str, tunnel: &str, timeout: Duration) -> Option<String> {
+    let routes = tokio::time::timeout(timeout, rtnl::routes())
+        .await
+        .ok()?
+        .ok()?;
+    let uplink = rtnl::ifname(route_manager::default_interface(&routes)?)?;
+    (uplink != current
+        && uplink != tunnel
+        && wg_controller::validate_interface_name(&uplink).is_ok())
+    .then_some(uplink)
+}
+
+/// Next route change reported by the kernel, or never without a monitor
+pub async fn next_rtnl_event(
+    rtnl_monitor: &mut Option<RtnlMonitor>,
+) -> Option<std::io::Result<RtnlEvent>> {
+    match rtnl_monitor {
+        Some(rtnl_monitor) => Some(rtnl_monitor.next().await),
+        None => std::future::pending().await,
+    }
+}
+
+impl Daemon {
+    /// Start or stop monitoring as the network detector reports joining or
+    /// leaving a monitored network
+    pub(super) async fn on_network_event(&mut self, event: NetworkEvent) -> Result<()> {
+        match event {
+            NetworkEvent::ConnectedToTarget(ssid) => {
+                tracing::info!("Network event: Connected to target SSID");
+                let message = if ssid.is_empty() {
+                    "Joined monitored network".to_string()
+                } else {
+                    format!("Joined monitored network {}", ssid)
+                };
+                record_event(&self.history, EventKind::Network, message);
+                self.status.ssid = if ssid.is_empty() { None } else { Some(ssid) };
+                self.on_monitored_network = true;
+                if self.docked {
+                    tracing::info!("Docked at home, not monitoring");
+                } else {
+                    self.state_tx.send(StateCommand::StartMonitoring).await?;
+                }
+            }
+            NetworkEvent::Disconnected => {
+                tracing::info!("Network event: Disconnected from target SSID");
+                record_event(&self.history, EventKind::Network, "Left monitored network");
+                self.status.ssid = None;
+                self.on_monitored_network = false;
+                self.state_tx.send(StateCommand::StopMonitoring).await?;
+            }
+            NetworkEvent::Roamed { ssid, bssid } => {
+                tracing::info!("Network event: Roamed to {} on {}", bssid, ssid);
+                record_event(
+                    &self.history,
+                    EventKind::Network,
+                    format!("Roamed to access point {} on {}", bssid, ssid),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-check the dock, stopping monitoring when docked at home and resuming it
+    /// (if still on a monitored network) when undocked
+    pub(super) async fn check_dock(&mut self) -> Result<()> {
+        let Some(dock) = &self.config.dock else {
+            return Ok(());
+        };
+        let now_docked = dock::is_docked(dock).await;
+        if now_docked == self.docked {
+            return Ok(());
+        }
+        self.docked = now_docked;
+        if now_docked {
+            tracing::info!(
+                "Docked at home on {}, the tunnel is not needed",
+                dock.interface
+            );
+            record_event(
+                &self.history,
+                EventKind::Network,
+                format!("Docked at home on {}", dock.interface),
+            );
+            self.state_tx.send(StateCommand::StopMonitoring).await?;
+        } else {
+            tracing::info!("Undocked from {}", dock.interface);
+            record_event(
+                &self.history,
+                EventKind::Network,
+                format!("Undocked from {}", dock.interface),
+            );
+            if self.on_monitored_network {
+                self.state_tx.send(StateCommand::StartMonitoring).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a route, address or link change
+    ///
+    /// Covers monitoring routes deleted by NetworkManager, dhclient or an admin,
+    /// default route changes (DHCP renewal, roaming) moving the gateway,
+    /// addresses assigned by DHCP, and monitored interfaces being unplugged,
+    /// docked or losing their carrier.
+    pub(super) async fn on_rtnl_event(&mut self, event: std::io::Result<RtnlEvent>) -> Result<()> {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::warn!("Route and link monitoring failed, disabling it: {}", e);
+                self.rtnl_monitor = None;
+                return Ok(());
+            }
+        };
+        self.check_dock().await?;
+
+        if let Some((name, running)) = event.link_state() {
+            return self.on_link_state(name, running).await;
+        }
+        let monitoring = self.state_manager.state() == TunnelState::Monitoring;
+
+        // Attach as soon as DHCP has assigned an address, and add the
+        // routes once the default route tells the gateway
+        let configured = match &event {
+            RtnlEvent::AddressAdded(address) if address.ip.is_ipv4() => Some(address.index),
+            RtnlEvent::RouteAdded(route) if route_manager::affects_gateway(route) => route.oif,
+            _ => None,
+        };
+        let configured_slot = configured
+            .and_then(rtnl::ifname)
+            .and_then(|name| self.monitor_ifaces.iter().position(|iface| *iface == name));
+        if configured_slot.is_some()
+            && matches!(event, RtnlEvent::AddressAdded(_))
+            && self.config.general.source_filter == SourceFilter::Local
+        {
+            sync_local_sources(&mut self.traffic_monitor, &self.monitor_ifaces).await;
+        }
+        if let Some(slot) = configured_slot.filter(|_| monitoring) {
+            if !self
+                .traffic_monitor
+                .is_attached_to(&self.monitor_ifaces[slot])
+                || (!self.dry_run && !self.route_managers[slot].has_active_routes())
+            {
+                self.state_tx
+                    .send(StateCommand::RetryEbpfAttachment)
+                    .await?;
+            }
+        }
+
+        let (deleted, gateway_changed) = match &event {
+            RtnlEvent::RouteRemoved(route) => (
+                self.route_managers
+                    .iter()
+                    .any(|rm| rm.table() == route.table),
+                route_manager::affects_gateway(route),
+            ),
+            RtnlEvent::RouteAdded(route) => (false, route_manager::affects_gateway(route)),
+            RtnlEvent::Lost => (true, true),
+            RtnlEvent::AddressAdded(_)
+            | RtnlEvent::LinkChanged { .. }
+            | RtnlEvent::LinkRemoved { .. } => (false, false),
+        };
+        if gateway_changed && self.settings.follow_default_route {
+            self.follow_default_route(monitoring).await?;
+        }
+        if gateway_changed && monitoring {
+            self.refresh_gateways().await;
+        }
+        if deleted && monitoring {
+            self.restore_routes().await;
+        }
+        Ok(())
+    }
+
+    /// Re-attach to a monitored interface that came back, forget the eBPF program
+    /// and routes of one that went away
+    async fn on_link_state(&mut self, name: &str, running: bool) -> Result<()> {
+        let Some(slot) = self.monitor_ifaces.iter().position(|iface| iface == name) else {
+            return Ok(());
+        };
+        let monitoring = self.state_manager.state() == TunnelState::Monitoring;
+        let was_running = self.link_running.insert(name.to_string(), running);
+        if running && was_running != Some(true) {
+            if was_running == Some(false) {
+                tracing::info!("Monitored interface {} is back", name);
+                record_event(
+                    &self.history,
+                    EventKind::Network,
+                    format!("Interface {} is up", name),
+                );
+            }
+            // Addresses kept across a carrier loss; otherwise DHCP's
+            // address event follows
+            if monitoring && !self.traffic_monitor.is_attached_to(name) {
+                self.state_tx
+                    .send(StateCommand::RetryEbpfAttachment)
+                    .await?;
+            }
+        } else if !running
+            && (self.traffic_monitor.is_attached_to(name)
+                || self.route_managers[slot].has_active_routes())
+        {
+            tracing::warn!(
+                "Monitored interface {} was removed or lost its carrier",
+                name
+            );
+            record_event(
+                &self.history,
+                EventKind::Network,
+                format!("Interface {} is down", name),
+            );
+            // The kernel drops the TC filter and the routes along with the
+            // interface (or its addresses); forget them so they are set up again
+            if let Err(e) = self.traffic_monitor.detach_from(name) {
+                tracing::debug!("eBPF program gone with the interface: {}", e);
+            }
+            if !self.dry_run {
+                if let Err(e) = self.route_managers[slot].remove_routes().await {
+                    tracing::warn!("Failed to remove monitoring routes: {:#}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Move monitoring of an auto-detected interface to the one now carrying
+    /// the default route
+    async fn follow_default_route(&mut self, monitoring: bool) -> Result<()> {
+        let current = &self.monitor_ifaces[0];
+        let Some(uplink) = default_route_moved(
+            current,
+            self.wg_controller.interface(),
+            self.settings.command_timeout,
+        )
+        .await
+        else {
+            return Ok(());
+        };
+
+        tracing::info!(
+            "Default route moved from {} to {}, monitoring {} instead",
+            current,
+            uplink,
+            uplink
+        );
+        record_event(
+            &self.history,
+            EventKind::Network,
+            format!("Monitoring moved from {} to {}", current, uplink),
+        );
+        if self.traffic_monitor.is_attached_to(current) {
+            if let Err(e) = self.traffic_monitor.detach_from(current) {
+                tracing::warn!("Failed to detach eBPF: {}", e);
+            }
+        }
+        if !self.dry_run {
+            if let Err(e) = self.route_managers[0].remove_routes().await {
+                tracing::warn!("Failed to remove monitoring routes: {:#}", e);
+            }
+        }
+        self.route_managers[0] = monitor_route_manager(&self.config, uplink.clone(), 0);
+        self.monitor_ifaces[0] = uplink;
+        // Attach to the new uplink (with the usual IP conflict checks)
+        if monitoring {
+            self.state_tx
+                .send(StateCommand::RetryEbpfAttachment)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Point the monitoring routes at a new default gateway
+    async fn refresh_gateways(&mut self) {
+        for route_manager in self.route_managers.iter_mut() {
+            match route_manager.refresh_gateway().await {
+                Ok(Some(gateway)) => record_event(
+                    &self.history,
+                    EventKind::Network,
+                    format!(
+                        "Monitoring routes on {} moved to new gateway {}",
+                        route_manager.interface(),
+                        gateway
+                    ),
+                ),
+                Ok(None) => {}
+                // Typically between removal of the old default route and
+                // arrival of the new one
+                Err(e) => tracing::debug!("Gateway not re-detected: {:#}", e),
+            }
+        }
+    }
+
+    /// Restore monitoring routes deleted outside the daemon
+    async fn restore_routes(&mut self) {
+        for route_manager in self.route_managers.iter_mut() {
+            match route_manager.restore_routes().await {
+                Ok(0) => {}
+                Ok(restored) => tracing::warn!(
+                    "Restored {} monitoring route(s) deleted outside the daemon",
+                    restored
+                ),
+                Err(e) => tracing::warn!("Failed to restore monitoring routes: {:#}", e),
+            }
+        }
+    }
+
+    /// Notice tunnels brought down or up outside the daemon
+    pub(super) async fn on_link_check(&mut self) -> Result<()> {
+        let state = self.state_manager.state();
+        if state == TunnelState::Active && !self.wg_controller.link_up() {
+            tracing::warn!(
+                "WireGuard interface {} went down outside the daemon",
+                self.wg_controller.interface()
+            );
+            self.traffic_monitor.detach_counters();
+            // Interface still exists but was set down: remove it so the next
+            // activation starts clean
+            if self.wg_controller.is_up().await {
+                if let Err(e) = self.wg_controller.bring_down().await {
+                    tracing::warn!("Failed to clean up tunnel: {:#}", e);
+                }
+            }
+            self.status
+                .set_notice("Tunnel was brought down externally".to_string());
+            self.state_tx.send(StateCommand::TunnelLost).await?;
+        } else if matches!(state, TunnelState::Inactive | TunnelState::Monitoring)
+            && self.wg_controller.link_up()
+        {
+            tracing::info!(
+                "WireGuard interface {} was brought up outside the daemon",
+                self.wg_controller.interface()
+            );
+            self.wg_controller.reset_activity();
+            self.state_tx.send(StateCommand::TunnelUpExternally).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,606 @@
+// Daemon setup
+
+//! Building the daemon's components from the config, cleaning up after a
+//! previous instance and restricting the process once everything is open
+
+use super::{unix_now, DaemonStatus};
+use crate::control::{ControlCommand, ControlServer};
+use crate::dbus_service::DbusService;
+use crate::endpoint;
+use crate::event_log::EventLog;
+use crate::history::SharedHistory;
+use crate::instance::Instance;
+use crate::kill_switch::{self, KillSwitch};
+use crate::landlock;
+use crate::native_tunnel::NativeTunnel;
+use crate::nfqueue::{self, TrafficHold};
+use crate::privileges;
+use crate::route_manager::RouteManager;
+use crate::rtnl::RtnlMonitor;
+use crate::seccomp;
+use crate::state::StateManager;
+use crate::state_file::SavedState;
+use crate::stats::LifetimeStats;
+use crate::traffic_monitor::TrafficMonitor;
+use crate::types::{
+    Config, IdleDetection, KillSwitchMode, MonitorRouting, SourceFilter, TunnelMode, TunnelState,
+};
+use crate::wg_controller::{self, WgController};
+use crate::wg_quick;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Log the settings that shape the daemon's behavior
+pub fn log_settings(config: &Config) {
+    if config.general.target_ssids.0.is_empty() && config.general.exclude_ssids.is_empty() {
+        tracing::info!("SSID filtering: monitoring ALL networks");
+    } else if config.general.target_ssids.0.is_empty() {
+        tracing::info!(
+            "SSID filtering: all networks EXCEPT {:?}",
+            config.general.exclude_ssids
+        );
+    } else if config.general.exclude_ssids.is_empty() {
+        tracing::info!("SSID filtering: ONLY {:?}", config.general.target_ssids.0);
+    } else {
+        tracing::info!(
+            "SSID filtering: {:?} EXCEPT {:?}",
+            config.general.target_ssids.0,
+            config.general.exclude_ssids
+        );
+    }
+
+    tracing::info!("WireGuard interface: {}", config.general.wg_interface);
+    tracing::info!("Idle timeout: {}s", config.general.idle_timeout);
+    if config.general.mode == TunnelMode::Manual {
+        tracing::info!("Manual mode: traffic does not activate the tunnel");
+    }
+    if config.general.min_active_secs > 0 {
+        tracing::info!(
+            "Minimum active duration: {}s",
+            config.general.min_active_secs
+        );
+    }
+    tracing::info!(
+        "Idle check interval: {}s, eBPF poll interval: {}ms",
+        config.general.idle_check_interval_secs,
+        config.general.ebpf_poll_interval_ms
+    );
+    if config.general.handshake_stale_secs > 0 {
+        tracing::info!(
+            "Tunnel health check: restart if handshake older than {}s",
+            config.general.handshake_stale_secs
+        );
+    }
+    if config.general.handshake_timeout_secs > 0 {
+        tracing::info!(
+            "Handshake verification timeout: {}s",
+            config.general.handshake_timeout_secs
+        );
+    }
+    tracing::info!("Target subnets: {}", config.subnets.ranges.join(", "));
+    if let Some(probe) = &config.probe {
+        match probe.port {
+            Some(port) => tracing::info!("Connectivity probe: TCP {}:{}", probe.host, port),
+            None => tracing::info!("Connectivity probe: ICMP {}", probe.host),
+        }
+    }
+}
+
+/// WireGuard controller for the configured backend, endpoints and idle detection
+///
+/// # Errors
+///
+/// Returns an error if the interface name or the native tunnel definition is
+/// invalid.
+pub fn wg_controller(config: &Config) -> Result<WgController> {
+    let command_timeout = Duration::from_secs(config.general.command_timeout_secs);
+    let idle_check_interval = Duration::from_secs(config.general.idle_check_interval_secs);
+
+    let mut wg_controller = WgController::new(
+        config.general.wg_interface.clone(),
+        config.general.nm_connection.clone(),
+    )
+    .context("Failed to create WireGuard controller")?
+    .with_command_timeout(command_timeout);
+    if let Some(native) = &config.native {
+        let tunnel =
+            NativeTunnel::from_config(config.general.wg_interface.clone(), native, command_timeout)
+                .context("Failed to load native tunnel definition")?;
+        tracing::info!(
+            "Tunnel backend: native netlink ({} peer(s))",
+            tunnel.peer_count()
+        );
+        wg_controller = wg_controller.with_native(tunnel);
+    }
+    // The wg-quick config in use, if any (inline [native] and NetworkManager have none)
+    let wg_quick_config =
+        wg_quick::config_in_use(config).and_then(|path| match wg_quick::load(&path) {
+            Ok(wg_config) => Some(wg_config),
+            Err(e) => {
+                tracing::debug!("Not using wg-quick config: {:#}", e);
+                None
+            }
+        });
+    if let Some(wg_config) = wg_quick_config
+        .as_ref()
+        .filter(|_| !config.general.narrow_allowed_ips)
+    {
+        for subnet in wg_config.uncovered_subnets(&config.subnets.ranges) {
+            tracing::warn!(
+                "Target subnet {} is not covered by any peer's AllowedIPs, its traffic will not use the tunnel",
+                subnet
+            );
+        }
+    }
+    if config.general.narrow_allowed_ips {
+        tracing::info!(
+            "AllowedIPs narrowed to target subnets on activation: {}",
+            config.subnets.ranges.join(", ")
+        );
+        wg_controller = wg_controller.with_narrowed_allowed_ips(config.subnets.ranges.clone());
+    }
+    if !config.general.activity_peers.is_empty() {
+        tracing::info!(
+            "Idle detection only counts peers: {}",
+            config.general.activity_peers.join(", ")
+        );
+        wg_controller = wg_controller.with_activity_peers(config.general.activity_peers.clone());
+    }
+    if config.general.ignore_keepalives {
+        wg_controller = wg_controller.with_keepalive_filter(idle_check_interval);
+    }
+    if config.general.activity_threshold_bytes > 0 {
+        tracing::info!(
+            "Activity threshold: {} bytes per idle check",
+            config.general.activity_threshold_bytes
+        );
+        wg_controller =
+            wg_controller.with_activity_threshold(config.general.activity_threshold_bytes);
+    }
+    match config.general.idle_detection {
+        IdleDetection::Bytes => {}
+        IdleDetection::Handshake => {
+            tracing::info!("Idle detection: handshake renewals");
+            wg_controller = wg_controller.with_idle_detection(IdleDetection::Handshake);
+        }
+        // Counters are read by the main loop rather than the controller
+        IdleDetection::Ebpf => tracing::info!("Idle detection: eBPF byte counters"),
+    }
+    if let Some(mtu) = config.general.mtu {
+        tracing::info!("Tunnel MTU: {}", mtu);
+        wg_controller = wg_controller.with_mtu(mtu);
+    }
+    if let Some(secs) = config.general.persistent_keepalive_secs {
+        tracing::info!("Persistent keepalive on activation: {}s", secs);
+        wg_controller = wg_controller.with_persistent_keepalive(secs);
+    }
+    if let Some(endpoints) = config.endpoints.clone() {
+        tracing::info!("Peer endpoints: {}", endpoints.addresses.join(", "));
+        wg_controller = wg_controller.with_endpoints(endpoints);
+    } else if let Some(wg_config) = &wg_quick_config {
+        // Endpoint hostnames are only resolved when configuring the interface
+        let hostnames = endpoint::hostname_endpoints(wg_config);
+        for peer in &hostnames {
+            tracing::info!(
+                "Endpoint {} will be re-resolved on activation",
+                peer.address
+            );
+        }
+        wg_controller = wg_controller.with_hostname_endpoints(hostnames);
+    }
+    Ok(wg_controller)
+}
+
+/// State machine with the configured timeouts, debounce and backoff
+pub fn state_manager(config: &Config) -> StateManager {
+    StateManager::new(config.general.idle_timeout)
+        .with_min_active(config.general.min_active_secs)
+        .with_activation_delay(config.general.activation_delay_ms)
+        .with_max_session(config.general.max_session_secs)
+        .with_cooldown(config.general.cooldown_secs)
+        .with_pause(config.general.pause_secs)
+        .with_manual_idle_timeout(config.general.manual_idle_timeout)
+        .with_flap_detection(
+            config.general.flap_threshold,
+            config.general.flap_window_secs,
+            config.general.flap_backoff_secs,
+        )
+        .with_activation_retries(
+            config.general.activation_retries,
+            config.general.activation_retry_secs,
+        )
+}
+
+/// The interfaces to monitor, auto-detected if not configured
+///
+/// # Errors
+///
+/// Returns an error if the configured name is invalid or no interface can be
+/// detected.
+pub async fn monitor_interfaces(config: &Config) -> Result<Vec<String>> {
+    let monitor_ifaces = match config.general.monitor_interface.clone() {
+        // Names validated with the config
+        _ if !config.general.monitor_interfaces.is_empty() => {
+            config.general.monitor_interfaces.clone()
+        }
+        Some(iface) => {
+            // Validate configured interface name
+            wg_controller::validate_interface_name(&iface)
+                .context("Configured monitor interface has invalid name")?;
+            vec![iface]
+        }
+        None => {
+            tracing::info!("Auto-detecting network interface...");
+            let detected = auto_detect_interface()
+                .await
+                .context("Failed to auto-detect network interface")?;
+            // Validate auto-detected interface name (defense-in-depth)
+            wg_controller::validate_interface_name(&detected)
+                .context("Auto-detected interface has invalid name")?;
+            vec![detected]
+        }
+    };
+    tracing::info!("Monitoring interface: {}", monitor_ifaces.join(", "));
+    Ok(monitor_ifaces)
+}
+
+/// Auto-detect the active network interface
+/// Attempts to find a wireless interface, falling back to the default route interface
+async fn auto_detect_interface() -> Result<String> {
+    // First, try to find wireless interfaces by checking /sys/class/net/*/wireless
+    if let Ok(entries) = std::fs::read_dir("/sys/class/net") {
+        for entry in entries.flatten() {
+            let iface_name = entry.file_name();
+            let wireless_path = format!("/sys/class/net/{}/wireless", iface_name.to_string_lossy());
+            if std::path::Path::new(&wireless_path).exists() {
+                tracing::info!(
+                    "Auto-detected wireless interface: {}",
+                    iface_name.to_string_lossy()
+                );
+                return Ok(iface_name.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    // Fall back to finding the default route interface
+    tracing::info!("No wireless interface found, detecting default route interface...");
+    let output = tokio::process::Command::new("ip")
+        .args(["route", "show", "default"])
+        .output()
+        .await
+        .context("Failed to execute 'ip route show default'")?;
+
+    if output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        // Parse output like: "default via 192.168.1.1 dev eth0 proto dhcp metric 100"
+        for line in stdout.lines() {
+            if let Some(dev_pos) = line.find(" dev ") {
+                let after_dev = &line[dev_pos + 5..];
+                if let Some(iface) = after_dev.split_whitespace().next() {
+                    tracing::info!("Auto-detected default route interface: {}", iface);
+                    return Ok(iface.to_string());
+                }
+            }
+        }
+    }
+
+    anyhow::bail!(
+        "Could not auto-detect network interface. Please specify monitor_interface in config."
+    )
+}
+
+/// Route manager for the monitored interface in `slot`
+pub fn monitor_route_manager(config: &Config, iface: String, slot: u32) -> RouteManager {
+    RouteManager::new(iface)
+        .with_slot(config.instance.monitor_slot(slot))
+        .with_command_timeout(Duration::from_secs(config.general.command_timeout_secs))
+        .with_fwmark(config.general.monitor_routing == MonitorRouting::Fwmark)
+        .with_metric(config.general.route_metric.unwrap_or(0))
+        .with_gateway_fallback(config.general.gateway_fallback)
+}
+
+/// Set up the kill switch: the classifier drops what it reports, "all" adds an
+/// nftables table, returned for the event loop to enable
+///
+/// # Errors
+///
+/// Returns an error if the classifier cannot be switched to dropping.
+pub fn kill_switch(
+    config: &Config,
+    traffic_monitor: &mut TrafficMonitor,
+    dry_run: bool,
+) -> Result<Option<KillSwitch>> {
+    let mode = config.general.kill_switch;
+    if mode != KillSwitchMode::Off && dry_run {
+        tracing::info!("Dry run: kill switch not installed");
+    } else if mode != KillSwitchMode::Off {
+        tracing::info!("Kill switch: {:?}", mode);
+        traffic_monitor
+            .set_kill_switch(true)
+            .context("Failed to enable the kill switch")?;
+    }
+    Ok((mode == KillSwitchMode::All && !dry_run).then(|| kill_switch::from_config(config)))
+}
+
+/// Restrict which sources may trigger activation
+///
+/// # Errors
+///
+/// Returns an error if the configured source ranges cannot be passed to eBPF.
+pub async fn source_filter(
+    config: &Config,
+    traffic_monitor: &mut TrafficMonitor,
+    monitor_ifaces: &[String],
+) -> Result<()> {
+    match config.general.source_filter {
+        SourceFilter::Any => {}
+        SourceFilter::Local => super::sync_local_sources(traffic_monitor, monitor_ifaces).await,
+        SourceFilter::Ranges => traffic_monitor
+            .set_source_filter(Some(&config.general.source_ranges))
+            .context("Failed to set the source filter")?,
+    }
+    Ok(())
+}
+
+/// The session log, if configured (a dry run has no sessions to log)
+pub fn event_log(config: &Config, dry_run: bool) -> Option<EventLog> {
+    let event_log = config
+        .general
+        .event_log
+        .clone()
+        .filter(|_| !dry_run)
+        .map(|path| {
+            EventLog::new(path).with_rotation(
+                config.general.event_log_max_bytes,
+                config.general.event_log_keep,
+                (config.general.event_log_max_days > 0)
+                    .then(|| Duration::from_secs(config.general.event_log_max_days * 86400)),
+            )
+        })?;
+    tracing::info!("Session log: {:?}", event_log.path());
+    Some(event_log)
+}
+
+/// Lifetime counters from previous runs; finished sessions are added as they end
+pub fn lifetime_stats(path: &Path) -> LifetimeStats {
+    let lifetime = LifetimeStats::load(path).unwrap_or_else(|e| {
+        tracing::warn!("Failed to load lifetime statistics: {:#}", e);
+        LifetimeStats::default()
+    });
+    tracing::info!(
+        "Lifetime statistics: {} activations, {}s active, {} bytes received, {} bytes sent",
+        lifetime.activations,
+        lifetime.active_secs,
+        lifetime.rx_bytes,
+        lifetime.tx_bytes
+    );
+    lifetime
+}
+
+/// Serve the control socket, forwarding commands to `control_tx`
+///
+/// The socket is optional: the daemon keeps working without it.
+pub fn serve_control_socket(
+    path: &Path,
+    history: &SharedHistory,
+    status: &DaemonStatus,
+    wg_controller: &WgController,
+    control_tx: &mpsc::Sender<ControlCommand>,
+) {
+    match ControlServer::bind(path) {
+        Ok(server) => {
+            let server = server
+                .with_history(history.clone())
+                .with_peers(wg_controller.wg_stats_interface().to_string())
+                .with_stats(status.stats.clone());
+            let control_tx = control_tx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.serve(control_tx).await {
+                    tracing::error!("Control socket error: {}", e);
+                }
+            });
+        }
+        Err(e) => {
+            tracing::warn!("Control socket unavailable: {}", e);
+        }
+    }
+}
+
+/// Register on D-Bus; optional too, GUIs can fall back to polling the state file
+pub async fn dbus_service(state: TunnelState) -> Option<DbusService> {
+    match DbusService::start(state).await {
+        Ok(service) => Some(service),
+        Err(e) => {
+            tracing::warn!("D-Bus service unavailable: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Remove monitoring routes and nftables tables a crashed instance left behind
+pub async fn remove_stale_state(
+    instance: &Instance,
+    route_managers: &[RouteManager],
+    kill_switch: bool,
+    command_timeout: Duration,
+    dry_run: bool,
+) {
+    // Monitoring routes of a crashed instance would point at a stale gateway
+    if dry_run {
+        tracing::info!("Dry run: not checking for stale monitoring routes");
+        return;
+    }
+    for route_manager in route_managers {
+        if let Err(e) = route_manager.remove_stale_routes().await {
+            tracing::warn!("Failed to check for stale monitoring routes: {:#}", e);
+        }
+    }
+
+    // A kill switch table left by a crashed instance would block all traffic
+    let table = instance.nft_table(kill_switch::NFT_TABLE);
+    if let Err(e) = kill_switch::remove_table(&table, command_timeout).await {
+        if kill_switch {
+            tracing::warn!("Failed to remove stale kill switch table: {:#}", e);
+        } else {
+            tracing::debug!("No stale kill switch table removed: {:#}", e);
+        }
+    }
+
+    // Same for the traffic hold table, which would keep queueing to nobody
+    let table = instance.nft_table(nfqueue::NFT_TABLE);
+    if let Err(e) = nfqueue::remove_table(&table, command_timeout).await {
+        tracing::debug!("No stale traffic hold table removed: {:#}", e);
+    }
+}
+
+/// Reconcile the state left behind by a previous daemon instance with reality
+///
+/// If the tunnel it brought up is still up on the same network, its session start,
+/// idle clock and cooldown carry over instead of starting from scratch. A tunnel
+/// it left up after the network changed is brought down.
+pub async fn resume_saved_state(
+    saved: &SavedState,
+    current_ssid: Option<&str>,
+    on_monitored_network: bool,
+    tunnel_up: bool,
+    state_manager: &mut StateManager,
+    wg_controller: &mut WgController,
+) {
+    let now = unix_now();
+    let was_up = matches!(
+        saved.state,
+        TunnelState::Activating | TunnelState::Active | TunnelState::Deactivating
+    );
+
+    if saved.ssid.as_deref() != current_ssid || !on_monitored_network {
+        // A manual tunnel is left alone, the link check picks it up again
+        if was_up && tunnel_up && !saved.manual {
+            tracing::info!(
+                "Tunnel left up by previous instance on {}, no longer on that network, bringing it down",
+                saved.ssid.as_deref().unwrap_or("another network")
+            );
+            if let Err(e) = wg_controller.bring_down().await {
+                tracing::error!("Failed to bring down tunnel: {:#}", e);
+            }
+        }
+        return;
+    }
+
+    if let Some(remaining) = saved
+        .cooldown_until
+        .and_then(|until| until.checked_sub(now))
+    {
+        tracing::info!(
+            "Resuming cooldown from previous instance ({}s left)",
+            remaining
+        );
+        state_manager.resume_cooldown(Duration::from_secs(remaining));
+    }
+
+    if saved.state != TunnelState::Active || !tunnel_up {
+        return;
+    }
+    let active_for = saved.session_start.map(|t| now.saturating_sub(t));
+    let idle_for = saved.last_activity.map(|t| now.saturating_sub(t));
+    tracing::info!(
+        "Resuming session from previous instance (up {}s, idle {}s)",
+        active_for.unwrap_or(0),
+        idle_for.unwrap_or(0)
+    );
+    state_manager.resume_session(Duration::from_secs(active_for.unwrap_or(0)), saved.manual);
+    if let Some(idle_for) = idle_for {
+        // Traffic from before the restart must not count as new activity
+        if let Err(e) = wg_controller.check_activity().await {
+            tracing::debug!("Failed to read tunnel counters: {:#}", e);
+        }
+        wg_controller.resume_activity(Duration::from_secs(idle_for));
+    }
+}
+
+/// Queue triggering traffic until the tunnel is up, if configured
+///
+/// Optional: without the queue, triggering traffic is still seen by eBPF.
+pub async fn traffic_hold(
+    config: &Config,
+    state: TunnelState,
+    dry_run: bool,
+) -> Option<TrafficHold> {
+    if !config.general.hold_traffic || dry_run {
+        return None;
+    }
+    match TrafficHold::start(
+        &config.instance.nft_table(nfqueue::NFT_TABLE),
+        config.instance.queue_num(),
+        &config.general.wg_interface,
+        &config.subnets.ranges,
+        state,
+        Duration::from_secs(config.general.command_timeout_secs),
+    )
+    .await
+    {
+        Ok(traffic_hold) => Some(traffic_hold),
+        Err(e) => {
+            tracing::warn!("Traffic hold unavailable: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Watch route, address and link changes
+///
+/// Optional: without it, deleted monitoring routes stay gone until the next
+/// attach, and a replugged interface or one still waiting for DHCP is only
+/// attached on the next network change.
+pub fn rtnl_monitor() -> Option<RtnlMonitor> {
+    match RtnlMonitor::new() {
+        Ok(rtnl_monitor) => Some(rtnl_monitor),
+        Err(e) => {
+            tracing::warn!("Route and link monitoring unavailable: {}", e);
+            None
+        }
+    }
+}
+
+/// Drop capabilities and apply the Landlock and seccomp restrictions, as
+/// configured
+///
+/// Everything needing more than CAP_NET_ADMIN must be loaded or open by now.
+///
+/// # Errors
+///
+/// Returns an error if the eBPF byte counters cannot be loaded or a
+/// restriction cannot be applied.
+pub fn restrict(config: &Config, traffic_monitor: &mut TrafficMonitor) -> Result<()> {
+    if config.general.drop_capabilities || config.general.user.is_some() {
+        if config.general.idle_detection == IdleDetection::Ebpf {
+            traffic_monitor
+                .load_counters()
+                .context("Failed to load eBPF byte counters")?;
+        }
+        let caps = privileges::required_capabilities(
+            config,
+            traffic_monitor.is_ebpf(),
+            privileges::last_cap()?,
+        );
+        privileges::drop_privileges(
+            &caps,
+            config.general.user.as_deref(),
+            &config.instance,
+            config.general.event_log.as_deref(),
+        )?;
+    }
+    if config.general.landlock {
+        landlock::restrict(config)?;
+    }
+    // Last, since it doesn't allow the syscalls used above
+    if config.general.seccomp {
+        if seccomp::supported() {
+            seccomp::install(config)?;
+        } else {
+            tracing::warn!("seccomp filter not supported on this architecture");
+        }
+    }
+    Ok(())
+}
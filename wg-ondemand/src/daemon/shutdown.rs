@@ -0,0 +1,102 @@
+// Daemon shutdown
+
+//! Tearing the daemon down: saving the session and statistics, removing what
+//! it added to the system and leaving an active tunnel for the next instance
+
+use super::commands::log_session;
+use super::{save_lifetime_stats, state_snapshot, unix_now, Daemon};
+use crate::control;
+use crate::state_file;
+use crate::types::TunnelState;
+use anyhow::Result;
+
+impl Daemon {
+    /// Save the running session, save statistics, remove the state file and control
+    /// socket, detach eBPF and bring down a tunnel that is still coming up
+    ///
+    /// An active tunnel stays up and its session is saved to the state directory,
+    /// so a restarted daemon carries on with it (see [`state_file::save_session`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if cleanup fails.
+    pub async fn shutdown(mut self) -> Result<()> {
+        self.monitor_handle.abort();
+
+        // A dry run never brought the tunnel up
+        let tunnel_state = if self.dry_run {
+            TunnelState::Inactive
+        } else {
+            self.state_manager.state()
+        };
+        self.save_session(tunnel_state);
+
+        if let Some(traffic_hold) = self.traffic_hold.take() {
+            traffic_hold.stop().await;
+        }
+        if let Some(kill_switch) = &mut self.kill_switch {
+            if let Err(e) = kill_switch.disable().await {
+                tracing::error!("Failed to disable kill switch: {:#}", e);
+            }
+        }
+
+        // Clean up state file and control socket
+        state_file::cleanup(&self.config.instance);
+        control::cleanup(self.config.instance.control_socket());
+
+        self.release(tunnel_state).await;
+        Ok(())
+    }
+
+    /// Save an active session for the next instance, or log the one a dry run
+    /// simulated, and persist lifetime statistics
+    fn save_session(&mut self, tunnel_state: TunnelState) {
+        if tunnel_state == TunnelState::Active {
+            // The next instance resumes the session and logs it when it ends
+            let snapshot = state_snapshot(&self.state_manager, &self.wg_controller, &self.status);
+            if let Err(e) = state_file::save_session(&self.config.instance, &snapshot) {
+                tracing::warn!("Failed to save session: {:#}", e);
+            }
+        } else if let Some(session) = self
+            .session
+            .take()
+            .filter(|_| self.state_manager.state() == TunnelState::Active)
+        {
+            self.lifetime.active_secs += unix_now().saturating_sub(session.start);
+            if let Some(event_log) = &self.event_log {
+                log_session(event_log, &session, &self.wg_controller, "daemon shutdown");
+            }
+        }
+        if !self.dry_run {
+            save_lifetime_stats(&self.config.instance, &self.lifetime, &self.wg_controller);
+        }
+    }
+
+    /// Detach eBPF and bring down a tunnel that is still coming up
+    ///
+    /// An active tunnel is left up for the next instance to resume.
+    async fn release(&mut self, tunnel_state: TunnelState) {
+        tracing::info!("Shutting down gracefully...");
+
+        self.traffic_monitor.detach_counters();
+
+        // Detach eBPF program if attached
+        if self.traffic_monitor.is_attached() {
+            tracing::info!("Detaching eBPF program...");
+            if let Err(e) = self.traffic_monitor.detach() {
+                tracing::error!("Failed to detach eBPF program: {}", e);
+            }
+        }
+
+        if tunnel_state == TunnelState::Active {
+            tracing::info!("Leaving WireGuard tunnel up for the next instance");
+        } else if tunnel_state == TunnelState::Activating {
+            tracing::info!("Bringing down WireGuard tunnel...");
+            if let Err(e) = self.wg_controller.bring_down().await {
+                tracing::error!("Failed to bring down tunnel: {}", e);
+            }
+        }
+
+        tracing::info!("Shutdown complete");
+    }
+}
@@ -0,0 +1,198 @@
+// Tunnel bring-up and teardown
+
+//! Bringing the tunnel up, verifying it works, and bringing it down
+
+use super::{record_event, sync_tunnel_identity, Daemon};
+use crate::history::EventKind;
+use crate::probe;
+use crate::state::StateCommand;
+use crate::stats;
+use crate::telemetry;
+use crate::types::ProbeConfig;
+use crate::wg_controller::WgController;
+use anyhow::{Context, Result};
+use std::time::{Duration, SystemTime};
+use tracing::Instrument;
+
+/// Bring the tunnel up and verify it actually works
+///
+/// Selects the failover endpoint (if configured), waits for a peer handshake
+/// (if `handshake_timeout` is nonzero) and runs the
+/// connectivity probe (if configured). If either check fails the tunnel is rolled
+/// back down so traffic isn't blackholed behind a broken tunnel. A passing probe
+/// can be followed by a path MTU probe that lowers the tunnel MTU.
+async fn activate_tunnel(
+    wg_controller: &mut WgController,
+    handshake_timeout: Duration,
+    probe: Option<&ProbeConfig>,
+) -> Result<()> {
+    let bring_up_started = SystemTime::now();
+    if let Err(e) = wg_controller.bring_up().await {
+        wg_controller.endpoint_failed();
+        return Err(e.into());
+    }
+
+    let verified = async {
+        wg_controller.apply_mtu().await?;
+        wg_controller.narrow_allowed_ips().await?;
+        wg_controller.apply_keepalive().await?;
+        wg_controller.apply_endpoint().await?;
+        if !handshake_timeout.is_zero() {
+            // Only report the tunnel up once the peer actually answers
+            wg_controller
+                .wait_for_handshake(bring_up_started, handshake_timeout)
+                .await?;
+        }
+        if let Some(probe) = probe {
+            probe::run(probe)
+                .await
+                .context("Connectivity probe failed")?;
+            if probe.path_mtu {
+                adjust_path_mtu(wg_controller, probe).await;
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = &verified {
+        tracing::warn!("Tunnel verification failed, rolling back: {:#}", e);
+        if let Err(e) = wg_controller.bring_down().await {
+            tracing::warn!("Failed to bring down unverified tunnel: {}", e);
+        }
+        wg_controller.endpoint_failed();
+    }
+    verified
+}
+
+/// Lower the tunnel MTU if large packets to the probe host are blackholed
+///
+/// Failures only leave the MTU unchanged; the tunnel already passed the probe.
+async fn adjust_path_mtu(wg_controller: &WgController, probe: &ProbeConfig) {
+    let current = match wg_controller.current_mtu() {
+        Ok(mtu) => mtu,
+        Err(e) => {
+            tracing::warn!("Skipping path MTU probe: {:#}", e);
+            return;
+        }
+    };
+    let Ok(host) = probe.host.parse() else {
+        return;
+    };
+
+    match probe::path_mtu(host, current, Duration::from_secs(probe.timeout_secs)).await {
+        Some(mtu) if mtu < current => {
+            tracing::warn!(
+                "Packets larger than {} bytes are dropped on this path, lowering MTU from {}",
+                mtu,
+                current
+            );
+            if let Err(e) = wg_controller.set_mtu(mtu).await {
+                tracing::warn!("{:#}", e);
+            }
+        }
+        Some(_) => tracing::debug!("Path MTU probe: MTU {} works", current),
+        None => tracing::warn!("Path MTU probe got no reply even at the minimum MTU"),
+    }
+}
+
+impl Daemon {
+    /// Bring the tunnel up (down first for a `restart`) and report the result to
+    /// the state machine
+    pub(super) async fn activate(&mut self, restart: bool) -> Result<()> {
+        if restart {
+            tracing::info!("Action: Restarting WireGuard tunnel");
+            self.traffic_monitor.detach_counters();
+            if let Err(e) = self.wg_controller.bring_down().await {
+                tracing::warn!("Failed to bring down unhealthy tunnel: {}", e);
+            }
+            self.wg_controller.endpoint_failed();
+        }
+        tracing::info!("Action: Activating WireGuard tunnel");
+        let span = tracing::info_span!(
+            "activation",
+            interface = %self.wg_controller.interface(),
+            attempt = self.state_manager.activation_attempt(),
+            trigger_delay_ms = self.activation_trigger_ns.map(|trigger_ns| {
+                stats::monotonic_now_ns().saturating_sub(trigger_ns) / 1_000_000
+            }),
+        );
+        let result = activate_tunnel(
+            &mut self.wg_controller,
+            self.settings.handshake_timeout,
+            self.config.probe.as_ref(),
+        )
+        .instrument(span)
+        .await;
+
+        if let Err(e) = result {
+            telemetry::record_activation(false);
+            tracing::error!("Failed to bring up tunnel: {}", e);
+            record_event(
+                &self.history,
+                EventKind::Error,
+                format!("Failed to bring up tunnel: {:#}", e),
+            );
+            self.state_tx.send(StateCommand::ActivationFailed).await?;
+            return Ok(());
+        }
+
+        telemetry::record_activation(true);
+        // Reset activity tracking when tunnel comes up
+        self.wg_controller.reset_activity();
+        sync_tunnel_identity(
+            &mut self.traffic_monitor,
+            &self.wg_controller,
+            &self.config.subnets.ranges,
+        )
+        .await;
+        if self.settings.use_ebpf_counters {
+            if let Err(e) = self
+                .traffic_monitor
+                .attach_counters(self.wg_controller.interface())
+            {
+                tracing::warn!("Failed to attach eBPF byte counters: {:#}", e);
+            }
+        }
+
+        if let Some(trigger_ns) = self.activation_trigger_ns.take() {
+            let latency =
+                Duration::from_nanos(stats::monotonic_now_ns().saturating_sub(trigger_ns));
+            let latencies = &mut self.status.activation_latency;
+            latencies.record(latency);
+            telemetry::record_activation_latency(latency);
+            tracing::info!(
+                "Tunnel activation latency: {}ms (min={}ms avg={}ms max={}ms)",
+                latency.as_millis(),
+                latencies.min().unwrap_or_default().as_millis(),
+                latencies.avg().unwrap_or_default().as_millis(),
+                latencies.max().unwrap_or_default().as_millis()
+            );
+        }
+
+        self.state_tx.send(StateCommand::TunnelUp).await?;
+        Ok(())
+    }
+
+    /// Bring the tunnel down and report it to the state machine
+    pub(super) async fn deactivate(&mut self) -> Result<()> {
+        tracing::info!("Action: Deactivating WireGuard tunnel");
+        self.traffic_monitor.detach_counters();
+        match self.wg_controller.bring_down().await {
+            Ok(_) => {
+                telemetry::record_deactivation();
+                self.wg_controller.reset_endpoint();
+                self.state_tx.send(StateCommand::TunnelDown).await?;
+            }
+            Err(e) => {
+                tracing::error!("Failed to bring down tunnel: {}", e);
+                record_event(
+                    &self.history,
+                    EventKind::Error,
+                    format!("Failed to bring down tunnel: {:#}", e),
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+// D-Bus service for state change notifications
+
+//! D-Bus service
+//!
+//! Exports the daemon state on the system bus and emits a `StateChanged` signal
+//! on every transition, so GUIs can react instantly instead of polling the state
+//! file. State names match the `STATE=` values of the state file.
+
+use crate::state_file;
+use crate::types::TunnelState;
+use anyhow::{Context, Result};
+use zbus::object_server::{InterfaceRef, SignalContext};
+use zbus::{connection, interface, Connection};
+
+/// Well-known bus name owned by the daemon
+pub const BUS_NAME: &str = "io.github.vly.WgOndemand";
+
+/// Object path of the daemon object
+pub const OBJECT_PATH: &str = "/io/github/vly/WgOndemand";
+
+/// D-Bus interface exposing the tunnel state
+struct Daemon {
+    state: TunnelState,
+    ssid: String,
+}
+
+#[interface(name = "io.github.vly.WgOndemand1")]
+impl Daemon {
+    /// Current tunnel state
+    #[zbus(property)]
+    fn state(&self) -> &str {
+        state_file::state_str(self.state)
+    }
+
+    /// SSID of the monitored network (empty when not connected to one)
+    #[zbus(property)]
+    fn ssid(&self) -> &str {
+        &self.ssid
+    }
+
+    /// Emitted on every tunnel state transition
+    #[zbus(signal, name = "StateChanged")]
+    async fn emit_state_changed(
+        ctxt: &SignalContext<'_>,
+        old: &str,
+        new: &str,
+        ssid: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// Connection to the system bus serving the daemon object
+pub struct DbusService {
+    // Keeps the bus name and object registered
+    _connection: Connection,
+    daemon: InterfaceRef<Daemon>,
+}
+
+impl DbusService {
+    /// Register the daemon object and claim [`BUS_NAME`] on the system bus
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the system bus is unreachable or the name cannot be
+    /// owned (another instance running, or no bus policy installed).
+    pub async fn start(state: TunnelState) -> Result<Self> {
+        let connection = connection::Builder::system()
+            .context("Failed to connect to system D-Bus")?
+            .name(BUS_NAME)
+            .context("Invalid bus name")?
+            .serve_at(
+                OBJECT_PATH,
+                Daemon {
+                    state,
+                    ssid: String::new(),
+                },
+            )
+            .context("Failed to register D-Bus object")?
+            .build()
+            .await
+            .with_context(|| format!("Failed to own D-Bus name {}", BUS_NAME))?;
+        let daemon = connection
+            .object_server()
+            .interface::<_, Daemon>(OBJECT_PATH)
+            .await
+            .context("Failed to look up D-Bus object")?;
+
+        Ok(Self {
+            _connection: connection,
+            daemon,
+        })
+    }
+
+    /// Publish a state transition: update the properties and emit `StateChanged`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the signal cannot be sent.
+    pub async fn state_changed(
+        &self,
+        old: TunnelState,
+        new: TunnelState,
+        ssid: Option<&str>,
+    ) -> Result<()> {
+        let ssid = ssid.unwrap_or("");
+        let ctxt = self.daemon.signal_context();
+        let mut daemon = self.daemon.get_mut().await;
+        let ssid_changed = daemon.ssid != ssid;
+        daemon.state = new;
+        daemon.ssid = ssid.to_string();
+
+        Daemon::emit_state_changed(
+            ctxt,
+            state_file::state_str(old),
+            state_file::state_str(new),
+            ssid,
+        )
+        .await
+        .context("Failed to emit StateChanged")?;
+        daemon
+            .state_changed(ctxt)
+            .await
+            .context("Failed to emit State property change")?;
+        if ssid_changed {
+            daemon
+                .ssid_changed(ctxt)
+                .await
+                .context("Failed to emit Ssid property change")?;
+        }
+        Ok(())
+    }
+}
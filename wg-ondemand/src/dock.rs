@@ -0,0 +1,155 @@
+// Docking station detection
+
+//! Docking station detection
+//!
+//! Docked at home, a laptop reaches the home network over the dock's wired
+//! interface and the tunnel is not needed, whatever the WiFi is connected to.
+//! With a `[dock]` section the dock counts as docked at home while its
+//! interface has a carrier and matches the configured gateway MAC address
+//! and/or subnet.
+
+use crate::config;
+use crate::route_manager;
+use crate::rtnl;
+use crate::types::DockConfig;
+use anyhow::{Context, Result};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+/// How long to wait for the routing table when looking up the gateway
+const ROUTE_LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// ARP table of the kernel
+const ARP_TABLE: &str = "/proc/net/arp";
+
+/// `ATF_COM` flag of a resolved ARP entry
+const ATF_COM: u32 = 0x2;
+
+/// Parse a MAC address written as six hex pairs separated by `:` or `-`
+pub fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mut bytes = [0u8; 6];
+    let mut parts = mac.split([':', '-']);
+    for byte in &mut bytes {
+        let part = parts.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    parts.next().is_none().then_some(bytes)
+}
+
+/// MAC address of `ip` on `iface` in the ARP table, if resolved
+fn arp_lookup(arp: &str, ip: Ipv4Addr, iface: &str) -> Option<[u8; 6]> {
+    // IP address  HW type  Flags  HW address  Mask  Device
+    arp.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [address, _, flags, mac, _, device] = fields[..] else {
+            return None;
+        };
+        let flags = u32::from_str_radix(flags.trim_start_matches("0x"), 16).ok()?;
+        (address.parse() == Ok(ip) && device == iface && flags & ATF_COM != 0)
+            .then(|| parse_mac(mac))
+            .flatten()
+    })
+}
+
+/// Whether the interface has a carrier (cable plugged into a powered dock)
+fn has_carrier(iface: &str) -> bool {
+    // Reading carrier fails while the interface is down
+    std::fs::read_to_string(format!("/sys/class/net/{}/carrier", iface))
+        .is_ok_and(|carrier| carrier.trim() == "1")
+}
+
+/// Whether an IPv4 address of the interface is in `subnet`
+async fn address_in_subnet(iface: &str, subnet: &str) -> Result<bool> {
+    let addresses = rtnl::interface_addresses(iface)
+        .await
+        .context("Failed to get interface addresses")?;
+    let subnets = [subnet.to_string()];
+    for address in addresses {
+        if let IpAddr::V4(ip) = address.ip {
+            if config::ip_in_subnets(u32::from_be_bytes(ip.octets()), &subnets)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// MAC address of the interface's gateway
+async fn gateway_mac(iface: &str) -> Result<Option<[u8; 6]>> {
+    let oif = rtnl::ifindex(iface)?;
+    let routes = tokio::time::timeout(ROUTE_LOOKUP_TIMEOUT, rtnl::routes())
+        .await
+        .context("Route lookup timed out")?
+        .context("Failed to get routes")?;
+    let Some(gateway) = route_manager::route_gateway(&routes, oif) else {
+        return Ok(None);
+    };
+    let arp = std::fs::read_to_string(ARP_TABLE).context("Failed to read the ARP table")?;
+    Ok(arp_lookup(&arp, gateway, iface))
+}
+
+async fn check(config: &DockConfig) -> Result<bool> {
+    if !has_carrier(&config.interface) {
+        return Ok(false);
+    }
+    if let Some(subnet) = &config.subnet {
+        if !address_in_subnet(&config.interface, subnet).await? {
+            return Ok(false);
+        }
+    }
+    if let Some(mac) = &config.gateway_mac {
+        // The gateway is only in the ARP table once something was sent through it
+        if gateway_mac(&config.interface).await? != parse_mac(mac) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Whether the machine is docked at home
+pub async fn is_docked(config: &DockConfig) -> bool {
+    check(config).await.unwrap_or_else(|e| {
+        tracing::debug!("Dock {} not detected: {:#}", config.interface, e);
+        false
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mac() {
+        let mac = [0xaa, 0xbb, 0xcc, 0x00, 0x11, 0x22];
+        assert_eq!(parse_mac("aa:bb:cc:00:11:22"), Some(mac));
+        assert_eq!(parse_mac("AA-BB-CC-00-11-22"), Some(mac));
+        assert_eq!(parse_mac("aa:bb:cc:00:11"), None);
+        assert_eq!(parse_mac("aa:bb:cc:00:11:22:33"), None);
+        assert_eq!(parse_mac("aa:bb:cc:00:11:2"), None);
+        assert_eq!(parse_mac("aa:bb:cc:00:11:zz"), None);
+    }
+
+    #[test]
+    fn test_arp_lookup() {
+        let arp = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         aa:bb:cc:00:11:22     *        enp0s13f0u1
+192.168.1.7      0x1         0x0         00:00:00:00:00:00     *        enp0s13f0u1
+10.0.0.1         0x1         0x2         de:ad:be:ef:00:01     *        wlp194s0
+";
+        let gateway = Ipv4Addr::new(192, 168, 1, 1);
+        assert_eq!(
+            arp_lookup(arp, gateway, "enp0s13f0u1"),
+            Some([0xaa, 0xbb, 0xcc, 0x00, 0x11, 0x22])
+        );
+        assert_eq!(arp_lookup(arp, gateway, "wlp194s0"), None);
+        // Incomplete entries are not resolved yet
+        assert_eq!(
+            arp_lookup(arp, Ipv4Addr::new(192, 168, 1, 7), "enp0s13f0u1"),
+            None
+        );
+    }
+}
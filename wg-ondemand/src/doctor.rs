@@ -0,0 +1,307 @@
+// Environment self-test
+
+//! Environment checks
+//!
+//! `wg-ondemand doctor` checks the things the daemon silently relies on: its
+//! capabilities, a kernel with BPF ring buffers, the clsact qdisc used to attach
+//! the traffic classifier, NetworkManager (or ConnMan) on the system bus and the external tools
+//! of the configured tunnel backend. Each problem comes with a hint, since the
+//! daemon itself only reports them as failed attaches or commands.
+
+use crate::privileges::{parse_cap_field, CAP_BPF, CAP_NET_ADMIN, CAP_SYS_ADMIN};
+use crate::types::{Config, KillSwitchMode, MonitorRouting, NetworkBackend};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use zbus::fdo::DBusProxy;
+use zbus::names::BusName;
+use zbus::Connection;
+
+/// Oldest kernel with BPF ring buffers (`BPF_MAP_TYPE_RINGBUF`)
+const MIN_KERNEL: (u32, u32) = (5, 8);
+
+/// Kernel version from which CAP_BPF exists (older kernels need CAP_SYS_ADMIN)
+const CAP_BPF_KERNEL: (u32, u32) = (5, 8);
+
+/// Bus name of NetworkManager
+const NM_BUS_NAME: &str = "org.freedesktop.NetworkManager";
+
+/// Bus name of ConnMan
+const CONNMAN_BUS_NAME: &str = "net.connman";
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Nothing to do
+    Ok,
+    /// Might cause problems depending on the setup
+    Warn,
+    /// The daemon will not work
+    Fail,
+}
+
+/// Result of a single check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// Outcome
+    pub status: Status,
+    /// What was found
+    pub message: String,
+    /// How to fix it (for warnings and failures)
+    pub hint: Option<String>,
+}
+
+impl Finding {
+    fn ok(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Ok,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: Status::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "[{:>4}] {}", label, self.message)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n       -> {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run all checks; `config` selects which tunnel tools are needed
+pub async fn run(config: Option<&Config>) -> Vec<Finding> {
+    let kernel = std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .ok()
+        .map(|release| release.trim().to_string());
+    let version = kernel.as_deref().and_then(parse_kernel_version);
+
+    let mut findings = vec![check_kernel(kernel.as_deref(), version)];
+    findings.extend(check_capabilities(version));
+    findings.push(check_clsact(kernel.as_deref()));
+    let backend = config.map(|config| config.general.network_backend);
+    findings.push(check_network_manager(backend.unwrap_or_default()).await);
+    findings.extend(check_tools(config));
+    findings
+}
+
+/// Check that the kernel supports BPF ring buffers
+fn check_kernel(release: Option<&str>, version: Option<(u32, u32)>) -> Finding {
+    match (release, version) {
+        (Some(release), Some(version)) if version >= MIN_KERNEL => {
+            Finding::ok(format!("Kernel {} supports BPF ring buffers", release))
+        }
+        (Some(release), Some(_)) => Finding::fail(
+            format!("Kernel {} is too old for BPF ring buffers", release),
+            format!(
+                "Upgrade to Linux {}.{} or newer",
+                MIN_KERNEL.0, MIN_KERNEL.1
+            ),
+        ),
+        _ => Finding::warn(
+            "Could not determine the kernel version",
+            format!(
+                "Linux {}.{} or newer is required for BPF ring buffers",
+                MIN_KERNEL.0, MIN_KERNEL.1
+            ),
+        ),
+    }
+}
+
+/// Check the effective capabilities needed to load eBPF and change routes
+fn check_capabilities(kernel: Option<(u32, u32)>) -> Vec<Finding> {
+    let Some(caps) = std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| parse_cap_field(&status, "CapEff"))
+    else {
+        return vec![Finding::warn(
+            "Could not read effective capabilities",
+            "Run the daemon as root or with CAP_NET_ADMIN and CAP_BPF",
+        )];
+    };
+    let has = |cap: u32| caps & (1 << cap) != 0;
+
+    let net_admin = if has(CAP_NET_ADMIN) {
+        Finding::ok("CAP_NET_ADMIN available")
+    } else {
+        Finding::fail(
+            "CAP_NET_ADMIN missing: routes, qdiscs and the tunnel cannot be changed",
+            "Run as root, or grant it with AmbientCapabilities=CAP_NET_ADMIN",
+        )
+    };
+    let needs_sys_admin = kernel.is_some_and(|version| version < CAP_BPF_KERNEL);
+    let bpf = if has(CAP_SYS_ADMIN) || (!needs_sys_admin && has(CAP_BPF)) {
+        Finding::ok("Capabilities for loading eBPF programs available")
+    } else if needs_sys_admin {
+        Finding::fail(
+            "CAP_SYS_ADMIN missing: this kernel requires it to load eBPF programs",
+            "Run as root, or grant it with AmbientCapabilities=CAP_SYS_ADMIN",
+        )
+    } else {
+        Finding::fail(
+            "CAP_BPF missing: the traffic detector cannot be loaded",
+            "Run as root, or grant it with AmbientCapabilities=CAP_BPF CAP_PERFMON",
+        )
+    };
+    vec![net_admin, bpf]
+}
+
+/// Check that the clsact qdisc (sch_ingress) is available
+fn check_clsact(kernel: Option<&str>) -> Finding {
+    let loaded = Path::new("/sys/module/sch_ingress").exists();
+    let available = kernel.is_some_and(|release| {
+        let modules = Path::new("/lib/modules").join(release);
+        ["modules.builtin", "modules.dep"].iter().any(|index| {
+            std::fs::read_to_string(modules.join(index))
+                .is_ok_and(|contents| contents.contains("/sch_ingress.ko"))
+        })
+    });
+    if loaded || available {
+        Finding::ok("clsact qdisc available (sch_ingress)")
+    } else {
+        Finding::warn(
+            "sch_ingress module not found: the clsact qdisc may be unavailable",
+            "Enable CONFIG_NET_SCH_INGRESS or install the kernel's extra modules",
+        )
+    }
+}
+
+/// Check that the network detection backend is running on the system bus
+async fn check_network_manager(backend: NetworkBackend) -> Finding {
+    let (name, bus_name) = match backend {
+        NetworkBackend::NetworkManager => ("NetworkManager", NM_BUS_NAME),
+        NetworkBackend::Connman => ("ConnMan", CONNMAN_BUS_NAME),
+    };
+    let hint = format!("SSID detection requires {} running on the system bus", name);
+    let connection = match Connection::system().await {
+        Ok(connection) => connection,
+        Err(e) => return Finding::fail(format!("System D-Bus unavailable: {}", e), hint),
+    };
+    let running = async {
+        let proxy = DBusProxy::new(&connection).await?;
+        proxy
+            .name_has_owner(BusName::try_from(bus_name)?)
+            .await
+            .map_err(zbus::Error::from)
+    }
+    .await;
+    match running {
+        Ok(true) => Finding::ok(format!("{} is running", name)),
+        Ok(false) => Finding::fail(format!("{} is not running", name), hint),
+        Err(e) => Finding::warn(format!("Could not query D-Bus: {}", e), hint),
+    }
+}
+
+/// Check that the external tools of the tunnel backend are installed
+fn check_tools(config: Option<&Config>) -> Vec<Finding> {
+    let mut tools = vec![("ip", "iproute2"), ("tc", "iproute2")];
+    match config {
+        Some(config) if config.general.nm_connection.is_some() => {
+            tools.push(("nmcli", "NetworkManager"));
+        }
+        // The native backend talks netlink itself
+        Some(config) if config.native.is_some() => {}
+        _ => tools.extend([("wg", "wireguard-tools"), ("wg-quick", "wireguard-tools")]),
+    }
+    if config.is_some_and(|config| config.probe.as_ref().is_some_and(|p| p.port.is_none())) {
+        tools.push(("ping", "iputils"));
+    }
+    if config.is_some_and(|config| {
+        config.general.kill_switch == KillSwitchMode::All
+            || config.general.hold_traffic
+            || config.general.monitor_routing == MonitorRouting::Fwmark
+    }) {
+        tools.push(("nft", "nftables"));
+    }
+
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    tools
+        .into_iter()
+        .map(|(tool, package)| match find_in_path(tool, &path) {
+            Some(found) => Finding::ok(format!("{} found at {}", tool, found.display())),
+            None => Finding::fail(
+                format!("{} not found in PATH", tool),
+                format!("Install {}", package),
+            ),
+        })
+        .collect()
+}
+
+/// Locate an executable in a PATH-style list of directories
+fn find_in_path(name: &str, path: &std::ffi::OsStr) -> Option<PathBuf> {
+    std::env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Major and minor version from a kernel release such as "6.1.0-13-amd64"
+fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cap_eff() {
+        let status = "Name:\tcat\nCapInh:\t0000000000000000\nCapEff:\t000001ffffffffff\n";
+        assert_eq!(parse_cap_field(status, "CapEff"), Some(0x1ff_ffff_ffff));
+        assert_eq!(parse_cap_field("Name:\tcat\n", "CapEff"), None);
+    }
+
+    #[test]
+    fn test_parse_kernel_version() {
+        assert_eq!(parse_kernel_version("6.1.0-13-amd64"), Some((6, 1)));
+        assert_eq!(parse_kernel_version("5.15.0"), Some((5, 15)));
+        assert_eq!(parse_kernel_version("4.19.276+"), Some((4, 19)));
+        assert_eq!(parse_kernel_version("unknown"), None);
+    }
+
+    #[test]
+    fn test_check_kernel() {
+        assert_eq!(check_kernel(Some("6.1.0"), Some((6, 1))).status, Status::Ok);
+        assert_eq!(
+            check_kernel(Some("4.19.0"), Some((4, 19))).status,
+            Status::Fail
+        );
+        assert_eq!(check_kernel(None, None).status, Status::Warn);
+    }
+
+    #[test]
+    fn test_find_in_path() {
+        let dir = std::env::temp_dir().join(format!("wg-ondemand-doctor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("wg"), "").unwrap();
+        let path = std::env::join_paths(["/nonexistent", dir.to_str().unwrap()]).unwrap();
+
+        assert_eq!(find_in_path("wg", &path), Some(dir.join("wg")));
+        assert_eq!(find_in_path("wg-quick", &path), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -7,14 +7,33 @@
 //! from the ringbuffer.
 
 use crate::config::parse_cidr;
+use crate::error::EbpfError;
+use crate::traffic_monitor::MAX_SOURCE_RANGES;
+use crate::types::TrafficEvent;
 use anyhow::{Context, Result};
 use aya::maps::RingBuf;
 use aya::{
     include_bytes_aligned,
-    maps::{Array, MapData},
-    programs::{tc::SchedClassifierLinkId, SchedClassifier, TcAttachType},
+    maps::{Array, HashMap, MapData, PerCpuArray, PerCpuValues},
+    programs::{
+        tc::{self, SchedClassifierLinkId, TcOptions},
+        SchedClassifier, TcAttachType,
+    },
+    util::nr_cpus,
     Bpf,
 };
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use wg_ondemand_common::{
+    EMPTY_SENTINEL, SETTING_DROP_MATCHED, SETTING_SOURCE_FILTER, SETTING_TUNNEL_IFINDEX,
+    SETTING_TUNNEL_MARK, WG_BYTES_RX, WG_BYTES_TX,
+};
+
+/// Byte counter programs for the WireGuard interface and their TC hooks
+const COUNTER_PROGRAMS: [(&str, TcAttachType); 2] = [
+    ("wg_ondemand_count_ingress", TcAttachType::Ingress),
+    ("wg_ondemand_count_egress", TcAttachType::Egress),
+];
 
 /// Validates that the network interface exists on the system.
 /// This prevents TOCTOU races where an interface could disappear between detection and use.
@@ -24,56 +43,176 @@ fn validate_interface_exists(interface: &str) -> Result<()> {
     Ok(())
 }
 
+/// Prefix shared by the names of all our TC programs
+const PROGRAM_PREFIX: &str = "wg_ondemand_";
+
+/// Find our filters in `tc filter show` output
+///
+/// Returns the (pref, handle) of each filter running one of our programs, only
+/// those at `priority` unless it is 0. Other filters on the interface are left
+/// alone.
+fn parse_stale_filters(output: &str, priority: u16) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter(|line| line.contains(PROGRAM_PREFIX))
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            let mut value_of = |key: &str| {
+                tokens
+                    .by_ref()
+                    .skip_while(|t| *t != key)
+                    .nth(1)
+                    .map(String::from)
+            };
+            let pref = value_of("pref")?;
+            let handle = value_of("handle")?;
+            Some((pref, handle))
+        })
+        .filter(|(pref, _)| priority == 0 || *pref == priority.to_string())
+        .collect()
+}
+
+/// Qdisc holding the TC hooks of an interface, per `tc qdisc show`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HookQdisc {
+    /// clsact, with both ingress and egress hooks
+    Clsact,
+    /// Legacy ingress qdisc, occupying the same handle without an egress hook
+    Ingress,
+    /// Neither
+    Missing,
+}
+
+/// Find the hook qdisc in `tc qdisc show dev X` output
+fn parse_hook_qdisc(output: &str) -> HookQdisc {
+    let kinds = || {
+        output
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(1))
+    };
+    if kinds().any(|kind| kind == "clsact") {
+        HookQdisc::Clsact
+    } else if kinds().any(|kind| kind == "ingress") {
+        HookQdisc::Ingress
+    } else {
+        HookQdisc::Missing
+    }
+}
+
+/// Run `tc` with `args` and return its output, if it succeeded
+fn tc_output(args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("tc").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Make sure `interface` has a clsact qdisc, returning whether it was created
+/// (rather than already there, e.g. set up by another TC user)
+fn ensure_clsact(interface: &str) -> Result<bool> {
+    match tc::qdisc_add_clsact(interface) {
+        Ok(()) => {
+            tracing::debug!("Created clsact qdisc on {}", interface);
+            Ok(true)
+        }
+        Err(e) if e.raw_os_error() == Some(libc::EEXIST) => {
+            let qdiscs = tc_output(&["qdisc", "show", "dev", interface]).unwrap_or_default();
+            if parse_hook_qdisc(&qdiscs) == HookQdisc::Ingress {
+                anyhow::bail!(
+                    "{} has an ingress qdisc, which has no egress hook; remove it \
+                    (`tc qdisc del dev {} ingress`) and re-add its filters under clsact",
+                    interface,
+                    interface
+                );
+            }
+            tracing::debug!("Using the existing clsact qdisc on {}", interface);
+            Ok(false)
+        }
+        Err(e) => {
+            // Attaching reports the actual problem
+            tracing::debug!("clsact qdisc on {}: {}", interface, e);
+            Ok(false)
+        }
+    }
+}
+
+/// Remove the clsact qdisc we created on `interface`, unless others added
+/// filters to it meanwhile
+fn remove_clsact(interface: &str) {
+    let in_use = ["ingress", "egress"].iter().any(|direction| {
+        tc_output(&["filter", "show", "dev", interface, direction])
+            .is_some_and(|filters| !filters.trim().is_empty())
+    });
+    if in_use {
+        tracing::debug!(
+            "Leaving the clsact qdisc on {}, other filters use it",
+            interface
+        );
+        return;
+    }
+    if tc_output(&["qdisc", "del", "dev", interface, "clsact"]).is_some() {
+        tracing::debug!("Removed the clsact qdisc from {}", interface);
+    } else {
+        // Usually gone with the interface
+        tracing::debug!("clsact qdisc on {} not removed", interface);
+    }
+}
+
 /// Clean up any stale eBPF programs from previous daemon crashes.
 /// If the daemon was killed with SIGKILL or crashed, the Drop implementation doesn't run,
 /// leaving eBPF programs attached to the interface. This cleanup ensures a clean slate.
-fn cleanup_stale_ebpf(interface: &str) -> Result<()> {
+///
+/// With a fixed `priority` only filters at that priority are removed, leaving
+/// those of instances running for other tunnels.
+fn cleanup_stale_ebpf(interface: &str, direction: &str, priority: u16) -> Result<()> {
     use std::process::Command;
 
-    log::debug!("Checking for stale eBPF programs on {}", interface);
+    tracing::debug!(
+        "Checking for stale eBPF programs on {} {}",
+        interface,
+        direction
+    );
 
-    // List existing TC filters on egress
+    // List existing TC filters
     let output = Command::new("tc")
-        .args(["filter", "show", "dev", interface, "egress"])
+        .args(["filter", "show", "dev", interface, direction])
         .output()
         .context("Failed to list TC filters")?;
 
     if !output.status.success() {
         // Interface might not have a qdisc yet, which is fine
-        log::debug!("No TC filters found on {} (this is normal)", interface);
+        tracing::debug!("No TC filters found on {} (this is normal)", interface);
         return Ok(());
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-
-    // Check if our program is already attached (look for common eBPF filter indicators)
-    // TC filter output contains "direct-action" and "not_in_hw" for eBPF programs
-    if stdout.contains("direct-action") || stdout.contains("bpf") {
-        log::warn!(
-            "Found stale TC filters on {}, cleaning up from previous daemon instance",
-            interface
-        );
+    let stale = parse_stale_filters(&String::from_utf8_lossy(&output.stdout), priority);
+    if stale.is_empty() {
+        tracing::debug!("No stale eBPF programs found on {}", interface);
+        return Ok(());
+    }
 
-        // Delete all egress filters (this removes all TC filters, not just ours)
-        // This is safe because we're about to attach our own filter
+    tracing::warn!(
+        "Found {} stale TC filter(s) on {} {}, cleaning up from previous daemon instance",
+        stale.len(),
+        interface,
+        direction
+    );
+    for (pref, handle) in stale {
         let status = Command::new("tc")
-            .args(["filter", "del", "dev", interface, "egress"])
+            .args([
+                "filter", "del", "dev", interface, direction, "protocol", "all", "pref", &pref,
+                "handle", &handle, "bpf",
+            ])
             .status()
-            .context("Failed to delete TC filters")?;
-
-        if status.success() {
-            log::info!(
-                "Successfully cleaned up stale eBPF programs on {}",
-                interface
-            );
-        } else {
-            log::warn!(
-                "Failed to clean up stale eBPF programs on {}, will attempt to attach anyway",
+            .context("Failed to delete TC filter")?;
+        if !status.success() {
+            tracing::warn!(
+                "Failed to remove stale TC filter (pref {}) on {}, will attempt to attach anyway",
+                pref,
                 interface
             );
         }
-    } else {
-        log::debug!("No stale eBPF programs found on {}", interface);
     }
 
     Ok(())
@@ -82,26 +221,36 @@ fn cleanup_stale_ebpf(interface: &str) -> Result<()> {
 /// Manages the lifecycle of the eBPF program
 pub struct EbpfManager {
     ebpf: Bpf,
-    interface: String,
-    link_id: Option<SchedClassifierLinkId>,
+    links: Vec<(String, SchedClassifierLinkId)>,
     ringbuf: Option<RingBuf<MapData>>,
+    counters_loaded: bool,
+    counter_links: Vec<(&'static str, SchedClassifierLinkId)>,
+    counter_interface: Option<String>,
+    /// Interfaces whose clsact qdisc we created, and remove again on detach
+    owned_qdiscs: HashSet<String>,
+    tc_priority: u16,
+    tc_handle: u32,
 }
 
 impl EbpfManager {
     /// Load eBPF program and configure subnet map
-    pub fn load(interface: &str, subnets: &[String]) -> Result<Self> {
+    ///
+    /// The first of `interfaces` must exist; the others may come and go (docks,
+    /// USB adapters).
+    pub fn load(interfaces: &[String], subnets: &[String]) -> Result<Self, EbpfError> {
         // Validate interface exists immediately before loading (prevents TOCTOU race)
-        validate_interface_exists(interface)?;
+        if let Some(primary) = interfaces.first() {
+            validate_interface_exists(primary)?;
+        }
 
-        // Clean up any stale eBPF programs from previous daemon crashes
-        cleanup_stale_ebpf(interface)?;
-        // Load eBPF program from embedded bytes
-        let mut ebpf = Bpf::load(include_bytes_aligned!(
-            "../../target/bpfel-unknown-none/release/wg-ondemand-ebpf"
-        ))
+        // Load eBPF program from embedded bytes (copied to OUT_DIR by build.rs)
+        let mut ebpf = Bpf::load(include_bytes_aligned!(concat!(
+            env!("OUT_DIR"),
+            "/wg-ondemand-ebpf"
+        )))
         .context("Failed to load eBPF program")?;
 
-        log::info!("Loaded eBPF program successfully");
+        tracing::info!("Loaded eBPF program successfully");
 
         // Configure subnet map
         let mut subnet_map: Array<_, [u32; 2]> = Array::try_from(
@@ -109,18 +258,16 @@ impl EbpfManager {
                 .context("Failed to get SUBNETS map")?,
         )?;
 
-        // Sentinel value for empty slots (must match eBPF code)
-        const EMPTY_SENTINEL: u32 = 0xFFFFFFFF;
-
         for (i, subnet_cidr) in subnets.iter().enumerate() {
             if i >= 16 {
-                log::warn!("Maximum 16 subnets supported, ignoring extras");
+                tracing::warn!("Maximum 16 subnets supported, ignoring extras");
                 break;
             }
 
-            let (network, mask) = parse_cidr(subnet_cidr)?;
+            let (network, mask) = parse_cidr(subnet_cidr)
+                .with_context(|| format!("Invalid subnet {}", subnet_cidr))?;
             subnet_map.set(i as u32, [network, mask], 0)?;
-            log::info!(
+            tracing::info!(
                 "Configured subnet {}: {} (network=0x{:08x} mask=0x{:08x})",
                 i,
                 subnet_cidr,
@@ -146,23 +293,65 @@ impl EbpfManager {
             .load()
             .context("Failed to load eBPF program into kernel")?;
 
-        log::info!("Loaded eBPF program into kernel");
+        tracing::info!("Loaded eBPF program into kernel");
 
         Ok(Self {
             ebpf,
-            interface: interface.to_string(),
-            link_id: None,
+            links: Vec::new(),
             ringbuf: None,
+            counters_loaded: false,
+            counter_links: Vec::new(),
+            counter_interface: None,
+            owned_qdiscs: HashSet::new(),
+            tc_priority: 0,
+            tc_handle: 0,
         })
     }
 
-    /// Attach eBPF program to TC egress hook
-    pub fn attach(&mut self) -> Result<()> {
-        if self.link_id.is_some() {
-            log::warn!("eBPF program already attached");
+    /// Remove the TC filters a crashed instance left on `interfaces`, also on
+    /// those not attached to right away (see [`with_tc_filter`](Self::with_tc_filter))
+    pub fn remove_stale_filters(&self, interfaces: &[String]) -> Result<(), EbpfError> {
+        for interface in interfaces {
+            if validate_interface_exists(interface).is_ok() {
+                cleanup_stale_ebpf(interface, "egress", self.tc_priority)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Attach the TC filters at `priority` with `handle` instead of letting the
+    /// kernel choose (0 for either keeps the kernel's choice), to order them
+    /// deterministically against other TC users such as Cilium or shapers
+    pub fn with_tc_filter(mut self, priority: u16, handle: u32) -> Self {
+        self.tc_priority = priority;
+        self.tc_handle = handle;
+        self
+    }
+
+    fn tc_options(&self) -> TcOptions {
+        TcOptions {
+            priority: self.tc_priority,
+            handle: self.tc_handle,
+        }
+    }
+
+    /// Attach eBPF program to TC egress hook of `interface`
+    #[tracing::instrument(name = "ebpf_attach", skip(self))]
+    pub fn attach(&mut self, interface: &str) -> Result<(), EbpfError> {
+        if self.is_attached_to(interface) {
+            tracing::warn!("eBPF program already attached");
             return Ok(());
         }
 
+        // A replugged or re-created interface comes without the clsact qdisc
+        if ensure_clsact(interface)? {
+            self.owned_qdiscs.insert(interface.to_string());
+        }
+        // Filters of a crashed instance would collide with a fixed priority and
+        // handle, or report traffic twice
+        cleanup_stale_ebpf(interface, "egress", self.tc_priority)?;
+
+        let options = self.tc_options();
         // Get TC program (already loaded when Bpf object was created)
         let program: &mut SchedClassifier = self
             .ebpf
@@ -172,15 +361,20 @@ impl EbpfManager {
             .context("Failed to convert to SchedClassifier")?;
 
         // Attach to TC egress hook and store the link ID
-        let link_id = match program.attach(&self.interface, TcAttachType::Egress) {
+        let link_id = match program.attach_with_options(interface, TcAttachType::Egress, options) {
             Ok(id) => id,
             Err(e) => {
-                log::error!("TC attach error: {:?}", e);
-                anyhow::bail!("Failed to attach to TC egress on {}: {}", self.interface, e);
+                tracing::error!("TC attach error: {:?}", e);
+                return Err(anyhow::anyhow!(
+                    "Failed to attach to TC egress on {}: {}",
+                    interface,
+                    e
+                )
+                .into());
             }
         };
 
-        self.link_id = Some(link_id);
+        self.links.push((interface.to_string(), link_id));
 
         // Cache ring buffer reference on first attach
         // take_map() can only be called once, so only do it if not already cached
@@ -194,28 +388,45 @@ impl EbpfManager {
             self.ringbuf = Some(rb);
         }
 
-        log::info!("Attached eBPF program to {} egress", self.interface);
+        tracing::info!("Attached eBPF program to {} egress", interface);
         Ok(())
     }
 
-    /// Detach eBPF program from TC hook
-    pub fn detach(&mut self) -> Result<()> {
-        if let Some(link_id) = self.link_id.take() {
-            let program: &mut SchedClassifier = self
-                .ebpf
-                .program_mut("wg_ondemand_tc")
-                .context("Failed to find program")?
-                .try_into()
-                .context("Failed to convert to SchedClassifier")?;
+    /// Detach eBPF program from the TC hooks of all interfaces
+    pub fn detach(&mut self) -> Result<(), EbpfError> {
+        let interfaces: Vec<String> = self.links.iter().map(|(name, _)| name.clone()).collect();
+        let mut result = Ok(());
+        for interface in interfaces {
+            if let Err(e) = self.detach_from(&interface) {
+                result = Err(e);
+            }
+        }
+        result
+    }
 
-            program
-                .detach(link_id)
-                .context("Failed to detach eBPF program")?;
+    /// Detach eBPF program from the TC hook of `interface`
+    pub fn detach_from(&mut self, interface: &str) -> Result<(), EbpfError> {
+        let Some(index) = self.links.iter().position(|(name, _)| name == interface) else {
+            return Ok(());
+        };
+        let (_, link_id) = self.links.remove(index);
+        let program: &mut SchedClassifier = self
+            .ebpf
+            .program_mut("wg_ondemand_tc")
+            .context("Failed to find program")?
+            .try_into()
+            .context("Failed to convert to SchedClassifier")?;
 
-            // Keep ringbuf cached - take_map() can only be called once per BPF object lifetime
-            // The ringbuf reference remains valid even when the program is detached
+        program
+            .detach(link_id)
+            .context("Failed to detach eBPF program")?;
 
-            log::info!("Detached eBPF program from {}", self.interface);
+        // Keep ringbuf cached - take_map() can only be called once per BPF object lifetime
+        // The ringbuf reference remains valid even when the program is detached
+
+        tracing::info!("Detached eBPF program from {}", interface);
+        if self.owned_qdiscs.remove(interface) {
+            remove_clsact(interface);
         }
         Ok(())
     }
@@ -228,14 +439,279 @@ impl EbpfManager {
         self.ringbuf.as_mut()
     }
 
-    /// Check if eBPF program is currently attached
+    /// Drain the traffic events reported since the last call
+    pub fn read_events(&mut self) -> Vec<TrafficEvent> {
+        let mut events = Vec::new();
+        if let Some(rb) = self.poll_events() {
+            while let Some(data) = rb.next() {
+                if data.len() == std::mem::size_of::<TrafficEvent>() {
+                    // Use read_unaligned to handle potentially unaligned data from ringbuffer
+                    // This prevents undefined behavior on architectures with strict alignment requirements
+                    events.push(unsafe {
+                        std::ptr::read_unaligned(data.as_ptr() as *const TrafficEvent)
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    /// Check if eBPF program is currently attached to any interface
     pub fn is_attached(&self) -> bool {
-        self.link_id.is_some()
+        !self.links.is_empty()
+    }
+
+    /// Check if eBPF program is currently attached to `interface`
+    pub fn is_attached_to(&self, interface: &str) -> bool {
+        self.links.iter().any(|(name, _)| name == interface)
+    }
+
+    /// Drop packets to the target subnets after reporting them (kill switch)
+    ///
+    /// The program is only attached while the tunnel is down, so this keeps the
+    /// triggering traffic from leaving in plaintext during activation.
+    pub fn set_kill_switch(&mut self, enabled: bool) -> Result<(), EbpfError> {
+        let mut settings: Array<_, u32> = Array::try_from(
+            self.ebpf
+                .map_mut("SETTINGS")
+                .context("Failed to get SETTINGS map")?,
+        )?;
+        settings.set(SETTING_DROP_MATCHED, u32::from(enabled), 0)?;
+        Ok(())
+    }
+
+    /// Let packets leaving through the WireGuard interface `ifindex`, or carrying
+    /// its firewall mark `fwmark` (its encapsulated packets), pass unreported:
+    /// they are already tunneled. 0 disables either check.
+    pub fn set_tunnel(&mut self, ifindex: u32, fwmark: u32) -> Result<(), EbpfError> {
+        let mut settings: Array<_, u32> = Array::try_from(
+            self.ebpf
+                .map_mut("SETTINGS")
+                .context("Failed to get SETTINGS map")?,
+        )?;
+        settings.set(SETTING_TUNNEL_IFINDEX, ifindex, 0)?;
+        settings.set(SETTING_TUNNEL_MARK, fwmark, 0)?;
+        Ok(())
+    }
+
+    /// Only report traffic from `ranges` (CIDR), or from any source if None
+    ///
+    /// An empty list lets nothing through. Ranges beyond
+    /// [`MAX_SOURCE_RANGES`] are ignored.
+    pub fn set_source_filter(&mut self, ranges: Option<&[String]>) -> Result<(), EbpfError> {
+        let mut sources: Array<_, [u32; 2]> = Array::try_from(
+            self.ebpf
+                .map_mut("SOURCES")
+                .context("Failed to get SOURCES map")?,
+        )?;
+        let enabled = ranges.is_some();
+        let ranges = ranges.unwrap_or_default();
+        for (i, range) in ranges.iter().take(MAX_SOURCE_RANGES).enumerate() {
+            let (network, mask) =
+                parse_cidr(range).with_context(|| format!("Invalid source range {}", range))?;
+            sources.set(i as u32, [network, mask], 0)?;
+        }
+        for i in ranges.len().min(MAX_SOURCE_RANGES)..MAX_SOURCE_RANGES {
+            sources.set(i as u32, [EMPTY_SENTINEL, EMPTY_SENTINEL], 0)?;
+        }
+
+        let mut settings: Array<_, u32> = Array::try_from(
+            self.ebpf
+                .map_mut("SETTINGS")
+                .context("Failed to get SETTINGS map")?,
+        )?;
+        settings.set(SETTING_SOURCE_FILTER, u32::from(enabled), 0)?;
+        Ok(())
+    }
+
+    /// Never report traffic to `ip`, e.g. a peer endpoint inside the subnets
+    ///
+    /// Returns false if it was already excluded.
+    pub fn exclude_destination(&mut self, ip: Ipv4Addr) -> Result<bool, EbpfError> {
+        let mut excluded: HashMap<_, u32, u8> = HashMap::try_from(
+            self.ebpf
+                .map_mut("EXCLUDED")
+                .context("Failed to get EXCLUDED map")?,
+        )?;
+        let key = u32::from(ip);
+        if excluded.get(&key, 0).is_ok() {
+            return Ok(false);
+        }
+        excluded.insert(key, 1, 0)?;
+        Ok(true)
+    }
+}
+
+impl EbpfManager {
+    /// Load the byte counter programs into the kernel without attaching them
+    ///
+    /// [`attach_counters`](Self::attach_counters) does this on first use; doing
+    /// it ahead allows dropping the capabilities needed to load programs.
+    pub fn load_counters(&mut self) -> Result<(), EbpfError> {
+        if self.counters_loaded {
+            return Ok(());
+        }
+        for (name, _) in COUNTER_PROGRAMS {
+            let program: &mut SchedClassifier = self
+                .ebpf
+                .program_mut(name)
+                .with_context(|| format!("Failed to find eBPF program '{}'", name))?
+                .try_into()
+                .context("Failed to convert to SchedClassifier")?;
+            program
+                .load()
+                .with_context(|| format!("Failed to load eBPF program '{}'", name))?;
+        }
+        self.counters_loaded = true;
+        Ok(())
+    }
+
+    /// Attach byte counters to both directions of the WireGuard interface
+    ///
+    /// The counters start from zero on every attach. The interface gets a clsact
+    /// qdisc if it has none, since it is usually created just before this call.
+    #[tracing::instrument(name = "ebpf_attach_counters", skip(self))]
+    pub fn attach_counters(&mut self, interface: &str) -> Result<(), EbpfError> {
+        if !self.counter_links.is_empty() {
+            return Ok(());
+        }
+        validate_interface_exists(interface)?;
+
+        if ensure_clsact(interface)? {
+            self.owned_qdiscs.insert(interface.to_string());
+        }
+        self.counter_interface = Some(interface.to_string());
+        // A tunnel left up by a crashed instance still has its counters attached
+        for direction in ["ingress", "egress"] {
+            cleanup_stale_ebpf(interface, direction, self.tc_priority)?;
+        }
+
+        let cpus = nr_cpus().context("Failed to count CPUs")?;
+        let mut counters: PerCpuArray<_, u64> = PerCpuArray::try_from(
+            self.ebpf
+                .map_mut("WG_BYTES")
+                .context("Failed to get WG_BYTES map")?,
+        )?;
+        for index in [WG_BYTES_RX, WG_BYTES_TX] {
+            let zeros = PerCpuValues::try_from(vec![0u64; cpus])
+                .context("Failed to build per-CPU values")?;
+            counters.set(index, zeros, 0)?;
+        }
+
+        self.load_counters()?;
+        for (name, attach_type) in COUNTER_PROGRAMS {
+            let options = self.tc_options();
+            let program: &mut SchedClassifier = self
+                .ebpf
+                .program_mut(name)
+                .with_context(|| format!("Failed to find eBPF program '{}'", name))?
+                .try_into()
+                .context("Failed to convert to SchedClassifier")?;
+            match program.attach_with_options(interface, attach_type, options) {
+                Ok(link_id) => self.counter_links.push((name, link_id)),
+                Err(e) => {
+                    self.detach_counters();
+                    return Err(anyhow::anyhow!(
+                        "Failed to attach byte counter to {}: {}",
+                        interface,
+                        e
+                    )
+                    .into());
+                }
+            }
+        }
+
+        tracing::info!("Attached eBPF byte counters to {}", interface);
+        Ok(())
+    }
+
+    /// Detach the WireGuard interface byte counters
+    ///
+    /// Errors are only logged: the counters go away with the interface anyway.
+    pub fn detach_counters(&mut self) {
+        let interface = self.counter_interface.take();
+        for (name, link_id) in self.counter_links.drain(..) {
+            let result = self
+                .ebpf
+                .program_mut(name)
+                .and_then(|program| <&mut SchedClassifier>::try_from(program).ok())
+                .map(|program| program.detach(link_id));
+            match result {
+                Some(Ok(())) => {}
+                Some(Err(e)) => tracing::debug!("Failed to detach {}: {}", name, e),
+                None => tracing::debug!("eBPF program '{}' not found", name),
+            }
+        }
+        if let Some(interface) = interface.filter(|iface| self.owned_qdiscs.remove(iface)) {
+            remove_clsact(&interface);
+        }
+    }
+
+    /// Check if the byte counters are attached
+    pub fn counters_attached(&self) -> bool {
+        !self.counter_links.is_empty()
+    }
+
+    /// Read the byte counters, summed across CPUs
+    /// Returns (rx_bytes, tx_bytes) since the counters were attached
+    pub fn read_counters(&self) -> Result<(u64, u64), EbpfError> {
+        let counters: PerCpuArray<_, u64> = PerCpuArray::try_from(
+            self.ebpf
+                .map("WG_BYTES")
+                .context("Failed to get WG_BYTES map")?,
+        )?;
+        let sum = |index: u32| -> Result<u64> { Ok(counters.get(&index, 0)?.iter().sum()) };
+        Ok((sum(WG_BYTES_RX)?, sum(WG_BYTES_TX)?))
     }
 }
 
 impl Drop for EbpfManager {
     fn drop(&mut self) {
+        self.detach_counters();
         let _ = self.detach();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stale_filters() {
+        let output = "\
+filter protocol all pref 49151 bpf chain 0
+filter protocol all pref 49151 bpf chain 0 handle 0x1 other_prog direct-action not_in_hw id 7 tag 0123 jited
+filter protocol all pref 49152 bpf chain 0
+filter protocol all pref 49152 bpf chain 0 handle 0x1 wg_ondemand_tc direct-action not_in_hw id 42 tag 4567 jited
+";
+        assert_eq!(
+            parse_stale_filters(output, 0),
+            vec![("49152".to_string(), "0x1".to_string())]
+        );
+        assert_eq!(
+            parse_stale_filters(output, 49152),
+            vec![("49152".to_string(), "0x1".to_string())]
+        );
+        // Another tunnel's instance
+        assert!(parse_stale_filters(output, 40001).is_empty());
+        assert!(parse_stale_filters("", 0).is_empty());
+    }
+
+    #[test]
+    fn test_parse_hook_qdisc() {
+        let output = "\
+qdisc fq_codel 0: root refcnt 2 limit 10240p flows 1024 quantum 1514 target 5ms interval 100ms memory_limit 32Mb ecn drop_batch 64
+qdisc clsact ffff: parent ffff:fff1
+";
+        assert_eq!(parse_hook_qdisc(output), HookQdisc::Clsact);
+        assert_eq!(
+            parse_hook_qdisc("qdisc ingress ffff: parent ffff:fff1 ----------------\n"),
+            HookQdisc::Ingress
+        );
+        assert_eq!(
+            parse_hook_qdisc("qdisc noqueue 0: root refcnt 2\n"),
+            HookQdisc::Missing
+        );
+        assert_eq!(parse_hook_qdisc(""), HookQdisc::Missing);
+    }
+}
@@ -0,0 +1,337 @@
+// Peer endpoint selection and failover
+
+//! Peer endpoint management
+//!
+//! Lets the tunnel fall back to alternate endpoints (e.g. a second port or a
+//! backup address) when the primary one doesn't work, or pick the one with the
+//! lowest round-trip time, by rewriting the peer endpoint of the running
+//! interface via netlink.
+
+use crate::probe;
+use crate::types::Config;
+use crate::wg_quick::{self, WgQuickConfig};
+use anyhow::{Context, Result};
+use futures_util::future::join_all;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use wireguard_control::{Backend, Device, DeviceUpdate, InterfaceName, Key, PeerConfigBuilder};
+
+/// Ordered list of candidate endpoints with the one currently in use
+#[derive(Debug, Clone)]
+pub struct EndpointList {
+    addresses: Vec<String>,
+    current: usize,
+}
+
+impl EndpointList {
+    /// Create a list from `host:port` addresses, starting with the first (primary)
+    pub fn new(addresses: Vec<String>) -> Self {
+        Self {
+            addresses,
+            current: 0,
+        }
+    }
+
+    /// Address of the endpoint currently in use
+    pub fn current(&self) -> &str {
+        &self.addresses[self.current]
+    }
+
+    /// Switch to the next endpoint, wrapping around to the primary
+    pub fn advance(&mut self) {
+        self.current = (self.current + 1) % self.addresses.len();
+    }
+
+    /// Go back to the primary endpoint
+    pub fn reset(&mut self) {
+        self.current = 0;
+    }
+
+    /// All candidate addresses, primary first
+    pub fn addresses(&self) -> &[String] {
+        &self.addresses
+    }
+
+    /// Use the endpoint at `index` (ignored if out of range)
+    pub fn select(&mut self, index: usize) {
+        if index < self.addresses.len() {
+            self.current = index;
+        }
+    }
+
+    /// Whether there is more than one endpoint to fail over between
+    pub fn has_alternates(&self) -> bool {
+        self.addresses.len() > 1
+    }
+}
+
+/// Peer endpoint declared by hostname in a wg-quick config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostnameEndpoint {
+    /// Peer public key (base64)
+    pub public_key: String,
+    /// Endpoint as `host:port`
+    pub address: String,
+}
+
+/// Check whether the host part of a `host:port` address is a name rather than an IP literal
+fn is_hostname(address: &str) -> bool {
+    address
+        .rsplit_once(':')
+        .map(|(host, _)| {
+            host.trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .is_err()
+        })
+        .unwrap_or(false)
+}
+
+/// Peer endpoints (`host:port`) known from the config
+///
+/// Collected from `[endpoints]`, inline `[native]` peers and the wg-quick config
+/// in use. NetworkManager connections are not inspected.
+pub fn configured(config: &Config) -> Vec<String> {
+    let mut endpoints: Vec<String> = config
+        .endpoints
+        .iter()
+        .flat_map(|endpoints| endpoints.addresses.iter().cloned())
+        .collect();
+    if let Some(native) = &config.native {
+        endpoints.extend(native.peers.iter().filter_map(|peer| peer.endpoint.clone()));
+    }
+    if let Some(path) = wg_quick::config_in_use(config) {
+        match wg_quick::load(&path) {
+            Ok(wg_config) => {
+                endpoints.extend(wg_config.peers.into_iter().filter_map(|peer| peer.endpoint))
+            }
+            Err(e) => tracing::debug!("No endpoints from {:?}: {:#}", path, e),
+        }
+    }
+    endpoints
+}
+
+/// Peers whose endpoint is given as a hostname in a wg-quick config
+///
+/// The kernel only knows the address the hostname resolved to when the interface
+/// was configured, so these endpoints need to be re-resolved to follow dynamic DNS.
+pub fn hostname_endpoints(config: &WgQuickConfig) -> Vec<HostnameEndpoint> {
+    config
+        .peers
+        .iter()
+        .filter_map(|peer| {
+            let address = peer.endpoint.as_ref().filter(|a| is_hostname(a))?;
+            Some(HostnameEndpoint {
+                public_key: peer.public_key.clone(),
+                address: address.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Check that an endpoint address has the form `host:port`
+///
+/// # Errors
+///
+/// Returns an error if the host is empty or the port is not a valid port number.
+pub fn validate_address(address: &str) -> Result<()> {
+    let (host, port) = address
+        .rsplit_once(':')
+        .with_context(|| format!("Endpoint '{}' must have the form host:port", address))?;
+    if host.is_empty() {
+        anyhow::bail!("Endpoint '{}' has an empty host", address);
+    }
+    port.parse::<u16>()
+        .with_context(|| format!("Endpoint '{}' has an invalid port", address))?;
+    Ok(())
+}
+
+/// Resolve an endpoint address, looking up hostnames via DNS
+pub async fn resolve(address: &str) -> Result<SocketAddr> {
+    tokio::net::lookup_host(address)
+        .await
+        .with_context(|| format!("Failed to resolve endpoint {}", address))?
+        .next()
+        .with_context(|| format!("Endpoint {} resolved to no addresses", address))
+}
+
+/// Measure the ICMP round-trip time to each address concurrently
+///
+/// Returns the index of the fastest reachable endpoint and its RTT, or None if
+/// none answered within `timeout`.
+pub async fn fastest(addresses: &[String], timeout: Duration) -> Option<(usize, Duration)> {
+    let rtts = join_all(addresses.iter().map(|address| async move {
+        let addr = resolve(address).await?;
+        probe::ping(addr.ip(), timeout).await
+    }))
+    .await;
+
+    for (address, rtt) in addresses.iter().zip(&rtts) {
+        match rtt {
+            Ok(rtt) => tracing::debug!("Endpoint {} RTT: {}ms", address, rtt.as_millis()),
+            Err(e) => tracing::debug!("Endpoint {} unreachable: {:#}", address, e),
+        }
+    }
+
+    best_rtt(&rtts)
+}
+
+/// Pick the index with the lowest successful RTT (earlier entries win ties)
+fn best_rtt<E>(rtts: &[std::result::Result<Duration, E>]) -> Option<(usize, Duration)> {
+    rtts.iter()
+        .enumerate()
+        .filter_map(|(i, rtt)| rtt.as_ref().ok().map(|rtt| (i, *rtt)))
+        .min_by_key(|(_, rtt)| *rtt)
+}
+
+/// Apply `update` to a peer of a running WireGuard interface
+///
+/// If `peer` (base64 public key) is None, the interface must have exactly one peer.
+pub async fn update_peer<F>(interface: &str, peer: Option<&str>, update: F) -> Result<()>
+where
+    F: FnOnce(PeerConfigBuilder) -> PeerConfigBuilder + Send + 'static,
+{
+    let iface_name: InterfaceName = interface
+        .parse()
+        .with_context(|| format!("Invalid interface name: {}", interface))?;
+    let peer = peer
+        .map(|p| Key::from_base64(p).context("Invalid peer public key"))
+        .transpose()?;
+
+    tokio::task::spawn_blocking(move || {
+        let key = match peer {
+            Some(key) => key,
+            None => {
+                let device = Device::get(&iface_name, Backend::Kernel)
+                    .context("Failed to get WireGuard device info")?;
+                match device.peers.as_slice() {
+                    [peer] => peer.config.public_key.clone(),
+                    peers => anyhow::bail!(
+                        "Interface has {} peers, set endpoints.peer to choose one",
+                        peers.len()
+                    ),
+                }
+            }
+        };
+
+        DeviceUpdate::new()
+            .add_peer(update(PeerConfigBuilder::new(&key)))
+            .apply(&iface_name, Backend::Kernel)
+            .context("Failed to update peer")
+    })
+    .await
+    .context("Netlink task panicked")?
+}
+
+/// Set the endpoint of a peer on a running WireGuard interface
+///
+/// If `peer` (base64 public key) is None, the interface must have exactly one peer.
+pub async fn set_peer_endpoint(
+    interface: &str,
+    peer: Option<&str>,
+    endpoint: SocketAddr,
+) -> Result<()> {
+    update_peer(interface, peer, move |builder| {
+        builder.set_endpoint(endpoint)
+    })
+    .await
+    .context("Failed to update peer endpoint")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_list_failover() {
+        let mut list = EndpointList::new(vec![
+            "home.example.com:51820".to_string(),
+            "203.0.113.5:443".to_string(),
+        ]);
+        assert!(list.has_alternates());
+        assert_eq!(list.current(), "home.example.com:51820");
+
+        list.advance();
+        assert_eq!(list.current(), "203.0.113.5:443");
+
+        // Wraps around to the primary
+        list.advance();
+        assert_eq!(list.current(), "home.example.com:51820");
+
+        list.advance();
+        list.reset();
+        assert_eq!(list.current(), "home.example.com:51820");
+    }
+
+    #[test]
+    fn test_best_rtt() {
+        let rtts: Vec<std::result::Result<Duration, ()>> = vec![
+            Err(()),
+            Ok(Duration::from_millis(40)),
+            Ok(Duration::from_millis(12)),
+            Ok(Duration::from_millis(12)),
+        ];
+        assert_eq!(best_rtt(&rtts), Some((2, Duration::from_millis(12))));
+
+        let unreachable: Vec<std::result::Result<Duration, ()>> = vec![Err(()), Err(())];
+        assert_eq!(best_rtt(&unreachable), None);
+    }
+
+    #[test]
+    fn test_endpoint_list_select() {
+        let mut list = EndpointList::new(vec!["a:1".to_string(), "b:2".to_string()]);
+        list.select(1);
+        assert_eq!(list.current(), "b:2");
+        list.select(5);
+        assert_eq!(list.current(), "b:2");
+    }
+
+    #[test]
+    fn test_hostname_endpoints() {
+        let contents = "\
+[Interface]
+PrivateKey = cHJpdmF0ZQ==
+Address = 10.0.0.2/32
+
+[Peer]
+PublicKey = aG9tZQ==
+Endpoint = home.example.com:51820  # dynamic DNS
+AllowedIPs = 192.168.1.0/24
+
+[Peer]
+PublicKey = b2ZmaWNl
+Endpoint = 203.0.113.5:51820
+
+[Peer]
+PublicKey = djY=
+Endpoint = [2001:db8::1]:51820
+";
+        let config = crate::wg_quick::parse(contents).unwrap();
+        assert_eq!(
+            hostname_endpoints(&config),
+            vec![HostnameEndpoint {
+                public_key: "aG9tZQ==".to_string(),
+                address: "home.example.com:51820".to_string(),
+            }]
+        );
+        let interface_only =
+            crate::wg_quick::parse("[Interface]\nEndpoint = x.example:1\n").unwrap();
+        assert!(hostname_endpoints(&interface_only).is_empty());
+    }
+
+    #[test]
+    fn test_validate_address() {
+        assert!(validate_address("home.example.com:51820").is_ok());
+        assert!(validate_address("203.0.113.5:443").is_ok());
+        assert!(validate_address("[2001:db8::1]:51820").is_ok());
+        assert!(validate_address("home.example.com").is_err());
+        assert!(validate_address(":51820").is_err());
+        assert!(validate_address("host:99999").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ip_literal() {
+        let addr = resolve("203.0.113.5:443").await.unwrap();
+        assert_eq!(addr, "203.0.113.5:443".parse().unwrap());
+    }
+}
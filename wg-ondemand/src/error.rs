@@ -0,0 +1,83 @@
+// Library error types
+
+//! Error types
+//!
+//! The public APIs of [`config`](crate::config), [`ebpf_loader`](crate::ebpf_loader),
+//! [`wg_controller`](crate::wg_controller) and [`ssid_monitor`](crate::ssid_monitor)
+//! return one error type per subsystem, so embedders can tell an invalid config from
+//! a failed eBPF attach or an unreachable D-Bus. Each wraps the full error chain
+//! (built with `anyhow` internally) and displays like it; [`WgOndemandError`]
+//! unifies them for callers that deal with several subsystems.
+
+use thiserror::Error;
+
+/// Configuration file missing, unparsable or invalid
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct ConfigError(#[from] anyhow::Error);
+
+/// eBPF program loading, attachment or map access failed
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct EbpfError(#[from] anyhow::Error);
+
+/// WireGuard tunnel control or statistics failed
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct TunnelError(#[from] anyhow::Error);
+
+/// System D-Bus or NetworkManager unavailable or misbehaving
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct DbusError(#[from] anyhow::Error);
+
+#[cfg(feature = "ebpf")]
+impl From<aya::maps::MapError> for EbpfError {
+    fn from(e: aya::maps::MapError) -> Self {
+        Self(e.into())
+    }
+}
+
+impl From<zbus::Error> for DbusError {
+    fn from(e: zbus::Error) -> Self {
+        Self(e.into())
+    }
+}
+
+/// Any error returned by the library
+#[derive(Debug, Error)]
+pub enum WgOndemandError {
+    /// See [`ConfigError`]
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// See [`EbpfError`]
+    #[error(transparent)]
+    Ebpf(#[from] EbpfError),
+    /// See [`TunnelError`]
+    #[error(transparent)]
+    Tunnel(#[from] TunnelError),
+    /// See [`DbusError`]
+    #[error(transparent)]
+    Dbus(#[from] DbusError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_chain_preserved() {
+        let inner = anyhow::anyhow!("no such file").context("Failed to read config file");
+        let err = WgOndemandError::from(ConfigError::from(inner));
+        assert!(matches!(err, WgOndemandError::Config(_)));
+        assert_eq!(err.to_string(), "Failed to read config file");
+
+        // Converting back into anyhow keeps the whole chain
+        let err = anyhow::Error::from(err);
+        assert_eq!(
+            format!("{:#}", err),
+            "Failed to read config file: no such file"
+        );
+        assert!(err.downcast_ref::<WgOndemandError>().is_some());
+    }
+}
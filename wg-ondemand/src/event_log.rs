@@ -0,0 +1,305 @@
+// Persistent session log
+
+//! Session log
+//!
+//! Optionally appends one JSON line per finished tunnel session to a file, recording
+//! when and why the tunnel came up, on which network, how long it stayed up and how
+//! much traffic it carried. Unlike the in-memory [`crate::history`], the log
+//! survives restarts and is meant for long-term auditing (e.g. with `jq`).
+//!
+//! So that it doesn't fill the disk of a long-running router, the log is rotated
+//! like logrotate does: `sessions.jsonl` becomes `sessions.jsonl.1`, the older
+//! files move up by one and those beyond the number to keep are deleted. The
+//! log is rotated before it would grow beyond its size limit or once its first
+//! session is older than the age limit; rotated files not written within the
+//! age limit are deleted.
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A finished tunnel session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionRecord<'a> {
+    /// Unix timestamp when the tunnel came up
+    pub start: u64,
+    /// Unix timestamp when the tunnel went down
+    pub end: u64,
+    /// What brought the tunnel up (traffic destination, "manual", "external", ...)
+    pub trigger: &'a str,
+    /// SSID of the network the session started on
+    pub ssid: Option<&'a str>,
+    /// Bytes received during the session
+    pub rx_bytes: u64,
+    /// Bytes sent during the session
+    pub tx_bytes: u64,
+    /// Why the tunnel went down
+    pub reason: &'a str,
+}
+
+impl SessionRecord<'_> {
+    /// Serialize as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"start\":{},\"end\":{},\"duration_secs\":{},\"trigger\":{},\"ssid\":{},\
+             \"rx_bytes\":{},\"tx_bytes\":{},\"reason\":{}}}",
+            self.start,
+            self.end,
+            self.end.saturating_sub(self.start),
+            json_string(self.trigger),
+            self.ssid
+                .map(json_string)
+                .unwrap_or_else(|| "null".to_string()),
+            self.rx_bytes,
+            self.tx_bytes,
+            json_string(self.reason)
+        )
+    }
+}
+
+/// Quote and escape a string for JSON
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `end` of the first session in a log, i.e. when it was started
+fn first_end(path: &Path) -> Option<u64> {
+    let mut line = String::new();
+    BufReader::new(File::open(path).ok()?)
+        .read_line(&mut line)
+        .ok()?;
+    let value = line.split("\"end\":").nth(1)?;
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    value[..digits].parse().ok()
+}
+
+/// Append-only JSON Lines session log
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    max_age: Option<Duration>,
+}
+
+impl EventLog {
+    /// Log sessions to `path` (created on first write), without rotation
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_bytes: 0,
+            keep: 0,
+            max_age: None,
+        }
+    }
+
+    /// Rotate the log before it grows beyond `max_bytes` (0: no limit) or once
+    /// its first session is older than `max_age`, keeping `keep` rotated files
+    pub fn with_rotation(mut self, max_bytes: u64, keep: usize, max_age: Option<Duration>) -> Self {
+        self.max_bytes = max_bytes;
+        self.keep = keep;
+        self.max_age = max_age;
+        self
+    }
+
+    /// Path of the log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of the `n`th rotated log, 1 being the newest
+    pub fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    /// Append a finished session
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log directory or file cannot be created, rotated
+    /// or written.
+    pub fn append(&self, record: &SessionRecord) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create log directory {:?}", dir))?;
+        }
+        let line = format!("{}\n", record.to_json());
+        self.rotate_if_needed(line.len() as u64, SystemTime::now())?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open session log {:?}", self.path))?;
+        file.write_all(line.as_bytes())
+            .with_context(|| format!("Failed to write session log {:?}", self.path))
+    }
+
+    /// Rotate before appending `incoming` bytes at `now`, and delete rotated
+    /// logs past the age limit
+    fn rotate_if_needed(&self, incoming: u64, now: SystemTime) -> Result<()> {
+        let size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        let too_big = self.max_bytes > 0 && size > 0 && size + incoming > self.max_bytes;
+        let too_old = self.max_age.is_some_and(|max_age| {
+            let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            first_end(&self.path).is_some_and(|first| now.saturating_sub(first) > max_age.as_secs())
+        });
+        if too_big || too_old {
+            self.rotate()?;
+        }
+
+        if let Some(max_age) = self.max_age {
+            for n in 1..=self.keep {
+                let path = self.rotated_path(n);
+                let expired =
+                    fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .is_ok_and(|modified| {
+                            now.duration_since(modified).is_ok_and(|age| age > max_age)
+                        });
+                if expired {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("Failed to delete {:?}", path))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Shift the rotated logs up by one and move the log to `.1`
+    fn rotate(&self) -> Result<()> {
+        let oldest = self.rotated_path(self.keep.max(1));
+        if oldest.exists() {
+            fs::remove_file(&oldest).with_context(|| format!("Failed to delete {:?}", oldest))?;
+        }
+        if self.keep == 0 {
+            return fs::remove_file(&self.path)
+                .with_context(|| format!("Failed to delete {:?}", self.path));
+        }
+        for n in (1..self.keep).rev() {
+            let from = self.rotated_path(n);
+            if from.exists() {
+                let to = self.rotated_path(n + 1);
+                fs::rename(&from, &to)
+                    .with_context(|| format!("Failed to rename {:?} to {:?}", from, to))?;
+            }
+        }
+        let to = self.rotated_path(1);
+        fs::rename(&self.path, &to)
+            .with_context(|| format!("Failed to rename {:?} to {:?}", self.path, to))?;
+        tracing::info!("Rotated session log {:?}", self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record<'a>(ssid: Option<&'a str>) -> SessionRecord<'a> {
+        SessionRecord {
+            start: 1_700_000_000,
+            end: 1_700_000_300,
+            trigger: "192.168.1.5:22/tcp",
+            ssid,
+            rx_bytes: 2048,
+            tx_bytes: 512,
+            reason: "idle timeout",
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        assert_eq!(
+            record(Some("Cafe \"Wi-Fi\"")).to_json(),
+            "{\"start\":1700000000,\"end\":1700000300,\"duration_secs\":300,\
+             \"trigger\":\"192.168.1.5:22/tcp\",\"ssid\":\"Cafe \\\"Wi-Fi\\\"\",\
+             \"rx_bytes\":2048,\"tx_bytes\":512,\"reason\":\"idle timeout\"}"
+        );
+        assert!(record(None).to_json().contains("\"ssid\":null"));
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\\b\nc\u{1}"), "\"a\\\\b\\nc\\u0001\"");
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = std::env::temp_dir().join(format!("wg-ondemand-log-test-{}", std::process::id()));
+        let log = EventLog::new(dir.join("sessions.jsonl"));
+        log.append(&record(None)).unwrap();
+        log.append(&record(Some("home"))).unwrap();
+
+        let contents = fs::read_to_string(log.path()).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("\"ssid\":\"home\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let dir =
+            std::env::temp_dir().join(format!("wg-ondemand-rotate-test-{}", std::process::id()));
+        let line = record(None).to_json().len() as u64 + 1;
+        // Two sessions per file, two rotated files
+        let log = EventLog::new(dir.join("sessions.jsonl")).with_rotation(2 * line, 2, None);
+        for _ in 0..7 {
+            log.append(&record(None)).unwrap();
+        }
+
+        let lines = |path: PathBuf| fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(log.path().to_path_buf()), 1);
+        assert_eq!(lines(log.rotated_path(1)), 2);
+        assert_eq!(lines(log.rotated_path(2)), 2);
+        assert!(!log.rotated_path(3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_by_age() {
+        let dir = std::env::temp_dir().join(format!("wg-ondemand-age-test-{}", std::process::id()));
+        let day = Duration::from_secs(86400);
+        let log = EventLog::new(dir.join("sessions.jsonl")).with_rotation(0, 3, Some(day));
+        log.append(&record(None)).unwrap();
+
+        // The first session ended at 1_700_000_300
+        let first = UNIX_EPOCH + Duration::from_secs(1_700_000_300);
+        log.rotate_if_needed(0, first + day / 2).unwrap();
+        assert!(!log.rotated_path(1).exists());
+        log.rotate_if_needed(0, first + day * 2).unwrap();
+        assert!(log.rotated_path(1).exists());
+        assert!(!log.path().exists());
+
+        // Rotated logs not written within the limit are deleted
+        let modified = fs::metadata(log.rotated_path(1))
+            .unwrap()
+            .modified()
+            .unwrap();
+        log.rotate_if_needed(0, modified + day / 2).unwrap();
+        assert!(log.rotated_path(1).exists());
+        log.rotate_if_needed(0, modified + day * 2).unwrap();
+        assert!(!log.rotated_path(1).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+// Public IP based network fingerprint
+
+//! External network fingerprint
+//!
+//! SSIDs say nothing about wired networks or mobile hotspots. With a
+//! `[fingerprint]` section, the public IPv4 address of the current network is
+//! looked up when monitoring starts, either over HTTP (via `curl`) or with a
+//! STUN binding request, and compared against the home ISP's address ranges
+//! and reverse DNS names. A match means the subnets are reachable without the
+//! tunnel.
+
+use crate::config;
+use crate::probe::PROBE_MARK;
+use crate::process;
+use crate::types::FingerprintConfig;
+use anyhow::{Context, Result};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::process::Command;
+
+/// STUN binding request message type
+const STUN_BINDING_REQUEST: u16 = 0x0001;
+
+/// STUN binding success response message type
+const STUN_BINDING_RESPONSE: u16 = 0x0101;
+
+/// STUN magic cookie (RFC 5389)
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+
+/// MAPPED-ADDRESS attribute (RFC 3489 servers)
+const STUN_MAPPED_ADDRESS: u16 = 0x0001;
+
+/// XOR-MAPPED-ADDRESS attribute
+const STUN_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// STUN header length
+const STUN_HEADER_LEN: usize = 20;
+
+/// STUN requests sent before giving up (UDP may drop them)
+const STUN_ATTEMPTS: u32 = 3;
+
+/// Extra time granted to `curl` and `getent` beyond the timeout before they are killed
+const COMMAND_GRACE: Duration = Duration::from_secs(2);
+
+/// Where the current network appears to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// Public address or its reverse DNS matches the home network
+    Home,
+    /// Anywhere else
+    Elsewhere,
+}
+
+/// Look up the public address and compare it against the home network
+///
+/// # Errors
+///
+/// Returns an error if the public address cannot be determined.
+pub async fn check(config: &FingerprintConfig) -> Result<Location> {
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let public_ip = public_ip(&config.url, timeout).await?;
+
+    if config::ip_in_subnets(u32::from(public_ip), &config.home_ranges)? {
+        tracing::info!("Public IP {} is in the home ranges", public_ip);
+        return Ok(Location::Home);
+    }
+
+    if !config.home_reverse_dns.is_empty() {
+        match reverse_dns(public_ip, timeout).await {
+            Some(name) if matches_domain(&name, &config.home_reverse_dns) => {
+                tracing::info!(
+                    "Public IP {} ({}) matches the home domains",
+                    public_ip,
+                    name
+                );
+                return Ok(Location::Home);
+            }
+            Some(name) => tracing::debug!("Public IP {} resolves to {}", public_ip, name),
+            None => tracing::debug!("Public IP {} has no reverse DNS name", public_ip),
+        }
+    }
+
+    tracing::debug!("Public IP {} is not the home network", public_ip);
+    Ok(Location::Elsewhere)
+}
+
+/// Public IPv4 address seen by `url` (`stun:host:port` or an HTTP(S) URL
+/// returning the address as plain text)
+pub async fn public_ip(url: &str, timeout: Duration) -> Result<Ipv4Addr> {
+    match url.strip_prefix("stun:") {
+        Some(server) => stun(server, timeout).await,
+        None => http(url, timeout).await,
+    }
+}
+
+/// Fetch the address as plain text with `curl`
+async fn http(url: &str, timeout: Duration) -> Result<Ipv4Addr> {
+    let mut cmd = Command::new("curl");
+    cmd.args([
+        "-4",
+        "-fsS",
+        "--max-time",
+        &timeout.as_secs().max(1).to_string(),
+        url,
+    ]);
+    let output = process::run(cmd, timeout + COMMAND_GRACE).await?;
+    anyhow::ensure!(
+        output.status.success(),
+        "curl {} failed: {}",
+        url,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    let body = String::from_utf8_lossy(&output.stdout);
+    body.trim()
+        .parse()
+        .with_context(|| format!("{} did not return an IPv4 address", url))
+}
+
+/// Ask the STUN `server` (`host:port`) for our mapped address
+async fn stun(server: &str, timeout: Duration) -> Result<Ipv4Addr> {
+    let addr = tokio::net::lookup_host(server)
+        .await
+        .with_context(|| format!("Failed to resolve STUN server {}", server))?
+        .find(SocketAddr::is_ipv4)
+        .with_context(|| format!("STUN server {} has no IPv4 address", server))?;
+
+    let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)).await?;
+    set_mark(&socket, PROBE_MARK)?;
+    socket.connect(addr).await?;
+
+    let transaction_id = transaction_id();
+    let request = binding_request(&transaction_id);
+    let mut buf = [0; 512];
+    for _ in 0..STUN_ATTEMPTS {
+        socket.send(&request).await?;
+        let per_attempt = timeout / STUN_ATTEMPTS;
+        match tokio::time::timeout(per_attempt, socket.recv(&mut buf)).await {
+            Ok(Ok(len)) => {
+                if let Some(ip) = parse_binding_response(&buf[..len], &transaction_id) {
+                    return Ok(ip);
+                }
+                tracing::debug!("Ignoring unexpected STUN response from {}", addr);
+            }
+            Ok(Err(e)) => return Err(e).context("STUN receive failed"),
+            Err(_) => {}
+        }
+    }
+    anyhow::bail!(
+        "No STUN response from {} within {}s",
+        server,
+        timeout.as_secs()
+    )
+}
+
+/// Mark the socket's packets so the kill switch and eBPF classifier let them through
+fn set_mark(socket: &UdpSocket, mark: u32) -> Result<()> {
+    // SAFETY: the option value points to a u32 of the given length
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MARK,
+            std::ptr::addr_of!(mark).cast(),
+            std::mem::size_of::<u32>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to set SO_MARK");
+    }
+    Ok(())
+}
+
+/// Transaction ID for a request; only needs to be unlikely to repeat
+fn transaction_id() -> [u8; 12] {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let mut id = [0; 12];
+    id[..8].copy_from_slice(&nanos.to_be_bytes());
+    id[8..].copy_from_slice(&std::process::id().to_be_bytes());
+    id
+}
+
+/// Binding request without attributes
+fn binding_request(transaction_id: &[u8; 12]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(STUN_HEADER_LEN);
+    msg.extend_from_slice(&STUN_BINDING_REQUEST.to_be_bytes());
+    msg.extend_from_slice(&0u16.to_be_bytes());
+    msg.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    msg.extend_from_slice(transaction_id);
+    msg
+}
+
+/// Mapped IPv4 address from a binding success response to `transaction_id`
+fn parse_binding_response(msg: &[u8], transaction_id: &[u8; 12]) -> Option<Ipv4Addr> {
+    if msg.len() < STUN_HEADER_LEN
+        || u16::from_be_bytes([msg[0], msg[1]]) != STUN_BINDING_RESPONSE
+        || msg[4..8] != STUN_MAGIC_COOKIE.to_be_bytes()
+        || msg[8..20] != transaction_id[..]
+    {
+        return None;
+    }
+    let len = usize::from(u16::from_be_bytes([msg[2], msg[3]]));
+    let attrs = msg.get(STUN_HEADER_LEN..STUN_HEADER_LEN + len)?;
+
+    let mut mapped = None;
+    let mut offset = 0;
+    while let Some(header) = attrs.get(offset..offset + 4) {
+        let kind = u16::from_be_bytes([header[0], header[1]]);
+        let attr_len = usize::from(u16::from_be_bytes([header[2], header[3]]));
+        let value = attrs.get(offset + 4..offset + 4 + attr_len)?;
+        // Family 0x01 is IPv4: reserved byte, family, port, address
+        if value.len() >= 8 && value[1] == 0x01 {
+            let addr = u32::from_be_bytes([value[4], value[5], value[6], value[7]]);
+            match kind {
+                STUN_XOR_MAPPED_ADDRESS => return Some(Ipv4Addr::from(addr ^ STUN_MAGIC_COOKIE)),
+                STUN_MAPPED_ADDRESS => mapped = Some(Ipv4Addr::from(addr)),
+                _ => {}
+            }
+        }
+        // Attributes are padded to 4 bytes
+        offset += 4 + attr_len.div_ceil(4) * 4;
+    }
+    mapped
+}
+
+/// Reverse DNS name of `ip` via the system resolver (`getent hosts`)
+async fn reverse_dns(ip: Ipv4Addr, timeout: Duration) -> Option<String> {
+    let mut cmd = Command::new("getent");
+    cmd.args(["hosts", &ip.to_string()]);
+    let output = process::run(cmd, timeout + COMMAND_GRACE).await.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .nth(1)
+        .map(str::to_string)
+}
+
+/// Whether `name` is one of `domains` or a subdomain of one
+fn matches_domain(name: &str, domains: &[String]) -> bool {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    domains.iter().any(|domain| {
+        let domain = domain.trim_matches('.').to_ascii_lowercase();
+        name == domain || name.ends_with(&format!(".{}", domain))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Binding success response carrying a single attribute
+    fn response(transaction_id: &[u8; 12], kind: u16, value: &[u8]) -> Vec<u8> {
+        let mut msg = STUN_BINDING_RESPONSE.to_be_bytes().to_vec();
+        msg.extend_from_slice(&((4 + value.len()) as u16).to_be_bytes());
+        msg.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+        msg.extend_from_slice(transaction_id);
+        msg.extend_from_slice(&kind.to_be_bytes());
+        msg.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        msg.extend_from_slice(value);
+        msg
+    }
+
+    #[test]
+    fn test_binding_request() {
+        let request = binding_request(&[7; 12]);
+        assert_eq!(request.len(), STUN_HEADER_LEN);
+        assert_eq!(&request[..4], &[0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(&request[4..8], &[0x21, 0x12, 0xA4, 0x42]);
+        assert_eq!(&request[8..], &[7; 12]);
+    }
+
+    #[test]
+    fn test_parse_binding_response() {
+        let id = [1; 12];
+        let ip = u32::from(Ipv4Addr::new(203, 0, 113, 7)) ^ STUN_MAGIC_COOKIE;
+        let mut value = vec![0, 0x01, 0x12, 0x34];
+        value.extend_from_slice(&ip.to_be_bytes());
+
+        let msg = response(&id, STUN_XOR_MAPPED_ADDRESS, &value);
+        assert_eq!(
+            parse_binding_response(&msg, &id),
+            Some(Ipv4Addr::new(203, 0, 113, 7))
+        );
+
+        // Response to another request
+        assert_eq!(parse_binding_response(&msg, &[2; 12]), None);
+
+        // Plain MAPPED-ADDRESS from an old server
+        let msg = response(&id, STUN_MAPPED_ADDRESS, &[0, 0x01, 0, 80, 198, 51, 100, 1]);
+        assert_eq!(
+            parse_binding_response(&msg, &id),
+            Some(Ipv4Addr::new(198, 51, 100, 1))
+        );
+
+        // Truncated
+        assert_eq!(parse_binding_response(&msg[..24], &id), None);
+    }
+
+    #[test]
+    fn test_matches_domain() {
+        let domains = vec!["dyn.example-isp.net".to_string()];
+        assert!(matches_domain("host-1-2.dyn.example-isp.net.", &domains));
+        assert!(matches_domain("DYN.Example-ISP.net", &domains));
+        assert!(!matches_domain("dyn.example-isp.net.evil.com", &domains));
+        assert!(!matches_domain("notdyn.example-isp.net", &domains));
+    }
+}
@@ -0,0 +1,260 @@
+// Recent event history
+
+//! Event history
+//!
+//! Keeps the last few significant daemon events (activations, deactivations and
+//! their reasons, network changes, errors) in memory so `wg-ondemand-ctl history`
+//! can answer why the tunnel came up or went down. Events are also broadcast live,
+//! together with every state transition, to `wg-ondemand-ctl watch`.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+
+/// Number of events kept by default
+pub const DEFAULT_HISTORY_SIZE: usize = 100;
+
+/// Events buffered per live subscriber before it starts missing some
+const LIVE_BUFFER_SIZE: usize = 64;
+
+/// Kind of a recorded event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Traffic or a user request started an activation
+    Trigger,
+    /// Tunnel came up
+    Activated,
+    /// Tunnel went down
+    Deactivated,
+    /// Monitored network joined or left
+    Network,
+    /// Something went wrong
+    Error,
+    /// Change a dry run would have made
+    DryRun,
+    /// State machine transition (live only, not kept)
+    State,
+}
+
+impl EventKind {
+    /// Short lowercase name used in the history listing
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Trigger => "trigger",
+            Self::Activated => "activated",
+            Self::Deactivated => "deactivated",
+            Self::Network => "network",
+            Self::Error => "error",
+            Self::DryRun => "dry-run",
+            Self::State => "state",
+        }
+    }
+}
+
+/// A recorded event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEvent {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    /// What happened
+    pub kind: EventKind,
+    /// Human-readable details
+    pub message: String,
+}
+
+/// Ring buffer of the most recent events
+#[derive(Debug, Clone)]
+pub struct EventHistory {
+    events: VecDeque<HistoryEvent>,
+    capacity: usize,
+    live: broadcast::Sender<HistoryEvent>,
+}
+
+/// History shared between the main loop and the control socket
+pub type SharedHistory = Arc<Mutex<EventHistory>>;
+
+impl EventHistory {
+    /// Create a history keeping up to `capacity` events (0 records nothing)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            live: broadcast::channel(LIVE_BUFFER_SIZE).0,
+        }
+    }
+
+    /// Record an event that happened now
+    pub fn record(&mut self, kind: EventKind, message: impl Into<String>) {
+        self.push(HistoryEvent {
+            timestamp: unix_now(),
+            kind,
+            message: message.into(),
+        });
+    }
+
+    /// Broadcast an event that happened now to live subscribers without keeping it
+    pub fn announce(&self, kind: EventKind, message: impl Into<String>) {
+        let _ = self.live.send(HistoryEvent {
+            timestamp: unix_now(),
+            kind,
+            message: message.into(),
+        });
+    }
+
+    /// Receive events as they are recorded or announced
+    pub fn subscribe(&self) -> broadcast::Receiver<HistoryEvent> {
+        self.live.subscribe()
+    }
+
+    /// Append an event, dropping the oldest one if full
+    ///
+    /// Live subscribers get it even if the history keeps nothing.
+    pub fn push(&mut self, event: HistoryEvent) {
+        let _ = self.live.send(event.clone());
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Recorded events, oldest first
+    pub fn events(&self) -> impl Iterator<Item = &HistoryEvent> {
+        self.events.iter()
+    }
+
+    /// Format the history as one line per event, oldest first
+    pub fn format(&self) -> String {
+        if self.events.is_empty() {
+            return "No events recorded".to_string();
+        }
+        self.events
+            .iter()
+            .map(HistoryEvent::format)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl HistoryEvent {
+    /// Format as a single line: time, kind and message
+    pub fn format(&self) -> String {
+        format!(
+            "{}  {:<11}  {}",
+            format_utc(self.timestamp),
+            self.kind.as_str(),
+            self.message.replace('\n', " ")
+        )
+    }
+}
+
+/// Current Unix time in seconds
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Default for EventHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
+/// Format a Unix timestamp as an ISO 8601 UTC date and time
+fn format_utc(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let secs = timestamp % 86_400;
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(timestamp: u64, message: &str) -> HistoryEvent {
+        HistoryEvent {
+            timestamp,
+            kind: EventKind::Activated,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut history = EventHistory::new(2);
+        history.push(event(1, "first"));
+        history.push(event(2, "second"));
+        history.push(event(3, "third"));
+
+        let messages: Vec<_> = history.events().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["second", "third"]);
+
+        let mut disabled = EventHistory::new(0);
+        disabled.record(EventKind::Error, "dropped");
+        assert_eq!(disabled.events().count(), 0);
+    }
+
+    #[test]
+    fn test_format() {
+        let mut history = EventHistory::new(10);
+        assert_eq!(history.format(), "No events recorded");
+
+        history.push(event(1_700_000_000, "Tunnel up"));
+        history.push(HistoryEvent {
+            timestamp: 1_700_000_060,
+            kind: EventKind::Deactivated,
+            message: "Idle\ntimeout".to_string(),
+        });
+        assert_eq!(
+            history.format(),
+            "2023-11-14T22:13:20Z  activated    Tunnel up\n\
+             2023-11-14T22:14:20Z  deactivated  Idle timeout"
+        );
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut history = EventHistory::new(0);
+        let mut live = history.subscribe();
+        history.record(EventKind::Network, "Joined monitored network");
+        history.announce(EventKind::State, "monitoring -> activating");
+
+        assert_eq!(live.try_recv().unwrap().message, "Joined monitored network");
+        let transition = live.try_recv().unwrap();
+        assert_eq!(transition.kind, EventKind::State);
+        assert!(live.try_recv().is_err());
+        assert_eq!(history.events().count(), 0);
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_utc(1_798_761_599), "2026-12-31T23:59:59Z");
+    }
+}
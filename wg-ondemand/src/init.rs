@@ -0,0 +1,531 @@
+// Interactive config generation
+
+//! Config init wizard
+//!
+//! `wg-ondemand init` looks for wireless interfaces, wg-quick configs,
+//! NetworkManager WireGuard connections and the current SSID, asks a few
+//! questions with the findings as defaults and writes a minimal valid config.
+//! Everything beyond the basics is left to the example config.
+
+use crate::config::{load_config, parse_cidr};
+use crate::process;
+use crate::ssid_monitor::SsidMonitor;
+use crate::wg_controller::validate_interface_name;
+use crate::wg_quick;
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Timeout for the nmcli queries made during detection
+const NMCLI_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Idle timeout suggested by the wizard (seconds)
+const DEFAULT_IDLE_TIMEOUT: u64 = 300;
+
+/// A tunnel the daemon could manage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TunnelCandidate {
+    /// WireGuard interface name
+    pub wg_interface: String,
+    /// NetworkManager connection bringing it up (wg-quick if None)
+    pub nm_connection: Option<String>,
+    /// IPv4 subnets routed through the tunnel, as suggested target subnets
+    pub subnets: Vec<String>,
+}
+
+/// What was found on the system
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Detected {
+    /// Wireless network interfaces
+    pub wireless: Vec<String>,
+    /// wg-quick configs and NetworkManager WireGuard connections
+    pub tunnels: Vec<TunnelCandidate>,
+    /// SSID of the current WiFi network
+    pub ssid: Option<String>,
+}
+
+/// Config values chosen in the wizard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitAnswers {
+    /// WireGuard interface to manage
+    pub wg_interface: String,
+    /// NetworkManager connection, if the tunnel is managed by NetworkManager
+    pub nm_connection: Option<String>,
+    /// Interface to monitor (auto-detected if None)
+    pub monitor_interface: Option<String>,
+    /// Networks where the tunnel is not needed
+    pub exclude_ssids: Vec<String>,
+    /// Target subnets
+    pub subnets: Vec<String>,
+    /// Idle timeout in seconds
+    pub idle_timeout: u64,
+}
+
+/// Look for interfaces, tunnels and the current SSID
+pub async fn detect() -> Detected {
+    let mut wireless: Vec<String> = std::fs::read_dir("/sys/class/net")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().join("wireless").exists())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    wireless.sort();
+
+    let mut tunnels = nm_tunnels().await;
+    let mut configs: Vec<_> = std::fs::read_dir(wg_quick::CONFIG_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+        .collect();
+    configs.sort();
+    for path in configs {
+        let Some(name) = path.file_stem().map(|s| s.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        if validate_interface_name(&name).is_err() {
+            continue;
+        }
+        let subnets = wg_quick::load(&path)
+            .map(|config| {
+                config
+                    .peers
+                    .iter()
+                    .flat_map(|peer| &peer.allowed_ips)
+                    .filter(|cidr| *cidr != "0.0.0.0/0" && parse_cidr(cidr).is_ok())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        tunnels.push(TunnelCandidate {
+            wg_interface: name,
+            nm_connection: None,
+            subnets,
+        });
+    }
+
+    let ssid = match SsidMonitor::new(Vec::new(), Vec::new()).await {
+        Ok(monitor) => monitor.current_ssid().await.ok().flatten(),
+        Err(_) => None,
+    };
+
+    Detected {
+        wireless,
+        tunnels,
+        ssid,
+    }
+}
+
+/// WireGuard connections known to NetworkManager
+async fn nm_tunnels() -> Vec<TunnelCandidate> {
+    let mut cmd = Command::new("nmcli");
+    cmd.args(["-t", "-f", "NAME,TYPE", "connection", "show"]);
+    let Ok(output) = process::run(cmd, NMCLI_TIMEOUT).await else {
+        return Vec::new();
+    };
+
+    let mut tunnels = Vec::new();
+    for name in parse_nm_wireguard(&String::from_utf8_lossy(&output.stdout)) {
+        // The daemon passes the name to nmcli and only accepts plain names
+        if validate_interface_name(&name).is_err() {
+            continue;
+        }
+        let mut cmd = Command::new("nmcli");
+        cmd.args([
+            "-g",
+            "connection.interface-name",
+            "connection",
+            "show",
+            &name,
+        ]);
+        let Ok(output) = process::run(cmd, NMCLI_TIMEOUT).await else {
+            continue;
+        };
+        let iface = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        tunnels.push(TunnelCandidate {
+            wg_interface: if iface.is_empty() {
+                name.clone()
+            } else {
+                iface
+            },
+            nm_connection: Some(name),
+            subnets: Vec::new(),
+        });
+    }
+    tunnels
+}
+
+/// Names of the WireGuard connections in `nmcli -t -f NAME,TYPE connection show` output
+fn parse_nm_wireguard(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.rsplit_once(':'))
+        .filter(|(_, conn_type)| *conn_type == "wireguard")
+        // Terse output escapes colons in names
+        .map(|(name, _)| name.replace("\\:", ":"))
+        .collect()
+}
+
+/// Print `question` with its default and read the answer (the default if empty)
+fn ask<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+    default: Option<&str>,
+) -> Result<String> {
+    match default {
+        Some(default) => write!(output, "{} [{}]: ", question, default)?,
+        None => write!(output, "{}: ", question)?,
+    }
+    output.flush()?;
+
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        anyhow::bail!("Aborted");
+    }
+    let answer = line.trim();
+    Ok(match default {
+        Some(default) if answer.is_empty() => default.to_string(),
+        _ => answer.to_string(),
+    })
+}
+
+/// Ask a yes/no question
+fn confirm<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+    default: bool,
+) -> Result<bool> {
+    loop {
+        let hint = if default { "Y/n" } else { "y/N" };
+        let answer = ask(input, output, &format!("{} [{}]", question, hint), None)?;
+        match answer.to_ascii_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => writeln!(output, "Please answer y or n")?,
+        }
+    }
+}
+
+/// Ask the wizard's questions, using `detected` for the defaults
+///
+/// # Errors
+///
+/// Returns an error if input ends before all questions are answered.
+pub fn interview<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    detected: &Detected,
+) -> Result<InitAnswers> {
+    // Tunnel to manage
+    let tunnel = if detected.tunnels.is_empty() {
+        writeln!(output, "No WireGuard configs or connections found.")?;
+        None
+    } else {
+        writeln!(output, "WireGuard tunnels found:")?;
+        for (i, tunnel) in detected.tunnels.iter().enumerate() {
+            match &tunnel.nm_connection {
+                Some(name) => writeln!(
+                    output,
+                    "  {}) {} (NetworkManager connection {})",
+                    i + 1,
+                    tunnel.wg_interface,
+                    name
+                )?,
+                None => writeln!(output, "  {}) {} (wg-quick)", i + 1, tunnel.wg_interface)?,
+            }
+        }
+        loop {
+            let answer = ask(input, output, "Tunnel to activate on demand", Some("1"))?;
+            match answer.parse::<usize>() {
+                Ok(n) if (1..=detected.tunnels.len()).contains(&n) => {
+                    break Some(detected.tunnels[n - 1].clone());
+                }
+                _ => writeln!(
+                    output,
+                    "Enter a number between 1 and {}",
+                    detected.tunnels.len()
+                )?,
+            }
+        }
+    };
+    let tunnel = match tunnel {
+        Some(tunnel) => tunnel,
+        None => loop {
+            let name = ask(input, output, "WireGuard interface (wg-quick)", Some("wg0"))?;
+            match validate_interface_name(&name) {
+                Ok(()) => {
+                    break TunnelCandidate {
+                        wg_interface: name,
+                        nm_connection: None,
+                        subnets: Vec::new(),
+                    }
+                }
+                Err(e) => writeln!(output, "{:#}", e)?,
+            }
+        },
+    };
+
+    // Subnets that trigger activation
+    let suggested = tunnel.subnets.join(", ");
+    let subnets = loop {
+        let answer = ask(
+            input,
+            output,
+            "Subnets to reach through the tunnel (comma-separated CIDRs)",
+            (!suggested.is_empty()).then_some(suggested.as_str()),
+        )?;
+        let subnets: Vec<String> = answer
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect();
+        match subnets.iter().find(|s| parse_cidr(s).is_err()) {
+            _ if subnets.is_empty() => writeln!(output, "Enter at least one subnet")?,
+            Some(invalid) => writeln!(output, "Invalid CIDR: {}", invalid)?,
+            None => break subnets,
+        }
+    };
+
+    // The current network is most likely home, where the subnets are local
+    let mut exclude_ssids = Vec::new();
+    if let Some(ssid) = &detected.ssid {
+        let question = format!(
+            "Is {} your home network, where the tunnel is not needed?",
+            ssid
+        );
+        if confirm(input, output, &question, true)? {
+            exclude_ssids.push(ssid.clone());
+        }
+    }
+
+    // Interface to watch for traffic
+    let monitor_interface = match detected.wireless.as_slice() {
+        [] => None,
+        [only] => Some(only.clone()),
+        [first, ..] => loop {
+            let answer = ask(
+                input,
+                output,
+                &format!("Interface to monitor ({})", detected.wireless.join(", ")),
+                Some(first),
+            )?;
+            if detected.wireless.contains(&answer) {
+                break Some(answer);
+            }
+            writeln!(output, "Unknown wireless interface: {}", answer)?;
+        },
+    };
+
+    let default_timeout = DEFAULT_IDLE_TIMEOUT.to_string();
+    let idle_timeout = loop {
+        let answer = ask(
+            input,
+            output,
+            "Seconds without traffic before the tunnel goes down",
+            Some(&default_timeout),
+        )?;
+        match answer.parse::<u64>() {
+            Ok(secs) if secs > 0 => break secs,
+            _ => writeln!(output, "Enter a number of seconds greater than 0")?,
+        }
+    };
+
+    Ok(InitAnswers {
+        wg_interface: tunnel.wg_interface,
+        nm_connection: tunnel.nm_connection,
+        monitor_interface,
+        exclude_ssids,
+        subnets,
+        idle_timeout,
+    })
+}
+
+/// Render the config file for the chosen values
+pub fn render(answers: &InitAnswers) -> String {
+    let quote = |s: &str| toml::Value::String(s.to_string()).to_string();
+    let list = |items: &[String]| {
+        items
+            .iter()
+            .map(|s| quote(s))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut out = String::from(
+        "# WireGuard On-Demand Activation Configuration\n\
+         # Generated by `wg-ondemand init`; see the example config for all options.\n\n\
+         [general]\n",
+    );
+    if answers.exclude_ssids.is_empty() {
+        out.push_str("# Monitoring all networks\n");
+    } else {
+        out.push_str(&format!(
+            "exclude_ssids = [{}]\n",
+            list(&answers.exclude_ssids)
+        ));
+    }
+    out.push_str(&format!(
+        "wg_interface = {}\n",
+        quote(&answers.wg_interface)
+    ));
+    if let Some(name) = &answers.nm_connection {
+        out.push_str(&format!("nm_connection = {}\n", quote(name)));
+    }
+    if let Some(iface) = &answers.monitor_interface {
+        out.push_str(&format!("monitor_interface = {}\n", quote(iface)));
+    }
+    out.push_str(&format!("idle_timeout = {}\n", answers.idle_timeout));
+    out.push_str(&format!(
+        "\n[subnets]\nranges = [{}]\n",
+        list(&answers.subnets)
+    ));
+    out
+}
+
+/// Run the wizard on the terminal and write the config to `path`
+///
+/// # Errors
+///
+/// Returns an error if input ends early, the file cannot be written or the
+/// written config does not load.
+pub async fn run(path: &Path) -> Result<()> {
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let mut output = std::io::stdout();
+
+    if path.exists()
+        && !confirm(
+            &mut input,
+            &mut output,
+            &format!("{} exists, overwrite it?", path.display()),
+            false,
+        )?
+    {
+        return Ok(());
+    }
+
+    writeln!(output, "Looking for interfaces and tunnels...")?;
+    let detected = detect().await;
+    let answers = interview(&mut input, &mut output, &detected)?;
+
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {:?}", dir))?;
+    }
+    std::fs::write(path, render(&answers))
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    load_config(path).context("Generated config is invalid")?;
+
+    writeln!(output, "Wrote {}", path.display())?;
+    writeln!(
+        output,
+        "Check it against the system with: wg-ondemand --check-config {}",
+        path.display()
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn detected() -> Detected {
+        Detected {
+            wireless: vec!["wlan0".to_string(), "wlan1".to_string()],
+            tunnels: vec![
+                TunnelCandidate {
+                    wg_interface: "wg-home".to_string(),
+                    nm_connection: Some("Home".to_string()),
+                    subnets: Vec::new(),
+                },
+                TunnelCandidate {
+                    wg_interface: "wg0".to_string(),
+                    nm_connection: None,
+                    subnets: vec!["192.168.1.0/24".to_string()],
+                },
+            ],
+            ssid: Some("HomeWiFi".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_parse_nm_wireguard() {
+        let output = "Home:wireguard\nHomeWiFi:802-11-wireless\nlab\\:vpn:wireguard\n";
+        assert_eq!(parse_nm_wireguard(output), ["Home", "lab:vpn"]);
+    }
+
+    #[test]
+    fn test_interview_defaults() {
+        // Second tunnel, then accept every default
+        let mut input = Cursor::new("2\n\n\n\n\n");
+        let mut output = Vec::new();
+        let answers = interview(&mut input, &mut output, &detected()).unwrap();
+        assert_eq!(
+            answers,
+            InitAnswers {
+                wg_interface: "wg0".to_string(),
+                nm_connection: None,
+                monitor_interface: Some("wlan0".to_string()),
+                exclude_ssids: vec!["HomeWiFi".to_string()],
+                subnets: vec!["192.168.1.0/24".to_string()],
+                idle_timeout: 300,
+            }
+        );
+    }
+
+    #[test]
+    fn test_interview_retries_invalid_answers() {
+        let mut input =
+            Cursor::new("3\n1\n\n10.0.0.0/33\n10.0.0.0/8, 10.1.0.0/16\nmaybe\nn\nwlan1\n0\n600\n");
+        let mut output = Vec::new();
+        let answers = interview(&mut input, &mut output, &detected()).unwrap();
+        assert_eq!(answers.nm_connection.as_deref(), Some("Home"));
+        assert_eq!(answers.subnets, ["10.0.0.0/8", "10.1.0.0/16"]);
+        assert!(answers.exclude_ssids.is_empty());
+        assert_eq!(answers.monitor_interface.as_deref(), Some("wlan1"));
+        assert_eq!(answers.idle_timeout, 600);
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Enter a number between 1 and 2"));
+        assert!(output.contains("Enter at least one subnet"));
+        assert!(output.contains("Invalid CIDR: 10.0.0.0/33"));
+        assert!(output.contains("Please answer y or n"));
+    }
+
+    #[test]
+    fn test_interview_eof() {
+        let mut input = Cursor::new("1\n");
+        assert!(interview(&mut input, &mut Vec::new(), &detected()).is_err());
+    }
+
+    #[test]
+    fn test_render_loads() {
+        let answers = InitAnswers {
+            wg_interface: "wg0".to_string(),
+            nm_connection: Some("Home".to_string()),
+            monitor_interface: None,
+            exclude_ssids: vec!["Home \"WiFi\"".to_string()],
+            subnets: vec!["192.168.1.0/24".to_string(), "10.0.0.0/8".to_string()],
+            idle_timeout: 120,
+        };
+        let path =
+            std::env::temp_dir().join(format!("wg-ondemand-init-{}.toml", std::process::id()));
+        std::fs::write(&path, render(&answers)).unwrap();
+        let config = load_config(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.general.exclude_ssids, ["Home \"WiFi\""]);
+        assert_eq!(config.general.nm_connection.as_deref(), Some("Home"));
+        assert_eq!(config.general.idle_timeout, 120);
+        assert_eq!(config.subnets.ranges, answers.subnets);
+    }
+}
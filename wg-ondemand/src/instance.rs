@@ -0,0 +1,159 @@
+// Per-tunnel runtime namespace
+
+//! Runtime namespace of a daemon instance
+//!
+//! With several `[tunnel.NAME]` sections, one daemon runs per tunnel, each
+//! started with `--tunnel NAME`. Such an instance keeps its state file, control
+//! socket, saved session and statistics in a directory named after the tunnel,
+//! and offsets its monitoring routing tables, TC filter priority, nftables
+//! tables and NFQUEUE number by the tunnel's position in the config (in name
+//! order), so instances running side by side don't collide. Without `--tunnel`
+//! the plain paths and IDs are used.
+
+use crate::control::CONTROL_SOCKET;
+use crate::nfqueue::QUEUE_NUM;
+use crate::route_manager::MAX_MONITOR_INTERFACES;
+use crate::state_file::{SESSION_FILE, STATE_FILE};
+use crate::stats::STATS_FILE;
+use std::path::{Path, PathBuf};
+
+/// Most tunnels a config may define, keeping the rule priorities of their
+/// monitoring tables ahead of the main table
+pub const MAX_TUNNELS: usize = 64;
+
+/// TC filter priority of the first named tunnel when `tc_priority` is unset
+pub const TUNNEL_TC_PRIORITY: u16 = 40000;
+
+/// Paths and IDs of one daemon instance
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Instance {
+    /// Tunnel selected with `--tunnel`
+    name: Option<String>,
+    /// Position of the tunnel in the config
+    index: u16,
+}
+
+impl Instance {
+    /// Namespace of the tunnel selected with `--tunnel`, or the plain one
+    pub fn new(tunnel: Option<&str>) -> Self {
+        Self {
+            name: tunnel.map(String::from),
+            index: 0,
+        }
+    }
+
+    /// Set the position of the tunnel in the config, which offsets the IDs
+    pub fn with_index(mut self, index: u16) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Tunnel selected with `--tunnel`, if any
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// `path` moved into a directory named after the tunnel
+    fn namespaced(&self, path: &str) -> PathBuf {
+        let path = Path::new(path);
+        match (&self.name, path.parent(), path.file_name()) {
+            (Some(name), Some(dir), Some(file)) => dir.join(name).join(file),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// State file read by status tools
+    pub fn state_file(&self) -> PathBuf {
+        self.namespaced(STATE_FILE)
+    }
+
+    /// Control socket
+    pub fn control_socket(&self) -> PathBuf {
+        self.namespaced(CONTROL_SOCKET)
+    }
+
+    /// Session saved across a restart
+    pub fn session_file(&self) -> PathBuf {
+        self.namespaced(SESSION_FILE)
+    }
+
+    /// Lifetime statistics
+    pub fn stats_file(&self) -> PathBuf {
+        self.namespaced(STATS_FILE)
+    }
+
+    /// Route manager slot (table and rule) of the monitored interface in `slot`
+    pub fn monitor_slot(&self, slot: u32) -> u32 {
+        u32::from(self.index) * MAX_MONITOR_INTERFACES as u32 + slot
+    }
+
+    /// TC filter priority from the configured one (0 lets the kernel choose)
+    ///
+    /// A named tunnel always uses a fixed priority, so it only replaces its
+    /// own stale filters and leaves those of the other tunnels alone.
+    pub fn tc_priority(&self, configured: Option<u16>) -> u16 {
+        match self.name {
+            Some(_) => configured
+                .unwrap_or(TUNNEL_TC_PRIORITY)
+                .saturating_add(self.index),
+            None => configured.unwrap_or(0),
+        }
+    }
+
+    /// nftables table `base` of this instance
+    pub fn nft_table(&self, base: &str) -> String {
+        match self.name {
+            Some(_) => format!("{}_{}", base, self.index),
+            None => base.to_string(),
+        }
+    }
+
+    /// NFQUEUE number of the traffic hold
+    pub fn queue_num(&self) -> u16 {
+        QUEUE_NUM + self.index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_instance() {
+        let instance = Instance::default();
+        assert_eq!(instance.state_file(), Path::new(STATE_FILE));
+        assert_eq!(instance.control_socket(), Path::new(CONTROL_SOCKET));
+        assert_eq!(instance.session_file(), Path::new(SESSION_FILE));
+        assert_eq!(instance.stats_file(), Path::new(STATS_FILE));
+        assert_eq!(instance.monitor_slot(1), 1);
+        assert_eq!(instance.tc_priority(None), 0);
+        assert_eq!(instance.tc_priority(Some(10)), 10);
+        assert_eq!(instance.nft_table("wg_ondemand"), "wg_ondemand");
+        assert_eq!(instance.queue_num(), QUEUE_NUM);
+    }
+
+    #[test]
+    fn test_named_instance() {
+        let instance = Instance::new(Some("lab")).with_index(2);
+        assert_eq!(
+            instance.state_file(),
+            Path::new("/run/wg-ondemand/lab/state")
+        );
+        assert_eq!(
+            instance.control_socket(),
+            Path::new("/run/wg-ondemand/lab/control.sock")
+        );
+        assert_eq!(
+            instance.stats_file(),
+            Path::new("/var/lib/wg-ondemand/lab/stats")
+        );
+        assert_eq!(
+            instance.monitor_slot(1),
+            2 * MAX_MONITOR_INTERFACES as u32 + 1
+        );
+        assert_eq!(instance.tc_priority(None), TUNNEL_TC_PRIORITY + 2);
+        assert_eq!(instance.tc_priority(Some(10)), 12);
+        assert_eq!(instance.nft_table("wg_ondemand"), "wg_ondemand_2");
+        assert_eq!(instance.queue_num(), QUEUE_NUM + 2);
+    }
+}
@@ -0,0 +1,214 @@
+// nftables kill switch
+
+//! Kill switch
+//!
+//! On a monitored network, traffic for the target subnets must not leave in
+//! plaintext while the tunnel is coming up. With `kill_switch = "subnets"` the
+//! eBPF classifier drops the packets it reports (see
+//! [`TrafficMonitor::set_kill_switch`](crate::traffic_monitor::TrafficMonitor::set_kill_switch)).
+//! `kill_switch = "all"` additionally installs an nftables table that drops all
+//! other traffic not leaving through the tunnel. Loopback, DHCP, DNS, IPv6
+//! neighbor discovery, the peer endpoints and the daemon's own probes stay
+//! reachable so the tunnel can be brought up. Traffic to the target subnets passes the table so the classifier
+//! still sees it.
+
+use crate::endpoint;
+use crate::probe::PROBE_MARK;
+use crate::process::{self, DEFAULT_COMMAND_TIMEOUT};
+use crate::types::Config;
+use anyhow::Result;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// nftables table owned by the daemon (in the `inet` family; see
+/// [`Instance::nft_table`](crate::instance::Instance::nft_table))
+pub const NFT_TABLE: &str = "wg_ondemand";
+
+/// Manages the nftables table of the all-traffic kill switch
+pub struct KillSwitch {
+    table: String,
+    wg_interface: String,
+    subnets: Vec<String>,
+    endpoint_ports: Vec<u16>,
+    command_timeout: Duration,
+    enabled: bool,
+}
+
+impl KillSwitch {
+    /// Create a kill switch letting traffic through `wg_interface`, to the
+    /// `subnets` (left to the eBPF classifier) and to UDP `endpoint_ports`
+    pub fn new(wg_interface: String, subnets: Vec<String>, endpoint_ports: Vec<u16>) -> Self {
+        Self {
+            table: NFT_TABLE.to_string(),
+            wg_interface,
+            subnets,
+            endpoint_ports,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            enabled: false,
+        }
+    }
+
+    /// Use the nftables table `table` instead of [`NFT_TABLE`]
+    pub fn with_table(mut self, table: String) -> Self {
+        self.table = table;
+        self
+    }
+
+    /// Set the timeout after which hung `nft` invocations are killed
+    pub fn with_command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
+
+    /// Whether the table is installed
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Install the table, replacing any left by a previous instance
+    pub async fn enable(&mut self) -> Result<()> {
+        if self.enabled {
+            return Ok(());
+        }
+        run_nft(&self.ruleset(), self.command_timeout).await?;
+        self.enabled = true;
+        tracing::info!("Kill switch enabled: blocking traffic outside the tunnel");
+        Ok(())
+    }
+
+    /// Remove the table
+    pub async fn disable(&mut self) -> Result<()> {
+        remove_table(&self.table, self.command_timeout).await?;
+        if std::mem::take(&mut self.enabled) {
+            tracing::info!("Kill switch disabled");
+        }
+        Ok(())
+    }
+
+    /// nftables commands replacing the table atomically
+    pub fn ruleset(&self) -> String {
+        let mut rules = vec![
+            "type filter hook output priority 0; policy accept".to_string(),
+            format!("oifname {{ \"lo\", \"{}\" }} accept", self.wg_interface),
+            format!("meta mark {:#x} accept", PROBE_MARK),
+        ];
+        if !self.subnets.is_empty() {
+            rules.push(format!("ip daddr {{ {} }} accept", self.subnets.join(", ")));
+        }
+        rules.push("udp dport { 53, 67, 547 } accept".to_string());
+        rules.push("tcp dport 53 accept".to_string());
+        rules.push(
+            "icmpv6 type { nd-router-solicit, nd-neighbor-solicit, nd-neighbor-advert } accept"
+                .to_string(),
+        );
+        if !self.endpoint_ports.is_empty() {
+            let ports: Vec<_> = self.endpoint_ports.iter().map(u16::to_string).collect();
+            rules.push(format!("udp dport {{ {} }} accept", ports.join(", ")));
+        }
+        rules.push("drop".to_string());
+
+        format!(
+            "add table inet {0}; delete table inet {0}; table inet {0} {{ chain output {{ {1}; }} }}",
+            self.table,
+            rules.join("; ")
+        )
+    }
+}
+
+/// Remove `table` if it exists, e.g. one left behind by a crashed instance
+pub async fn remove_table(table: &str, timeout: Duration) -> Result<()> {
+    // Adding first makes the delete succeed whether or not the table exists
+    run_nft(
+        &format!("add table inet {0}; delete table inet {0}", table),
+        timeout,
+    )
+    .await
+}
+
+/// Run `nft` with `commands` as a single transaction
+pub async fn run_nft(commands: &str, timeout: Duration) -> Result<()> {
+    let mut cmd = Command::new("nft");
+    cmd.arg(commands);
+    let output = process::run(cmd, timeout).await?;
+    anyhow::ensure!(
+        output.status.success(),
+        "nft failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(())
+}
+
+/// UDP ports of the peer endpoints known from the config (see
+/// [`endpoint::configured`])
+pub fn endpoint_ports(config: &Config) -> Vec<u16> {
+    let mut ports: Vec<u16> = endpoint::configured(config)
+        .iter()
+        .filter_map(|endpoint| endpoint.rsplit_once(':')?.1.parse().ok())
+        .collect();
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+/// Build the kill switch for `config`, warning if no endpoint is known
+pub fn from_config(config: &Config) -> KillSwitch {
+    let ports = endpoint_ports(config);
+    if ports.is_empty() {
+        tracing::warn!(
+            "No peer endpoint ports found in the config; the kill switch may block handshakes"
+        );
+    }
+    KillSwitch::new(
+        config.general.wg_interface.clone(),
+        config.subnets.ranges.clone(),
+        ports,
+    )
+    .with_table(config.instance.nft_table(NFT_TABLE))
+    .with_command_timeout(Duration::from_secs(config.general.command_timeout_secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ruleset() {
+        let kill_switch = KillSwitch::new(
+            "wg0".to_string(),
+            vec!["192.168.1.0/24".to_string(), "10.0.0.0/8".to_string()],
+            vec![51820],
+        );
+        assert_eq!(
+            kill_switch.ruleset(),
+            "add table inet wg_ondemand; delete table inet wg_ondemand; \
+             table inet wg_ondemand { chain output { \
+             type filter hook output priority 0; policy accept; \
+             oifname { \"lo\", \"wg0\" } accept; \
+             meta mark 0x77676f70 accept; \
+             ip daddr { 192.168.1.0/24, 10.0.0.0/8 } accept; \
+             udp dport { 53, 67, 547 } accept; \
+             tcp dport 53 accept; \
+             icmpv6 type { nd-router-solicit, nd-neighbor-solicit, nd-neighbor-advert } accept; \
+             udp dport { 51820 } accept; \
+             drop; } }"
+        );
+
+        // Empty sets are invalid in nftables
+        let ruleset = KillSwitch::new("wg0".to_string(), vec![], vec![]).ruleset();
+        assert!(!ruleset.contains("ip daddr"));
+        assert!(!ruleset.contains("{  }"));
+    }
+
+    #[test]
+    fn test_endpoint_ports() {
+        let config: Config = toml::from_str(
+            "[general]\nwg_interface = \"wg0\"\n\n[subnets]\nranges = [\"192.168.1.0/24\"]\n\n\
+             [endpoints]\naddresses = [\"home.example.com:51820\", \"203.0.113.1:443\"]\n\n\
+             [native]\nprivate_key_file = \"/dev/null\"\naddresses = [\"10.0.0.2/32\"]\n\n\
+             [[native.peers]]\npublic_key = \"cHVibGlj\"\nendpoint = \"[2001:db8::1]:51820\"\n\
+             allowed_ips = [\"192.168.1.0/24\"]\n",
+        )
+        .unwrap();
+        assert_eq!(endpoint_ports(&config), [443, 51820]);
+    }
+}
@@ -0,0 +1,281 @@
+// Landlock filesystem restriction
+
+//! Landlock filesystem restriction
+//!
+//! The daemon parses data from the network as root, yet after startup it only
+//! writes its runtime directory, the statistics directory and the event log
+//! (its directory if the log is rotated). A
+//! Landlock ruleset installed on all threads limits writes to those, and reads
+//! and execution to the system directories the daemon and the commands it runs
+//! need (`/usr`, `/etc`, `/proc`, `/sys`, ...). The config, keys and wg-quick
+//! config are read before, and the eBPF object is embedded in the binary.
+//! `wg-quick` additionally gets `/run`, `/tmp`, `/proc/sys/net` and
+//! `/etc/resolv.conf` for its sysctls, resolvconf and firewall locks.
+//!
+//! The restriction is opt-in with `landlock = true`, since it also applies to
+//! wg-quick hooks (`PostUp`/`PreDown`, `SaveConfig`) and other scripts the
+//! commands start. Kernels without Landlock (before 5.13, or not enabled in
+//! `lsm=`) then run unrestricted with a warning.
+
+use crate::privileges;
+use crate::types::Config;
+use anyhow::{Context, Result};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Filesystem access rights (linux/landlock.h)
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+/// REMOVE_DIR up to MAKE_SYM, the rest of ABI version 1
+const ACCESS_MAKE_AND_REMOVE: u64 = 0x1ff0;
+/// Since ABI version 2
+const ACCESS_REFER: u64 = 1 << 13;
+/// Since ABI version 3
+const ACCESS_TRUNCATE: u64 = 1 << 14;
+
+/// Rights that apply to files rather than directories
+const FILE_ACCESS: u64 = ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE | ACCESS_TRUNCATE;
+
+const READ: u64 = ACCESS_READ_FILE | ACCESS_READ_DIR;
+const READ_EXECUTE: u64 = READ | ACCESS_EXECUTE;
+const READ_WRITE: u64 =
+    READ | ACCESS_WRITE_FILE | ACCESS_MAKE_AND_REMOVE | ACCESS_REFER | ACCESS_TRUNCATE;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+/// Read and run programs and libraries
+const SYSTEM_DIRS: [&str; 7] = ["/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc"];
+/// Read only: interfaces, routes, sockets, resolv.conf links into /run
+const READ_DIRS: [&str; 3] = ["/proc", "/sys", "/run"];
+/// Where wg-quick writes besides
+const WG_QUICK_DIRS: [&str; 4] = ["/run", "/tmp", "/proc/sys/net", "/etc/resolv.conf"];
+
+/// Ruleset applied by [`restrict_thread`]
+static RULESET: AtomicI32 = AtomicI32::new(-1);
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Rights handled (denied unless allowed by a rule) at Landlock ABI `abi`
+pub fn handled_access(abi: u32) -> u64 {
+    let mut access = READ_EXECUTE | ACCESS_WRITE_FILE | ACCESS_MAKE_AND_REMOVE;
+    if abi >= 2 {
+        access |= ACCESS_REFER;
+    }
+    if abi >= 3 {
+        access |= ACCESS_TRUNCATE;
+    }
+    access
+}
+
+/// Paths the daemon may access after startup, with their rights
+pub fn rules(config: &Config) -> Vec<(PathBuf, u64)> {
+    let mut rules: Vec<(PathBuf, u64)> = SYSTEM_DIRS
+        .iter()
+        .map(|dir| (PathBuf::from(dir), READ_EXECUTE))
+        .chain(READ_DIRS.iter().map(|dir| (PathBuf::from(dir), READ)))
+        .collect();
+    // Standard streams of commands
+    rules.push((PathBuf::from("/dev/null"), READ_WRITE));
+    rules.extend(
+        [config.instance.state_file(), config.instance.stats_file()]
+            .iter()
+            .filter_map(|path| Some((path.parent()?.to_path_buf(), READ_WRITE))),
+    );
+    if let Some(path) = &config.general.event_log {
+        // Rotation creates and renames files next to it
+        let rotates =
+            config.general.event_log_max_bytes > 0 || config.general.event_log_max_days > 0;
+        match path
+            .parent()
+            .filter(|dir| rotates && !dir.as_os_str().is_empty())
+        {
+            Some(dir) => rules.push((dir.to_path_buf(), READ_WRITE)),
+            None => rules.push((path.clone(), READ_WRITE)),
+        }
+    }
+    if config.native.is_none() && config.general.nm_connection.is_none() {
+        rules.extend(
+            WG_QUICK_DIRS
+                .iter()
+                .map(|dir| (PathBuf::from(dir), READ_WRITE)),
+        );
+    }
+    rules
+}
+
+/// Restrict the filesystem access of all threads to [`rules`]
+///
+/// Paths that don't exist are skipped, except the daemon's own directories and
+/// event log, which are created first.
+///
+/// # Errors
+///
+/// Returns an error if the ruleset cannot be built or applied to every thread.
+/// A kernel without Landlock is not an error.
+pub fn restrict(config: &Config) -> Result<()> {
+    // SAFETY: a null attribute with the version flag only queries the ABI
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 0 {
+        let e = std::io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::ENOSYS | libc::EOPNOTSUPP) => {
+                tracing::warn!("Landlock unavailable, filesystem access is not restricted");
+                return Ok(());
+            }
+            _ => return Err(e).context("Failed to query the Landlock ABI version"),
+        }
+    }
+    let handled = handled_access(abi as u32);
+
+    for path in [config.instance.state_file(), config.instance.stats_file()] {
+        let dir = path.parent().context("No state directory")?;
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    }
+    if let Some(path) = &config.general.event_log {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to create {:?}", path))?;
+    }
+
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    // SAFETY: valid attribute of the given size
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to create Landlock ruleset");
+    }
+    // SAFETY: a new file descriptor owned by nothing else
+    let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+    for (path, access) in rules(config) {
+        let Ok(file) = std::fs::File::options()
+            .read(true)
+            .custom_flags(libc::O_PATH)
+            .open(&path)
+        else {
+            tracing::debug!("Skipping Landlock rule for missing {:?}", path);
+            continue;
+        };
+        let mut access = access & handled;
+        if !file.metadata().is_ok_and(|metadata| metadata.is_dir()) {
+            access &= FILE_ACCESS;
+        }
+        let rule = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: file.as_raw_fd(),
+        };
+        // SAFETY: valid rule attribute and ruleset
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &rule,
+                0,
+            )
+        };
+        if rc != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to add Landlock rule for {:?}", path));
+        }
+    }
+
+    RULESET.store(ruleset.as_raw_fd(), Ordering::SeqCst);
+    privileges::on_all_threads("Landlock ruleset", restrict_thread)?;
+    tracing::info!("Restricted filesystem access with Landlock (ABI {})", abi);
+    Ok(())
+}
+
+/// Apply the ruleset to the calling thread; only makes raw syscalls
+fn restrict_thread() -> bool {
+    let ruleset = RULESET.load(Ordering::SeqCst);
+    // SAFETY: prctl and landlock_restrict_self with valid arguments
+    unsafe {
+        // Required without CAP_SYS_ADMIN
+        libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == 0
+            && libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handled_access() {
+        assert_eq!(handled_access(1), 0x1fff);
+        assert_eq!(handled_access(2), 0x3fff);
+        assert_eq!(handled_access(3), 0x7fff);
+        // Device ioctls (ABI 5) are left to the other restrictions
+        assert_eq!(handled_access(6), 0x7fff);
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut config: Config = toml::from_str(
+            "[general]\nwg_interface = \"wg0\"\nevent_log = \"/var/log/wg-ondemand.jsonl\"\n\n\
+             [subnets]\nranges = [\"192.168.1.0/24\"]\n",
+        )
+        .unwrap();
+        let writable = |rules: &[(PathBuf, u64)]| -> Vec<PathBuf> {
+            rules
+                .iter()
+                .filter(|(_, access)| access & ACCESS_WRITE_FILE != 0)
+                .map(|(path, _)| path.clone())
+                .collect()
+        };
+
+        // wg-quick
+        let rules = rules(&config);
+        assert!(writable(&rules).contains(&PathBuf::from("/proc/sys/net")));
+        assert!(rules.contains(&(PathBuf::from("/usr"), READ_EXECUTE)));
+
+        config.general.nm_connection = Some("home-vpn".to_string());
+        assert_eq!(
+            writable(&super::rules(&config)),
+            [
+                PathBuf::from("/dev/null"),
+                PathBuf::from("/run/wg-ondemand"),
+                PathBuf::from("/var/lib/wg-ondemand"),
+                PathBuf::from("/var/log"),
+            ]
+        );
+
+        // Without rotation only the log itself is written
+        config.general.event_log_max_bytes = 0;
+        assert!(
+            writable(&super::rules(&config)).contains(&PathBuf::from("/var/log/wg-ondemand.jsonl"))
+        );
+    }
+}
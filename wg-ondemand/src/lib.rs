@@ -11,19 +11,82 @@
 //! # Main Components
 //!
 //! - [`config`]: Configuration file parsing and validation
+//! - [`completions`]: Shell completion scripts for `wg-ondemand` and `wg-ondemand-ctl`
+//! - [`config_check`]: Configuration cross-checks against the system
+//! - [`connman`]: Network detection via ConnMan's D-Bus API
+//! - [`control`]: Unix control socket for runtime commands
+//! - [`daemon`]: Embeddable daemon wiring all components together
+//! - [`dbus_service`]: D-Bus object emitting state change signals
+//! - [`dock`]: Docking station detection
+//! - [`doctor`]: Environment checks for `wg-ondemand doctor`
 //! - [`ebpf_loader`]: eBPF program management for traffic monitoring
+//! - [`endpoint`]: Peer endpoint failover
+//! - [`error`]: Typed errors returned by the library API
+//! - [`event_log`]: Persistent JSON Lines log of tunnel sessions
+//! - [`fingerprint`]: Public IP fingerprint of the current network
+//! - [`history`]: In-memory history of recent daemon events
+//! - [`init`]: Interactive config generation for `wg-ondemand init`
+//! - [`instance`]: Per-tunnel runtime paths and IDs
+//! - [`kill_switch`]: nftables kill switch for traffic outside the tunnel
+//! - [`landlock`]: Landlock filesystem restriction after startup
+//! - [`native_tunnel`]: Native WireGuard interface management via netlink
+//! - [`network_detector`]: Pluggable network change detection backends
+//! - [`nfqueue`]: NFQUEUE hold-and-release of traffic while the tunnel comes up
+//! - [`privileges`]: Capability dropping and user switch after startup
+//! - [`probe`]: Post-activation connectivity probe
+//! - [`process`]: External command execution with timeouts
 //! - [`route_manager`]: Dynamic route management for traffic detection
+//! - [`rtnl`]: rtnetlink route operations
+//! - [`seccomp`]: seccomp syscall allowlist installed after startup
+//! - [`socket_scan`]: Socket table scanning fallback for traffic detection
 //! - [`ssid_monitor`]: Network/SSID change detection via D-Bus
 //! - [`state`]: State machine for tunnel lifecycle management
 //! - [`state_file`]: State file writing for external monitoring
+//! - [`stats`]: Runtime statistics such as activation latency
+//! - [`syslog`]: Log output to the system logger
+//! - [`telemetry`]: OpenTelemetry export of traces and metrics
+//! - [`traffic_monitor`]: Traffic detection backends (eBPF or socket scanning)
 //! - [`types`]: Shared data structures
 //! - [`wg_controller`]: WireGuard tunnel control and statistics
+//! - [`wg_quick`]: wg-quick config file parsing
 
+pub mod completions;
 pub mod config;
+pub mod config_check;
+pub mod connman;
+pub mod control;
+pub mod daemon;
+pub mod dbus_service;
+pub mod dock;
+pub mod doctor;
+#[cfg(feature = "ebpf")]
 pub mod ebpf_loader;
+pub mod endpoint;
+pub mod error;
+pub mod event_log;
+pub mod fingerprint;
+pub mod history;
+pub mod init;
+pub mod instance;
+pub mod kill_switch;
+pub mod landlock;
+pub mod native_tunnel;
+pub mod network_detector;
+pub mod nfqueue;
+pub mod privileges;
+pub mod probe;
+pub mod process;
 pub mod route_manager;
+pub mod rtnl;
+pub mod seccomp;
+pub mod socket_scan;
 pub mod ssid_monitor;
 pub mod state;
 pub mod state_file;
+pub mod stats;
+pub mod syslog;
+pub mod telemetry;
+pub mod traffic_monitor;
 pub mod types;
 pub mod wg_controller;
+pub mod wg_quick;
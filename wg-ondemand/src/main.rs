@@ -1,47 +1,26 @@
 // WireGuard On-Demand Activation Daemon
 
 use anyhow::{Context, Result};
-use clap::Parser;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::signal;
-use tokio::sync::mpsc;
-use tokio::time::interval;
+use clap::{CommandFactory, Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tracing_subscriber::{
+    fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
+#[cfg(feature = "otel")]
+use wg_ondemand::telemetry::Telemetry;
 use wg_ondemand::{
-    config::{self, load_config},
-    ebpf_loader::EbpfManager,
-    route_manager::RouteManager,
-    ssid_monitor::{NetworkEvent, SsidMonitor},
-    state::{StateAction, StateCommand, StateManager},
-    state_file,
-    types::{TrafficEvent, TunnelState},
-    wg_controller::{self, WgController},
+    completions::{self, Shell},
+    config::{load_config, load_config_with_overrides, ConfigOverrides},
+    config_check,
+    control::{self, ControlCommand},
+    daemon::{Daemon, DaemonHandle},
+    doctor, init,
+    instance::Instance,
+    syslog::Syslog,
+    types::LogTarget,
 };
 
-// Configuration constants for main event loop
-/// Size of the channel buffer for network events (SSID changes)
-const NETWORK_EVENT_CHANNEL_SIZE: usize = 32;
-
-/// Size of the channel buffer for state commands
-const STATE_COMMAND_CHANNEL_SIZE: usize = 32;
-
-/// Interval for checking tunnel idle timeout (seconds)
-/// Should be frequent enough to detect idle timeouts accurately
-const IDLE_CHECK_INTERVAL_SECS: u64 = 60;
-
-/// Interval for polling eBPF ringbuffer events (milliseconds)
-/// 1 second balances responsiveness with battery efficiency
-/// This reduces CPU wakeups from 864K/day to 86K/day
-const EBPF_POLL_INTERVAL_MILLIS: u64 = 1000;
-
-/// Maximum number of retry attempts for eBPF attachment when interface has no IP
-const MAX_ATTACHMENT_RETRIES: u8 = 5;
-
-/// Initial retry delay in seconds (exponential backoff: 1s, 2s, 4s, 8s, 16s)
-const INITIAL_RETRY_DELAY_SECS: u64 = 1;
-
 #[derive(Parser)]
 #[command(name = "wg-ondemand")]
 #[command(about = "On-demand WireGuard VPN activation daemon", long_about = None)]
@@ -49,170 +28,126 @@ struct Args {
     /// Path to configuration file
     #[arg(short, long, default_value = "/etc/wg-ondemand/config.toml")]
     config: PathBuf,
-}
-
-/// Get the IPv4 address assigned to a network interface
-/// Returns the IP as u32 in network byte order (big endian), or None if no IPv4 address assigned
-fn get_interface_ip(interface: &str) -> Result<Option<u32>> {
-    let interfaces = if_addrs::get_if_addrs().context("Failed to get interface addresses")?;
-
-    for iface in interfaces {
-        if iface.name == interface {
-            if let if_addrs::IfAddr::V4(ipv4) = iface.addr {
-                let ip_u32 = u32::from_be_bytes(ipv4.ip.octets());
-                return Ok(Some(ip_u32));
-            }
-        }
-    }
 
-    Ok(None)
-}
+    /// Check the configuration at PATH against the system and exit
+    #[arg(long, value_name = "PATH")]
+    check_config: Option<PathBuf>,
 
-/// Auto-detect the active network interface
-/// Attempts to find a wireless interface, falling back to the default route interface
-async fn auto_detect_interface() -> Result<String> {
-    // First, try to find wireless interfaces by checking /sys/class/net/*/wireless
-    if let Ok(entries) = std::fs::read_dir("/sys/class/net") {
-        for entry in entries.flatten() {
-            let iface_name = entry.file_name();
-            let wireless_path = format!("/sys/class/net/{}/wireless", iface_name.to_string_lossy());
-            if std::path::Path::new(&wireless_path).exists() {
-                log::info!(
-                    "Auto-detected wireless interface: {}",
-                    iface_name.to_string_lossy()
-                );
-                return Ok(iface_name.to_string_lossy().to_string());
-            }
-        }
-    }
+    /// Detect traffic and log route and tunnel changes without making them
+    #[arg(long)]
+    dry_run: bool,
 
-    // Fall back to finding the default route interface
-    log::info!("No wireless interface found, detecting default route interface...");
-    let output = tokio::process::Command::new("ip")
-        .args(["route", "show", "default"])
-        .output()
-        .await
-        .context("Failed to execute 'ip route show default'")?;
+    /// Name of the [tunnel.NAME] section to run or control (required if there are several)
+    #[arg(long, value_name = "NAME")]
+    tunnel: Option<String>,
 
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        // Parse output like: "default via 192.168.1.1 dev eth0 proto dhcp metric 100"
-        for line in stdout.lines() {
-            if let Some(dev_pos) = line.find(" dev ") {
-                let after_dev = &line[dev_pos + 5..];
-                if let Some(iface) = after_dev.split_whitespace().next() {
-                    log::info!("Auto-detected default route interface: {}", iface);
-                    return Ok(iface.to_string());
-                }
-            }
-        }
-    }
+    /// WireGuard interface to manage (overrides general.wg_interface)
+    #[arg(long, value_name = "IFACE")]
+    wg_interface: Option<String>,
 
-    anyhow::bail!(
-        "Could not auto-detect network interface. Please specify monitor_interface in config."
-    )
-}
+    /// Seconds without traffic before deactivating (overrides general.idle_timeout)
+    #[arg(long, value_name = "SECS")]
+    idle_timeout: Option<u64>,
 
-/// Spawn a background task to retry eBPF attachment with exponential backoff
-/// Returns true if retry task was spawned, false if one is already running
-fn spawn_attachment_retry_task(
-    interface: String,
-    state_tx: mpsc::Sender<StateCommand>,
-    retry_in_progress: Arc<AtomicBool>,
-) {
-    // Check if retry is already in progress
-    if retry_in_progress.swap(true, Ordering::SeqCst) {
-        log::debug!("eBPF attachment retry already in progress, skipping");
-        return;
-    }
+    /// Interface to monitor for traffic (overrides general.monitor_interface)
+    #[arg(long, value_name = "IFACE")]
+    monitor_interface: Option<String>,
 
-    log::info!(
-        "Spawning eBPF attachment retry task (will retry up to {} times with exponential backoff)",
-        MAX_ATTACHMENT_RETRIES
-    );
+    /// Target subnet in CIDR notation; repeat to give several (replaces subnets.ranges)
+    #[arg(long = "subnet", value_name = "CIDR")]
+    subnets: Vec<String>,
 
-    tokio::spawn(async move {
-        let mut delay_secs = INITIAL_RETRY_DELAY_SECS;
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-        for attempt in 1..=MAX_ATTACHMENT_RETRIES {
-            // Wait before retry (exponential backoff)
-            log::info!(
-                "eBPF attachment retry attempt {}/{} in {}s...",
-                attempt,
-                MAX_ATTACHMENT_RETRIES,
-                delay_secs
-            );
-            tokio::time::sleep(Duration::from_secs(delay_secs)).await;
+#[derive(Subcommand)]
+enum Command {
+    /// Send a command to the running daemon via its control socket
+    Control {
+        /// Command to send (keep-alive, up, down, history, peers, stats, watch)
+        command: String,
+    },
+    /// Check capabilities, kernel support and required tools, then exit
+    Doctor,
+    /// Interactively create a config file at the --config path
+    Init,
+    /// Print a shell completion script
+    #[command(hide = true)]
+    Completions {
+        /// Shell to generate completions for
+        shell: Shell,
+        /// Complete wg-ondemand-ctl instead of wg-ondemand
+        #[arg(long)]
+        ctl: bool,
+    },
+}
 
-            // Check if interface now has an IP address
-            match get_interface_ip(&interface) {
-                Ok(Some(_ip)) => {
-                    log::info!(
-                        "Interface {} now has IP address, triggering eBPF attachment",
-                        interface
-                    );
-                    // Send retry command to trigger attachment
-                    if let Err(e) = state_tx.send(StateCommand::RetryEbpfAttachment).await {
-                        log::error!("Failed to send retry command: {}", e);
-                    }
-                    // Success - stop retrying
-                    retry_in_progress.store(false, Ordering::SeqCst);
-                    return;
-                }
-                Ok(None) => {
-                    log::debug!(
-                        "Interface {} still has no IP address (attempt {}/{})",
-                        interface,
-                        attempt,
-                        MAX_ATTACHMENT_RETRIES
-                    );
-                }
-                Err(e) => {
-                    log::warn!("Failed to check interface IP during retry: {}", e);
-                }
+/// Translate Unix signals into daemon requests
+///
+/// SIGTERM and SIGINT shut the daemon down; SIGUSR1 and SIGUSR2 manually activate
+/// and deactivate the tunnel without the control socket.
+async fn forward_signals(
+    handle: DaemonHandle,
+    mut sigterm: Signal,
+    mut sigint: Signal,
+    mut sigusr1: Signal,
+    mut sigusr2: Signal,
+) {
+    loop {
+        let cmd = tokio::select! {
+            _ = sigterm.recv() => {
+                tracing::info!("Received SIGTERM");
+                handle.shutdown();
+                return;
             }
-
-            // Exponential backoff: double the delay for next attempt
-            delay_secs *= 2;
+            _ = sigint.recv() => {
+                tracing::info!("Received SIGINT");
+                handle.shutdown();
+                return;
+            }
+            _ = sigusr1.recv() => {
+                tracing::info!("Received SIGUSR1, activating tunnel");
+                ControlCommand::Up
+            }
+            _ = sigusr2.recv() => {
+                tracing::info!("Received SIGUSR2, deactivating tunnel");
+                ControlCommand::Down
+            }
+        };
+        if let Err(e) = handle.send(cmd).await {
+            tracing::error!("{:#}", e);
+            return;
         }
-
-        // All retries exhausted
-        log::error!(
-            "Failed to attach eBPF after {} attempts. Interface {} still has no IP address. \
-            Consider restarting the daemon after DHCP completes.",
-            MAX_ATTACHMENT_RETRIES,
-            interface
-        );
-        retry_in_progress.store(false, Ordering::SeqCst);
-    });
+    }
 }
 
-/// Perform graceful shutdown: clean up resources before exiting
-#[allow(unused_mut)]
-async fn graceful_shutdown(
-    mut ebpf_manager: EbpfManager,
-    mut wg_controller: WgController,
-    tunnel_state: TunnelState,
-) -> Result<()> {
-    log::info!("Shutting down gracefully...");
-
-    // Detach eBPF program if attached
-    if ebpf_manager.is_attached() {
-        log::info!("Detaching eBPF program...");
-        if let Err(e) = ebpf_manager.detach() {
-            log::error!("Failed to detach eBPF program: {}", e);
+/// Print environment findings, failing if any check failed
+async fn doctor(config_path: &Path) -> Result<()> {
+    // Without a usable config, check for the tools of every backend
+    let config = match load_config(config_path) {
+        Ok(config) => {
+            println!("[  ok] Config {} is valid", config_path.display());
+            Some(config)
         }
-    }
-
-    // Bring down tunnel if active
-    if tunnel_state == TunnelState::Active || tunnel_state == TunnelState::Activating {
-        log::info!("Bringing down WireGuard tunnel...");
-        if let Err(e) = wg_controller.bring_down().await {
-            log::error!("Failed to bring down tunnel: {}", e);
+        Err(e) => {
+            println!("[FAIL] Config {}: {:#}", config_path.display(), e);
+            None
         }
+    };
+    let findings = doctor::run(config.as_ref()).await;
+    for finding in &findings {
+        println!("{}", finding);
     }
 
-    log::info!("Shutdown complete");
+    let failed = findings
+        .iter()
+        .filter(|f| f.status == doctor::Status::Fail)
+        .count()
+        + usize::from(config.is_none());
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed", failed);
+    }
     Ok(())
 }
 
@@ -234,369 +169,131 @@ async fn async_main() -> Result<()> {
     // Parse command line arguments
     let args = Args::parse();
 
-    // Load configuration
-    let config = load_config(&args.config)
-        .with_context(|| format!("Failed to load config from {:?}", args.config))?;
-
-    // Initialize logging
-    env_logger::Builder::from_env(
-        env_logger::Env::default().default_filter_or(&config.general.log_level),
-    )
-    .init();
-
-    log::info!("Starting wg-ondemand daemon");
-
-    // Log SSID filtering configuration
-    if config.general.target_ssids.0.is_empty() && config.general.exclude_ssids.is_empty() {
-        log::info!("SSID filtering: monitoring ALL networks");
-    } else if config.general.target_ssids.0.is_empty() {
-        log::info!(
-            "SSID filtering: all networks EXCEPT {:?}",
-            config.general.exclude_ssids
-        );
-    } else if config.general.exclude_ssids.is_empty() {
-        log::info!("SSID filtering: ONLY {:?}", config.general.target_ssids.0);
-    } else {
-        log::info!(
-            "SSID filtering: {:?} EXCEPT {:?}",
-            config.general.target_ssids.0,
-            config.general.exclude_ssids
-        );
-    }
-
-    log::info!("WireGuard interface: {}", config.general.wg_interface);
-    log::info!("Idle timeout: {}s", config.general.idle_timeout);
-    log::info!("Target subnets: {}", config.subnets.ranges.join(", "));
-
-    // Initialize components
-    let mut wg_controller = WgController::new(
-        config.general.wg_interface.clone(),
-        config.general.nm_connection.clone(),
-    )
-    .context("Failed to create WireGuard controller")?;
-    let mut state_manager = StateManager::new(config.general.idle_timeout);
-
-    // Determine monitor interface (auto-detect if not specified)
-    let monitor_iface = match config.general.monitor_interface.clone() {
-        Some(iface) => {
-            // Validate configured interface name
-            wg_controller::validate_interface_name(&iface)
-                .context("Configured monitor interface has invalid name")?;
-            iface
+    // Client mode: forward a command to the running daemon (of the tunnel
+    // given with --tunnel) and exit
+    let control_socket = Instance::new(args.tunnel.as_deref()).control_socket();
+    match args.command {
+        Some(Command::Control { command }) if command.trim() == "watch" => {
+            control::watch(&control_socket, |line| println!("{}", line)).await?;
+            return Ok(());
         }
-        None => {
-            log::info!("Auto-detecting network interface...");
-            let detected = auto_detect_interface()
-                .await
-                .context("Failed to auto-detect network interface")?;
-            // Validate auto-detected interface name (defense-in-depth)
-            wg_controller::validate_interface_name(&detected)
-                .context("Auto-detected interface has invalid name")?;
-            detected
+        Some(Command::Control { command }) => {
+            let reply = control::send_command(&control_socket, &command).await?;
+            println!("{}", reply);
+            return Ok(());
         }
-    };
-
-    log::info!("Monitoring interface: {}", monitor_iface);
-
-    // Load eBPF program (includes interface existence validation)
-    let mut ebpf_manager = EbpfManager::load(&monitor_iface, &config.subnets.ranges)
-        .context("Failed to load eBPF program")?;
-
-    // Create route manager for traffic detection
-    let mut route_manager = RouteManager::new(monitor_iface.clone());
-
-    // Create SSID monitor
-    let ssid_monitor = SsidMonitor::new(
-        config.general.target_ssids.0.clone(),
-        config.general.exclude_ssids.clone(),
-    )
-    .await
-    .context("Failed to create SSID monitor")?;
-
-    // Channels for communication
-    let (network_tx, mut network_rx) = mpsc::channel::<NetworkEvent>(NETWORK_EVENT_CHANNEL_SIZE);
-    let (state_tx, mut state_rx) = mpsc::channel::<StateCommand>(STATE_COMMAND_CHANNEL_SIZE);
-
-    // Track whether an eBPF attachment retry task is running
-    let retry_in_progress = Arc::new(AtomicBool::new(false));
-
-    // Check initial SSID and tunnel state before spawning monitor
-    let initial_connected = ssid_monitor.is_connected_to_target().await.unwrap_or(false);
-    let tunnel_already_up = wg_controller.is_up().await;
-
-    if initial_connected {
-        if tunnel_already_up {
-            log::info!(
-                "Already connected to monitored network and tunnel is up, transitioning to Active state"
-            );
-            // State sequence: Inactive -> Monitoring -> Active (tunnel already up)
-            state_tx.send(StateCommand::StartMonitoring).await?;
-            state_tx.send(StateCommand::TunnelAlreadyUp).await?;
-        } else {
-            log::info!("Already connected to monitored network, starting monitoring");
-            state_tx.send(StateCommand::StartMonitoring).await?;
+        Some(Command::Doctor) => return doctor(&args.config).await,
+        Some(Command::Init) => return init::run(&args.config).await,
+        Some(Command::Completions { shell, ctl }) => {
+            let mut cmd = if ctl {
+                completions::ctl_command()
+            } else {
+                Args::command()
+            };
+            completions::generate(shell, &mut cmd, &mut std::io::stdout());
+            return Ok(());
         }
+        None => {}
     }
 
-    // Spawn SSID monitor task
-    // Store the handle so we can monitor it for failures
-    let mut monitor_handle = tokio::spawn(async move {
-        if let Err(e) = ssid_monitor.monitor(network_tx).await {
-            log::error!("SSID monitor error: {}", e);
-            // Return error to signal failure
-            Err::<(), anyhow::Error>(e)
-        } else {
-            Ok(())
-        }
-    });
-
-    // Idle check timer
-    let mut idle_timer = interval(Duration::from_secs(IDLE_CHECK_INTERVAL_SECS));
-
-    // eBPF event check timer
-    let mut ebpf_timer = interval(Duration::from_millis(EBPF_POLL_INTERVAL_MILLIS));
-
-    log::info!("Daemon started successfully");
-
-    // Set up signal handlers for graceful shutdown
-    let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
-        .context("Failed to set up SIGTERM handler")?;
-    let mut sigint = signal::unix::signal(signal::unix::SignalKind::interrupt())
-        .context("Failed to set up SIGINT handler")?;
-
-    // Track current SSID for state file updates
-    let mut current_ssid: Option<String> = None;
-
-    // Write initial state
-    let _ = state_file::write_state(state_manager.state(), None);
-
-    // Main event loop
-    loop {
-        tokio::select! {
-            // Shutdown signals
-            _ = sigterm.recv() => {
-                log::info!("Received SIGTERM");
-                break;
-            }
-            _ = sigint.recv() => {
-                log::info!("Received SIGINT");
-                break;
-            }
-
-            // Monitor SSID monitor task for failures (fail-fast approach)
-            monitor_result = &mut monitor_handle => {
-                match monitor_result {
-                    Ok(Ok(())) => {
-                        log::error!("SSID monitor task exited unexpectedly");
-                    }
-                    Ok(Err(e)) => {
-                        log::error!("SSID monitor task failed: {}", e);
-                    }
-                    Err(e) => {
-                        log::error!("SSID monitor task panicked: {}", e);
-                    }
-                }
-                anyhow::bail!("SSID monitor task terminated, aborting daemon for systemd restart");
-            }
-
-            // Network events (SSID changes)
-            Some(event) = network_rx.recv() => {
-                match event {
-                    NetworkEvent::ConnectedToTarget(ssid) => {
-                        log::info!("Network event: Connected to target SSID");
-                        current_ssid = if ssid.is_empty() { None } else { Some(ssid) };
-                        state_tx.send(StateCommand::StartMonitoring).await?;
-                    }
-                    NetworkEvent::Disconnected => {
-                        log::info!("Network event: Disconnected from target SSID");
-                        current_ssid = None;
-                        // Reset retry flag so a new retry can be spawned on next connection
-                        retry_in_progress.store(false, Ordering::SeqCst);
-                        state_tx.send(StateCommand::StopMonitoring).await?;
-                    }
-                }
-            }
-
-            // State commands
-            Some(cmd) = state_rx.recv() => {
-                let action = state_manager.handle_command(cmd);
-
-                match action {
-                    StateAction::AttachEbpf => {
-                        // Check if local IP conflicts with configured subnets
-                        match get_interface_ip(&monitor_iface) {
-                            Ok(Some(local_ip)) => {
-                                // Check if local IP is within any configured subnet
-                                match config::ip_in_subnets(local_ip, &config.subnets.ranges) {
-                                    Ok(true) => {
-                                        let ip_bytes = local_ip.to_be_bytes();
-                                        log::warn!(
-                                            "Local IP {}.{}.{}.{} conflicts with configured subnet ranges. \
-                                            Skipping eBPF attachment to avoid routing loops. \
-                                            This network appears to use the same IP range as your home network.",
-                                            ip_bytes[0], ip_bytes[1], ip_bytes[2], ip_bytes[3]
-                                        );
-                                        // Don't attach eBPF - would cause routing issues
-                                    }
-                                    Ok(false) => {
-                                        // Safe to attach - local IP doesn't conflict
-                                        log::info!("Action: Attaching eBPF program and adding monitoring routes");
-
-                                        // Add monitoring routes first
-                                        if let Err(e) = route_manager.add_routes(&config.subnets.ranges).await {
-                                            log::error!("Failed to add monitoring routes: {}", e);
-                                        }
-
-                                        // Then attach eBPF
-                                        if let Err(e) = ebpf_manager.attach() {
-                                            log::error!("Failed to attach eBPF: {}", e);
-                                        } else {
-                                            log::info!("eBPF program attached and monitoring traffic");
-                                        }
-                                    }
-                                    Err(e) => {
-                                        log::error!("Failed to check IP subnet overlap: {}", e);
-                                    }
-                                }
-                            }
-                            Ok(None) => {
-                                log::warn!(
-                                    "Interface {} has no IPv4 address yet. Will retry with exponential backoff.",
-                                    monitor_iface
-                                );
-                                // Spawn retry task to check for IP address and retry attachment
-                                spawn_attachment_retry_task(
-                                    monitor_iface.clone(),
-                                    state_tx.clone(),
-                                    retry_in_progress.clone(),
-                                );
-                            }
-                            Err(e) => {
-                                log::error!("Failed to get interface IP: {}", e);
-                            }
-                        }
-                    }
-
-                    StateAction::DetachEbpf => {
-                        log::info!("Action: Detaching eBPF program and removing monitoring routes");
-
-                        // Detach eBPF first
-                        if let Err(e) = ebpf_manager.detach() {
-                            log::error!("Failed to detach eBPF: {}", e);
-                        }
-
-                        // Then remove routes
-                        if let Err(e) = route_manager.remove_routes().await {
-                            log::error!("Failed to remove monitoring routes: {}", e);
-                        }
-                    }
-
-                    StateAction::ActivateTunnel => {
-                        log::info!("Action: Activating WireGuard tunnel");
-                        match wg_controller.bring_up().await {
-                            Ok(_) => {
-                                // Reset activity tracking when tunnel comes up
-                                wg_controller.reset_activity();
-                                state_tx.send(StateCommand::TunnelUp).await?;
-                            }
-                            Err(e) => {
-                                log::error!("Failed to bring up tunnel: {}", e);
-                            }
-                        }
-                    }
-
-                    StateAction::DeactivateTunnel => {
-                        log::info!("Action: Deactivating WireGuard tunnel");
-                        match wg_controller.bring_down().await {
-                            Ok(_) => {
-                                state_tx.send(StateCommand::TunnelDown).await?;
-                            }
-                            Err(e) => {
-                                log::error!("Failed to bring down tunnel: {}", e);
-                            }
-                        }
-                    }
-
-                    StateAction::None => {}
-                }
-
-                // Write state file after any state transition
-                let ssid_ref = current_ssid.as_deref();
-                if let Err(e) = state_file::write_state(state_manager.state(), ssid_ref) {
-                    log::warn!("Failed to write state file: {}", e);
-                }
-            }
-
-            // eBPF events (traffic detection) - check periodically
-            _ = ebpf_timer.tick() => {
-                // Poll cached ring buffer (no map lookup overhead)
-                if let Some(rb) = ebpf_manager.poll_events() {
-                    while let Some(data) = rb.next() {
-                            if data.len() == std::mem::size_of::<TrafficEvent>() {
-                                // Use read_unaligned to handle potentially unaligned data from ringbuffer
-                                // This prevents undefined behavior on architectures with strict alignment requirements
-                                let event: TrafficEvent = unsafe {
-                                    std::ptr::read_unaligned(data.as_ptr() as *const TrafficEvent)
-                                };
-
-                                let ip_bytes = event.dest_ip.to_be_bytes();
-                                log::debug!(
-                                    "Traffic detected: {}.{}.{}.{}:{} (proto={})",
-                                    ip_bytes[0], ip_bytes[1], ip_bytes[2], ip_bytes[3],
-                                    event.dest_port,
-                                    event.protocol
-                                );
-
-                                // Notify state manager (apply backpressure - never silently drop events)
-                                // If channel fills, state manager is broken and we should fail-fast
-                                if let Err(e) = state_tx.send(StateCommand::TrafficDetected).await {
-                                    log::error!("State manager channel closed: {}", e);
-                                    anyhow::bail!("State manager task died unexpectedly");
-                                }
-                            }
-                        }
-                    }
-                }
-
-            // Idle timer tick - check for tunnel inactivity
-            _ = idle_timer.tick() => {
-                // Only check idle when tunnel is active
-                if state_manager.state() == TunnelState::Active {
-                    // Check for WireGuard tunnel activity
-                    match wg_controller.check_activity().await {
-                        Ok(has_activity) => {
-                            if has_activity {
-                                log::debug!("Tunnel activity detected");
-                            }
-                        }
-                        Err(e) => {
-                            log::warn!("Failed to check WireGuard activity: {}", e);
-                        }
-                    }
+    let overrides = ConfigOverrides {
+        tunnel: args.tunnel,
+        wg_interface: args.wg_interface,
+        idle_timeout: args.idle_timeout,
+        monitor_interface: args.monitor_interface,
+        subnets: args.subnets,
+    };
 
-                    // Check if idle timeout reached
-                    if let Some(idle_duration) = wg_controller.idle_duration() {
-                        let idle_timeout = state_manager.idle_timeout();
-                        if idle_duration > idle_timeout {
-                            log::info!(
-                                "Idle timeout reached ({:.0}s of {:.0}s)",
-                                idle_duration.as_secs_f32(),
-                                idle_timeout.as_secs_f32()
-                            );
-                            // Trigger deactivation via state manager
-                            state_tx.send(StateCommand::IdleTimeout).await?;
-                        }
-                    }
-                }
-            }
+    // Validate a config (e.g. as ExecStartPre) without starting the daemon
+    if let Some(path) = args.check_config {
+        let config = load_config_with_overrides(&path, &overrides)
+            .with_context(|| format!("Failed to load config from {:?}", path))?;
+        let problems = config_check::check(&config).await;
+        if problems.is_empty() {
+            println!("{}: configuration OK", path.display());
+            return Ok(());
+        }
+        for problem in &problems {
+            eprintln!("{}: {}", path.display(), problem);
         }
+        anyhow::bail!("{} problem(s) found in {:?}", problems.len(), path);
     }
 
-    // Clean up state file
-    state_file::cleanup();
+    // Load configuration
+    let mut config = load_config_with_overrides(&args.config, &overrides)
+        .with_context(|| format!("Failed to load config from {:?}", args.config))?;
+    config.general.dry_run |= args.dry_run;
+
+    // Initialize logging (RUST_LOG overrides the configured level); closing
+    // spans log how long activations, eBPF attachment and route changes took
+    let fmt = tracing_subscriber::fmt::layer().with_span_events(FmtSpan::CLOSE);
+    let fmt = match config.general.log_target {
+        LogTarget::Stderr => fmt.boxed(),
+        // The logger adds the time and the priority carries the level
+        LogTarget::Syslog => fmt
+            .with_writer(Syslog::open())
+            .with_ansi(false)
+            .without_time()
+            .with_level(false)
+            .boxed(),
+    };
+    // The same spans exported to an OpenTelemetry collector
+    #[cfg(feature = "otel")]
+    let telemetry = config
+        .otel
+        .as_ref()
+        .map(Telemetry::init)
+        .transpose()
+        .context("Failed to set up OpenTelemetry export")?;
+    #[cfg(feature = "otel")]
+    let otel = telemetry.as_ref().map(Telemetry::layer);
+    #[cfg(not(feature = "otel"))]
+    let otel = None::<tracing_subscriber::layer::Identity>;
+    tracing_subscriber::registry()
+        .with(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new(&config.general.log_level)),
+        )
+        .with(fmt)
+        .with(otel)
+        .init();
+
+    tracing::info!("Starting wg-ondemand daemon");
+    #[cfg(feature = "otel")]
+    if let Some(otel) = &config.otel {
+        tracing::info!("Exporting traces and metrics to {}", otel.endpoint);
+    }
 
-    // Perform graceful shutdown
-    graceful_shutdown(ebpf_manager, wg_controller, state_manager.state()).await?;
+    // Set up signal handlers for graceful shutdown before startup, so a signal
+    // arriving while the daemon sets up is not fatal
+    let sigterm = signal(SignalKind::terminate()).context("Failed to set up SIGTERM handler")?;
+    let sigint = signal(SignalKind::interrupt()).context("Failed to set up SIGINT handler")?;
+
+    // Manual override without the control socket: USR1 activates, USR2 deactivates
+    let sigusr1 =
+        signal(SignalKind::user_defined1()).context("Failed to set up SIGUSR1 handler")?;
+    let sigusr2 =
+        signal(SignalKind::user_defined2()).context("Failed to set up SIGUSR2 handler")?;
+
+    let mut daemon = Daemon::new(config).await?;
+    tokio::spawn(forward_signals(
+        daemon.handle(),
+        sigterm,
+        sigint,
+        sigusr1,
+        sigusr2,
+    ));
+
+    let result = async {
+        daemon.run().await?;
+        daemon.shutdown().await
+    }
+    .await;
 
-    Ok(())
+    // Off the runtime threads: waits for the collector and drops its HTTP client
+    #[cfg(feature = "otel")]
+    if let Some(telemetry) = telemetry {
+        tokio::task::spawn_blocking(move || telemetry.shutdown()).await?;
+    }
+    result
 }
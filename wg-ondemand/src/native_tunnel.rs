@@ -0,0 +1,383 @@
+// Native WireGuard interface management
+
+//! Native tunnel backend
+//!
+//! Creates, configures and destroys the WireGuard interface directly through
+//! netlink (wireguard-control) instead of shelling out to wg-quick. The interface
+//! is defined either inline in `[native]` or by an existing wg-quick config.
+//! Addresses, MTU and routes for the peers' allowed IPs are set with `ip`.
+
+use crate::endpoint;
+use crate::process;
+use crate::types::{NativePeerConfig, NativeTunnelConfig};
+use crate::wg_quick::{self, WgQuickConfig, WgQuickPeer};
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+use wireguard_control::{
+    AllowedIp, Backend, Device, DeviceUpdate, InterfaceName, Key, PeerConfigBuilder,
+};
+
+/// Parse an address in CIDR notation (IPv4 or IPv6)
+///
+/// # Errors
+///
+/// Returns an error if the address or prefix length is invalid.
+pub fn parse_allowed_ip(cidr: &str) -> Result<AllowedIp> {
+    let allowed: AllowedIp = cidr
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid CIDR '{}' (expected address/prefix)", cidr))?;
+    let max_prefix = if allowed.address.is_ipv4() { 32 } else { 128 };
+    if allowed.cidr > max_prefix {
+        anyhow::bail!("Prefix length of '{}' must be <= {}", cidr, max_prefix);
+    }
+    Ok(allowed)
+}
+
+/// Parse an allowed IP for the native backend, which cannot route a full tunnel
+///
+/// # Errors
+///
+/// Returns an error if the CIDR is invalid or a default route (`/0`).
+pub fn check_native_allowed_ip(cidr: &str) -> Result<AllowedIp> {
+    let allowed = parse_allowed_ip(cidr).context("Invalid allowed_ips entry")?;
+    // Default routes need wg-quick's policy routing to avoid looping the tunnel's
+    // own traffic
+    if allowed.cidr == 0 {
+        anyhow::bail!(
+            "allowed_ips {} (full tunnel) is not supported by the native backend, use wg-quick",
+            cidr
+        );
+    }
+    Ok(allowed)
+}
+
+/// Decode a base64 WireGuard key
+fn parse_key(value: &str, what: &str) -> Result<Key> {
+    Key::from_base64(value.trim()).map_err(|_| anyhow::anyhow!("Invalid {}", what))
+}
+
+/// Read a base64 WireGuard key from a file
+fn read_key(path: &Path) -> Result<Key> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read key {:?}", path))?;
+    parse_key(&contents, &format!("key in {:?}", path))
+}
+
+/// Peer with keys decoded
+struct Peer {
+    public_key: Key,
+    preshared_key: Option<Key>,
+    endpoint: Option<String>,
+    allowed_ips: Vec<String>,
+    persistent_keepalive: Option<u16>,
+}
+
+/// WireGuard interface managed directly via netlink
+pub struct NativeTunnel {
+    interface: String,
+    private_key: Key,
+    addresses: Vec<String>,
+    listen_port: Option<u16>,
+    mtu: Option<u32>,
+    peers: Vec<Peer>,
+    command_timeout: Duration,
+}
+
+impl NativeTunnel {
+    /// Create a native tunnel for `interface` from the `[native]` definition
+    ///
+    /// If `config_file` is set, the interface and peers are read from that wg-quick
+    /// config instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a key or the wg-quick config cannot be read or is invalid.
+    pub fn from_config(
+        interface: String,
+        config: &NativeTunnelConfig,
+        command_timeout: Duration,
+    ) -> Result<Self> {
+        if let Some(path) = &config.config_file {
+            return Self::from_wg_quick(interface, &wg_quick::load(path)?, command_timeout);
+        }
+
+        let private_key_file = config
+            .private_key_file
+            .as_ref()
+            .context("native.private_key_file is not set")?;
+        let peers = config
+            .peers
+            .iter()
+            .map(|peer: &NativePeerConfig| {
+                Ok(Peer {
+                    public_key: parse_key(&peer.public_key, "peer public key")?,
+                    preshared_key: peer
+                        .preshared_key_file
+                        .as_deref()
+                        .map(read_key)
+                        .transpose()?,
+                    endpoint: peer.endpoint.clone(),
+                    allowed_ips: peer.allowed_ips.clone(),
+                    persistent_keepalive: peer.persistent_keepalive,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            interface,
+            private_key: read_key(private_key_file)?,
+            addresses: config.addresses.clone(),
+            listen_port: config.listen_port,
+            mtu: config.mtu,
+            peers,
+            command_timeout,
+        })
+    }
+
+    /// Create a native tunnel for `interface` from a parsed wg-quick config
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if keys are missing or invalid, or a peer routes a full tunnel.
+    pub fn from_wg_quick(
+        interface: String,
+        config: &WgQuickConfig,
+        command_timeout: Duration,
+    ) -> Result<Self> {
+        let private_key = config
+            .interface
+            .private_key
+            .as_deref()
+            .context("wg-quick config has no PrivateKey")?;
+        if !config.interface.dns.is_empty() {
+            tracing::warn!(
+                "DNS settings from the wg-quick config are not applied by the native backend"
+            );
+        }
+
+        let peers = config
+            .peers
+            .iter()
+            .map(|peer: &WgQuickPeer| {
+                for cidr in &peer.allowed_ips {
+                    check_native_allowed_ip(cidr)?;
+                }
+                Ok(Peer {
+                    public_key: parse_key(&peer.public_key, "peer PublicKey")?,
+                    preshared_key: peer
+                        .preshared_key
+                        .as_deref()
+                        .map(|psk| parse_key(psk, "PresharedKey"))
+                        .transpose()?,
+                    endpoint: peer.endpoint.clone(),
+                    allowed_ips: peer.allowed_ips.clone(),
+                    persistent_keepalive: peer.persistent_keepalive,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            interface,
+            private_key: parse_key(private_key, "PrivateKey")?,
+            addresses: config.interface.addresses.clone(),
+            listen_port: config.interface.listen_port,
+            mtu: config.interface.mtu,
+            peers,
+            command_timeout,
+        })
+    }
+
+    /// Number of configured peers
+    pub fn peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Run `ip` with the given arguments, failing on a non-zero exit status
+    async fn ip(&self, args: &[&str]) -> Result<()> {
+        let mut cmd = Command::new("ip");
+        cmd.args(args);
+        let output = process::run(cmd, self.command_timeout).await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "ip {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    /// Build the peer configuration, resolving endpoint hostnames
+    async fn peer_builder(peer: &Peer) -> Result<PeerConfigBuilder> {
+        let mut builder = PeerConfigBuilder::new(&peer.public_key).replace_allowed_ips();
+        if let Some(address) = &peer.endpoint {
+            builder = builder.set_endpoint(endpoint::resolve(address).await?);
+        }
+        if let Some(psk) = &peer.preshared_key {
+            builder = builder.set_preshared_key(psk.clone());
+        }
+        if let Some(interval) = peer.persistent_keepalive {
+            builder = builder.set_persistent_keepalive_interval(interval);
+        }
+        for cidr in &peer.allowed_ips {
+            let allowed = parse_allowed_ip(cidr)?;
+            builder = builder.add_allowed_ip(allowed.address, allowed.cidr);
+        }
+        Ok(builder)
+    }
+
+    /// Create and configure the interface, then bring it up with addresses and routes
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any step fails; a partially configured interface is removed.
+    pub async fn up(&self) -> Result<()> {
+        let result = self.configure().await;
+        if result.is_err() {
+            if let Err(e) = self.down().await {
+                tracing::warn!("Failed to remove partially configured interface: {}", e);
+            }
+        }
+        result
+    }
+
+    async fn configure(&self) -> Result<()> {
+        let mut update = DeviceUpdate::new()
+            .set_private_key(self.private_key.clone())
+            .replace_peers();
+        if let Some(port) = self.listen_port {
+            update = update.set_listen_port(port);
+        }
+        for peer in &self.peers {
+            update = update.add_peer(Self::peer_builder(peer).await?);
+        }
+
+        let iface_name = self.interface_name()?;
+        tokio::task::spawn_blocking(move || update.apply(&iface_name, Backend::Kernel))
+            .await
+            .context("Netlink task panicked")?
+            .context("Failed to configure WireGuard interface")?;
+
+        for address in &self.addresses {
+            self.ip(&["address", "add", address, "dev", &self.interface])
+                .await?;
+        }
+
+        let mtu = self.mtu.map(|mtu| mtu.to_string());
+        match &mtu {
+            Some(mtu) => {
+                self.ip(&["link", "set", "mtu", mtu, "up", "dev", &self.interface])
+                    .await?
+            }
+            None => {
+                self.ip(&["link", "set", "up", "dev", &self.interface])
+                    .await?
+            }
+        }
+
+        for cidr in self.peers.iter().flat_map(|p| &p.allowed_ips) {
+            self.ip(&["route", "replace", cidr, "dev", &self.interface])
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Destroy the interface (succeeds if it is already gone)
+    pub async fn down(&self) -> Result<()> {
+        let iface_name = self.interface_name()?;
+        tokio::task::spawn_blocking(move || {
+            if !Device::list(Backend::Kernel)?.contains(&iface_name) {
+                return Ok(());
+            }
+            Device::get(&iface_name, Backend::Kernel)?.delete()
+        })
+        .await
+        .context("Netlink task panicked")?
+        .context("Failed to delete WireGuard interface")
+    }
+
+    fn interface_name(&self) -> Result<InterfaceName> {
+        self.interface
+            .parse()
+            .with_context(|| format!("Invalid interface name: {}", self.interface))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_allowed_ip() {
+        let v4 = parse_allowed_ip("192.168.1.0/24").unwrap();
+        assert_eq!(
+            v4.address,
+            "192.168.1.0".parse::<std::net::IpAddr>().unwrap()
+        );
+        assert_eq!(v4.cidr, 24);
+
+        let v6 = parse_allowed_ip("fd00::/64").unwrap();
+        assert_eq!(v6.cidr, 64);
+
+        assert!(parse_allowed_ip("192.168.1.0/33").is_err());
+        assert!(parse_allowed_ip("192.168.1.0").is_err());
+        assert!(parse_allowed_ip("not-an-ip/24").is_err());
+    }
+
+    #[test]
+    fn test_check_native_allowed_ip() {
+        assert!(check_native_allowed_ip("192.168.1.0/24").is_ok());
+        assert!(check_native_allowed_ip("0.0.0.0/0").is_err());
+        assert!(check_native_allowed_ip("::/0").is_err());
+    }
+
+    #[test]
+    fn test_from_wg_quick() {
+        let config = wg_quick::parse(
+            "[Interface]\n\
+             PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\n\
+             Address = 10.0.0.2/32\n\
+             [Peer]\n\
+             PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\n\
+             AllowedIPs = 192.168.1.0/24\n",
+        )
+        .unwrap();
+        let tunnel =
+            NativeTunnel::from_wg_quick("wg0".to_string(), &config, Duration::from_secs(5))
+                .unwrap();
+        assert_eq!(tunnel.peer_count(), 1);
+        assert_eq!(tunnel.addresses, vec!["10.0.0.2/32".to_string()]);
+
+        let mut full_tunnel = config.clone();
+        full_tunnel.peers[0].allowed_ips = vec!["0.0.0.0/0".to_string()];
+        assert!(NativeTunnel::from_wg_quick(
+            "wg0".to_string(),
+            &full_tunnel,
+            Duration::from_secs(5)
+        )
+        .is_err());
+
+        let mut no_key = config.clone();
+        no_key.interface.private_key = None;
+        assert!(
+            NativeTunnel::from_wg_quick("wg0".to_string(), &no_key, Duration::from_secs(5))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_read_key() {
+        let path = std::env::temp_dir().join(format!("wg-ondemand-key-{}", std::process::id()));
+        std::fs::write(&path, "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\n").unwrap();
+        assert!(read_key(&path).is_ok());
+
+        std::fs::write(&path, "garbage").unwrap();
+        assert!(read_key(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_key(&path).is_err());
+    }
+}
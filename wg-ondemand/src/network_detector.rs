@@ -0,0 +1,176 @@
+// Pluggable network detection backends
+
+//! Network detection
+//!
+//! The daemon only needs to know whether the machine is on a monitored network
+//! and to be told when that changes. [`NetworkDetector`] abstracts this so
+//! backends other than NetworkManager's D-Bus API
+//! ([`SsidMonitor`](crate::ssid_monitor::SsidMonitor)) can be plugged in, and
+//! [`ScriptedDetector`] replays fixed events for tests without a system bus.
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+/// Network event types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// Connected to the target SSID (with SSID name)
+    ConnectedToTarget(String),
+    /// Disconnected from the target SSID (or connected to different network)
+    Disconnected,
+    /// Roamed to another access point of the monitored network, while staying
+    /// connected (NetworkManager only)
+    Roamed {
+        /// SSID of the network
+        ssid: String,
+        /// BSSID of the new access point
+        bssid: String,
+    },
+}
+
+/// Check `ssid` against the whitelist/blacklist rules
+///
+/// The blacklist takes precedence; an empty whitelist allows every SSID.
+pub fn is_monitored_ssid(ssid: &str, target_ssids: &[String], exclude_ssids: &[String]) -> bool {
+    let listed = |ssids: &[String]| ssids.iter().any(|s| s == ssid);
+    // First check blacklist (takes precedence)
+    if listed(exclude_ssids) {
+        tracing::debug!("SSID '{}' is in exclude list", ssid);
+        return false;
+    }
+
+    // Then check whitelist
+    if target_ssids.is_empty() {
+        // Empty whitelist means "all SSIDs" (except those excluded)
+        tracing::debug!("SSID '{}' allowed (monitor all mode)", ssid);
+        true
+    } else {
+        // Non-empty whitelist: must be in the list
+        let is_target = listed(target_ssids);
+        if is_target {
+            tracing::debug!("SSID '{}' is in target list", ssid);
+        } else {
+            tracing::debug!("SSID '{}' not in target list", ssid);
+        }
+        is_target
+    }
+}
+
+/// Source of network change events
+pub trait NetworkDetector: Send + Sync {
+    /// Name (SSID) of the network currently connected to, monitored or not
+    fn current_network(&self) -> BoxFuture<'_, Result<Option<String>>>;
+
+    /// Whether the current network is one the daemon should monitor
+    fn is_connected_to_target(&self) -> BoxFuture<'_, Result<bool>>;
+
+    /// Send an event on `tx` whenever the monitored network is joined or left
+    ///
+    /// Runs until the event source goes away; returning means no further
+    /// changes will be detected.
+    fn monitor(&self, tx: mpsc::Sender<NetworkEvent>) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Detector replaying a fixed sequence of events
+///
+/// Starts on the monitored network `initial` (if any), sends `events` in order
+/// once monitoring starts and then stays idle, as a quiet network would.
+pub struct ScriptedDetector {
+    current: Mutex<Option<String>>,
+    events: Mutex<VecDeque<NetworkEvent>>,
+}
+
+impl ScriptedDetector {
+    /// Create a detector starting on `initial` that will send `events`
+    pub fn new(initial: Option<String>, events: Vec<NetworkEvent>) -> Self {
+        Self {
+            current: Mutex::new(initial),
+            events: Mutex::new(events.into()),
+        }
+    }
+
+    fn current(&self) -> Option<String> {
+        self.current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl NetworkDetector for ScriptedDetector {
+    fn current_network(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move { Ok(self.current()) })
+    }
+
+    fn is_connected_to_target(&self) -> BoxFuture<'_, Result<bool>> {
+        Box::pin(async move { Ok(self.current().is_some()) })
+    }
+
+    fn monitor(&self, tx: mpsc::Sender<NetworkEvent>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            loop {
+                let event = self
+                    .events
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .pop_front();
+                let Some(event) = event else {
+                    break;
+                };
+                *self.current.lock().unwrap_or_else(|e| e.into_inner()) = match &event {
+                    NetworkEvent::ConnectedToTarget(ssid) | NetworkEvent::Roamed { ssid, .. } => {
+                        Some(ssid.clone())
+                    }
+                    NetworkEvent::Disconnected => None,
+                };
+                if tx.send(event).await.is_err() {
+                    return Ok(());
+                }
+            }
+            // Keep running like a real detector on an unchanging network
+            std::future::pending().await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_monitored_ssid() {
+        let home = vec!["home".to_string()];
+        assert!(is_monitored_ssid("cafe", &[], &[]));
+        assert!(!is_monitored_ssid("home", &[], &home));
+        assert!(is_monitored_ssid("home", &home, &[]));
+        assert!(!is_monitored_ssid("cafe", &home, &[]));
+        assert!(!is_monitored_ssid("home", &home, &home));
+    }
+
+    #[tokio::test]
+    async fn test_scripted_detector() {
+        let detector: Box<dyn NetworkDetector> = Box::new(ScriptedDetector::new(
+            None,
+            vec![
+                NetworkEvent::ConnectedToTarget("home".to_string()),
+                NetworkEvent::Disconnected,
+            ],
+        ));
+        assert!(!detector.is_connected_to_target().await.unwrap());
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let monitor = tokio::spawn(async move {
+            let _ = detector.monitor(tx).await;
+        });
+        assert_eq!(
+            rx.recv().await,
+            Some(NetworkEvent::ConnectedToTarget("home".to_string()))
+        );
+        assert_eq!(rx.recv().await, Some(NetworkEvent::Disconnected));
+        assert!(rx.try_recv().is_err());
+        monitor.abort();
+    }
+}
@@ -0,0 +1,619 @@
+// NFQUEUE hold-and-release of triggering traffic
+
+//! Hold-and-release of triggering traffic
+//!
+//! Normally the packet that triggers activation leaves through the monitoring
+//! route in plaintext (or is dropped by the kill switch), and the connection only
+//! succeeds once the application retries. With `hold_traffic = true` an nftables
+//! table queues traffic to the target subnets to userspace instead. While the
+//! tunnel is coming up the packets are held and reported as traffic events. Once
+//! it is active they are re-injected with [`RELEASE_MARK`], which makes the kernel
+//! route them again, now through the tunnel. Released packets are masqueraded on
+//! the tunnel interface since their source address belongs to the local network.
+//!
+//! Packets are dropped if activation is abandoned or they are held longer than
+//! [`HOLD_TIMEOUT`]. Outside monitored networks queued packets are accepted
+//! right away. The queue is bound with the `bypass` flag, so traffic flows
+//! normally if the daemon is not listening.
+
+use crate::kill_switch::run_nft;
+use crate::probe::PROBE_MARK;
+use crate::types::{TrafficEvent, TunnelState};
+use anyhow::{Context, Result};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Instant};
+
+/// Netfilter queue number used by the daemon (see
+/// [`Instance::queue_num`](crate::instance::Instance::queue_num))
+pub const QUEUE_NUM: u16 = 51820;
+
+/// Mark set on released packets so they are routed again and masqueraded
+pub const RELEASE_MARK: u32 = 0x7767_6f64;
+
+/// nftables table owned by the traffic hold (in the `ip` family; see
+/// [`Instance::nft_table`](crate::instance::Instance::nft_table))
+pub const NFT_TABLE: &str = "wg_ondemand_hold";
+
+/// Longest time a packet is held before it is dropped
+pub const HOLD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Bytes of each packet copied to userspace (IPv4 header and ports)
+const COPY_RANGE: u32 = 64;
+
+/// Capacity of the channel reporting held traffic to the daemon
+const EVENT_CHANNEL_SIZE: usize = 64;
+
+// Netlink and nfnetlink_queue constants (linux/netlink.h, linux/netfilter/nfnetlink_queue.h)
+const NLMSG_HDRLEN: usize = 16;
+const NFGENMSG_LEN: usize = 4;
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_ACK: u16 = 4;
+const NFNL_SUBSYS_QUEUE: u16 = 3;
+const NFQNL_MSG_PACKET: u16 = 0;
+const NFQNL_MSG_VERDICT: u16 = 1;
+const NFQNL_MSG_CONFIG: u16 = 2;
+const NFQA_PACKET_HDR: u16 = 1;
+const NFQA_VERDICT_HDR: u16 = 2;
+const NFQA_MARK: u16 = 3;
+const NFQA_PAYLOAD: u16 = 10;
+const NFQA_CFG_CMD: u16 = 1;
+const NFQA_CFG_PARAMS: u16 = 2;
+const NFQNL_CFG_CMD_BIND: u8 = 1;
+const NFQNL_COPY_PACKET: u8 = 2;
+const NF_DROP: u32 = 0;
+const NF_ACCEPT: u32 = 1;
+
+/// Queued packet: its id and the copied start of the IP packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct QueuedPacket {
+    id: u32,
+    payload: Vec<u8>,
+}
+
+/// Round up to the 4-byte netlink alignment
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Build an nfnetlink_queue message with the given attributes
+fn message(msg_type: u16, flags: u16, seq: u32, queue: u16, attrs: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut buf = vec![0; NLMSG_HDRLEN];
+    buf.extend_from_slice(&[libc::AF_UNSPEC as u8, 0]);
+    buf.extend_from_slice(&queue.to_be_bytes());
+    for (attr_type, payload) in attrs {
+        let len = 4 + payload.len();
+        buf.extend_from_slice(&(len as u16).to_ne_bytes());
+        buf.extend_from_slice(&attr_type.to_ne_bytes());
+        buf.extend_from_slice(payload);
+        buf.resize(align(buf.len()), 0);
+    }
+
+    let len = buf.len() as u32;
+    buf[0..4].copy_from_slice(&len.to_ne_bytes());
+    buf[4..6].copy_from_slice(&((NFNL_SUBSYS_QUEUE << 8) | msg_type).to_ne_bytes());
+    buf[6..8].copy_from_slice(&flags.to_ne_bytes());
+    buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+    buf
+}
+
+/// Verdict for a queued packet, optionally re-marking it
+fn verdict_message(seq: u32, queue: u16, id: u32, verdict: u32, mark: Option<u32>) -> Vec<u8> {
+    let mut header = verdict.to_be_bytes().to_vec();
+    header.extend_from_slice(&id.to_be_bytes());
+    let mark = mark.map(u32::to_be_bytes);
+    let mut attrs: Vec<(u16, &[u8])> = vec![(NFQA_VERDICT_HDR, &header)];
+    if let Some(mark) = &mark {
+        attrs.push((NFQA_MARK, mark));
+    }
+    message(NFQNL_MSG_VERDICT, NLM_F_REQUEST, seq, queue, &attrs)
+}
+
+/// Split a netlink datagram into messages: (type, message payload)
+fn messages(buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    let mut rest = buf;
+    std::iter::from_fn(move || {
+        if rest.len() < NLMSG_HDRLEN {
+            return None;
+        }
+        let len = u32::from_ne_bytes(rest[0..4].try_into().ok()?) as usize;
+        if len < NLMSG_HDRLEN || len > rest.len() {
+            return None;
+        }
+        let msg_type = u16::from_ne_bytes(rest[4..6].try_into().ok()?);
+        let payload = &rest[NLMSG_HDRLEN..len];
+        rest = &rest[align(len).min(rest.len())..];
+        Some((msg_type, payload))
+    })
+}
+
+/// Attributes following the nfgenmsg header of a message payload
+fn attributes(payload: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    let mut rest = payload.get(NFGENMSG_LEN..).unwrap_or_default();
+    std::iter::from_fn(move || {
+        if rest.len() < 4 {
+            return None;
+        }
+        let len = u16::from_ne_bytes([rest[0], rest[1]]) as usize;
+        // The top bits carry the nested/byte-order flags
+        let attr_type = u16::from_ne_bytes([rest[2], rest[3]]) & 0x3fff;
+        if len < 4 || len > rest.len() {
+            return None;
+        }
+        let value = &rest[4..len];
+        rest = &rest[align(len).min(rest.len())..];
+        Some((attr_type, value))
+    })
+}
+
+/// Queued packets in a datagram read from the queue socket
+fn parse_packets(buf: &[u8]) -> Vec<QueuedPacket> {
+    messages(buf)
+        .filter(|(msg_type, _)| *msg_type == (NFNL_SUBSYS_QUEUE << 8) | NFQNL_MSG_PACKET)
+        .filter_map(|(_, payload)| {
+            let mut id = None;
+            let mut packet = Vec::new();
+            for (attr_type, value) in attributes(payload) {
+                match attr_type {
+                    NFQA_PACKET_HDR => {
+                        id = value
+                            .get(0..4)
+                            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+                    }
+                    NFQA_PAYLOAD => packet = value.to_vec(),
+                    _ => {}
+                }
+            }
+            Some(QueuedPacket {
+                id: id?,
+                payload: packet,
+            })
+        })
+        .collect()
+}
+
+/// Traffic event for the start of an IPv4 packet, as the eBPF classifier reports it
+fn traffic_event(packet: &[u8], timestamp: u64) -> Option<TrafficEvent> {
+    let version_ihl = *packet.first()?;
+    if version_ihl >> 4 != 4 {
+        return None;
+    }
+    let header_len = usize::from(version_ihl & 0x0f) * 4;
+    let protocol = *packet.get(9)?;
+    let dest = packet.get(16..20)?;
+    let dest_port = match protocol {
+        6 | 17 => packet
+            .get(header_len + 2..header_len + 4)
+            .map_or(0, |p| u16::from_be_bytes([p[0], p[1]])),
+        _ => 0,
+    };
+    Some(TrafficEvent {
+        timestamp,
+        dest_ip: u32::from_be_bytes([dest[0], dest[1], dest[2], dest[3]]),
+        dest_port,
+        protocol,
+        _padding: 0,
+    })
+}
+
+/// nftables commands installing `table`, which sends traffic to `queue` and
+/// masquerades released packets
+pub fn ruleset(table: &str, queue: u16, wg_interface: &str, subnets: &[String]) -> String {
+    format!(
+        "add table ip {table}; delete table ip {table}; table ip {table} {{ \
+         chain output {{ type route hook output priority -150; policy accept; \
+         meta mark {probe:#x} accept; oifname != \"{iface}\" ip daddr {{ {subnets} }} meta mark != {mark:#x} \
+         queue num {queue} bypass; }} \
+         chain postrouting {{ type nat hook postrouting priority 100; policy accept; \
+         oifname \"{iface}\" meta mark {mark:#x} masquerade; }} }}",
+        table = table,
+        iface = wg_interface,
+        subnets = subnets.join(", "),
+        mark = RELEASE_MARK,
+        probe = PROBE_MARK,
+        queue = queue,
+    )
+}
+
+/// Remove `table` if it exists, e.g. one left behind by a crashed instance
+pub async fn remove_table(table: &str, timeout: Duration) -> Result<()> {
+    run_nft(
+        &format!("add table ip {0}; delete table ip {0}", table),
+        timeout,
+    )
+    .await
+}
+
+/// Netlink socket bound to a netfilter queue
+struct QueueSocket {
+    fd: AsyncFd<OwnedFd>,
+    queue: u16,
+    seq: u32,
+}
+
+impl QueueSocket {
+    /// Open a socket and bind it to `queue`, copying the start of each packet
+    async fn bind(queue: u16) -> Result<Self> {
+        // SAFETY: plain socket(2) call; the descriptor is owned right away
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_NETFILTER,
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error()).context("Failed to open netfilter socket");
+        }
+        // SAFETY: `raw` is a freshly created descriptor not owned elsewhere
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // SAFETY: an all-zero sockaddr_nl is valid; the kernel assigns the port id
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        // SAFETY: `addr` is a valid sockaddr_nl of the given size
+        let ret = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error()).context("Failed to bind netfilter socket");
+        }
+
+        let mut socket = Self {
+            fd: AsyncFd::new(fd).context("Failed to register netfilter socket")?,
+            queue,
+            seq: 0,
+        };
+        socket
+            .configure(NFQA_CFG_CMD, &[NFQNL_CFG_CMD_BIND, 0, 0, 0])
+            .await
+            .with_context(|| format!("Failed to bind netfilter queue {}", queue))?;
+        let mut params = COPY_RANGE.to_be_bytes().to_vec();
+        params.push(NFQNL_COPY_PACKET);
+        socket
+            .configure(NFQA_CFG_PARAMS, &params)
+            .await
+            .context("Failed to set netfilter queue copy mode")?;
+        Ok(socket)
+    }
+
+    /// Send a config message and wait for its acknowledgement
+    async fn configure(&mut self, attr_type: u16, value: &[u8]) -> Result<()> {
+        self.seq += 1;
+        let msg = message(
+            NFQNL_MSG_CONFIG,
+            NLM_F_REQUEST | NLM_F_ACK,
+            self.seq,
+            self.queue,
+            &[(attr_type, value)],
+        );
+        self.send(&msg).await?;
+
+        let mut buf = vec![0; 4096];
+        loop {
+            let len = self.recv(&mut buf).await?;
+            for (msg_type, payload) in messages(&buf[..len]) {
+                if msg_type == NLMSG_ERROR {
+                    let errno = payload
+                        .get(0..4)
+                        .map_or(0, |b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]));
+                    if errno != 0 {
+                        return Err(io::Error::from_raw_os_error(-errno).into());
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Send a verdict for a held packet
+    async fn verdict(&mut self, id: u32, verdict: u32, mark: Option<u32>) {
+        self.seq += 1;
+        let msg = verdict_message(self.seq, self.queue, id, verdict, mark);
+        if let Err(e) = self.send(&msg).await {
+            tracing::warn!("Failed to send verdict for queued packet {}: {}", id, e);
+        }
+    }
+
+    async fn send(&self, buf: &[u8]) -> io::Result<()> {
+        loop {
+            let mut guard = self.fd.writable().await?;
+            let result = guard.try_io(|fd| {
+                // SAFETY: `buf` is valid for reads of its length
+                let ret = unsafe {
+                    libc::send(
+                        fd.as_raw_fd(),
+                        buf.as_ptr() as *const libc::c_void,
+                        buf.len(),
+                        0,
+                    )
+                };
+                if ret < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+            if let Ok(result) = result {
+                return result;
+            }
+        }
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let result = guard.try_io(|fd| {
+                // SAFETY: `buf` is valid for writes of its length
+                let ret = unsafe {
+                    libc::recv(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        0,
+                    )
+                };
+                if ret < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(ret as usize)
+                }
+            });
+            if let Ok(result) = result {
+                return result;
+            }
+        }
+    }
+}
+
+/// Queue listener holding traffic to the target subnets while the tunnel comes up
+pub struct TrafficHold {
+    task: JoinHandle<()>,
+    events: mpsc::Receiver<TrafficEvent>,
+    state: watch::Sender<TunnelState>,
+    table: String,
+    command_timeout: Duration,
+}
+
+impl TrafficHold {
+    /// Bind `queue` and install the nftables `table`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the queue cannot be bound (another listener, missing
+    /// CAP_NET_ADMIN) or `nft` fails.
+    pub async fn start(
+        table: &str,
+        queue: u16,
+        wg_interface: &str,
+        subnets: &[String],
+        state: TunnelState,
+        command_timeout: Duration,
+    ) -> Result<Self> {
+        // Bind before queueing anything so no packet waits for a listener
+        let socket = QueueSocket::bind(queue).await?;
+        run_nft(
+            &ruleset(table, queue, wg_interface, subnets),
+            command_timeout,
+        )
+        .await
+        .context("Failed to install traffic hold rules")?;
+
+        let (events_tx, events) = mpsc::channel(EVENT_CHANNEL_SIZE);
+        let (state, state_rx) = watch::channel(state);
+        let task = tokio::spawn(hold_packets(socket, events_tx, state_rx));
+        tracing::info!(
+            "Holding traffic to target subnets on queue {} until the tunnel is up",
+            queue
+        );
+        Ok(Self {
+            task,
+            events,
+            state,
+            table: table.to_string(),
+            command_timeout,
+        })
+    }
+
+    /// Tell the listener about a state change (releases or drops held packets)
+    pub fn set_state(&self, state: TunnelState) {
+        self.state.send_if_modified(|current| {
+            let changed = *current != state;
+            *current = state;
+            changed
+        });
+    }
+
+    /// Next packet that was queued while monitoring
+    pub async fn next_event(&mut self) -> Option<TrafficEvent> {
+        self.events.recv().await
+    }
+
+    /// Remove the nftables table and stop listening
+    pub async fn stop(self) {
+        if let Err(e) = remove_table(&self.table, self.command_timeout).await {
+            tracing::error!("Failed to remove traffic hold rules: {:#}", e);
+        }
+        // Packets still queued are dropped with the socket
+        self.task.abort();
+    }
+}
+
+/// Listener task: hold, release or pass queued packets depending on the state
+async fn hold_packets(
+    mut socket: QueueSocket,
+    events: mpsc::Sender<TrafficEvent>,
+    mut state: watch::Receiver<TunnelState>,
+) {
+    let mut held: Vec<(u32, Instant)> = Vec::new();
+    let mut expiry = interval(Duration::from_secs(1));
+    let mut buf = vec![0; 8192];
+
+    loop {
+        tokio::select! {
+            received = socket.recv(&mut buf) => {
+                let len = match received {
+                    Ok(len) => len,
+                    Err(e) => {
+                        // ENOBUFS: the kernel dropped queued packets while we were busy
+                        tracing::warn!("Netfilter queue receive failed: {}", e);
+                        continue;
+                    }
+                };
+                for packet in parse_packets(&buf[..len]) {
+                    let current = *state.borrow();
+                    if !matches!(current, TunnelState::Monitoring | TunnelState::Activating) {
+                        socket.verdict(packet.id, NF_ACCEPT, None).await;
+                        continue;
+                    }
+                    held.push((packet.id, Instant::now()));
+                    let timestamp = crate::stats::monotonic_now_ns();
+                    if let Some(event) = traffic_event(&packet.payload, timestamp) {
+                        if events.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+
+            changed = state.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                let current = *state.borrow_and_update();
+                let (verdict, mark, what) = match current {
+                    TunnelState::Activating => continue,
+                    TunnelState::Active => (NF_ACCEPT, Some(RELEASE_MARK), "Released"),
+                    _ => (NF_DROP, None, "Dropped"),
+                };
+                if !held.is_empty() {
+                    tracing::info!("{} {} held packet(s)", what, held.len());
+                }
+                for (id, _) in held.drain(..) {
+                    socket.verdict(id, verdict, mark).await;
+                }
+            }
+
+            _ = expiry.tick() => {
+                let now = Instant::now();
+                let expired = held
+                    .iter()
+                    .take_while(|(_, since)| now.duration_since(*since) > HOLD_TIMEOUT)
+                    .count();
+                if expired > 0 {
+                    tracing::debug!("Dropping {} packet(s) held too long", expired);
+                }
+                for (id, _) in held.drain(..expired) {
+                    socket.verdict(id, NF_DROP, None).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packet message as the kernel sends it: header (id), then payload
+    fn packet_message(id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut header = id.to_be_bytes().to_vec();
+        header.extend_from_slice(&[0x08, 0x00, 3]);
+        message(
+            NFQNL_MSG_PACKET,
+            0,
+            0,
+            QUEUE_NUM,
+            &[(NFQA_PACKET_HDR, &header), (NFQA_PAYLOAD, payload)],
+        )
+    }
+
+    /// IPv4 + TCP header start for a packet to 192.168.1.10:22
+    fn tcp_packet() -> Vec<u8> {
+        let mut packet = vec![0u8; 24];
+        packet[0] = 0x45;
+        packet[9] = 6;
+        packet[12..16].copy_from_slice(&[10, 0, 0, 5]);
+        packet[16..20].copy_from_slice(&[192, 168, 1, 10]);
+        packet[22..24].copy_from_slice(&22u16.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_parse_packets() {
+        let mut buf = packet_message(7, &tcp_packet());
+        buf.extend(packet_message(8, &[0x45]));
+        let packets = parse_packets(&buf);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].id, 7);
+        assert_eq!(packets[0].payload, tcp_packet());
+        assert_eq!(packets[1].id, 8);
+
+        // Truncated datagrams yield what is complete
+        assert_eq!(parse_packets(&buf[..buf.len() - 1]).len(), 1);
+        assert!(parse_packets(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_traffic_event() {
+        let event = traffic_event(&tcp_packet(), 42).unwrap();
+        assert_eq!(event.timestamp, 42);
+        assert_eq!(event.destination(), "192.168.1.10:22/tcp");
+
+        let mut icmp = tcp_packet();
+        icmp[9] = 1;
+        assert_eq!(
+            traffic_event(&icmp, 0).unwrap().destination(),
+            "192.168.1.10:0/icmp"
+        );
+        assert!(traffic_event(&[0x60; 40], 0).is_none());
+        assert!(traffic_event(&[0x45; 10], 0).is_none());
+    }
+
+    #[test]
+    fn test_verdict_message() {
+        let msg = verdict_message(3, QUEUE_NUM, 7, NF_ACCEPT, Some(RELEASE_MARK));
+        let (msg_type, payload) = messages(&msg).next().unwrap();
+        assert_eq!(msg_type, (NFNL_SUBSYS_QUEUE << 8) | NFQNL_MSG_VERDICT);
+        assert_eq!(&payload[2..4], &QUEUE_NUM.to_be_bytes());
+
+        let attrs: Vec<_> = attributes(payload).collect();
+        assert_eq!(attrs[0], (NFQA_VERDICT_HDR, &[0, 0, 0, 1, 0, 0, 0, 7][..]));
+        assert_eq!(attrs[1], (NFQA_MARK, &RELEASE_MARK.to_be_bytes()[..]));
+        assert_eq!(
+            attributes(payload_of(&verdict_message(4, 0, 7, NF_DROP, None))).count(),
+            1
+        );
+    }
+
+    fn payload_of(msg: &[u8]) -> &[u8] {
+        &msg[NLMSG_HDRLEN..]
+    }
+
+    #[test]
+    fn test_ruleset() {
+        let ruleset = ruleset(
+            NFT_TABLE,
+            QUEUE_NUM,
+            "wg0",
+            &["192.168.1.0/24".to_string(), "10.0.0.0/8".to_string()],
+        );
+        assert!(ruleset.starts_with(
+            "add table ip wg_ondemand_hold; delete table ip wg_ondemand_hold; table ip wg_ondemand_hold {"
+        ));
+        assert!(ruleset.contains(
+            "oifname != \"wg0\" ip daddr { 192.168.1.0/24, 10.0.0.0/8 } meta mark != 0x77676f64 queue num 51820 bypass;"
+        ));
+        assert!(ruleset.contains("oifname \"wg0\" meta mark 0x77676f64 masquerade;"));
+        assert!(ruleset.contains("meta mark 0x77676f70 accept; oifname != \"wg0\""));
+    }
+}
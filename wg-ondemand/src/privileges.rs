@@ -0,0 +1,528 @@
+// Capability dropping after startup
+
+//! Dropping privileges after startup
+//!
+//! Once the eBPF programs are loaded and the netlink, D-Bus and control sockets
+//! are open, the daemon only needs CAP_NET_ADMIN for routes, qdiscs and the
+//! tunnel, CAP_BPF to update eBPF maps (kernels before 6.5 check it on every
+//! `bpf()` call with unprivileged BPF disabled) and CAP_NET_RAW for `ping`
+//! probes. With `drop_capabilities`, everything else is removed from the
+//! permitted, effective and bounding sets, and the retained capabilities are
+//! made ambient so the commands the daemon runs keep them. With `user`, the
+//! daemon also switches to that user, keeping the same capabilities.
+//!
+//! Capabilities are per thread and the runtime, D-Bus and others have started
+//! threads by then, so each change is applied on every thread by signalling it
+//! (as the C library does for `setuid`), then checked in `/proc/self/task`.
+
+use crate::instance::Instance;
+use crate::types::{Config, EndpointSelection};
+use anyhow::{Context, Result};
+use std::ffi::CString;
+use std::path::Path;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Capability bit numbers (linux/capability.h)
+pub const CAP_NET_ADMIN: u32 = 12;
+/// See [`CAP_NET_ADMIN`]
+pub const CAP_NET_RAW: u32 = 13;
+/// See [`CAP_NET_ADMIN`]
+pub const CAP_SYS_ADMIN: u32 = 21;
+/// See [`CAP_NET_ADMIN`]
+pub const CAP_BPF: u32 = 39;
+
+/// `_LINUX_CAPABILITY_VERSION_3`: 64-bit sets as two 32-bit words
+const CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// How long to wait for the other threads to handle the signal
+const THREAD_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Rounds of signalling threads that missed a change (exited or newly started)
+const MAX_ROUNDS: usize = 5;
+
+/// Action the signal handler applies, as a `fn() -> bool` (null before the first)
+static ACTION: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+/// Capabilities to retain, as a bit mask
+static RETAIN: AtomicU64 = AtomicU64::new(0);
+/// Highest capability number of the running kernel
+static LAST_CAP: AtomicU64 = AtomicU64::new(0);
+/// Threads that handled the signal since it was last sent
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+/// Threads on which the action failed
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Capabilities the daemon still needs after startup
+///
+/// `ebpf` is whether the eBPF classifier is in use; kernels without CAP_BPF need
+/// CAP_SYS_ADMIN for the map updates instead.
+pub fn required_capabilities(config: &Config, ebpf: bool, last_cap: u32) -> Vec<u32> {
+    let mut caps = vec![CAP_NET_ADMIN];
+    let pings = config
+        .probe
+        .as_ref()
+        .is_some_and(|probe| probe.port.is_none() || probe.path_mtu)
+        || config
+            .endpoints
+            .as_ref()
+            .is_some_and(|endpoints| endpoints.selection == EndpointSelection::Latency)
+        || config.general.presence_probe.is_some()
+        || !config.general.local_hosts.is_empty();
+    if pings {
+        caps.push(CAP_NET_RAW);
+    }
+    if ebpf {
+        caps.push(if last_cap >= CAP_BPF {
+            CAP_BPF
+        } else {
+            CAP_SYS_ADMIN
+        });
+    }
+    caps
+}
+
+/// Highest capability number the running kernel knows
+pub fn last_cap() -> Result<u32> {
+    std::fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .context("Failed to read /proc/sys/kernel/cap_last_cap")
+}
+
+/// Bit mask of `caps`
+fn mask(caps: &[u32]) -> u64 {
+    caps.iter().fold(0, |mask, cap| mask | 1 << cap)
+}
+
+/// A capability set line (`CapEff`, `CapBnd`, ...) from /proc/<pid>/status
+pub fn parse_cap_field(status: &str, field: &str) -> Option<u64> {
+    let value = status.lines().find_map(|line| {
+        line.strip_prefix(field)
+            .and_then(|rest| rest.strip_prefix(':'))
+    })?;
+    u64::from_str_radix(value.trim(), 16).ok()
+}
+
+/// Drop to the capabilities in `caps`, switching to `user` first if given
+///
+/// The runtime and statistics directories of `instance` and the event log
+/// are handed over to `user` so the daemon can still write them.
+///
+/// # Errors
+///
+/// Returns an error if the user doesn't exist or any thread could not be
+/// restricted, in which case the daemon should not go on.
+pub fn drop_privileges(
+    caps: &[u32],
+    user: Option<&str>,
+    instance: &Instance,
+    event_log: Option<&Path>,
+) -> Result<()> {
+    let retain = mask(caps);
+    RETAIN.store(retain, Ordering::SeqCst);
+    LAST_CAP.store(u64::from(last_cap()?), Ordering::SeqCst);
+
+    let account = user.map(lookup_user).transpose()?;
+    if let Some((uid, gid)) = account {
+        let mut paths = Vec::new();
+        for path in [instance.state_file(), instance.stats_file()] {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {:?}", dir))?;
+                paths.push(dir.to_path_buf());
+            }
+        }
+        if let Some(path) = event_log {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to create {:?}", path))?;
+            paths.push(path.to_path_buf());
+        }
+        for path in paths.iter().filter(|path| path.exists()) {
+            chown_tree(path, uid, gid)?;
+        }
+    }
+
+    on_all_threads("capability bounding set", bound)?;
+    verify(|status| parse_cap_field(status, "CapBnd") == Some(retain))
+        .context("Failed to drop the bounding capability set")?;
+
+    if let Some((uid, gid)) = account {
+        // The C library applies these to every thread
+        // SAFETY: plain syscall wrappers without pointers
+        unsafe {
+            if libc::setgroups(0, std::ptr::null()) != 0 {
+                return Err(std::io::Error::last_os_error()).context("setgroups failed");
+            }
+            if libc::setgid(gid) != 0 {
+                return Err(std::io::Error::last_os_error()).context("setgid failed");
+            }
+            if libc::setuid(uid) != 0 {
+                return Err(std::io::Error::last_os_error()).context("setuid failed");
+            }
+        }
+    }
+
+    on_all_threads("capabilities", restrict)?;
+    verify(|status| {
+        parse_cap_field(status, "CapEff") == Some(retain)
+            && parse_cap_field(status, "CapPrm") == Some(retain)
+    })
+    .context("Failed to drop capabilities")?;
+
+    let names: Vec<String> = caps.iter().map(|cap| cap_name(*cap)).collect();
+    match user {
+        Some(user) => tracing::info!("Running as {} with capabilities {}", user, names.join(", ")),
+        None => tracing::info!("Dropped all capabilities except {}", names.join(", ")),
+    }
+    Ok(())
+}
+
+/// Name of a capability kept by [`required_capabilities`]
+fn cap_name(cap: u32) -> String {
+    match cap {
+        CAP_NET_ADMIN => "CAP_NET_ADMIN".to_string(),
+        CAP_NET_RAW => "CAP_NET_RAW".to_string(),
+        CAP_SYS_ADMIN => "CAP_SYS_ADMIN".to_string(),
+        CAP_BPF => "CAP_BPF".to_string(),
+        other => format!("capability {}", other),
+    }
+}
+
+/// UID and primary GID of `user`
+fn lookup_user(user: &str) -> Result<(libc::uid_t, libc::gid_t)> {
+    let name = CString::new(user).context("Invalid user name")?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 || result.is_null() {
+        anyhow::bail!("User {} not found", user);
+    }
+    Ok((passwd.pw_uid, passwd.pw_gid))
+}
+
+/// Change the owner of `path` and, for a directory, everything in it
+fn chown_tree(path: &Path, uid: libc::uid_t, gid: libc::gid_t) -> Result<()> {
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+        .with_context(|| format!("Failed to change the owner of {:?}", path))?;
+    if path.is_dir() && !path.is_symlink() {
+        for entry in
+            std::fs::read_dir(path).with_context(|| format!("Failed to read {:?}", path))?
+        {
+            chown_tree(&entry?.path(), uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
+/// The signal used to reach the other threads
+fn signal() -> libc::c_int {
+    libc::SIGRTMIN()
+}
+
+fn install_handler() -> Result<()> {
+    // SAFETY: the handler only makes async-signal-safe syscalls and touches atomics
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle_signal as extern "C" fn(libc::c_int) as usize;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal(), &action, std::ptr::null_mut()) != 0 {
+            return Err(std::io::Error::last_os_error())
+                .context("Failed to install signal handler");
+        }
+    }
+    Ok(())
+}
+
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    let action = ACTION.load(Ordering::SeqCst);
+    if action.is_null() {
+        return;
+    }
+    // SAFETY: only ever set from a `fn() -> bool` in `on_all_threads`
+    let action = unsafe { std::mem::transmute::<*mut (), fn() -> bool>(action) };
+    if !action() {
+        FAILED.fetch_add(1, Ordering::SeqCst);
+    }
+    HANDLED.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Keep capabilities across a user switch and shrink the bounding set of the
+/// calling thread; only makes raw syscalls
+fn bound() -> bool {
+    let retain = RETAIN.load(Ordering::SeqCst);
+    let last_cap = LAST_CAP.load(Ordering::SeqCst);
+    let mut ok = true;
+    // SAFETY: prctl with valid arguments
+    unsafe {
+        ok &= libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) == 0;
+        for cap in (0..=last_cap).filter(|cap| retain & (1 << cap) == 0) {
+            ok &= libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) == 0;
+        }
+    }
+    ok
+}
+
+/// Set the permitted, effective, inheritable and ambient sets of the calling
+/// thread; only makes raw syscalls
+fn restrict() -> bool {
+    let retain = RETAIN.load(Ordering::SeqCst);
+    let last_cap = LAST_CAP.load(Ordering::SeqCst);
+    let header = CapHeader {
+        version: CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let word = |shift: u32| {
+        let bits = (retain >> shift) as u32;
+        CapData {
+            effective: bits,
+            permitted: bits,
+            inheritable: bits,
+        }
+    };
+    let data = [word(0), word(32)];
+    let mut ok = true;
+    // SAFETY: prctl and capset with valid arguments; capset reads the two structs only
+    unsafe {
+        ok &= libc::syscall(libc::SYS_capset, &header, data.as_ptr()) == 0;
+        for cap in (0..=last_cap).filter(|cap| retain & (1 << cap) != 0) {
+            ok &= libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE, cap, 0, 0) == 0;
+        }
+        ok &= libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0) == 0;
+    }
+    ok
+}
+
+/// Thread IDs of the process
+fn threads() -> Result<Vec<libc::pid_t>> {
+    Ok(std::fs::read_dir("/proc/self/task")
+        .context("Failed to list threads")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect())
+}
+
+/// Run `action` on the calling thread, then on every other thread of the
+/// process from a signal handler, repeating for threads started meanwhile
+///
+/// `action` runs in a signal handler, so it may only make raw syscalls and use
+/// atomics. `what` names the change in errors.
+///
+/// # Errors
+///
+/// Returns an error if `action` fails on any thread or a thread doesn't handle
+/// the signal in time.
+pub fn on_all_threads(what: &str, action: fn() -> bool) -> Result<()> {
+    // Before the handler, which may run as soon as it is installed
+    ACTION.store(action as *mut (), Ordering::SeqCst);
+    FAILED.store(0, Ordering::SeqCst);
+    install_handler()?;
+    if !action() {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to apply {} to the main thread", what));
+    }
+    // SAFETY: no arguments
+    let (pid, own) = unsafe { (libc::getpid(), libc::gettid()) };
+    let mut done = vec![own];
+    for _ in 0..MAX_ROUNDS {
+        let pending: Vec<_> = threads()?
+            .into_iter()
+            .filter(|tid| !done.contains(tid))
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        HANDLED.store(0, Ordering::SeqCst);
+        let mut signalled = 0;
+        for tid in &pending {
+            // SAFETY: tgkill with a thread ID of this process; it may have exited
+            let rc = unsafe { libc::syscall(libc::SYS_tgkill, pid, *tid, signal()) };
+            if rc == 0 {
+                signalled += 1;
+            }
+        }
+        let deadline = Instant::now() + THREAD_TIMEOUT;
+        while HANDLED.load(Ordering::SeqCst) < signalled && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let handled = HANDLED.load(Ordering::SeqCst);
+        if handled < signalled {
+            anyhow::bail!(
+                "{} of {} threads did not apply {}",
+                signalled - handled,
+                signalled,
+                what
+            );
+        }
+        let failed = FAILED.load(Ordering::SeqCst);
+        if failed > 0 {
+            anyhow::bail!("Failed to apply {} to {} threads", what, failed);
+        }
+        done.extend(pending);
+    }
+    anyhow::bail!("Threads kept starting while applying {}", what)
+}
+
+/// Check `ok` against the status of every thread
+fn verify(ok: impl Fn(&str) -> bool) -> Result<()> {
+    for tid in threads()? {
+        let path = format!("/proc/self/task/{}/status", tid);
+        // The thread may have exited since
+        let Ok(status) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        if !ok(&status) {
+            anyhow::bail!("Thread {} still has other capabilities", tid);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ProbeConfig;
+    use std::process::{Command, Stdio};
+
+    /// Set when the test binary is re-run to change the user of a child
+    const BROADCAST_CHILD: &str = "WG_ONDEMAND_PRIVILEGES_CHILD";
+
+    /// Switch the calling thread (only) to `nobody`, losing its capabilities
+    fn become_nobody() -> bool {
+        // SAFETY: the raw syscall, unlike setuid(3), only changes this thread
+        unsafe { libc::syscall(libc::SYS_setresuid, 65534, 65534, 65534) == 0 }
+    }
+
+    #[test]
+    fn test_on_all_threads() {
+        if std::env::var_os(BROADCAST_CHILD).is_some() {
+            let (release, wait) = std::sync::mpsc::channel::<()>();
+            let thread = std::thread::spawn(move || wait.recv());
+            on_all_threads("user switch", become_nobody).unwrap();
+
+            let tids = threads().unwrap();
+            assert!(tids.len() >= 2);
+            for tid in tids {
+                let status =
+                    std::fs::read_to_string(format!("/proc/self/task/{}/status", tid)).unwrap();
+                let uid = status
+                    .lines()
+                    .find(|line| line.starts_with("Uid:"))
+                    .unwrap();
+                assert_eq!(
+                    uid.split_whitespace().skip(1).collect::<Vec<_>>(),
+                    ["65534"; 4],
+                    "thread {}",
+                    tid
+                );
+                assert_eq!(
+                    parse_cap_field(&status, "CapEff"),
+                    Some(0),
+                    "thread {}",
+                    tid
+                );
+                assert_eq!(
+                    parse_cap_field(&status, "CapPrm"),
+                    Some(0),
+                    "thread {}",
+                    tid
+                );
+            }
+            release.send(()).unwrap();
+            thread.join().unwrap().unwrap();
+            return;
+        }
+        // SAFETY: no arguments
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        // The user can't be switched back, so this runs in a child process
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "privileges::tests::test_on_all_threads",
+                "--test-threads=1",
+            ])
+            .env(BROADCAST_CHILD, "1")
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_parse_cap_field() {
+        let status = "Name:\tcat\nCapInh:\t0000000000000000\nCapPrm:\t0000000000003000\nCapEff:\t000001ffffffffff\nCapBnd:\t0000008000003000\n";
+        assert_eq!(parse_cap_field(status, "CapEff"), Some(0x1ff_ffff_ffff));
+        assert_eq!(parse_cap_field(status, "CapPrm"), Some(0x3000));
+        assert_eq!(parse_cap_field(status, "CapBnd"), Some(mask(&[12, 13, 39])));
+        assert_eq!(parse_cap_field(status, "CapAmb"), None);
+    }
+
+    #[test]
+    fn test_required_capabilities() {
+        let mut config: Config = toml::from_str(
+            "[general]\nwg_interface = \"wg0\"\n\n[subnets]\nranges = [\"192.168.1.0/24\"]\n",
+        )
+        .unwrap();
+        assert_eq!(required_capabilities(&config, false, 40), [CAP_NET_ADMIN]);
+        assert_eq!(
+            required_capabilities(&config, true, 40),
+            [CAP_NET_ADMIN, CAP_BPF]
+        );
+        // Kernels before CAP_BPF
+        assert_eq!(
+            required_capabilities(&config, true, 37),
+            [CAP_NET_ADMIN, CAP_SYS_ADMIN]
+        );
+
+        // Only ICMP probes ping
+        let mut probe = ProbeConfig {
+            host: "10.0.0.1".to_string(),
+            port: Some(443),
+            timeout_secs: 5,
+            path_mtu: false,
+        };
+        config.probe = Some(probe.clone());
+        assert_eq!(required_capabilities(&config, false, 40), [CAP_NET_ADMIN]);
+        probe.path_mtu = true;
+        config.probe = Some(probe);
+        assert_eq!(
+            required_capabilities(&config, false, 40),
+            [CAP_NET_ADMIN, CAP_NET_RAW]
+        );
+        config.probe = None;
+        config.general.presence_probe = Some("10.0.0.1".to_string());
+        assert_eq!(
+            required_capabilities(&config, false, 40),
+            [CAP_NET_ADMIN, CAP_NET_RAW]
+        );
+    }
+}
@@ -0,0 +1,275 @@
+// Post-activation connectivity probe
+
+//! Connectivity probe
+//!
+//! After the tunnel comes up, optionally checks that a host inside the target
+//! subnets is actually reachable through it, either by TCP connect or by ICMP
+//! echo (via `ping`). A failing probe means the tunnel would blackhole traffic.
+
+use crate::process;
+use crate::types::ProbeConfig;
+use anyhow::{Context, Result};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+/// Extra time granted to `ping` beyond its own deadline before it is killed
+const PING_GRACE: Duration = Duration::from_secs(2);
+
+/// Smallest tunnel MTU (the IPv6 minimum link MTU)
+pub const MIN_TUNNEL_MTU: u32 = 1280;
+
+/// Largest tunnel MTU (jumbo frames)
+pub const MAX_TUNNEL_MTU: u32 = 9000;
+
+/// Firewall mark of the daemon's own probes (presence ping, STUN), which the
+/// eBPF classifier, the kill switch and the traffic hold let through
+pub const PROBE_MARK: u32 = 0x7767_6f70;
+
+/// IPv4 + ICMP header bytes added to a ping payload
+const ICMP_OVERHEAD: u32 = 28;
+
+/// Probe the configured host
+///
+/// # Errors
+///
+/// Returns an error describing why the host could not be reached within the timeout.
+pub async fn run(config: &ProbeConfig) -> Result<()> {
+    let host: Ipv4Addr = config
+        .host
+        .parse()
+        .with_context(|| format!("Invalid probe host: {}", config.host))?;
+    let timeout = Duration::from_secs(config.timeout_secs);
+
+    match config.port {
+        Some(port) => tcp_connect(SocketAddr::from((host, port)), timeout).await,
+        None => {
+            let rtt = ping(IpAddr::V4(host), timeout).await?;
+            tracing::info!(
+                "Connectivity probe succeeded: ICMP echo from {} ({}ms)",
+                host,
+                rtt.as_millis()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Check that a TCP connection to `addr` can be established
+async fn tcp_connect(addr: SocketAddr, timeout: Duration) -> Result<()> {
+    match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => {
+            tracing::info!("Connectivity probe succeeded: TCP connect to {}", addr);
+            Ok(())
+        }
+        Ok(Err(e)) => Err(e).with_context(|| format!("TCP connect to {} failed", addr)),
+        Err(_) => anyhow::bail!(
+            "TCP connect to {} timed out after {}s",
+            addr,
+            timeout.as_secs()
+        ),
+    }
+}
+
+/// Check that `host` answers a single ICMP echo request, returning the round-trip time
+pub async fn ping(host: IpAddr, timeout: Duration) -> Result<Duration> {
+    let mut cmd = Command::new("ping");
+    cmd.args([
+        "-c",
+        "1",
+        "-W",
+        &timeout.as_secs().max(1).to_string(),
+        &host.to_string(),
+    ]);
+
+    let started = Instant::now();
+    let output = process::run(cmd, timeout + PING_GRACE).await?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "No ICMP echo reply from {} within {}s",
+            host,
+            timeout.as_secs()
+        );
+    }
+
+    // Prefer ping's own measurement; process startup would inflate ours
+    Ok(parse_ping_rtt(&String::from_utf8_lossy(&output.stdout))
+        .unwrap_or_else(|| started.elapsed()))
+}
+
+/// Check whether the presence probe `host` answers an ICMP echo without the tunnel
+///
+/// The ping carries [`PROBE_MARK`] so it is neither reported as traffic
+/// nor dropped by the kill switch.
+pub async fn presence(host: Ipv4Addr, timeout: Duration) -> bool {
+    let mut cmd = Command::new("ping");
+    cmd.args([
+        "-c",
+        "1",
+        "-W",
+        &timeout.as_secs().max(1).to_string(),
+        "-m",
+        &PROBE_MARK.to_string(),
+        &host.to_string(),
+    ]);
+    match process::run(cmd, timeout + PING_GRACE).await {
+        Ok(output) => output.status.success(),
+        Err(e) => {
+            tracing::warn!("Presence probe failed: {:#}", e);
+            false
+        }
+    }
+}
+
+/// Ping all `hosts` at once, returning the first that answers within `timeout`
+pub async fn first_reachable(hosts: &[Ipv4Addr], timeout: Duration) -> Option<Ipv4Addr> {
+    let pings = hosts.iter().map(|&host| async move {
+        match ping(IpAddr::V4(host), timeout).await {
+            Ok(_) => Some(host),
+            Err(e) => {
+                tracing::debug!("{:#}", e);
+                None
+            }
+        }
+    });
+    futures_util::future::join_all(pings)
+        .await
+        .into_iter()
+        .flatten()
+        .next()
+}
+
+/// Check whether an unfragmentable ICMP echo of `mtu` bytes gets an answer from `host`
+async fn ping_mtu(host: Ipv4Addr, mtu: u32, timeout: Duration) -> bool {
+    let mut cmd = Command::new("ping");
+    cmd.args([
+        "-c",
+        "1",
+        "-M",
+        "do",
+        "-s",
+        &(mtu - ICMP_OVERHEAD).to_string(),
+        "-W",
+        &timeout.as_secs().max(1).to_string(),
+        &host.to_string(),
+    ]);
+
+    match process::run(cmd, timeout + PING_GRACE).await {
+        Ok(output) => output.status.success(),
+        Err(e) => {
+            tracing::debug!("MTU probe with {} bytes failed: {:#}", mtu, e);
+            false
+        }
+    }
+}
+
+/// Binary search for the largest MTU in `min..=max` for which `fits` holds
+///
+/// Returns None if even `min` does not fit, in which case the path is broken
+/// for other reasons and the MTU cannot be determined.
+async fn search_mtu<F, Fut>(min: u32, max: u32, mut fits: F) -> Option<u32>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    if !fits(min).await {
+        return None;
+    }
+    let (mut lo, mut hi) = (min, max);
+    while lo < hi {
+        let mid = lo + (hi - lo).div_ceil(2);
+        if fits(mid).await {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    Some(lo)
+}
+
+/// Find the largest MTU up to `max` at which pings to `host` through the tunnel
+/// still get answered
+///
+/// Large packets are sent with the don't-fragment bit set; if the encapsulated
+/// packets exceed the path MTU of the uplink and are silently dropped, only
+/// smaller sizes get a reply.
+pub async fn path_mtu(host: Ipv4Addr, max: u32, timeout: Duration) -> Option<u32> {
+    search_mtu(MIN_TUNNEL_MTU, max.max(MIN_TUNNEL_MTU), |mtu| {
+        ping_mtu(host, mtu, timeout)
+    })
+    .await
+}
+
+/// Extract the round-trip time from ping output ("... time=12.3 ms")
+fn parse_ping_rtt(output: &str) -> Option<Duration> {
+    let value = output.split("time=").nth(1)?.split_whitespace().next()?;
+    let millis: f64 = value.parse().ok()?;
+    Some(Duration::from_micros((millis * 1000.0).round() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_tcp_probe_success() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let config = ProbeConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(addr.port()),
+            timeout_secs: 5,
+            path_mtu: false,
+        };
+        assert!(run(&config).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probe_refused() {
+        // Bind then drop to get a port with nothing listening
+        let port = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+
+        let config = ProbeConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            timeout_secs: 5,
+            path_mtu: false,
+        };
+        assert!(run(&config).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_ping_rtt() {
+        let output = "PING 192.168.1.10 (192.168.1.10) 56(84) bytes of data.\n\
+            64 bytes from 192.168.1.10: icmp_seq=1 ttl=64 time=12.3 ms\n";
+        assert_eq!(parse_ping_rtt(output), Some(Duration::from_micros(12_300)));
+        assert_eq!(parse_ping_rtt("no reply"), None);
+    }
+
+    #[tokio::test]
+    async fn test_search_mtu() {
+        let path = |limit: u32| move |mtu: u32| std::future::ready(mtu <= limit);
+
+        assert_eq!(search_mtu(1280, 1420, path(1420)).await, Some(1420));
+        assert_eq!(search_mtu(1280, 1420, path(1392)).await, Some(1392));
+        assert_eq!(search_mtu(1280, 1420, path(1280)).await, Some(1280));
+        assert_eq!(search_mtu(1280, 1420, path(1000)).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_probe_invalid_host() {
+        let config = ProbeConfig {
+            host: "not-an-ip".to_string(),
+            port: Some(22),
+            timeout_secs: 5,
+            path_mtu: false,
+        };
+        assert!(run(&config).await.is_err());
+    }
+}
@@ -0,0 +1,87 @@
+// External command execution with timeouts
+
+//! External command execution
+//!
+//! Runs helper processes (ip, nmcli, wg-quick) with a deadline so that a stalled
+//! NetworkManager or hung child cannot wedge the daemon's event loop. Children
+//! that exceed the timeout are killed and reported as errors.
+
+use anyhow::{Context, Result};
+use std::process::Output;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Default timeout for external commands
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Render a command line for log and error messages
+fn describe(cmd: &Command) -> String {
+    let std_cmd = cmd.as_std();
+    std::iter::once(std_cmd.get_program())
+        .chain(std_cmd.get_args())
+        .map(|s| s.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Run a command to completion, collecting its output
+///
+/// # Errors
+///
+/// Returns an error if the command cannot be spawned or does not finish within
+/// `timeout`, in which case the child is killed.
+pub async fn run(mut cmd: Command, timeout: Duration) -> Result<Output> {
+    let description = describe(&cmd);
+
+    // Dropping the output future on timeout kills the child
+    cmd.kill_on_drop(true);
+
+    match tokio::time::timeout(timeout, cmd.output()).await {
+        Ok(output) => output.with_context(|| format!("Failed to execute `{}`", description)),
+        Err(_) => {
+            tracing::error!(
+                "`{}` did not finish within {}s, killed",
+                description,
+                timeout.as_secs()
+            );
+            anyhow::bail!("`{}` timed out after {}s", description, timeout.as_secs())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let mut cmd = Command::new("ip");
+        cmd.args(["route", "show", "dev", "wlan0"]);
+        assert_eq!(describe(&cmd), "ip route show dev wlan0");
+    }
+
+    #[tokio::test]
+    async fn test_run_success() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo hello"]);
+        let output = run(cmd, DEFAULT_COMMAND_TIMEOUT).await.unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+    }
+
+    #[tokio::test]
+    async fn test_run_timeout_kills_child() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("10");
+        let started = std::time::Instant::now();
+        let err = run(cmd, Duration::from_millis(100)).await.unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_run_missing_program() {
+        let cmd = Command::new("wg-ondemand-nonexistent-binary");
+        assert!(run(cmd, DEFAULT_COMMAND_TIMEOUT).await.is_err());
+    }
+}
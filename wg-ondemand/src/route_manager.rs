@@ -2,17 +2,116 @@
 //!
 //! Manages temporary routes that direct monitored subnets through the WiFi gateway,
 //! allowing eBPF egress hooks to detect traffic even when the VPN is down.
+//! Routes are changed over rtnetlink (see [`rtnl`](crate::rtnl)).
+//!
+//! The routes live in a dedicated table, [`MONITOR_TABLE`], selected by a policy
+//! routing rule ahead of the main table. They never replace routes NetworkManager
+//! or the user put into the main table, and cleaning up (also after a crash) is
+//! flushing the table and deleting the rule.
+//!
+//! Each monitored interface gets a manager of its own with a slot
+//! ([`RouteManager::with_slot`]): its own table and rule, numbered upwards from
+//! these. Lower slots are looked up first, so while several uplinks are connected
+//! the first one listed carries the monitored traffic, and when its routes vanish
+//! (unplugged, disconnected) lookups fall through to the next.
+//!
+//! In fwmark mode ([`RouteManager::with_fwmark`]) the rule only applies to
+//! packets carrying [`MONITOR_MARK`], which an nftables route hook sets on traffic
+//! to the subnets (the eBPF classifier runs after routing, too late to steer it).
+//! All other lookups never see the table.
 
+use crate::kill_switch::run_nft;
+use crate::process::DEFAULT_COMMAND_TIMEOUT;
+use crate::rtnl::{self, Route, MAIN_TABLE};
+use crate::types::GatewayFallback;
 use anyhow::{Context, Result};
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
 use std::net::Ipv4Addr;
-use tokio::process::Command;
+use std::time::Duration;
+
+/// Routing table holding the monitoring routes (of slot 0)
+pub const MONITOR_TABLE: u32 = 51821;
+
+/// Priority of the rule selecting [`MONITOR_TABLE`], ahead of the main table (32766)
+/// (of slot 0)
+pub const MONITOR_RULE_PRIORITY: u32 = 32000;
+
+/// Most interfaces monitored at once (one slot, table and rule each)
+pub const MAX_MONITOR_INTERFACES: usize = 8;
+
+/// Firewall mark selecting [`MONITOR_TABLE`] in fwmark mode
+pub const MONITOR_MARK: u32 = 0x7767_6f6d;
+
+/// nftables table marking traffic to the subnets in fwmark mode (`ip` family,
+/// slot 0; other slots append their number)
+pub const NFT_TABLE: &str = "wg_ondemand_mark";
+
+/// nftables commands creating `table`, which marks unmarked traffic to `subnets`
+/// before routing
+pub fn mark_ruleset(table: &str, subnets: &[String]) -> String {
+    format!(
+        "add table ip {table}; delete table ip {table}; table ip {table} {{ \
+         chain output {{ type route hook output priority -160; policy accept; \
+         ip daddr {{ {subnets} }} meta mark 0 meta mark set {mark:#x}; }} }}",
+        subnets = subnets.join(", "),
+        mark = MONITOR_MARK,
+    )
+}
+
+/// Gateway of the interface's default route, or of any route through it
+pub fn route_gateway(routes: &[Route], oif: u32) -> Option<Ipv4Addr> {
+    let candidates = || {
+        routes
+            .iter()
+            .filter(move |route| route.oif == Some(oif) && route.table == MAIN_TABLE)
+    };
+    candidates()
+        .filter(|route| route.is_default())
+        .chain(candidates())
+        .find_map(|route| route.gateway)
+}
+
+/// Whether a change to `route` may change the gateway (a default route in the
+/// main table, e.g. replaced on DHCP renewal or after roaming)
+pub fn affects_gateway(route: &Route) -> bool {
+    route.is_default() && route.table == MAIN_TABLE
+}
+
+/// Interface of the preferred (lowest metric) default route in the main table,
+/// i.e. the uplink currently carrying traffic
+pub fn default_interface(routes: &[Route]) -> Option<u32> {
+    routes
+        .iter()
+        .filter(|route| affects_gateway(route) && !route.blackhole)
+        .filter_map(|route| Some((route.metric, route.oif?)))
+        .min()
+        .map(|(_, oif)| oif)
+}
+
+/// Route in the main table that already covers part of `subnet` (e.g. a second
+/// NIC on that network), which a monitoring route would shadow
+fn conflicting_route<'a>(routes: &'a [Route], subnet: &Route) -> Option<&'a Route> {
+    routes.iter().find(|route| {
+        route.table == MAIN_TABLE
+            && !route.is_default()
+            && !route.blackhole
+            && route.overlaps(subnet)
+    })
+}
 
 /// Manages temporary routes for traffic monitoring
 pub struct RouteManager {
     interface: String,
     gateway: Option<Ipv4Addr>,
-    active_routes: HashSet<String>,
+    active_routes: HashMap<String, Route>,
+    rule_installed: bool,
+    fwmark: bool,
+    metric: u32,
+    fallback: GatewayFallback,
+    slot: u32,
+    command_timeout: Duration,
 }
 
 impl RouteManager {
@@ -21,92 +120,376 @@ impl RouteManager {
         Self {
             interface,
             gateway: None,
-            active_routes: HashSet::new(),
+            active_routes: HashMap::new(),
+            rule_installed: false,
+            fwmark: false,
+            metric: 0,
+            fallback: GatewayFallback::None,
+            slot: 0,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
         }
     }
 
-    /// Detect gateway IP by parsing `ip route show dev <interface>`
-    async fn detect_gateway(&self) -> Result<Ipv4Addr> {
-        let output = Command::new("ip")
-            .args(["route", "show", "dev", &self.interface])
-            .output()
-            .await
-            .context("Failed to get routes")?;
+    /// Set the timeout after which hung netlink requests are abandoned
+    pub fn with_command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
 
-        anyhow::ensure!(output.status.success(), "ip route command failed");
+    /// Only route packets marked with [`MONITOR_MARK`] through the monitoring table
+    pub fn with_fwmark(mut self, enabled: bool) -> Self {
+        self.fwmark = enabled;
+        self
+    }
+
+    /// Set the metric of the monitoring routes (0 is the kernel default)
+    pub fn with_metric(mut self, metric: u32) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Set what the monitoring routes point at when no gateway is found
+    pub fn with_gateway_fallback(mut self, fallback: GatewayFallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Use the table and rule of `slot` (one per monitored interface; lower slots
+    /// take precedence)
+    pub fn with_slot(mut self, slot: u32) -> Self {
+        self.slot = slot;
+        self
+    }
 
-        String::from_utf8_lossy(&output.stdout)
-            .lines()
-            .find_map(|line| {
-                line.find(" via ")
-                    .and_then(|pos| line[pos + 5..].split_whitespace().next())
-                    .and_then(|s| s.parse::<Ipv4Addr>().ok())
-            })
+    /// Routing table holding this manager's routes
+    pub fn table(&self) -> u32 {
+        MONITOR_TABLE + self.slot
+    }
+
+    /// Priority of the rule selecting [`table`](Self::table)
+    fn rule_priority(&self) -> u32 {
+        MONITOR_RULE_PRIORITY + self.slot
+    }
+
+    /// nftables table marking traffic in fwmark mode
+    fn nft_table(&self) -> String {
+        if self.slot == 0 {
+            NFT_TABLE.to_string()
+        } else {
+            format!("{}{}", NFT_TABLE, self.slot)
+        }
+    }
+
+    /// Monitored interface
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    /// Monitoring route to `subnet` via `gateway`, or the fallback without one
+    fn monitoring_route(&self, subnet: &str, gateway: Option<Ipv4Addr>, oif: u32) -> Result<Route> {
+        let route = Route::to_cidr(subnet)?.dev(oif).metric(self.metric);
+        let mut route = match (gateway, self.fallback) {
+            (Some(gateway), _) => route.via(gateway),
+            (None, GatewayFallback::Blackhole) => route.blackhole(),
+            (None, _) => route,
+        };
+        route.table = self.table();
+        Ok(route)
+    }
+
+    /// Mark of the rule selecting the monitoring table, if any
+    fn rule_mark(&self) -> Option<u32> {
+        self.fwmark.then_some(MONITOR_MARK)
+    }
+
+    /// Run a netlink request, giving up after the command timeout
+    async fn netlink<T>(&self, request: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+        tokio::time::timeout(self.command_timeout, request)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "netlink request timed out"))?
+    }
+
+    /// Detect the gateway from the interface's routes in the main table
+    async fn detect_gateway(&self, oif: u32) -> Result<Ipv4Addr> {
+        let routes = self
+            .netlink(rtnl::routes())
+            .await
+            .context("Failed to get routes")?;
+        route_gateway(&routes, oif)
             .with_context(|| format!("No gateway found for {}", self.interface))
     }
 
     /// Add monitoring routes for configured subnets
+    #[tracing::instrument(name = "add_routes", skip_all, fields(interface = %self.interface))]
     pub async fn add_routes(&mut self, subnets: &[String]) -> Result<()> {
+        let oif = rtnl::ifindex(&self.interface)?;
         if self.gateway.is_none() {
-            self.gateway = Some(self.detect_gateway().await?);
+            match self.detect_gateway(oif).await {
+                Ok(gateway) => self.gateway = Some(gateway),
+                Err(e) if self.fallback != GatewayFallback::None => {
+                    tracing::info!("{:#}, adding {:?} routes instead", e, self.fallback);
+                }
+                Err(e) => return Err(e),
+            }
         }
-        let gateway = self.gateway.unwrap();
+        let gateway = self.gateway;
+        let next_hop = match (gateway, self.fallback) {
+            (Some(gateway), _) => format!("via {} dev {}", gateway, self.interface),
+            (None, GatewayFallback::Blackhole) => "blackhole".to_string(),
+            (None, _) => format!("dev {}", self.interface),
+        };
 
-        for subnet in subnets {
-            if self.active_routes.contains(subnet) {
-                continue;
+        if !self.rule_installed {
+            if self.fwmark && !subnets.is_empty() {
+                run_nft(
+                    &mark_ruleset(&self.nft_table(), subnets),
+                    self.command_timeout,
+                )
+                .await
+                .context("Failed to install the monitoring mark table")?;
+            }
+            match self
+                .netlink(rtnl::add_rule(
+                    self.rule_priority(),
+                    self.table(),
+                    self.rule_mark(),
+                ))
+                .await
+            {
+                Ok(()) => {}
+                Err(e) if e.raw_os_error() == Some(libc::EEXIST) => {}
+                Err(e) if e.raw_os_error() == Some(libc::EPERM) => {
+                    return Err(e).context("Not permitted to add rules (CAP_NET_ADMIN required)");
+                }
+                Err(e) => return Err(e).context("Failed to add monitoring table rule"),
             }
+            tracing::debug!(
+                "Rule active: priority {} lookup {}",
+                self.rule_priority(),
+                self.table()
+            );
+            self.rule_installed = true;
+        }
+
+        let new_subnets: Vec<&String> = subnets
+            .iter()
+            .filter(|subnet| !self.active_routes.contains_key(*subnet))
+            .collect();
+        let existing = if new_subnets.is_empty() {
+            Vec::new()
+        } else {
+            self.netlink(rtnl::routes())
+                .await
+                .context("Failed to get routes")?
+        };
 
-            let success = Command::new("ip")
-                .args([
-                    "route",
-                    "add",
-                    subnet,
-                    "via",
-                    &gateway.to_string(),
-                    "dev",
-                    &self.interface,
-                ])
-                .status()
-                .await?
-                .success();
-
-            if success || self.route_exists(subnet, &gateway).await? {
-                log::info!(
-                    "Route active: {} via {} dev {}",
-                    subnet,
-                    gateway,
-                    self.interface
+        for subnet in new_subnets {
+            let route = self.monitoring_route(subnet, gateway, oif)?;
+            // Shadowing a live local network would break connectivity to it
+            if let Some(conflict) = conflicting_route(&existing, &route) {
+                let dev = conflict
+                    .oif
+                    .and_then(rtnl::ifname)
+                    .unwrap_or_else(|| "?".to_string());
+                let next_hop = match conflict.gateway {
+                    Some(gateway) => format!("{} dev {}", gateway, dev),
+                    None => dev,
+                };
+                tracing::warn!(
+                    "Route to {} via {} exists, not adding monitoring route for {}",
+                    conflict.cidr(),
+                    next_hop,
+                    subnet
                 );
-                self.active_routes.insert(subnet.clone());
+                continue;
             }
+
+            match self.netlink(rtnl::add_route(&route)).await {
+                Ok(()) => {}
+                // Left by an earlier attach or a previous instance
+                Err(e) if e.raw_os_error() == Some(libc::EEXIST) => {
+                    if !self.route_exists(&route).await? {
+                        tracing::warn!(
+                            "Another route to {} exists, not adding monitoring route",
+                            subnet
+                        );
+                        continue;
+                    }
+                }
+                Err(e) if e.raw_os_error() == Some(libc::EPERM) => {
+                    return Err(e).context("Not permitted to add routes (CAP_NET_ADMIN required)");
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to add route {} {}: {}", subnet, next_hop, e);
+                    continue;
+                }
+            }
+
+            tracing::info!(
+                "Route active: {} {} table {} metric {}",
+                subnet,
+                next_hop,
+                self.table(),
+                self.metric
+            );
+            self.active_routes.insert(subnet.clone(), route);
         }
 
         Ok(())
     }
 
-    /// Remove all managed routes
+    /// Re-add managed routes deleted outside the daemon (NetworkManager or
+    /// dhclient rewriting routes), returning how many were restored
+    #[tracing::instrument(name = "restore_routes", skip_all, fields(interface = %self.interface))]
+    pub async fn restore_routes(&mut self) -> Result<usize> {
+        if self.active_routes.is_empty() {
+            return Ok(0);
+        }
+        let routes = self
+            .netlink(rtnl::routes())
+            .await
+            .context("Failed to get routes")?;
+        let missing: Vec<String> = self
+            .active_routes
+            .iter()
+            .filter(|(_, route)| !routes.contains(route))
+            .map(|(subnet, _)| subnet.clone())
+            .collect();
+        if missing.is_empty() {
+            return Ok(0);
+        }
+        for subnet in &missing {
+            self.active_routes.remove(subnet);
+        }
+        self.add_routes(&missing).await?;
+        Ok(missing
+            .iter()
+            .filter(|subnet| self.active_routes.contains_key(*subnet))
+            .count())
+    }
+
+    /// Re-detect the gateway and move the managed routes to it if it changed,
+    /// returning the new gateway
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no gateway is found (e.g. the default route was removed
+    /// and DHCP hasn't added the new one yet); the routes are left as they are.
+    #[tracing::instrument(name = "refresh_gateway", skip_all, fields(interface = %self.interface))]
+    pub async fn refresh_gateway(&mut self) -> Result<Option<Ipv4Addr>> {
+        if self.active_routes.is_empty() {
+            return Ok(None);
+        }
+        let oif = rtnl::ifindex(&self.interface)?;
+        let gateway = self.detect_gateway(oif).await?;
+        if self.gateway == Some(gateway) {
+            return Ok(None);
+        }
+
+        let subnets: Vec<String> = self.active_routes.keys().cloned().collect();
+        for (subnet, route) in self.active_routes.drain() {
+            match tokio::time::timeout(self.command_timeout, rtnl::del_route(&route)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ESRCH) => {}
+                Ok(Err(e)) => tracing::warn!("Failed to remove route {}: {}", subnet, e),
+                Err(_) => tracing::warn!("Timed out removing route {}", subnet),
+            }
+        }
+        tracing::info!(
+            "Gateway of {} changed from {} to {}",
+            self.interface,
+            self.gateway.map_or("none".to_string(), |gw| gw.to_string()),
+            gateway
+        );
+        self.gateway = Some(gateway);
+        self.add_routes(&subnets).await?;
+        Ok(Some(gateway))
+    }
+
+    /// Remove all managed routes by flushing the monitoring table and its rule
+    #[tracing::instrument(name = "remove_routes", skip_all, fields(interface = %self.interface))]
     pub async fn remove_routes(&mut self) -> Result<()> {
-        for subnet in self.active_routes.drain() {
-            let _ = Command::new("ip")
-                .args(["route", "del", &subnet])
-                .status()
-                .await;
-            log::info!("Removed route: {}", subnet);
+        let subnets: Vec<String> = self
+            .active_routes
+            .drain()
+            .map(|(subnet, _)| subnet)
+            .collect();
+        let rule_installed = std::mem::take(&mut self.rule_installed);
+        // The next network may well have a different gateway
+        self.gateway = None;
+        self.flush().await?;
+        if !subnets.is_empty() || rule_installed {
+            tracing::info!("Removed monitoring routes: {}", subnets.join(", "));
         }
         Ok(())
     }
 
-    async fn route_exists(&self, subnet: &str, gateway: &Ipv4Addr) -> Result<bool> {
-        let output = Command::new("ip")
-            .args(["route", "show", subnet])
-            .output()
-            .await?;
+    /// Flush the monitoring table and delete the rule selecting it (of either
+    /// mode, in case the mode changed since a crashed instance added it)
+    async fn flush(&self) -> Result<usize> {
+        let removed = self
+            .netlink(rtnl::flush_table(self.table()))
+            .await
+            .context("Failed to flush monitoring table")?;
+        for mark in [None, Some(MONITOR_MARK)] {
+            match self
+                .netlink(rtnl::del_rule(self.rule_priority(), self.table(), mark))
+                .await
+            {
+                Ok(()) => {}
+                Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {}
+                Err(e) => return Err(e).context("Failed to delete monitoring table rule"),
+            }
+        }
+        if self.fwmark {
+            // Adding first makes the delete succeed whether or not the table exists
+            run_nft(
+                &format!("add table ip {0}; delete table ip {0}", self.nft_table()),
+                self.command_timeout,
+            )
+            .await
+            .context("Failed to remove the monitoring mark table")?;
+        }
+        Ok(removed)
+    }
+
+    /// Remove monitoring routes left behind by a previous daemon instance
+    ///
+    /// A daemon that crashed leaves its routes through the (possibly since changed)
+    /// gateway in place, which would leak traffic for the monitored subnets to
+    /// whatever network the machine is on now. Only the monitoring table is
+    /// touched, so this must run before this instance adds routes.
+    #[tracing::instrument(
+        name = "remove_stale_routes",
+        skip_all,
+        fields(interface = %self.interface)
+    )]
+    pub async fn remove_stale_routes(&self) -> Result<()> {
+        let removed = self.flush().await?;
+        if removed > 0 {
+            tracing::warn!(
+                "Removed {} stale monitoring route(s) from previous instance",
+                removed
+            );
+        }
+        Ok(())
+    }
 
-        Ok(output.status.success() && {
-            let out = String::from_utf8_lossy(&output.stdout);
-            out.contains(&gateway.to_string()) && out.contains(&self.interface)
-        })
+    /// Whether a route to the same destination through the same gateway and
+    /// interface as `route` exists in its table
+    async fn route_exists(&self, route: &Route) -> Result<bool> {
+        let routes = self
+            .netlink(rtnl::routes())
+            .await
+            .context("Failed to get routes")?;
+        Ok(routes.iter().any(|existing| {
+            existing.table == route.table
+                && existing.destination == route.destination
+                && existing.prefix_len == route.prefix_len
+                && existing.gateway == route.gateway
+                && existing.oif == route.oif
+                && existing.metric == route.metric
+        }))
     }
 
     /// Clear cached gateway (useful when interface state changes)
@@ -122,18 +505,16 @@ impl RouteManager {
 
 impl Drop for RouteManager {
     fn drop(&mut self) {
-        if !self.has_active_routes() {
-            return;
-        }
-
-        if let Ok(handle) = tokio::runtime::Handle::try_current() {
-            for subnet in self.active_routes.drain() {
-                let _ = handle.block_on(async {
-                    Command::new("ip")
-                        .args(["route", "del", &subnet])
-                        .status()
-                        .await
-                });
+        for (subnet, route) in self.active_routes.drain() {
+            if let Err(e) = rtnl::del_route_blocking(&route) {
+                tracing::warn!("Failed to remove route {}: {}", subnet, e);
+            }
+        }
+        if std::mem::take(&mut self.rule_installed) {
+            if let Err(e) =
+                rtnl::del_rule_blocking(self.rule_priority(), self.table(), self.rule_mark())
+            {
+                tracing::warn!("Failed to remove monitoring table rule: {}", e);
             }
         }
     }
@@ -154,8 +535,153 @@ mod tests {
     #[test]
     fn test_active_routes() {
         let mut rm = RouteManager::new("wlan0".to_string());
-        rm.active_routes.insert("192.168.1.0/24".to_string());
+        let route = Route::to_cidr("192.168.1.0/24").unwrap();
+        rm.active_routes.insert("192.168.1.0/24".to_string(), route);
         assert!(rm.has_active_routes());
+        // Keep Drop from touching the real routing table
+        rm.active_routes.clear();
+    }
+
+    #[test]
+    fn test_route_gateway() {
+        let routes = vec![
+            Route::to_cidr("10.0.0.0/24").unwrap().dev(2),
+            Route::to_cidr("192.168.1.0/24")
+                .unwrap()
+                .via(Ipv4Addr::new(10, 0, 0, 1))
+                .dev(2),
+            Route::to_cidr("0.0.0.0/0")
+                .unwrap()
+                .via(Ipv4Addr::new(172, 16, 0, 1))
+                .dev(2),
+            Route::to_cidr("0.0.0.0/0")
+                .unwrap()
+                .via(Ipv4Addr::new(192, 0, 2, 1))
+                .dev(3),
+        ];
+        // The default route wins over other routes through the interface
+        assert_eq!(
+            route_gateway(&routes, 2),
+            Some(Ipv4Addr::new(172, 16, 0, 1))
+        );
+        assert_eq!(
+            route_gateway(&routes[..2], 2),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(route_gateway(&routes[..1], 2), None);
+        assert_eq!(route_gateway(&routes, 4), None);
+    }
+
+    #[test]
+    fn test_mark_ruleset() {
+        let ruleset = mark_ruleset(
+            NFT_TABLE,
+            &["192.168.1.0/24".to_string(), "10.0.0.0/8".to_string()],
+        );
+        assert!(ruleset.starts_with(
+            "add table ip wg_ondemand_mark; delete table ip wg_ondemand_mark; table ip wg_ondemand_mark {"
+        ));
+        assert!(ruleset.contains("type route hook output priority -160;"));
+        assert!(ruleset.contains(
+            "ip daddr { 192.168.1.0/24, 10.0.0.0/8 } meta mark 0 meta mark set 0x77676f6d;"
+        ));
+    }
+
+    #[test]
+    fn test_slot() {
+        let rm = RouteManager::new("wlan0".to_string());
+        assert_eq!(
+            (rm.table(), rm.rule_priority(), rm.nft_table().as_str()),
+            (MONITOR_TABLE, MONITOR_RULE_PRIORITY, NFT_TABLE)
+        );
+        let rm = RouteManager::new("eth0".to_string()).with_slot(1);
+        assert_eq!(
+            (rm.table(), rm.rule_priority(), rm.nft_table().as_str()),
+            (51822, 32001, "wg_ondemand_mark1")
+        );
+        let route = rm.monitoring_route("10.0.0.0/8", None, 3).unwrap();
+        assert_eq!(route.table, 51822);
+    }
+
+    #[test]
+    fn test_conflicting_route() {
+        let subnet = Route::to_cidr("192.168.1.0/24").unwrap();
+        let default = Route::to_cidr("0.0.0.0/0")
+            .unwrap()
+            .via(Ipv4Addr::new(10, 0, 0, 1))
+            .dev(2);
+        let other_lan = Route::to_cidr("10.0.0.0/24").unwrap().dev(2);
+        let mut monitoring = Route::to_cidr("192.168.1.0/24").unwrap().dev(2);
+        monitoring.table = MONITOR_TABLE;
+        let mut routes = vec![default, other_lan, monitoring];
+        assert_eq!(conflicting_route(&routes, &subnet), None);
+
+        // Second NIC on the monitored subnet
+        let second_nic = Route::to_cidr("192.168.1.0/25").unwrap().dev(5);
+        routes.push(second_nic.clone());
+        assert_eq!(conflicting_route(&routes, &subnet), Some(&second_nic));
+    }
+
+    #[test]
+    fn test_monitoring_route() {
+        let gateway = Ipv4Addr::new(192, 168, 1, 1);
+        let rm = RouteManager::new("wlan0".to_string()).with_metric(600);
+        let route = rm.monitoring_route("10.0.0.0/8", Some(gateway), 3).unwrap();
+        assert_eq!(route.gateway, Some(gateway));
+        assert_eq!(route.oif, Some(3));
+        assert_eq!(route.table, MONITOR_TABLE);
+        assert_eq!(route.metric, 600);
+
+        let route = rm.monitoring_route("10.0.0.0/8", None, 3).unwrap();
+        assert_eq!((route.gateway, route.oif), (None, Some(3)));
+
+        let rm = rm.with_gateway_fallback(GatewayFallback::Blackhole);
+        let route = rm.monitoring_route("10.0.0.0/8", None, 3).unwrap();
+        assert!(route.blackhole);
+        assert_eq!(route.oif, None);
+        assert!(
+            !rm.monitoring_route("10.0.0.0/8", Some(gateway), 3)
+                .unwrap()
+                .blackhole
+        );
+    }
+
+    #[test]
+    fn test_affects_gateway() {
+        let default = Route::to_cidr("0.0.0.0/0")
+            .unwrap()
+            .via(Ipv4Addr::new(192, 168, 1, 1));
+        assert!(affects_gateway(&default));
+        let mut monitoring = default.clone();
+        monitoring.table = MONITOR_TABLE;
+        assert!(!affects_gateway(&monitoring));
+        assert!(!affects_gateway(&Route::to_cidr("192.168.1.0/24").unwrap()));
+    }
+
+    #[test]
+    fn test_default_interface() {
+        let default = Route::to_cidr("0.0.0.0/0").unwrap();
+        let routes = vec![
+            Route::to_cidr("10.0.0.0/24").unwrap().dev(2),
+            default
+                .clone()
+                .via(Ipv4Addr::new(10, 0, 0, 1))
+                .dev(3)
+                .metric(600),
+            default
+                .clone()
+                .via(Ipv4Addr::new(172, 16, 0, 1))
+                .dev(4)
+                .metric(100),
+        ];
+        // The dock's Ethernet (lower metric) wins over WiFi
+        assert_eq!(default_interface(&routes), Some(4));
+        assert_eq!(default_interface(&routes[..2]), Some(3));
+        assert_eq!(default_interface(&routes[..1]), None);
+
+        let mut other_table = default.dev(5);
+        other_table.table = MONITOR_TABLE;
+        assert_eq!(default_interface(&[other_table]), None);
     }
 
     #[test]
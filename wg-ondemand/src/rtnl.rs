@@ -0,0 +1,742 @@
+// Route netlink helpers
+
+//! rtnetlink route operations
+//!
+//! Reads and changes IPv4 routes and policy routing rules with `RTM_*ROUTE` and
+//! `RTM_*RULE` requests, and reads interface addresses with `RTM_GETADDR`,
+//! instead of running `ip`, so every change is a single kernel
+//! transaction and failures carry the kernel's error code (`EEXIST`, `EPERM`,
+//! `ESRCH`, ...) in [`io::Error::raw_os_error`]. The requests block, but the
+//! kernel answers them immediately; they run on the blocking thread pool.
+//!
+//! [`RtnlMonitor`] subscribes to the kernel's IPv4 route, address and link
+//! notifications, so routes changed by others (NetworkManager, dhclient, an
+//! admin), addresses assigned by DHCP and interfaces that come and go (USB
+//! adapters, driver reloads) are noticed right away.
+
+use anyhow::{Context, Result};
+use netlink_packet_core::{
+    NetlinkMessage, NetlinkPayload, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL, NLM_F_REQUEST,
+};
+use netlink_packet_route::address::{AddressAttribute, AddressMessage};
+use netlink_packet_route::link::{LinkAttribute, LinkFlags, LinkMessage};
+use netlink_packet_route::route::{
+    RouteAddress, RouteAttribute, RouteHeader, RouteMessage, RouteProtocol, RouteScope, RouteType,
+};
+use netlink_packet_route::rule::{RuleAction, RuleAttribute, RuleMessage};
+use netlink_packet_route::{AddressFamily, RouteNetlinkMessage};
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+/// The main routing table
+pub const MAIN_TABLE: u32 = RouteHeader::RT_TABLE_MAIN as u32;
+
+/// An IPv4 unicast or blackhole route
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    /// Destination network address
+    pub destination: Ipv4Addr,
+    /// Destination prefix length
+    pub prefix_len: u8,
+    /// Next hop, if not directly connected
+    pub gateway: Option<Ipv4Addr>,
+    /// Index of the outgoing interface
+    pub oif: Option<u32>,
+    /// Routing table
+    pub table: u32,
+    /// Route metric; among routes to the same destination the lowest wins
+    pub metric: u32,
+    /// Whether packets to the destination are silently dropped
+    pub blackhole: bool,
+}
+
+impl Route {
+    /// Route to `cidr` (e.g. `192.168.1.0/24`) in the main table
+    pub fn to_cidr(cidr: &str) -> Result<Self> {
+        let (network, mask) = crate::config::parse_cidr(cidr)?;
+        Ok(Self {
+            destination: Ipv4Addr::from(network),
+            prefix_len: mask.count_ones() as u8,
+            gateway: None,
+            oif: None,
+            table: MAIN_TABLE,
+            metric: 0,
+            blackhole: false,
+        })
+    }
+
+    /// Set the next hop
+    pub fn via(mut self, gateway: Ipv4Addr) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    /// Set the outgoing interface
+    pub fn dev(mut self, oif: u32) -> Self {
+        self.oif = Some(oif);
+        self
+    }
+
+    /// Set the metric
+    pub fn metric(mut self, metric: u32) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Drop packets instead of forwarding them (no next hop)
+    pub fn blackhole(mut self) -> Self {
+        self.blackhole = true;
+        self.gateway = None;
+        self.oif = None;
+        self
+    }
+
+    /// Destination in CIDR notation
+    pub fn cidr(&self) -> String {
+        format!("{}/{}", self.destination, self.prefix_len)
+    }
+
+    /// Whether this is a default route
+    pub fn is_default(&self) -> bool {
+        self.prefix_len == 0
+    }
+
+    /// Whether the destinations of both routes share addresses (one contains the other)
+    pub fn overlaps(&self, other: &Route) -> bool {
+        let prefix_len = self.prefix_len.min(other.prefix_len).min(32);
+        let mask = u32::MAX
+            .checked_shl(32 - u32::from(prefix_len))
+            .unwrap_or(0);
+        u32::from(self.destination) & mask == u32::from(other.destination) & mask
+    }
+}
+
+/// An IPv4 or IPv6 address assigned to an interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    /// Index of the interface
+    pub index: u32,
+    /// The address itself
+    pub ip: IpAddr,
+    /// Prefix length of the network it is on
+    pub prefix_len: u8,
+}
+
+/// Index of the interface called `name`
+pub fn ifindex(name: &str) -> Result<u32> {
+    let c_name = CString::new(name).context("Invalid interface name")?;
+    // SAFETY: c_name is a valid NUL-terminated string
+    let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+    if index == 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("Interface {} not found", name));
+    }
+    Ok(index)
+}
+
+/// Name of the interface with index `index`, if it exists
+pub fn ifname(index: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    // SAFETY: buf holds IF_NAMESIZE bytes as if_indextoname requires
+    let name = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+    // SAFETY: on success buf holds a NUL-terminated name
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// All IPv4 unicast and blackhole routes, in every table
+pub async fn routes() -> io::Result<Vec<Route>> {
+    let mut request = RouteMessage::default();
+    request.header.address_family = AddressFamily::Inet;
+    let responses = request_blocking(
+        RouteNetlinkMessage::GetRoute(request),
+        NLM_F_REQUEST | NLM_F_DUMP,
+    )
+    .await?;
+
+    Ok(responses
+        .into_iter()
+        .filter_map(|response| match response.payload {
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewRoute(msg)) => parse_route(&msg),
+            _ => None,
+        })
+        .collect())
+}
+
+/// All IPv4 and IPv6 addresses, of every interface
+pub async fn addresses() -> io::Result<Vec<Address>> {
+    let responses = request_blocking(
+        RouteNetlinkMessage::GetAddress(AddressMessage::default()),
+        NLM_F_REQUEST | NLM_F_DUMP,
+    )
+    .await?;
+
+    Ok(responses
+        .into_iter()
+        .filter_map(|response| match response.payload {
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewAddress(msg)) => {
+                parse_address(&msg)
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+/// Addresses of the interface called `name`, none if it doesn't exist
+pub async fn interface_addresses(name: &str) -> io::Result<Vec<Address>> {
+    let Ok(index) = ifindex(name) else {
+        return Ok(Vec::new());
+    };
+    let mut addresses = addresses().await?;
+    addresses.retain(|address| address.index == index);
+    Ok(addresses)
+}
+
+/// Add `route`, failing with `EEXIST` if a route to the destination exists
+pub async fn add_route(route: &Route) -> io::Result<()> {
+    request_blocking(
+        RouteNetlinkMessage::NewRoute(route_message(route)),
+        NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL,
+    )
+    .await
+    .map(drop)
+}
+
+/// Delete `route`, failing with `ESRCH` if no such route exists
+///
+/// Unset fields (gateway, interface) match any route to the destination.
+pub async fn del_route(route: &Route) -> io::Result<()> {
+    request_blocking(
+        RouteNetlinkMessage::DelRoute(delete_message(route)),
+        NLM_F_REQUEST | NLM_F_ACK,
+    )
+    .await
+    .map(drop)
+}
+
+/// Delete `route` synchronously, for use outside the runtime (e.g. in `Drop`)
+pub fn del_route_blocking(route: &Route) -> io::Result<()> {
+    netlink_request::netlink_request_rtnl(
+        RouteNetlinkMessage::DelRoute(delete_message(route)),
+        Some(NLM_F_REQUEST | NLM_F_ACK),
+    )
+    .map(drop)
+}
+
+/// Delete all IPv4 routes in `table`, returning how many were removed
+pub async fn flush_table(table: u32) -> io::Result<usize> {
+    let mut removed = 0;
+    for route in routes().await?.iter().filter(|route| route.table == table) {
+        match del_route(route).await {
+            Ok(()) => removed += 1,
+            // Removed concurrently, e.g. with its interface
+            Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(removed)
+}
+
+/// Add an IPv4 rule looking up `table` at `priority` for all traffic, or only
+/// for packets carrying `fwmark`, failing with `EEXIST` if it exists
+pub async fn add_rule(priority: u32, table: u32, fwmark: Option<u32>) -> io::Result<()> {
+    request_blocking(
+        RouteNetlinkMessage::NewRule(rule_message(priority, table, fwmark)),
+        NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL,
+    )
+    .await
+    .map(drop)
+}
+
+/// Delete the rule added by [`add_rule`], failing with `ENOENT` if it doesn't exist
+pub async fn del_rule(priority: u32, table: u32, fwmark: Option<u32>) -> io::Result<()> {
+    request_blocking(
+        RouteNetlinkMessage::DelRule(rule_message(priority, table, fwmark)),
+        NLM_F_REQUEST | NLM_F_ACK,
+    )
+    .await
+    .map(drop)
+}
+
+/// Delete a rule synchronously, for use outside the runtime (e.g. in `Drop`)
+pub fn del_rule_blocking(priority: u32, table: u32, fwmark: Option<u32>) -> io::Result<()> {
+    netlink_request::netlink_request_rtnl(
+        RouteNetlinkMessage::DelRule(rule_message(priority, table, fwmark)),
+        Some(NLM_F_REQUEST | NLM_F_ACK),
+    )
+    .map(drop)
+}
+
+/// Route, address or link change made by anyone, as reported by a [`RtnlMonitor`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RtnlEvent {
+    /// A route was added or replaced
+    RouteAdded(Route),
+    /// A route was deleted
+    RouteRemoved(Route),
+    /// An IPv4 address was assigned to an interface
+    AddressAdded(Address),
+    /// An interface appeared or changed (flags, carrier, name)
+    LinkChanged {
+        /// Interface index
+        index: u32,
+        /// Interface name
+        name: String,
+        /// Whether the interface is up with a carrier
+        running: bool,
+    },
+    /// An interface was removed
+    LinkRemoved {
+        /// Interface index
+        index: u32,
+        /// Interface name
+        name: String,
+    },
+    /// Notifications were dropped because the socket buffer overflowed; any
+    /// route may have changed
+    Lost,
+}
+
+impl RtnlEvent {
+    /// Interface name and whether it is running, for link events (removed
+    /// interfaces are not running)
+    pub fn link_state(&self) -> Option<(&str, bool)> {
+        match self {
+            Self::LinkChanged { name, running, .. } => Some((name, *running)),
+            Self::LinkRemoved { name, .. } => Some((name, false)),
+            _ => None,
+        }
+    }
+}
+
+/// Socket subscribed to IPv4 route, IPv4 address and link notifications
+pub struct RtnlMonitor {
+    fd: AsyncFd<OwnedFd>,
+    pending: VecDeque<RtnlEvent>,
+    buf: Vec<u8>,
+}
+
+impl RtnlMonitor {
+    /// Open a netlink socket in the IPv4 route, IPv4 address and link multicast groups
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the socket cannot be opened or bound.
+    pub fn new() -> io::Result<Self> {
+        // SAFETY: plain socket(2) call; the descriptor is owned right away
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `raw` is a freshly created descriptor not owned elsewhere
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // SAFETY: an all-zero sockaddr_nl is valid; the kernel assigns the port id
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups =
+            (libc::RTMGRP_IPV4_ROUTE | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_LINK) as u32;
+        // SAFETY: `addr` is a valid sockaddr_nl of the given size
+        let ret = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+            pending: VecDeque::new(),
+            buf: vec![0; 32 * 1024],
+        })
+    }
+
+    /// Wait for the next route, address or link change
+    pub async fn next(&mut self) -> io::Result<RtnlEvent> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Ok(change);
+            }
+            let len = match self.recv().await {
+                Ok(len) => len,
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => return Ok(RtnlEvent::Lost),
+                Err(e) => return Err(e),
+            };
+            self.pending.extend(parse_events(&self.buf[..len]));
+        }
+    }
+
+    async fn recv(&mut self) -> io::Result<usize> {
+        let buf = &mut self.buf;
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let result = guard.try_io(|fd| {
+                // SAFETY: `buf` is valid for writes of its length
+                let ret = unsafe {
+                    libc::recv(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        0,
+                    )
+                };
+                if ret < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(ret as usize)
+                }
+            });
+            if let Ok(result) = result {
+                return result;
+            }
+        }
+    }
+}
+
+/// Route, address and link changes in a datagram of notifications
+fn parse_events(mut buf: &[u8]) -> Vec<RtnlEvent> {
+    let mut changes = Vec::new();
+    while !buf.is_empty() {
+        let Ok(msg) = NetlinkMessage::<RouteNetlinkMessage>::deserialize(buf) else {
+            break;
+        };
+        let change = match &msg.payload {
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewRoute(route)) => {
+                parse_route(route).map(RtnlEvent::RouteAdded)
+            }
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::DelRoute(route)) => {
+                parse_route(route).map(RtnlEvent::RouteRemoved)
+            }
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewAddress(address)) => {
+                parse_address(address).map(RtnlEvent::AddressAdded)
+            }
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewLink(link)) => link_name(link)
+                .map(|name| RtnlEvent::LinkChanged {
+                    index: link.header.index,
+                    name,
+                    running: link.header.flags.contains(LinkFlags::Running),
+                }),
+            NetlinkPayload::InnerMessage(RouteNetlinkMessage::DelLink(link)) => link_name(link)
+                .map(|name| RtnlEvent::LinkRemoved {
+                    index: link.header.index,
+                    name,
+                }),
+            _ => None,
+        };
+        changes.extend(change);
+        let len = msg.header.length as usize;
+        if len == 0 || len > buf.len() {
+            break;
+        }
+        // Messages are padded to 4 bytes
+        buf = &buf[((len + 3) & !3).min(buf.len())..];
+    }
+    changes
+}
+
+/// Interface name in a link message
+fn link_name(msg: &LinkMessage) -> Option<String> {
+    msg.attributes.iter().find_map(|attribute| match attribute {
+        LinkAttribute::IfName(name) => Some(name.clone()),
+        _ => None,
+    })
+}
+
+/// Send `message` on the blocking thread pool and collect the responses
+async fn request_blocking(
+    message: RouteNetlinkMessage,
+    flags: u16,
+) -> io::Result<Vec<NetlinkMessage<RouteNetlinkMessage>>> {
+    tokio::task::spawn_blocking(move || netlink_request::netlink_request_rtnl(message, Some(flags)))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Request message for adding or deleting `route`
+fn route_message(route: &Route) -> RouteMessage {
+    let mut msg = RouteMessage::default();
+    msg.header.address_family = AddressFamily::Inet;
+    msg.header.destination_prefix_length = route.prefix_len;
+    msg.header.protocol = RouteProtocol::Static;
+    msg.header.scope = if route.gateway.is_some() || route.blackhole {
+        RouteScope::Universe
+    } else {
+        RouteScope::Link
+    };
+    msg.header.kind = if route.blackhole {
+        RouteType::BlackHole
+    } else {
+        RouteType::Unicast
+    };
+    // Tables above 255 only fit in the attribute
+    msg.header.table = u8::try_from(route.table).unwrap_or(RouteHeader::RT_TABLE_UNSPEC);
+    msg.attributes.push(RouteAttribute::Table(route.table));
+    msg.attributes
+        .push(RouteAttribute::Destination(RouteAddress::Inet(
+            route.destination,
+        )));
+    if let Some(gateway) = route.gateway {
+        msg.attributes
+            .push(RouteAttribute::Gateway(RouteAddress::Inet(gateway)));
+    }
+    if let Some(oif) = route.oif {
+        msg.attributes.push(RouteAttribute::Oif(oif));
+    }
+    if route.metric != 0 {
+        msg.attributes.push(RouteAttribute::Priority(route.metric));
+    }
+    msg
+}
+
+/// Request message for deleting `route`, matching any protocol and scope (the
+/// route may have been added by `ip route` or another instance)
+fn delete_message(route: &Route) -> RouteMessage {
+    let mut msg = route_message(route);
+    msg.header.protocol = RouteProtocol::Unspec;
+    msg.header.scope = RouteScope::NoWhere;
+    msg
+}
+
+/// Request message for a rule looking up `table` at `priority`
+fn rule_message(priority: u32, table: u32, fwmark: Option<u32>) -> RuleMessage {
+    let mut msg = RuleMessage::default();
+    msg.header.family = AddressFamily::Inet;
+    msg.header.action = RuleAction::ToTable;
+    msg.header.table = u8::try_from(table).unwrap_or(RouteHeader::RT_TABLE_UNSPEC);
+    msg.attributes.push(RuleAttribute::Priority(priority));
+    msg.attributes.push(RuleAttribute::Table(table));
+    if let Some(mark) = fwmark {
+        msg.attributes.push(RuleAttribute::FwMark(mark));
+        msg.attributes.push(RuleAttribute::FwMask(u32::MAX));
+    }
+    msg
+}
+
+/// IPv4 unicast or blackhole route described by a kernel route message
+fn parse_route(msg: &RouteMessage) -> Option<Route> {
+    if msg.header.address_family != AddressFamily::Inet
+        || !matches!(msg.header.kind, RouteType::Unicast | RouteType::BlackHole)
+    {
+        return None;
+    }
+    let mut route = Route {
+        destination: Ipv4Addr::UNSPECIFIED,
+        prefix_len: msg.header.destination_prefix_length,
+        gateway: None,
+        oif: None,
+        table: u32::from(msg.header.table),
+        metric: 0,
+        blackhole: msg.header.kind == RouteType::BlackHole,
+    };
+    for attribute in &msg.attributes {
+        match attribute {
+            RouteAttribute::Destination(RouteAddress::Inet(addr)) => route.destination = *addr,
+            RouteAttribute::Gateway(RouteAddress::Inet(addr)) => route.gateway = Some(*addr),
+            RouteAttribute::Oif(oif) => route.oif = Some(*oif),
+            RouteAttribute::Table(table) => route.table = *table,
+            RouteAttribute::Priority(metric) => route.metric = *metric,
+            _ => {}
+        }
+    }
+    Some(route)
+}
+
+/// Address in an address message: the local address, which differs from
+/// `IFA_ADDRESS` (the peer) on point-to-point links
+fn parse_address(msg: &AddressMessage) -> Option<Address> {
+    let mut address = None;
+    for attribute in &msg.attributes {
+        match attribute {
+            AddressAttribute::Local(ip) => address = Some(*ip),
+            AddressAttribute::Address(ip) => address = address.or(Some(*ip)),
+            _ => {}
+        }
+    }
+    Some(Address {
+        index: msg.header.index,
+        ip: address?,
+        prefix_len: msg.header.prefix_len,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_to_cidr() {
+        let route = Route::to_cidr("192.168.1.7/24").unwrap();
+        assert_eq!(route.destination, Ipv4Addr::new(192, 168, 1, 0));
+        assert_eq!(route.prefix_len, 24);
+        assert_eq!(route.cidr(), "192.168.1.0/24");
+        assert_eq!(route.table, MAIN_TABLE);
+        assert!(!route.is_default());
+        assert!(Route::to_cidr("0.0.0.0/0").unwrap().is_default());
+        assert!(Route::to_cidr("fd00::/64").is_err());
+    }
+
+    #[test]
+    fn test_route_overlaps() {
+        let lan = Route::to_cidr("192.168.1.0/24").unwrap();
+        assert!(lan.overlaps(&Route::to_cidr("192.168.1.128/25").unwrap()));
+        assert!(lan.overlaps(&Route::to_cidr("192.168.0.0/16").unwrap()));
+        assert!(lan.overlaps(&Route::to_cidr("0.0.0.0/0").unwrap()));
+        assert!(!lan.overlaps(&Route::to_cidr("192.168.2.0/24").unwrap()));
+        assert!(Route::to_cidr("10.1.2.3/32")
+            .unwrap()
+            .overlaps(&Route::to_cidr("10.1.2.3/32").unwrap()));
+    }
+
+    #[test]
+    fn test_route_message_round_trip() {
+        let route = Route::to_cidr("10.0.0.0/8")
+            .unwrap()
+            .via(Ipv4Addr::new(192, 168, 0, 1))
+            .dev(3);
+        let msg = route_message(&route);
+        assert_eq!(msg.header.scope, RouteScope::Universe);
+        assert_eq!(msg.header.table, RouteHeader::RT_TABLE_MAIN);
+        assert_eq!(parse_route(&msg), Some(route));
+
+        // Custom table beyond the header's u8
+        let mut route = Route::to_cidr("10.0.0.0/8").unwrap().dev(3).metric(600);
+        route.table = 51820;
+        let msg = route_message(&route);
+        assert_eq!(msg.header.scope, RouteScope::Link);
+        assert_eq!(msg.header.table, RouteHeader::RT_TABLE_UNSPEC);
+        assert_eq!(parse_route(&msg), Some(route));
+
+        let route = Route::to_cidr("10.0.0.0/8").unwrap().dev(3).blackhole();
+        let msg = route_message(&route);
+        assert_eq!(msg.header.kind, RouteType::BlackHole);
+        assert_eq!(msg.header.scope, RouteScope::Universe);
+        assert_eq!(parse_route(&msg), Some(route));
+    }
+
+    #[test]
+    fn test_rule_message() {
+        let msg = rule_message(32000, 51821, None);
+        assert_eq!(msg.header.family, AddressFamily::Inet);
+        assert_eq!(msg.header.action, RuleAction::ToTable);
+        assert_eq!(msg.header.table, RouteHeader::RT_TABLE_UNSPEC);
+        assert_eq!(
+            msg.attributes,
+            [RuleAttribute::Priority(32000), RuleAttribute::Table(51821)]
+        );
+
+        let msg = rule_message(32000, 51821, Some(0x10));
+        assert_eq!(
+            msg.attributes[2..],
+            [RuleAttribute::FwMark(0x10), RuleAttribute::FwMask(u32::MAX)]
+        );
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let mut route = Route::to_cidr("10.0.0.0/8").unwrap().dev(3);
+        route.table = 51821;
+        let mut link = LinkMessage::default();
+        link.header.index = 4;
+        link.header.flags = LinkFlags::Up | LinkFlags::Running;
+        link.attributes
+            .push(LinkAttribute::IfName("wlan1".to_string()));
+        let address = Address {
+            index: 4,
+            ip: IpAddr::V4(Ipv4Addr::new(192, 168, 178, 20)),
+            prefix_len: 24,
+        };
+        let mut address_msg = AddressMessage::default();
+        address_msg.header.family = AddressFamily::Inet;
+        address_msg.header.index = address.index;
+        address_msg.header.prefix_len = address.prefix_len;
+        address_msg
+            .attributes
+            .push(AddressAttribute::Local(address.ip));
+        let mut buf = Vec::new();
+        for payload in [
+            RouteNetlinkMessage::NewRoute(route_message(&route)),
+            RouteNetlinkMessage::DelRoute(route_message(&route)),
+            RouteNetlinkMessage::NewAddress(address_msg),
+            RouteNetlinkMessage::NewLink(link.clone()),
+            RouteNetlinkMessage::DelLink(link),
+        ] {
+            let mut msg = NetlinkMessage::from(payload);
+            msg.finalize();
+            let start = buf.len();
+            buf.resize(start + msg.buffer_len(), 0);
+            msg.serialize(&mut buf[start..]);
+        }
+
+        assert_eq!(
+            parse_events(&buf),
+            [
+                RtnlEvent::RouteAdded(route.clone()),
+                RtnlEvent::RouteRemoved(route),
+                RtnlEvent::AddressAdded(address),
+                RtnlEvent::LinkChanged {
+                    index: 4,
+                    name: "wlan1".to_string(),
+                    running: true,
+                },
+                RtnlEvent::LinkRemoved {
+                    index: 4,
+                    name: "wlan1".to_string()
+                },
+            ]
+        );
+        assert_eq!(parse_events(&buf[..10]), []);
+    }
+
+    #[test]
+    fn test_parse_address() {
+        let mut msg = AddressMessage::default();
+        msg.header.index = 3;
+        msg.header.prefix_len = 64;
+        assert_eq!(parse_address(&msg), None);
+
+        let ip: IpAddr = "fd00::7".parse().unwrap();
+        msg.attributes.push(AddressAttribute::Address(ip));
+        assert_eq!(
+            parse_address(&msg),
+            Some(Address {
+                index: 3,
+                ip,
+                prefix_len: 64
+            })
+        );
+
+        // Point-to-point: the peer comes first, the local address wins
+        let mut msg = AddressMessage::default();
+        let local = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        msg.attributes
+            .push(AddressAttribute::Address(IpAddr::V4(Ipv4Addr::new(
+                10, 0, 0, 1,
+            ))));
+        msg.attributes.push(AddressAttribute::Local(local));
+        assert_eq!(parse_address(&msg).map(|address| address.ip), Some(local));
+    }
+
+    #[test]
+    fn test_parse_route_skips_other_kinds() {
+        let mut msg = route_message(&Route::to_cidr("10.0.0.0/8").unwrap());
+        msg.header.kind = RouteType::Local;
+        assert_eq!(parse_route(&msg), None);
+    }
+}
@@ -0,0 +1,480 @@
+// seccomp-bpf syscall allowlist
+
+//! seccomp syscall allowlist
+//!
+//! Once startup is done, the daemon's event loop only reads and writes sockets
+//! and files, updates eBPF maps and runs commands (`ip`, `tc`, `nft`, `ping`,
+//! and `nmcli` or `wg-quick` for those backends). A seccomp filter installed on
+//! all threads then limits the process, and the commands it runs, to the
+//! syscalls these need: mounting, tracing, loading kernel modules, switching
+//! namespaces and the like fail with `ENOSYS`, so a compromised daemon can't
+//! use them either. Syscalls of another architecture kill the process.
+//!
+//! The filter is opt-in with `seccomp = true`, since wg-quick hooks
+//! (`PostUp`/`PreDown`) and other user scripts run under it too. When
+//! something breaks only under the daemon, denied syscalls show up as `ENOSYS`
+//! errors in `strace -f`.
+
+use crate::types::Config;
+use anyhow::{Context, Result};
+use libc::{c_long, sock_filter};
+
+/// `AUDIT_ARCH_*` of the syscalls the filter allows (linux/audit.h)
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_003E);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_00B7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// x32 syscalls share the x86_64 architecture and have this bit set
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Offsets into `struct seccomp_data`
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+
+/// Syscalls of the daemon itself: the runtime, sockets (netlink, D-Bus, the
+/// control socket), state files, eBPF maps and starting commands
+const DAEMON_SYSCALLS: &[c_long] = &[
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_close,
+    libc::SYS_close_range,
+    libc::SYS_openat,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_statfs,
+    libc::SYS_fstatfs,
+    libc::SYS_getdents64,
+    libc::SYS_readlinkat,
+    libc::SYS_faccessat,
+    libc::SYS_faccessat2,
+    libc::SYS_getcwd,
+    libc::SYS_mkdirat,
+    libc::SYS_unlinkat,
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+    libc::SYS_fchmod,
+    libc::SYS_fchmodat,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_ftruncate,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_flock,
+    libc::SYS_dup,
+    libc::SYS_dup3,
+    libc::SYS_pipe2,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mprotect,
+    libc::SYS_mremap,
+    libc::SYS_madvise,
+    libc::SYS_brk,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_kill,
+    libc::SYS_tgkill,
+    libc::SYS_futex,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_set_robust_list,
+    libc::SYS_set_tid_address,
+    libc::SYS_rseq,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_gettimeofday,
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_epoll_pwait2,
+    libc::SYS_eventfd2,
+    // Timeouts of the polling reactor zbus runs on
+    libc::SYS_timerfd_create,
+    libc::SYS_timerfd_settime,
+    libc::SYS_timerfd_gettime,
+    libc::SYS_ppoll,
+    libc::SYS_pselect6,
+    libc::SYS_socket,
+    libc::SYS_socketpair,
+    libc::SYS_connect,
+    libc::SYS_accept4,
+    libc::SYS_bind,
+    libc::SYS_listen,
+    libc::SYS_sendto,
+    libc::SYS_recvfrom,
+    libc::SYS_sendmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_recvmmsg,
+    libc::SYS_shutdown,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockopt,
+    libc::SYS_getrandom,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_getppid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_getresuid,
+    libc::SYS_getresgid,
+    libc::SYS_getgroups,
+    libc::SYS_uname,
+    libc::SYS_sysinfo,
+    libc::SYS_prctl,
+    libc::SYS_prlimit64,
+    libc::SYS_getrusage,
+    libc::SYS_umask,
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_wait4,
+    libc::SYS_waitid,
+    libc::SYS_pidfd_open,
+    libc::SYS_pidfd_send_signal,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_bpf,
+];
+
+/// Further syscalls of the commands run by every backend (`ip`, `tc`, `nft`,
+/// `ping`, `curl`, `getent`); the filter is inherited across `execve`
+const COMMAND_SYSCALLS: &[c_long] = &[
+    libc::SYS_chdir,
+    libc::SYS_fchdir,
+    libc::SYS_setpgid,
+    libc::SYS_getpgid,
+    libc::SYS_getsid,
+    libc::SYS_setsid,
+    libc::SYS_rt_sigsuspend,
+    libc::SYS_rt_sigtimedwait,
+    libc::SYS_getitimer,
+    libc::SYS_setitimer,
+    libc::SYS_times,
+    // ping drops its privileges, which can't gain any
+    libc::SYS_capget,
+    libc::SYS_capset,
+    libc::SYS_setuid,
+    libc::SYS_setgid,
+    libc::SYS_setresuid,
+    libc::SYS_setresgid,
+    libc::SYS_setgroups,
+    libc::SYS_copy_file_range,
+    libc::SYS_sendfile,
+    libc::SYS_sched_getparam,
+    libc::SYS_sched_getscheduler,
+];
+
+/// Legacy syscalls that C libraries and static binaries still use on x86_64
+#[cfg(target_arch = "x86_64")]
+const LEGACY_SYSCALLS: &[c_long] = &[
+    libc::SYS_arch_prctl,
+    libc::SYS_fadvise64,
+    libc::SYS_open,
+    libc::SYS_stat,
+    libc::SYS_lstat,
+    libc::SYS_access,
+    libc::SYS_readlink,
+    libc::SYS_getdents,
+    libc::SYS_poll,
+    libc::SYS_select,
+    libc::SYS_pipe,
+    libc::SYS_dup2,
+    libc::SYS_fork,
+    libc::SYS_vfork,
+    libc::SYS_epoll_create,
+    libc::SYS_epoll_wait,
+    libc::SYS_getpgrp,
+    libc::SYS_alarm,
+    libc::SYS_time,
+    libc::SYS_unlink,
+    libc::SYS_rename,
+    libc::SYS_mkdir,
+    libc::SYS_rmdir,
+    libc::SYS_chmod,
+];
+#[cfg(not(target_arch = "x86_64"))]
+const LEGACY_SYSCALLS: &[c_long] = &[];
+
+/// Further syscalls of `nmcli` (GLib) and `wg-quick` (bash with `wg`,
+/// `resolvconf`, `sysctl` and the firewall tools)
+const SHELL_SYSCALLS: &[c_long] = &[
+    libc::SYS_inotify_init1,
+    libc::SYS_inotify_add_watch,
+    libc::SYS_inotify_rm_watch,
+    libc::SYS_memfd_create,
+    libc::SYS_sched_getattr,
+    libc::SYS_sched_setattr,
+    libc::SYS_linkat,
+    libc::SYS_symlinkat,
+    libc::SYS_utimensat,
+    libc::SYS_fchown,
+    libc::SYS_fchownat,
+    libc::SYS_splice,
+    libc::SYS_tee,
+];
+
+/// Whether the filter can be installed on this architecture
+pub fn supported() -> bool {
+    AUDIT_ARCH.is_some()
+}
+
+/// Syscalls allowed for `config`
+///
+/// `nmcli` and `wg-quick` need more than the commands of the native backend.
+pub fn allowed_syscalls(config: &Config) -> Vec<c_long> {
+    let mut syscalls: Vec<c_long> = [DAEMON_SYSCALLS, COMMAND_SYSCALLS, LEGACY_SYSCALLS].concat();
+    if config.native.is_none() {
+        syscalls.extend_from_slice(SHELL_SYSCALLS);
+    }
+    syscalls.sort_unstable();
+    syscalls.dedup();
+    syscalls
+}
+
+/// A BPF instruction
+fn stmt(code: u32, k: u32) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+/// A BPF conditional jump; `jt` and `jf` count instructions to skip
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter {
+        code: (libc::BPF_JMP | code | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// Classic BPF program allowing `syscalls` of `arch`
+///
+/// Other syscalls return `ENOSYS`, which programs treat as an old kernel;
+/// syscalls of other architectures kill the process.
+pub fn filter(arch: u32, syscalls: &[c_long]) -> Vec<sock_filter> {
+    let load = |offset| stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset);
+    let ret = |action| stmt(libc::BPF_RET | libc::BPF_K, action);
+    let deny = libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32;
+
+    let mut program = vec![
+        load(DATA_ARCH),
+        jump(libc::BPF_JEQ, arch, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(DATA_NR),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    program.extend([jump(libc::BPF_JGE, X32_SYSCALL_BIT, 0, 1), ret(deny)]);
+    for &nr in syscalls {
+        program.extend([
+            jump(libc::BPF_JEQ, nr as u32, 0, 1),
+            ret(libc::SECCOMP_RET_ALLOW),
+        ]);
+    }
+    program.push(ret(deny));
+    program
+}
+
+/// Install the allowlist for `config` on all threads of the process
+///
+/// Threads started later and commands inherit it. It can't be removed again.
+///
+/// # Errors
+///
+/// Returns an error if the architecture is unsupported or the kernel rejects
+/// the filter (e.g. built without `CONFIG_SECCOMP_FILTER`).
+pub fn install(config: &Config) -> Result<()> {
+    let arch = AUDIT_ARCH.context("seccomp filter not supported on this architecture")?;
+    let syscalls = allowed_syscalls(config);
+    let program = filter(arch, &syscalls);
+    let fprog = libc::sock_fprog {
+        len: program
+            .len()
+            .try_into()
+            .context("seccomp filter too long")?,
+        filter: program.as_ptr().cast_mut(),
+    };
+
+    // SAFETY: prctl and seccomp with valid arguments; the kernel copies the program
+    unsafe {
+        // Required to install a filter without CAP_SYS_ADMIN; also set on the
+        // other threads by TSYNC
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to set no_new_privs");
+        }
+        let rc = libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &fprog,
+        );
+        if rc < 0 {
+            return Err(std::io::Error::last_os_error())
+                .context("Failed to install seccomp filter");
+        }
+        if rc > 0 {
+            anyhow::bail!("Failed to install seccomp filter on thread {}", rc);
+        }
+    }
+
+    tracing::info!(
+        "Installed seccomp filter allowing {} syscalls",
+        syscalls.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    /// Set when the test binary is re-run to install the filter in a child
+    const FILTERED_CHILD: &str = "WG_ONDEMAND_SECCOMP_CHILD";
+
+    /// Run `program` on a syscall the way the kernel does
+    fn run(program: &[sock_filter], arch: u32, nr: c_long) -> u32 {
+        let mut acc = 0;
+        let mut pc = 0;
+        loop {
+            let insn = program[pc];
+            pc += 1;
+            match u32::from(insn.code) {
+                code if code == libc::BPF_LD | libc::BPF_W | libc::BPF_ABS => {
+                    acc = if insn.k == DATA_ARCH { arch } else { nr as u32 };
+                }
+                code if code == libc::BPF_RET | libc::BPF_K => return insn.k,
+                code => {
+                    let taken = if code == libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K {
+                        acc == insn.k
+                    } else {
+                        assert_eq!(code, libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K);
+                        acc >= insn.k
+                    };
+                    pc += usize::from(if taken { insn.jt } else { insn.jf });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let arch = 0xC000_003E;
+        let program = filter(arch, &[libc::SYS_read, libc::SYS_write]);
+        let deny = libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32;
+        assert_eq!(run(&program, arch, libc::SYS_read), libc::SECCOMP_RET_ALLOW);
+        assert_eq!(
+            run(&program, arch, libc::SYS_write),
+            libc::SECCOMP_RET_ALLOW
+        );
+        assert_eq!(run(&program, arch, libc::SYS_ptrace), deny);
+        assert_eq!(
+            run(&program, 0xC000_00B7, libc::SYS_read),
+            libc::SECCOMP_RET_KILL_PROCESS
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            run(&program, arch, libc::SYS_read | X32_SYSCALL_BIT as c_long),
+            deny
+        );
+    }
+
+    #[test]
+    fn test_allowed_syscalls() {
+        let mut config: Config = toml::from_str(
+            "[general]\nwg_interface = \"wg0\"\n\n[subnets]\nranges = [\"192.168.1.0/24\"]\n",
+        )
+        .unwrap();
+        let shell = allowed_syscalls(&config);
+        for nr in [libc::SYS_execve, libc::SYS_bpf, libc::SYS_inotify_init1] {
+            assert!(shell.contains(&nr));
+        }
+        for nr in [libc::SYS_ptrace, libc::SYS_mount, libc::SYS_init_module] {
+            assert!(!shell.contains(&nr));
+        }
+
+        // The native backend doesn't run nmcli or wg-quick
+        config.native = Some(toml::from_str("config_file = \"/etc/wireguard/wg0.conf\"").unwrap());
+        let native = allowed_syscalls(&config);
+        assert!(native.contains(&libc::SYS_execve));
+        assert!(!native.contains(&libc::SYS_inotify_init1));
+        assert!(native.contains(&libc::SYS_timerfd_settime));
+        assert!(native.len() < shell.len());
+    }
+
+    #[test]
+    fn test_reactor_under_native_filter() {
+        if std::env::var_os(FILTERED_CHILD).is_some() {
+            // As zbus, whose reactor is already running when the filter is installed
+            let (reader, mut writer) = UnixStream::pair().unwrap();
+            let reader = async_io::Async::new(reader).unwrap();
+            let mut config: Config = toml::from_str(
+                "[general]\nwg_interface = \"wg0\"\n\n[subnets]\nranges = [\"192.168.1.0/24\"]\n",
+            )
+            .unwrap();
+            config.native =
+                Some(toml::from_str("config_file = \"/etc/wireguard/wg0.conf\"").unwrap());
+            install(&config).unwrap();
+
+            let writer = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                writer.write_all(b"x").unwrap();
+            });
+            async_io::block_on(reader.readable()).unwrap();
+            writer.join().unwrap();
+            return;
+        }
+        if !supported() {
+            return;
+        }
+
+        // The filter can't be removed, so it is installed in a child process
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "seccomp::tests::test_reactor_under_native_filter",
+                "--test-threads=1",
+            ])
+            .env(FILTERED_CHILD, "1")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let status = loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                break status;
+            }
+            if Instant::now() > deadline {
+                child.kill().unwrap();
+                child.wait().unwrap();
+                panic!("Socket never became readable under the seccomp filter");
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        assert!(status.success());
+    }
+}
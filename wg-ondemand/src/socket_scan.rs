@@ -0,0 +1,261 @@
+// Socket table scanning fallback for traffic detection
+
+//! Traffic detection without eBPF
+//!
+//! Where loading or attaching eBPF programs isn't permitted (locked-down
+//! kernels, missing `CAP_BPF`), `/proc/net/tcp` and `/proc/net/udp` are scanned
+//! periodically instead. A TCP socket in `SYN_SENT` or a connected UDP socket
+//! whose remote address is in the target subnets is reported as a traffic
+//! event, once per connection attempt.
+//!
+//! This only sees the host's own sockets in the daemon's network namespace:
+//! forwarded, bridged and container traffic is missed, as are unconnected UDP
+//! sockets (`sendto`). Packets can't be dropped, so the kill switch needs eBPF.
+
+use crate::config;
+use crate::stats::monotonic_now_ns;
+use crate::types::TrafficEvent;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+/// Socket tables scanned, with the IP protocol of their sockets
+const SOCKET_TABLES: [(&str, u8); 2] = [
+    ("/proc/net/tcp", IPPROTO_TCP),
+    ("/proc/net/udp", IPPROTO_UDP),
+];
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// `st` of a TCP socket waiting for the answer to its SYN
+const TCP_SYN_SENT: u8 = 0x02;
+
+/// `st` of a connected UDP socket
+const UDP_ESTABLISHED: u8 = 0x01;
+
+/// Interval floor: the tables are much more expensive to read than the ring buffer
+pub const MIN_SCAN_INTERVAL_MS: u64 = 500;
+
+/// A socket from a `/proc/net` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Socket {
+    /// IP protocol
+    pub protocol: u8,
+    /// Local address and port
+    pub local: SocketAddrV4,
+    /// Remote address and port
+    pub remote: SocketAddrV4,
+    /// Connection state (`st` column)
+    pub state: u8,
+}
+
+/// Parse an `ADDRESS:PORT` column (address as the raw in-memory `__be32`)
+fn parse_address(field: &str) -> Option<SocketAddrV4> {
+    let (ip, port) = field.split_once(':')?;
+    let ip = u32::from_str_radix(ip, 16).ok()?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    Some(SocketAddrV4::new(Ipv4Addr::from(ip.to_ne_bytes()), port))
+}
+
+/// Parse the contents of `/proc/net/tcp` or `/proc/net/udp`, skipping the header
+pub fn parse_table(contents: &str, protocol: u8) -> Vec<Socket> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            let local = parse_address(fields.next()?)?;
+            let remote = parse_address(fields.next()?)?;
+            let state = u8::from_str_radix(fields.next()?, 16).ok()?;
+            Some(Socket {
+                protocol,
+                local,
+                remote,
+                state,
+            })
+        })
+        .collect()
+}
+
+/// Whether the socket is trying to reach its remote address
+fn is_outgoing(socket: &Socket) -> bool {
+    match socket.protocol {
+        IPPROTO_TCP => socket.state == TCP_SYN_SENT,
+        IPPROTO_UDP => socket.state == UDP_ESTABLISHED && !socket.remote.ip().is_unspecified(),
+        _ => false,
+    }
+}
+
+/// Reports connection attempts to the target subnets found in the socket tables
+pub struct SocketScanner {
+    subnets: Vec<String>,
+    sources: Option<Vec<String>>,
+    excluded: HashSet<Ipv4Addr>,
+    seen: HashSet<Socket>,
+}
+
+impl SocketScanner {
+    /// Create a scanner for traffic to `subnets` (CIDR)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a subnet is invalid or the socket tables can't be read.
+    pub fn new(subnets: &[String]) -> Result<Self> {
+        for subnet in subnets {
+            config::parse_cidr(subnet).with_context(|| format!("Invalid subnet {}", subnet))?;
+        }
+        for (path, _) in SOCKET_TABLES {
+            std::fs::metadata(path).with_context(|| format!("Cannot read {}", path))?;
+        }
+        Ok(Self {
+            subnets: subnets.to_vec(),
+            sources: None,
+            excluded: HashSet::new(),
+            seen: HashSet::new(),
+        })
+    }
+
+    /// Only report sockets bound to `ranges` (CIDR), or any if None
+    pub fn set_source_filter(&mut self, ranges: Option<&[String]>) {
+        self.sources = ranges.map(<[String]>::to_vec);
+    }
+
+    /// Never report sockets connecting to `ip`; returns whether it was new
+    pub fn exclude_destination(&mut self, ip: Ipv4Addr) -> bool {
+        self.excluded.insert(ip)
+    }
+
+    /// Forget reported sockets, so those still connecting are reported again
+    pub fn reset(&mut self) {
+        self.seen.clear();
+    }
+
+    /// Read the socket tables and report new connection attempts
+    pub fn scan(&mut self) -> Vec<TrafficEvent> {
+        let mut sockets = Vec::new();
+        for (path, protocol) in SOCKET_TABLES {
+            match std::fs::read_to_string(path) {
+                Ok(contents) => sockets.extend(parse_table(&contents, protocol)),
+                Err(e) => tracing::debug!("Failed to read {}: {}", path, e),
+            }
+        }
+        self.report(sockets)
+    }
+
+    /// Traffic events for matching sockets not reported by the previous scan
+    fn report(&mut self, sockets: Vec<Socket>) -> Vec<TrafficEvent> {
+        let matching: HashSet<Socket> = sockets
+            .into_iter()
+            .filter(|socket| self.matches(socket))
+            .collect();
+        let timestamp = monotonic_now_ns();
+        let events = matching
+            .difference(&self.seen)
+            .map(|socket| TrafficEvent {
+                timestamp,
+                dest_ip: u32::from(*socket.remote.ip()),
+                dest_port: socket.remote.port(),
+                protocol: socket.protocol,
+                _padding: 0,
+            })
+            .collect();
+        self.seen = matching;
+        events
+    }
+
+    fn matches(&self, socket: &Socket) -> bool {
+        let dest = *socket.remote.ip();
+        let in_ranges = |ip: Ipv4Addr, ranges: &[String]| {
+            config::ip_in_subnets(u32::from(ip), ranges).unwrap_or(false)
+        };
+        is_outgoing(socket)
+            && in_ranges(dest, &self.subnets)
+            && !self.excluded.contains(&dest)
+            && self
+                .sources
+                .as_deref()
+                .is_none_or(|sources| in_ranges(*socket.local.ip(), sources))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `/proc/net` address column for `ip:port`
+    fn column(ip: [u8; 4], port: u16) -> String {
+        format!("{:08X}:{:04X}", u32::from_ne_bytes(ip), port)
+    }
+
+    fn table(rows: &[(String, String, u8)]) -> String {
+        let mut contents = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n".to_string();
+        for (i, (local, remote, state)) in rows.iter().enumerate() {
+            contents.push_str(&format!(
+                "{:4}: {} {} {:02X} 00000000:00000000 00:00000000 00000000  1000        0 {} 1 0000000000000000 20 4 0 10 -1\n",
+                i, local, remote, state, 1000 + i
+            ));
+        }
+        contents
+    }
+
+    #[test]
+    fn test_parse_table() {
+        let contents = table(&[
+            (column([0, 0, 0, 0], 22), column([0, 0, 0, 0], 0), 0x0A),
+            (
+                column([192, 168, 1, 20], 51000),
+                column([10, 0, 0, 5], 443),
+                TCP_SYN_SENT,
+            ),
+        ]);
+        let sockets = parse_table(&contents, IPPROTO_TCP);
+        assert_eq!(sockets.len(), 2);
+        assert_eq!(sockets[0].local, "0.0.0.0:22".parse().unwrap());
+        assert_eq!(sockets[1].local, "192.168.1.20:51000".parse().unwrap());
+        assert_eq!(sockets[1].remote, "10.0.0.5:443".parse().unwrap());
+        assert_eq!(sockets[1].state, TCP_SYN_SENT);
+        assert!(parse_table("header only\n", IPPROTO_TCP).is_empty());
+    }
+
+    #[test]
+    fn test_report() {
+        let mut scanner = SocketScanner {
+            subnets: vec!["10.0.0.0/24".to_string()],
+            sources: None,
+            excluded: HashSet::new(),
+            seen: HashSet::new(),
+        };
+        let socket = |remote: &str, protocol, state| Socket {
+            protocol,
+            local: "192.168.1.20:51000".parse().unwrap(),
+            remote: remote.parse().unwrap(),
+            state,
+        };
+        let connecting = socket("10.0.0.5:443", IPPROTO_TCP, TCP_SYN_SENT);
+        let sockets = vec![
+            connecting,
+            socket("10.0.0.5:80", IPPROTO_TCP, 0x01),
+            socket("192.168.2.1:443", IPPROTO_TCP, TCP_SYN_SENT),
+            socket("10.0.0.6:53", IPPROTO_UDP, UDP_ESTABLISHED),
+        ];
+
+        let events = scanner.report(sockets.clone());
+        let mut destinations: Vec<String> = events.iter().map(TrafficEvent::destination).collect();
+        destinations.sort();
+        assert_eq!(destinations, ["10.0.0.5:443/tcp", "10.0.0.6:53/udp"]);
+
+        // Reported once per connection attempt
+        assert!(scanner.report(sockets.clone()).is_empty());
+        scanner.report(vec![]);
+        assert_eq!(scanner.report(vec![connecting]).len(), 1);
+
+        // Excluded destinations and filtered sources
+        scanner.reset();
+        scanner.exclude_destination(Ipv4Addr::new(10, 0, 0, 5));
+        scanner.set_source_filter(Some(&["172.16.0.0/12".to_string()]));
+        assert!(scanner.report(sockets.clone()).is_empty());
+        scanner.set_source_filter(Some(&["192.168.1.20/32".to_string()]));
+        assert_eq!(scanner.report(sockets).len(), 1);
+    }
+}
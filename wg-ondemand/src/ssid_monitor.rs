@@ -4,20 +4,62 @@
 //!
 //! This module monitors WiFi network changes using NetworkManager's D-Bus interface,
 //! detecting when the system connects to or disconnects from the target SSID.
-
+//! A network only counts as connected once NetworkManager has finished activating
+//! it (DHCP, DNS), waiting at most a bounded time for that, and, behind a captive
+//! portal, once NetworkManager's connectivity check reports full internet access.
+//! Restarts of NetworkManager or the D-Bus daemon are ridden out by reconnecting.
+//! While connected, a change of the wireless device's active access point within
+//! the same SSID is reported as a roam.
+
+use crate::error::DbusError;
+use crate::network_detector::{is_monitored_ssid, NetworkDetector};
 use anyhow::{Context, Result};
-use futures_util::stream::StreamExt;
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, StreamExt};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
 use tokio::sync::mpsc;
-use zbus::{proxy, Connection};
-
-/// Network event types
-#[derive(Debug, Clone)]
-pub enum NetworkEvent {
-    /// Connected to the target SSID (with SSID name)
-    ConnectedToTarget(String),
-    /// Disconnected from the target SSID (or connected to different network)
-    Disconnected,
-}
+use tokio::time::Instant;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::{proxy, Connection, MessageStream};
+
+pub use crate::network_detector::NetworkEvent;
+
+/// NetworkManager connectivity states (`NMConnectivityState`)
+const NM_CONNECTIVITY_UNKNOWN: u32 = 0;
+const NM_CONNECTIVITY_PORTAL: u32 = 2;
+const NM_CONNECTIVITY_FULL: u32 = 4;
+
+/// How often NetworkManager is asked to re-check connectivity while deferred
+const CONNECTIVITY_RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// First delay before reconnecting to D-Bus, doubled on each failure
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest delay between reconnection attempts
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// `NM_DEVICE_TYPE_WIFI`
+const NM_DEVICE_TYPE_WIFI: u32 = 2;
+
+/// `NM_DEVICE_STATE_ACTIVATED`
+const NM_DEVICE_STATE_ACTIVATED: u32 = 100;
+
+/// Connection type of WiFi connections
+const WIRELESS_CONNECTION_TYPE: &str = "802-11-wireless";
+
+/// State changes of any NetworkManager device
+const DEVICE_STATE_SIGNALS: &str = "type='signal',sender='org.freedesktop.NetworkManager',\
+    interface='org.freedesktop.NetworkManager.Device',member='StateChanged'";
+
+/// Property changes of any NetworkManager wireless device
+const WIRELESS_PROPERTY_SIGNALS: &str = "type='signal',sender='org.freedesktop.NetworkManager',\
+    interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',\
+    arg0='org.freedesktop.NetworkManager.Device.Wireless'";
+
+/// How often the primary connection is polled while it is still activating
+const ACTIVATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 /// D-Bus proxy for NetworkManager
 #[proxy(
@@ -33,6 +75,16 @@ trait NetworkManager {
     /// Get all active connections
     #[zbus(property)]
     fn active_connections(&self) -> zbus::Result<Vec<zbus::zvariant::OwnedObjectPath>>;
+
+    /// Get the result of the last connectivity check
+    #[zbus(property)]
+    fn connectivity(&self) -> zbus::Result<u32>;
+
+    /// Re-run the connectivity check
+    fn check_connectivity(&self) -> zbus::Result<u32>;
+
+    /// Get all network devices
+    fn get_devices(&self) -> zbus::Result<Vec<zbus::zvariant::OwnedObjectPath>>;
 }
 
 /// D-Bus proxy for active connection
@@ -54,6 +106,21 @@ trait ActiveConnection {
     fn devices(&self) -> zbus::Result<Vec<zbus::zvariant::OwnedObjectPath>>;
 }
 
+/// D-Bus proxy for a network device
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Device",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait Device {
+    /// Get the device type (`NMDeviceType`)
+    #[zbus(property)]
+    fn device_type(&self) -> zbus::Result<u32>;
+
+    /// Get the device state (`NMDeviceState`)
+    #[zbus(property)]
+    fn state(&self) -> zbus::Result<u32>;
+}
+
 /// D-Bus proxy for wireless device
 #[proxy(
     interface = "org.freedesktop.NetworkManager.Device.Wireless",
@@ -74,13 +141,39 @@ trait AccessPoint {
     /// Get the SSID as raw bytes
     #[zbus(property)]
     fn ssid(&self) -> zbus::Result<Vec<u8>>;
+
+    /// Get the BSSID
+    #[zbus(property)]
+    fn hw_address(&self) -> zbus::Result<String>;
+}
+
+/// The access point the current WiFi device is associated with
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AccessPoint {
+    ssid: String,
+    bssid: String,
+}
+
+impl AccessPoint {
+    /// Whether moving from `self` to `other` is a roam within the same network
+    fn is_roam_to(&self, other: &AccessPoint) -> bool {
+        self.ssid == other.ssid && !self.bssid.eq_ignore_ascii_case(&other.bssid)
+    }
+
+    /// Whether moving from `self` to `other` switches to another network
+    fn is_network_change_to(&self, other: &AccessPoint) -> bool {
+        self.ssid != other.ssid
+    }
 }
 
 /// SSID monitor
 pub struct SsidMonitor {
     target_ssids: Vec<String>,
     exclude_ssids: Vec<String>,
-    connection: Connection,
+    connectivity_check: bool,
+    connection_wait: Duration,
+    /// Replaced when the system bus is reconnected
+    connection: RwLock<Connection>,
 }
 
 impl SsidMonitor {
@@ -89,7 +182,10 @@ impl SsidMonitor {
     /// # Arguments
     /// * `target_ssids` - Whitelist of SSIDs to monitor. If empty, monitors all SSIDs.
     /// * `exclude_ssids` - Blacklist of SSIDs to exclude. Takes precedence over target_ssids.
-    pub async fn new(target_ssids: Vec<String>, exclude_ssids: Vec<String>) -> Result<Self> {
+    pub async fn new(
+        target_ssids: Vec<String>,
+        exclude_ssids: Vec<String>,
+    ) -> Result<Self, DbusError> {
         let connection = Connection::system()
             .await
             .context("Failed to connect to system D-Bus")?;
@@ -97,45 +193,120 @@ impl SsidMonitor {
         Ok(Self {
             target_ssids,
             exclude_ssids,
-            connection,
+            connectivity_check: false,
+            connection_wait: Duration::ZERO,
+            connection: RwLock::new(connection),
         })
     }
 
-    /// Get the current SSID
-    pub async fn current_ssid(&self) -> Result<Option<String>> {
-        let nm = NetworkManagerProxy::new(&self.connection)
+    fn connection(&self) -> Connection {
+        self.connection
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Wait up to `wait` for NetworkManager to finish activating a monitored
+    /// connection before reporting it as connected
+    pub fn with_connection_wait(mut self, wait: Duration) -> Self {
+        self.connection_wait = wait;
+        self
+    }
+
+    /// Whether the current WiFi device has finished activating (IP configured)
+    pub async fn is_activated(&self) -> Result<bool, DbusError> {
+        let Some(device) = self.wifi_device().await? else {
+            return Ok(false);
+        };
+        let device = DeviceProxy::builder(&self.connection())
+            .path(&device)?
+            .build()
+            .await?;
+        Ok(device.state().await? == NM_DEVICE_STATE_ACTIVATED)
+    }
+
+    /// The wireless device whose network is the current one
+    ///
+    /// That of the primary connection if it is WiFi. Behind a VPN (such as the
+    /// tunnel itself) or without a primary connection, the activated WiFi device
+    /// underneath; a wired primary connection takes precedence over WiFi.
+    async fn wifi_device(&self) -> Result<Option<OwnedObjectPath>, DbusError> {
+        let connection = self.connection();
+        let nm = NetworkManagerProxy::new(&connection)
             .await
             .context("Failed to create NetworkManager proxy")?;
 
-        // Get primary connection
-        let primary = match nm.primary_connection().await {
-            Ok(p) => p,
-            Err(_) => return Ok(None),
-        };
+        let primary = nm
+            .primary_connection()
+            .await
+            .ok()
+            .filter(|primary| primary.as_str() != "/");
+        if let Some(primary) = primary {
+            let active_conn = ActiveConnectionProxy::builder(&connection)
+                .path(&primary)?
+                .build()
+                .await?;
+            let kind = active_conn.connection_type().await?;
+            if kind == WIRELESS_CONNECTION_TYPE {
+                return Ok(active_conn.devices().await?.into_iter().next());
+            }
+            if !is_virtual_connection(&kind) {
+                return Ok(None);
+            }
+        }
 
-        if primary.as_str() == "/" {
-            return Ok(None);
+        for path in nm.get_devices().await? {
+            let device = DeviceProxy::builder(&connection)
+                .path(&path)?
+                .build()
+                .await?;
+            if device.device_type().await? == NM_DEVICE_TYPE_WIFI
+                && device.state().await? == NM_DEVICE_STATE_ACTIVATED
+            {
+                return Ok(Some(path));
+            }
         }
+        Ok(None)
+    }
 
-        // Get active connection details
-        let active_conn = ActiveConnectionProxy::builder(&self.connection)
-            .path(&primary)?
-            .build()
-            .await?;
+    /// Only report a monitored network as connected once NetworkManager's
+    /// connectivity check reports full internet access (or is disabled)
+    pub fn with_connectivity_check(mut self, enabled: bool) -> Self {
+        self.connectivity_check = enabled;
+        self
+    }
 
-        // Check if it's a wireless connection
-        if active_conn.connection_type().await? != "802-11-wireless" {
-            return Ok(None);
+    /// Whether the connectivity check allows monitoring
+    ///
+    /// Returns `true` if the check is disabled here or in NetworkManager, or if
+    /// NetworkManager reports full connectivity.
+    pub async fn has_connectivity(&self) -> Result<bool, DbusError> {
+        if !self.connectivity_check {
+            return Ok(true);
         }
+        let nm = NetworkManagerProxy::new(&self.connection())
+            .await
+            .context("Failed to create NetworkManager proxy")?;
+        let state = nm.connectivity().await?;
+        if state == NM_CONNECTIVITY_PORTAL {
+            tracing::debug!("Captive portal detected");
+        }
+        Ok(connectivity_allows_monitoring(state))
+    }
+
+    /// Get the current SSID
+    pub async fn current_ssid(&self) -> Result<Option<String>, DbusError> {
+        Ok(self.access_point().await?.map(|ap| ap.ssid))
+    }
 
-        // Get wireless device
-        let devices = active_conn.devices().await?;
-        if devices.is_empty() {
+    /// Get the current access point
+    async fn access_point(&self) -> Result<Option<AccessPoint>, DbusError> {
+        let Some(device) = self.wifi_device().await? else {
             return Ok(None);
-        }
+        };
 
-        let wireless_dev = WirelessDeviceProxy::builder(&self.connection)
-            .path(&devices[0])?
+        let wireless_dev = WirelessDeviceProxy::builder(&self.connection())
+            .path(&device)?
             .build()
             .await?;
 
@@ -145,7 +316,7 @@ impl SsidMonitor {
             return Ok(None);
         }
 
-        let ap = AccessPointProxy::builder(&self.connection)
+        let ap = AccessPointProxy::builder(&self.connection())
             .path(&ap_path)?
             .build()
             .await?;
@@ -154,7 +325,10 @@ impl SsidMonitor {
         let ssid_bytes = ap.ssid().await?;
         let ssid = String::from_utf8(ssid_bytes).context("Invalid UTF-8 in SSID")?;
 
-        Ok(Some(ssid))
+        Ok(Some(AccessPoint {
+            ssid,
+            bssid: ap.hw_address().await?,
+        }))
     }
 
     /// Check if connected to a monitored SSID (respecting whitelist/blacklist rules)
@@ -162,99 +336,334 @@ impl SsidMonitor {
     /// Returns `true` if:
     /// - Connected to WiFi network AND
     /// - (target_ssids is empty OR current SSID is in target_ssids) AND
-    /// - Current SSID is NOT in exclude_ssids
-    pub async fn is_connected_to_target(&self) -> Result<bool> {
-        match self.current_ssid().await? {
-            Some(ssid) => {
-                // First check blacklist (takes precedence)
-                if self.exclude_ssids.contains(&ssid) {
-                    log::debug!("SSID '{}' is in exclude list", ssid);
-                    return Ok(false);
-                }
+    /// - Current SSID is NOT in exclude_ssids AND
+    /// - The connectivity check (if enabled) reports full connectivity AND
+    /// - The connection has finished activating (if waited for)
+    pub async fn is_connected_to_target(&self) -> Result<bool, DbusError> {
+        Ok(self.is_target_ssid().await?
+            && self.has_connectivity().await?
+            && (self.connection_wait.is_zero() || self.is_activated().await?))
+    }
 
-                // Then check whitelist
-                if self.target_ssids.is_empty() {
-                    // Empty whitelist means "all SSIDs" (except those excluded)
-                    log::debug!("SSID '{}' allowed (monitor all mode)", ssid);
-                    Ok(true)
-                } else {
-                    // Non-empty whitelist: must be in the list
-                    let is_target = self.target_ssids.contains(&ssid);
-                    if is_target {
-                        log::debug!("SSID '{}' is in target list", ssid);
-                    } else {
-                        log::debug!("SSID '{}' not in target list", ssid);
-                    }
-                    Ok(is_target)
-                }
-            }
-            None => Ok(false),
-        }
+    /// Check the current SSID against the whitelist/blacklist rules
+    async fn is_target_ssid(&self) -> Result<bool, DbusError> {
+        Ok(self
+            .current_ssid()
+            .await?
+            .is_some_and(|ssid| is_monitored_ssid(&ssid, &self.target_ssids, &self.exclude_ssids)))
     }
 
     /// Monitor for network changes and send events
-    pub async fn monitor(&self, tx: mpsc::Sender<NetworkEvent>) -> Result<()> {
-        let nm = NetworkManagerProxy::new(&self.connection).await?;
-        let mut stream = nm.receive_primary_connection_changed().await;
-
-        let mut was_connected = self.is_connected_to_target().await?;
-
+    ///
+    /// Survives NetworkManager and D-Bus restarts: the system bus is reconnected
+    /// with backoff and the current network re-checked, reporting only what
+    /// changed meanwhile, so an active tunnel is left alone. Returns once the
+    /// receiver is gone.
+    pub async fn monitor(&self, tx: mpsc::Sender<NetworkEvent>) -> Result<(), DbusError> {
         // Log monitoring configuration
         if self.target_ssids.is_empty() && self.exclude_ssids.is_empty() {
-            log::info!("Starting SSID monitor: monitoring ALL networks");
+            tracing::info!("Starting SSID monitor: monitoring ALL networks");
         } else if self.target_ssids.is_empty() {
-            log::info!(
+            tracing::info!(
                 "Starting SSID monitor: monitoring all EXCEPT {:?}",
                 self.exclude_ssids
             );
         } else if self.exclude_ssids.is_empty() {
-            log::info!(
+            tracing::info!(
                 "Starting SSID monitor: monitoring ONLY {:?}",
                 self.target_ssids
             );
         } else {
-            log::info!(
+            tracing::info!(
                 "Starting SSID monitor: monitoring {:?} EXCEPT {:?}",
                 self.target_ssids,
                 self.exclude_ssids
             );
         }
 
-        if was_connected {
-            if let Ok(Some(current)) = self.current_ssid().await {
-                log::info!("Already connected to monitored SSID: {}", current);
+        let mut last_connected = None;
+        let mut delay = RECONNECT_INITIAL_DELAY;
+        loop {
+            let started = Instant::now();
+            match self.watch(&tx, &mut last_connected).await {
+                Ok(()) => tracing::warn!("NetworkManager signals ended, reconnecting to D-Bus"),
+                Err(e) => tracing::warn!(
+                    "NetworkManager monitoring failed, reconnecting to D-Bus: {}",
+                    e
+                ),
+            }
+            if tx.is_closed() {
+                return Ok(());
+            }
+
+            // Only back off further if the last connection didn't last
+            if started.elapsed() > RECONNECT_MAX_DELAY {
+                delay = RECONNECT_INITIAL_DELAY;
+            }
+            loop {
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                match Connection::system().await {
+                    Ok(connection) => {
+                        *self.connection.write().unwrap_or_else(|e| e.into_inner()) = connection;
+                        break;
+                    }
+                    Err(e) => tracing::warn!("Failed to reconnect to the system D-Bus: {}", e),
+                }
+            }
+        }
+    }
+
+    /// Watch NetworkManager over the current connection until its signals end
+    ///
+    /// `last_connected` carries the monitored state across reconnections.
+    async fn watch(
+        &self,
+        tx: &mpsc::Sender<NetworkEvent>,
+        last_connected: &mut Option<bool>,
+    ) -> Result<(), DbusError> {
+        let connection = self.connection();
+        let nm = NetworkManagerProxy::new(&connection).await?;
+        let primary_changes = nm.receive_primary_connection_changed().await.map(|_| ());
+        let connectivity_changes = nm.receive_connectivity_changed().await.map(|_| ());
+        // The primary connection doesn't change when WiFi switches underneath a
+        // VPN, but the active connections and the devices' states do
+        let active_changes = nm.receive_active_connections_changed().await.map(|_| ());
+        let device_changes = MessageStream::for_match_rule(DEVICE_STATE_SIGNALS, &connection, None)
+            .await?
+            .map(|_| ());
+        // Roams only show in the wireless device's ActiveAccessPoint
+        let roams = MessageStream::for_match_rule(WIRELESS_PROPERTY_SIGNALS, &connection, None)
+            .await?
+            .filter(|message| {
+                let changed = message.as_ref().is_ok_and(|message| {
+                    message
+                        .body()
+                        .deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+                        .is_ok_and(|(_, changed, _)| changed.contains_key("ActiveAccessPoint"))
+                });
+                std::future::ready(changed)
+            })
+            .map(|_| ());
+        // A restarted NetworkManager comes back with a new unique name
+        let owner_changes = nm.inner().receive_owner_changed().await?.map(|_| ());
+        let mut changes = stream::select(
+            stream::select(
+                stream::select(primary_changes, connectivity_changes),
+                stream::select(active_changes, device_changes),
+            ),
+            stream::select(roams, owner_changes),
+        );
+        let mut recheck = tokio::time::interval(CONNECTIVITY_RECHECK_INTERVAL);
+        recheck.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut activation_poll = tokio::time::interval(ACTIVATION_POLL_INTERVAL);
+
+        let is_target = self.is_target_ssid().await?;
+        let is_online = is_target && self.has_connectivity().await?;
+        let mut was_connected =
+            is_online && (self.connection_wait.is_zero() || self.is_activated().await?);
+        let mut deferred = is_target && !is_online;
+        // Set while a monitored connection is still getting its address and DNS
+        let mut activating_since = (is_online && !was_connected).then(Instant::now);
+        // The access point while connected, to notice roams
+        let mut access_point = if was_connected {
+            self.access_point().await?
+        } else {
+            None
+        };
+
+        // After a reconnect, only report what changed while D-Bus was unavailable
+        match last_connected.replace(was_connected) {
+            Some(before) if before != was_connected => self.report(tx, was_connected).await,
+            Some(_) => tracing::info!("Reconnected to NetworkManager, network unchanged"),
+            None if was_connected => {
+                if let Ok(Some(current)) = self.current_ssid().await {
+                    tracing::info!("Already connected to monitored SSID: {}", current);
+                }
             }
+            None => {}
+        }
+        if deferred {
+            tracing::info!(
+                "On a monitored SSID without full connectivity (captive portal?), deferring monitoring"
+            );
         }
 
-        while let Some(_signal) = stream.next().await {
-            let is_connected = match self.is_connected_to_target().await {
-                Ok(c) => c,
+        loop {
+            tokio::select! {
+                change = changes.next() => {
+                    if change.is_none() {
+                        break;
+                    }
+                }
+                _ = recheck.tick(), if deferred => {
+                    // NetworkManager re-checks rarely on its own; ask it so a
+                    // portal cleared in the browser is noticed quickly
+                    if let Err(e) = nm.check_connectivity().await {
+                        tracing::debug!("Failed to request a connectivity check: {}", e);
+                    }
+                    continue;
+                }
+                // The active connection's state isn't among the watched signals
+                _ = activation_poll.tick(), if activating_since.is_some() && !was_connected => {}
+            }
+
+            let is_target = match self.is_target_ssid().await {
+                Ok(t) => t,
                 Err(e) => {
-                    log::warn!("Failed to check SSID: {}", e);
+                    tracing::warn!("Failed to check SSID: {}", e);
                     continue;
                 }
             };
+            let is_online = is_target
+                && match self.has_connectivity().await {
+                    Ok(c) => c,
+                    Err(e) => {
+                        tracing::warn!("Failed to check connectivity: {}", e);
+                        continue;
+                    }
+                };
+
+            // PrimaryConnection changes before DHCP and DNS are done, which
+            // would make the gateway detection fail; wait, but not forever
+            let activated = !is_online
+                || self.connection_wait.is_zero()
+                || match self.is_activated().await {
+                    Ok(a) => a,
+                    Err(e) => {
+                        tracing::warn!("Failed to check the connection state: {}", e);
+                        continue;
+                    }
+                };
+            let waited_out = if is_online && !activated {
+                let since = *activating_since.get_or_insert_with(|| {
+                    tracing::info!(
+                        "Waiting for NetworkManager to finish activating the connection"
+                    );
+                    Instant::now()
+                });
+                let waited_out = since.elapsed() >= self.connection_wait;
+                if waited_out && !was_connected {
+                    tracing::warn!(
+                        "Connection still activating after {}s, monitoring anyway",
+                        self.connection_wait.as_secs()
+                    );
+                }
+                waited_out
+            } else {
+                activating_since = None;
+                false
+            };
+            let is_connected = is_online && (activated || waited_out);
 
-            if is_connected && !was_connected {
-                if let Ok(Some(current)) = self.current_ssid().await {
-                    log::info!("Connected to monitored SSID: {}", current);
-                    let _ = tx.send(NetworkEvent::ConnectedToTarget(current)).await;
-                } else {
-                    // Fallback if we can't get SSID
-                    let _ = tx
-                        .send(NetworkEvent::ConnectedToTarget(String::new()))
-                        .await;
+            let now_deferred = is_target && !is_online;
+            if now_deferred && !deferred {
+                tracing::info!(
+                    "On a monitored SSID without full connectivity (captive portal?), deferring monitoring"
+                );
+            }
+            deferred = now_deferred;
+
+            if is_connected != was_connected {
+                self.report(tx, is_connected).await;
+            }
+
+            if is_connected {
+                let current = match self.access_point().await {
+                    Ok(ap) => ap,
+                    Err(e) => {
+                        tracing::debug!("Failed to check the access point: {}", e);
+                        access_point.clone()
+                    }
+                };
+                if let (Some(before), Some(after)) = (&access_point, &current) {
+                    // Moving between two monitored SSIDs keeps us connected,
+                    // but the network to report has changed
+                    if was_connected && before.is_network_change_to(after) {
+                        tracing::info!(
+                            "Switched from monitored SSID {} to {}",
+                            before.ssid,
+                            after.ssid
+                        );
+                        let _ = tx
+                            .send(NetworkEvent::ConnectedToTarget(after.ssid.clone()))
+                            .await;
+                    } else if was_connected && before.is_roam_to(after) {
+                        tracing::info!(
+                            "Roamed from {} to {} on {}",
+                            before.bssid,
+                            after.bssid,
+                            after.ssid
+                        );
+                        let _ = tx
+                            .send(NetworkEvent::Roamed {
+                                ssid: after.ssid.clone(),
+                                bssid: after.bssid.clone(),
+                            })
+                            .await;
+                    }
                 }
-            } else if !is_connected && was_connected {
-                log::info!("Disconnected from monitored SSID");
-                let _ = tx.send(NetworkEvent::Disconnected).await;
+                access_point = current;
+            } else {
+                access_point = None;
             }
 
             was_connected = is_connected;
+            *last_connected = Some(is_connected);
         }
 
         Ok(())
     }
+
+    /// Send the event for joining or leaving the monitored network
+    async fn report(&self, tx: &mpsc::Sender<NetworkEvent>, connected: bool) {
+        if connected {
+            if let Ok(Some(current)) = self.current_ssid().await {
+                tracing::info!("Connected to monitored SSID: {}", current);
+                let _ = tx.send(NetworkEvent::ConnectedToTarget(current)).await;
+            } else {
+                // Fallback if we can't get SSID
+                let _ = tx
+                    .send(NetworkEvent::ConnectedToTarget(String::new()))
+                    .await;
+            }
+        } else {
+            tracing::info!("Disconnected from monitored SSID");
+            let _ = tx.send(NetworkEvent::Disconnected).await;
+        }
+    }
+}
+
+/// Whether a connection type is layered over another connection (VPNs,
+/// tunnels), so the network is that of the device underneath
+fn is_virtual_connection(kind: &str) -> bool {
+    matches!(
+        kind,
+        "vpn" | "wireguard" | "tun" | "ip-tunnel" | "vxlan" | "macsec" | "loopback"
+    )
+}
+
+/// Whether a NetworkManager connectivity state allows monitoring
+///
+/// `UNKNOWN` means the check is disabled in NetworkManager and is not waited for.
+/// `NONE`, `PORTAL` and `LIMITED` all defer: a portal often shows as `LIMITED`
+/// until NetworkManager has finished probing it.
+fn connectivity_allows_monitoring(state: u32) -> bool {
+    matches!(state, NM_CONNECTIVITY_UNKNOWN | NM_CONNECTIVITY_FULL)
+}
+
+impl NetworkDetector for SsidMonitor {
+    fn current_network(&self) -> BoxFuture<'_, anyhow::Result<Option<String>>> {
+        Box::pin(async move { Ok(self.current_ssid().await?) })
+    }
+
+    fn is_connected_to_target(&self) -> BoxFuture<'_, anyhow::Result<bool>> {
+        Box::pin(async move { Ok(SsidMonitor::is_connected_to_target(self).await?) })
+    }
+
+    fn monitor(&self, tx: mpsc::Sender<NetworkEvent>) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move { Ok(SsidMonitor::monitor(self, tx).await?) })
+    }
 }
 
 #[cfg(test)]
@@ -268,6 +677,49 @@ mod tests {
         assert_eq!(target, "TestSSID");
     }
 
+    #[test]
+    fn test_is_virtual_connection() {
+        assert!(is_virtual_connection("vpn"));
+        assert!(is_virtual_connection("wireguard"));
+        assert!(!is_virtual_connection(WIRELESS_CONNECTION_TYPE));
+        assert!(!is_virtual_connection("802-3-ethernet"));
+    }
+
+    #[test]
+    fn test_is_roam_to() {
+        let ap = |ssid: &str, bssid: &str| AccessPoint {
+            ssid: ssid.to_string(),
+            bssid: bssid.to_string(),
+        };
+        let home = ap("home", "AA:BB:CC:00:00:01");
+        assert!(home.is_roam_to(&ap("home", "AA:BB:CC:00:00:02")));
+        assert!(!home.is_roam_to(&ap("home", "aa:bb:cc:00:00:01")));
+        assert!(!home.is_roam_to(&ap("cafe", "AA:BB:CC:00:00:02")));
+    }
+
+    #[test]
+    fn test_is_network_change_to() {
+        let ap = |ssid: &str, bssid: &str| AccessPoint {
+            ssid: ssid.to_string(),
+            bssid: bssid.to_string(),
+        };
+        let home = ap("home", "AA:BB:CC:00:00:01");
+        // Monitored SSID A to monitored SSID B
+        assert!(home.is_network_change_to(&ap("home-5g", "AA:BB:CC:00:00:02")));
+        assert!(home.is_network_change_to(&ap("office", "AA:BB:CC:00:00:01")));
+        assert!(!home.is_network_change_to(&ap("home", "AA:BB:CC:00:00:02")));
+        assert!(!home.is_network_change_to(&home));
+    }
+
+    #[test]
+    fn test_connectivity_allows_monitoring() {
+        assert!(connectivity_allows_monitoring(NM_CONNECTIVITY_UNKNOWN));
+        assert!(connectivity_allows_monitoring(NM_CONNECTIVITY_FULL));
+        assert!(!connectivity_allows_monitoring(1)); // NONE
+        assert!(!connectivity_allows_monitoring(NM_CONNECTIVITY_PORTAL));
+        assert!(!connectivity_allows_monitoring(3)); // LIMITED
+    }
+
     #[test]
     fn test_network_event_types() {
         let event = NetworkEvent::ConnectedToTarget("TestSSID".to_string());
@@ -6,17 +6,29 @@
 //! and deactivation based on network events, traffic detection, and idle timeouts.
 
 use crate::types::TunnelState;
-use std::time::Duration;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Upper bound for the flap backoff cooldown
+const MAX_FLAP_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// Upper bound for the delay between tunnel activation retries
+const MAX_ACTIVATION_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Number of state events buffered per subscriber before the oldest are dropped
+const EVENT_CHANNEL_SIZE: usize = 64;
 
 /// Commands that trigger state transitions
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StateCommand {
     /// Start monitoring (connected to target SSID)
     StartMonitoring,
     /// Stop monitoring (disconnected from target SSID)
     StopMonitoring,
-    /// Traffic detected to target subnet
-    TrafficDetected,
+    /// Traffic detected to target subnet, with the event's kernel timestamp
+    /// (`CLOCK_MONOTONIC` nanoseconds)
+    TrafficDetected(u64),
     /// Tunnel successfully brought up
     TunnelUp,
     /// Tunnel brought down
@@ -27,6 +39,24 @@ pub enum StateCommand {
     TunnelAlreadyUp,
     /// Retry eBPF attachment after interface gets IP address
     RetryEbpfAttachment,
+    /// Maximum session duration reached (tunnel must go down regardless of activity)
+    SessionLimitReached,
+    /// User requested activation (bypasses cooldown and debounce)
+    ForceActivate,
+    /// User requested deactivation (pauses traffic-triggered activation)
+    ForceDeactivate,
+    /// Tunnel bring-up failed
+    ActivationFailed,
+    /// Scheduled activation retry is due
+    RetryActivation,
+    /// Tunnel interface appeared without the daemon bringing it up (manual session)
+    TunnelUpExternally,
+    /// Active tunnel failed its health check (stale handshake or unreachable peer)
+    TunnelUnhealthy,
+    /// Tunnel interface went away while active (brought down outside the daemon)
+    TunnelLost,
+    /// Presence probe answered without the tunnel (on the home network)
+    HomeNetworkReachable,
 }
 
 /// Actions to take in response to state changes
@@ -40,15 +70,60 @@ pub enum StateAction {
     AttachEbpf,
     /// Detach eBPF program and remove monitoring routes
     DetachEbpf,
+    /// Send RetryActivation after the given delay
+    ScheduleActivationRetry(Duration),
+    /// Bring the tunnel down and up again
+    RestartTunnel,
     /// No action needed
     None,
 }
 
+/// A handled command that changed the state or requested an action
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateEvent {
+    /// Command that was handled
+    pub command: StateCommand,
+    /// State before the command
+    pub from: TunnelState,
+    /// State after the command
+    pub to: TunnelState,
+    /// Action requested by the state machine
+    pub action: StateAction,
+}
+
 /// State machine manager
 pub struct StateManager {
     state: TunnelState,
     idle_timeout: Duration,
+    min_active: Duration,
+    active_since: Option<Instant>,
+    activation_delay: Duration,
+    /// Kernel timestamp of the event that opened the debounce window
+    pending_traffic_since: Option<u64>,
+    max_session: Option<Duration>,
+    cooldown: Duration,
+    pause: Duration,
+    cooldown_until: Option<Instant>,
+    flap_threshold: usize,
+    flap_window: Duration,
+    flap_backoff: Duration,
+    activations: VecDeque<Instant>,
+    backoff_level: u32,
+    activation_retries: u32,
+    activation_retry_delay: Duration,
+    activation_attempts: u32,
+    retry_pending: bool,
+    restarting: bool,
+    health_restarts: u32,
     on_monitored_ssid: bool,
+    resumed_active_for: Option<Duration>,
+    resumed_manual: bool,
+    manual: bool,
+    /// Activation forced by the user off a monitored network, tracked as a
+    /// manual session once up
+    manual_activation: bool,
+    manual_idle_timeout: bool,
+    events: broadcast::Sender<StateEvent>,
 }
 
 impl StateManager {
@@ -57,18 +132,219 @@ impl StateManager {
         Self {
             state: TunnelState::Inactive,
             idle_timeout: Duration::from_secs(idle_timeout_secs),
+            min_active: Duration::ZERO,
+            active_since: None,
+            activation_delay: Duration::ZERO,
+            pending_traffic_since: None,
+            max_session: None,
+            cooldown: Duration::ZERO,
+            pause: Duration::ZERO,
+            cooldown_until: None,
+            flap_threshold: 0,
+            flap_window: Duration::ZERO,
+            flap_backoff: Duration::ZERO,
+            activations: VecDeque::new(),
+            backoff_level: 0,
+            activation_retries: 0,
+            activation_retry_delay: Duration::ZERO,
+            activation_attempts: 0,
+            retry_pending: false,
+            restarting: false,
+            health_restarts: 0,
             on_monitored_ssid: false,
+            resumed_active_for: None,
+            resumed_manual: false,
+            manual: false,
+            manual_activation: false,
+            manual_idle_timeout: false,
+            events: broadcast::channel(EVENT_CHANNEL_SIZE).0,
+        }
+    }
+
+    /// Set the minimum time the tunnel stays up before idle timeout may deactivate it
+    pub fn with_min_active(mut self, min_active_secs: u64) -> Self {
+        self.min_active = Duration::from_secs(min_active_secs);
+        self
+    }
+
+    /// Let the idle timeout deactivate tunnels brought up outside the daemon
+    /// (by default they stay up until brought down the same way)
+    pub fn with_manual_idle_timeout(mut self, enabled: bool) -> Self {
+        self.manual_idle_timeout = enabled;
+        self
+    }
+
+    /// Set the debounce window: traffic must be seen at least twice within this window
+    /// before the tunnel is activated (0 activates on the first event)
+    pub fn with_activation_delay(mut self, activation_delay_ms: u64) -> Self {
+        self.activation_delay = Duration::from_millis(activation_delay_ms);
+        self
+    }
+
+    /// Set the maximum time the tunnel may stay up before it is forcibly deactivated
+    pub fn with_max_session(mut self, max_session_secs: Option<u64>) -> Self {
+        self.max_session = max_session_secs.map(Duration::from_secs);
+        self
+    }
+
+    /// Set how long re-activation by traffic is suppressed after an idle deactivation
+    pub fn with_cooldown(mut self, cooldown_secs: u64) -> Self {
+        self.cooldown = Duration::from_secs(cooldown_secs);
+        self
+    }
+
+    /// Set how long a forced deactivation holds off traffic-triggered activation
+    pub fn with_pause(mut self, pause_secs: u64) -> Self {
+        self.pause = Duration::from_secs(pause_secs);
+        self
+    }
+
+    /// Enable flap detection: more than `threshold` activations within `window_secs`
+    /// puts the daemon into backoff, suppressing re-activation for `backoff_secs`,
+    /// doubled for every consecutive flap (0 threshold disables)
+    pub fn with_flap_detection(
+        mut self,
+        threshold: u32,
+        window_secs: u64,
+        backoff_secs: u64,
+    ) -> Self {
+        self.flap_threshold = threshold as usize;
+        self.flap_window = Duration::from_secs(window_secs);
+        self.flap_backoff = Duration::from_secs(backoff_secs);
+        self
+    }
+
+    /// Set how often a failed tunnel bring-up is retried, starting after `retry_secs`
+    /// and doubling the delay on each attempt
+    pub fn with_activation_retries(mut self, retries: u32, retry_secs: u64) -> Self {
+        self.activation_retries = retries;
+        self.activation_retry_delay = Duration::from_secs(retry_secs);
+        self
+    }
+
+    /// Delay before the given (1-based) activation retry
+    fn activation_retry_delay(&self, attempt: u32) -> Duration {
+        self.activation_retry_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(MAX_ACTIVATION_RETRY_DELAY)
+    }
+
+    /// Enter Activating with a fresh retry budget
+    fn begin_activation(&mut self) -> StateAction {
+        self.state = TunnelState::Activating;
+        self.manual_activation = false;
+        self.activation_attempts = 0;
+        self.retry_pending = false;
+        self.restarting = false;
+        StateAction::ActivateTunnel
+    }
+
+    /// Record a tunnel activation for flap detection
+    fn record_activation(&mut self) {
+        if self.flap_threshold == 0 {
+            return;
+        }
+        self.activations.push_back(Instant::now());
+    }
+
+    /// Check recent activations after a deactivation and enter or leave backoff
+    fn check_flapping(&mut self) {
+        if self.flap_threshold == 0 {
+            return;
+        }
+
+        let window = self.flap_window;
+        while self
+            .activations
+            .front()
+            .is_some_and(|t| t.elapsed() > window)
+        {
+            self.activations.pop_front();
+        }
+
+        if self.activations.len() <= self.flap_threshold {
+            if self.backoff_level > 0 {
+                tracing::info!("Tunnel no longer flapping, leaving backoff");
+                self.backoff_level = 0;
+            }
+            return;
+        }
+
+        self.backoff_level += 1;
+        let backoff = self
+            .flap_backoff
+            .saturating_mul(2u32.saturating_pow(self.backoff_level - 1))
+            .min(MAX_FLAP_BACKOFF);
+        let until = Instant::now() + backoff;
+        self.cooldown_until = Some(self.cooldown_until.map_or(until, |t| t.max(until)));
+        tracing::warn!(
+            "Tunnel flapping ({} activations in {}s), backing off for {}s (level {})",
+            self.activations.len(),
+            window.as_secs(),
+            backoff.as_secs(),
+            self.backoff_level
+        );
+    }
+
+    /// Record a traffic event and decide whether it confirms activation
+    ///
+    /// The first event opens a debounce window; a further event within the window
+    /// confirms the traffic is a real flow rather than a stray packet. The window
+    /// is measured between the events' kernel timestamps, since events are
+    /// drained in batches once per poll interval.
+    fn confirm_traffic(&mut self, timestamp: u64) -> bool {
+        if self.activation_delay.is_zero() {
+            return true;
+        }
+
+        let delay_ns = self.activation_delay.as_nanos() as u64;
+        match self.pending_traffic_since {
+            Some(since) if timestamp.saturating_sub(since) <= delay_ns => {
+                self.pending_traffic_since = None;
+                true
+            }
+            _ => {
+                // First event, or previous window expired without a follow-up
+                self.pending_traffic_since = Some(timestamp);
+                false
+            }
         }
     }
 
+    /// Subscribe to state transitions and actions
+    ///
+    /// Every handled command that changes the state or requests an action is
+    /// published as a [`StateEvent`]. A subscriber that falls behind by more than
+    /// a few dozen events gets [`broadcast::error::RecvError::Lagged`] and skips
+    /// ahead.
+    pub fn subscribe(&self) -> broadcast::Receiver<StateEvent> {
+        self.events.subscribe()
+    }
+
     /// Handle a state command and return the action to take
     pub fn handle_command(&mut self, cmd: StateCommand) -> StateAction {
-        log::debug!("State: {:?}, Command: {:?}", self.state, cmd);
+        let from = self.state;
+        let action = self.transition(cmd);
+        if from != self.state || action != StateAction::None {
+            // Fails only when nobody is subscribed
+            let _ = self.events.send(StateEvent {
+                command: cmd,
+                from,
+                to: self.state,
+                action: action.clone(),
+            });
+        }
+        action
+    }
+
+    /// Apply a state command to the state machine
+    fn transition(&mut self, cmd: StateCommand) -> StateAction {
+        tracing::debug!("State: {:?}, Command: {:?}", self.state, cmd);
 
         match (self.state, cmd) {
             // Start monitoring when connected to target SSID
             (TunnelState::Inactive, StateCommand::StartMonitoring) => {
-                log::info!("Starting monitoring (connected to target SSID)");
+                tracing::info!("Starting monitoring (connected to target SSID)");
                 self.state = TunnelState::Monitoring;
                 self.on_monitored_ssid = true;
                 StateAction::AttachEbpf
@@ -76,14 +352,28 @@ impl StateManager {
 
             // Stop monitoring when disconnected - tear down everything
             (TunnelState::Monitoring, StateCommand::StopMonitoring) => {
-                log::info!("Stopping monitoring (disconnected from target SSID)");
+                tracing::info!("Stopping monitoring (disconnected from target SSID)");
                 self.state = TunnelState::Inactive;
                 self.on_monitored_ssid = false;
+                self.pending_traffic_since = None;
                 StateAction::DetachEbpf
             }
 
+            // A manual tunnel is the user's business, whatever network we're on
+            (TunnelState::Active, StateCommand::StopMonitoring) if self.manual => {
+                tracing::info!("Disconnected from target SSID, leaving manual tunnel up");
+                self.on_monitored_ssid = false;
+                StateAction::None
+            }
+
+            (TunnelState::Active, StateCommand::StartMonitoring)
+            | (TunnelState::Activating, StateCommand::StartMonitoring) => {
+                self.on_monitored_ssid = true;
+                StateAction::None
+            }
+
             (TunnelState::Active, StateCommand::StopMonitoring) => {
-                log::info!("Disconnected from target SSID, deactivating tunnel");
+                tracing::info!("Disconnected from target SSID, deactivating tunnel");
                 self.state = TunnelState::Deactivating;
                 self.on_monitored_ssid = false;
                 // First deactivate tunnel, then detach eBPF
@@ -91,78 +381,283 @@ impl StateManager {
             }
 
             (TunnelState::Activating, StateCommand::StopMonitoring) => {
-                log::warn!("Disconnected while activating tunnel");
+                tracing::warn!("Disconnected while activating tunnel");
                 self.state = TunnelState::Inactive;
                 self.on_monitored_ssid = false;
+                self.retry_pending = false;
+                self.restarting = false;
                 StateAction::DetachEbpf
             }
 
             // Traffic detected while monitoring -> activate tunnel
-            (TunnelState::Monitoring, StateCommand::TrafficDetected) => {
-                log::info!("Traffic detected, activating tunnel");
-                self.state = TunnelState::Activating;
-                StateAction::ActivateTunnel
+            (TunnelState::Monitoring, StateCommand::TrafficDetected(timestamp)) => {
+                if let Some(remaining) = self.cooldown_remaining() {
+                    tracing::debug!(
+                        "Traffic detected during cooldown ({}s left), not activating",
+                        remaining.as_secs()
+                    );
+                    return StateAction::None;
+                }
+                if !self.confirm_traffic(timestamp) {
+                    tracing::debug!(
+                        "Traffic detected, waiting up to {}ms for confirmation before activating",
+                        self.activation_delay.as_millis()
+                    );
+                    return StateAction::None;
+                }
+                tracing::info!("Traffic detected, activating tunnel");
+                self.begin_activation()
             }
 
             // Tunnel already up at startup (skip activation, go straight to Active)
             (TunnelState::Monitoring, StateCommand::TunnelAlreadyUp) => {
-                log::info!("Tunnel already up, transitioning to Active state");
+                tracing::info!("Tunnel already up, transitioning to Active state");
                 self.state = TunnelState::Active;
+                self.manual = std::mem::take(&mut self.resumed_manual);
+                let now = Instant::now();
+                self.active_since = Some(
+                    self.resumed_active_for
+                        .take()
+                        .and_then(|d| now.checked_sub(d))
+                        .unwrap_or(now),
+                );
                 StateAction::None // No action needed, tunnel is already up
             }
 
             // Tunnel successfully brought up
             (TunnelState::Activating, StateCommand::TunnelUp) => {
-                log::info!("Tunnel activated successfully");
+                tracing::info!("Tunnel activated successfully");
                 self.state = TunnelState::Active;
+                self.manual = std::mem::take(&mut self.manual_activation);
+                self.active_since = Some(Instant::now());
+                self.activation_attempts = 0;
+                self.retry_pending = false;
+                if std::mem::take(&mut self.restarting) {
+                    // eBPF was already detached while the tunnel was active
+                    return StateAction::None;
+                }
+                self.record_activation();
+                StateAction::DetachEbpf
+            }
+
+            // Health check failed - restart the tunnel
+            (TunnelState::Active, StateCommand::TunnelUnhealthy) => {
+                self.health_restarts += 1;
+                tracing::warn!(
+                    "Tunnel unhealthy, restarting (restart #{})",
+                    self.health_restarts
+                );
+                self.state = TunnelState::Activating;
+                self.activation_attempts = 0;
+                self.retry_pending = false;
+                self.restarting = true;
+                StateAction::RestartTunnel
+            }
+
+            // Tunnel bring-up failed - retry with backoff, or give up and keep monitoring
+            (TunnelState::Activating, StateCommand::ActivationFailed) => {
+                self.activation_attempts += 1;
+                if self.activation_attempts <= self.activation_retries {
+                    let delay = self.activation_retry_delay(self.activation_attempts);
+                    tracing::warn!(
+                        "Tunnel activation failed, retry {}/{} in {}s",
+                        self.activation_attempts,
+                        self.activation_retries,
+                        delay.as_secs()
+                    );
+                    self.retry_pending = true;
+                    return StateAction::ScheduleActivationRetry(delay);
+                }
+
+                // Hold off traffic-triggered activation for one more backoff step so
+                // a persistently broken tunnel is not hammered on every packet
+                let holdoff = self.activation_retry_delay(self.activation_attempts);
+                tracing::error!(
+                    "Tunnel activation failed after {} attempt(s), returning to monitoring",
+                    self.activation_attempts
+                );
+                self.activation_attempts = 0;
+                self.retry_pending = false;
+                if !holdoff.is_zero() {
+                    self.cooldown_until = Some(Instant::now() + holdoff);
+                }
+                let restarting = std::mem::take(&mut self.restarting);
+                if self.on_monitored_ssid {
+                    self.state = TunnelState::Monitoring;
+                    if restarting {
+                        // eBPF was detached while the tunnel was active
+                        StateAction::AttachEbpf
+                    } else {
+                        // eBPF stays attached while activating, nothing to re-attach
+                        StateAction::None
+                    }
+                } else {
+                    self.state = TunnelState::Inactive;
+                    StateAction::DetachEbpf
+                }
+            }
+
+            // On the home network - no tunnel needed, stop watching for traffic
+            // until the network changes (the next StartMonitoring)
+            (TunnelState::Activating, StateCommand::HomeNetworkReachable) => {
+                tracing::info!(
+                    "Home network reachable without the tunnel, skipping activation \
+                    and pausing monitoring until the network changes"
+                );
+                self.state = TunnelState::Inactive;
+                self.on_monitored_ssid = false;
+                self.pending_traffic_since = None;
+                self.activation_attempts = 0;
+                self.retry_pending = false;
+                self.restarting = false;
                 StateAction::DetachEbpf
             }
 
+            // Scheduled retry is due
+            (TunnelState::Activating, StateCommand::RetryActivation) if self.retry_pending => {
+                tracing::info!("Retrying tunnel activation");
+                self.retry_pending = false;
+                StateAction::ActivateTunnel
+            }
+
             // Tunnel brought down successfully
             (TunnelState::Deactivating, StateCommand::TunnelDown) => {
+                self.check_flapping();
+                if self.on_monitored_ssid {
+                    tracing::info!("Tunnel deactivated, returning to monitoring");
+                    self.state = TunnelState::Monitoring;
+                    StateAction::AttachEbpf
+                } else {
+                    tracing::info!("Tunnel deactivated, returning to inactive");
+                    self.state = TunnelState::Inactive;
+                    StateAction::DetachEbpf
+                }
+            }
+
+            // Tunnel brought up behind our back (e.g. `wg-quick up`)
+            (TunnelState::Inactive, StateCommand::TunnelUpExternally)
+            | (TunnelState::Monitoring, StateCommand::TunnelUpExternally) => {
+                tracing::info!("Tunnel brought up externally, tracking it as a manual session");
+                self.state = TunnelState::Active;
+                self.manual = true;
+                self.active_since = Some(Instant::now());
+                self.pending_traffic_since = None;
+                // Monitoring routes would send traffic around the tunnel
+                StateAction::DetachEbpf
+            }
+
+            // Tunnel brought down behind our back (e.g. `wg-quick down`)
+            (TunnelState::Active, StateCommand::TunnelLost) => {
                 if self.on_monitored_ssid {
-                    log::info!("Tunnel deactivated, returning to monitoring");
+                    tracing::info!("Tunnel went down externally, returning to monitoring");
                     self.state = TunnelState::Monitoring;
                     StateAction::AttachEbpf
                 } else {
-                    log::info!("Tunnel deactivated, returning to inactive");
+                    tracing::info!("Tunnel went down externally, returning to inactive");
                     self.state = TunnelState::Inactive;
                     StateAction::DetachEbpf
                 }
             }
 
+            // Manual sessions are only ended by the user unless configured otherwise
+            (TunnelState::Active, StateCommand::IdleTimeout) if !self.idle_timeout_applies() => {
+                tracing::debug!("Idle timeout ignored for manual tunnel");
+                StateAction::None
+            }
+
+            // Idle timeout reached too soon after activation - keep tunnel up
+            (TunnelState::Active, StateCommand::IdleTimeout)
+                if self.active_for().is_some_and(|d| d < self.min_active) =>
+            {
+                tracing::info!(
+                    "Idle timeout ignored, tunnel active for less than min_active_secs ({}s)",
+                    self.min_active.as_secs()
+                );
+                StateAction::None
+            }
+
             // Idle timeout reached - deactivate tunnel
             (TunnelState::Active, StateCommand::IdleTimeout) => {
-                log::info!("Idle timeout reached, deactivating tunnel");
+                tracing::info!("Idle timeout reached, deactivating tunnel");
+                self.state = TunnelState::Deactivating;
+                if !self.cooldown.is_zero() {
+                    self.cooldown_until = Some(Instant::now() + self.cooldown);
+                }
+                StateAction::DeactivateTunnel
+            }
+
+            // User forced activation - skip cooldown and debounce
+            (TunnelState::Monitoring, StateCommand::ForceActivate) => {
+                tracing::info!("Activation requested by user, activating tunnel");
+                self.cooldown_until = None;
+                self.pending_traffic_since = None;
+                self.begin_activation()
+            }
+
+            // Off a monitored network nothing would bring the tunnel up or down on
+            // its own, so it is the user's like one brought up outside the daemon
+            (TunnelState::Inactive, StateCommand::ForceActivate) => {
+                tracing::info!(
+                    "Activation requested by user, activating tunnel as a manual session"
+                );
+                let action = self.begin_activation();
+                self.manual_activation = true;
+                action
+            }
+
+            // User forced deactivation - take the tunnel down and pause monitoring
+            (TunnelState::Active, StateCommand::ForceDeactivate) => {
+                tracing::info!(
+                    "Deactivation requested by user, pausing activation for {}s",
+                    self.pause.as_secs()
+                );
+                self.state = TunnelState::Deactivating;
+                self.pause_activation();
+                StateAction::DeactivateTunnel
+            }
+
+            (TunnelState::Monitoring, StateCommand::ForceDeactivate) => {
+                tracing::info!(
+                    "Deactivation requested by user, pausing activation for {}s",
+                    self.pause.as_secs()
+                );
+                self.pending_traffic_since = None;
+                self.pause_activation();
+                StateAction::None
+            }
+
+            // Session limit reached - deactivate tunnel and return to monitoring
+            (TunnelState::Active, StateCommand::SessionLimitReached) => {
+                tracing::info!("Maximum session duration reached, deactivating tunnel");
                 self.state = TunnelState::Deactivating;
                 StateAction::DeactivateTunnel
             }
 
             // Retry eBPF attachment (e.g., after interface gets IP address)
             (TunnelState::Monitoring, StateCommand::RetryEbpfAttachment) => {
-                log::info!("Retrying eBPF attachment");
+                tracing::info!("Retrying eBPF attachment");
                 StateAction::AttachEbpf
             }
 
             // Disconnected while deactivating (e.g., idle timeout triggered, then SSID changed)
             (TunnelState::Deactivating, StateCommand::StopMonitoring) => {
-                log::info!("Disconnected from target SSID while deactivating");
+                tracing::info!("Disconnected from target SSID while deactivating");
                 self.on_monitored_ssid = false;
                 StateAction::None // Continue deactivating, will go to Inactive when TunnelDown arrives
             }
 
             // Ignore traffic events while activating, deactivating, or active
             // (eBPF traffic events only trigger tunnel activation, not idle reset)
-            (TunnelState::Activating, StateCommand::TrafficDetected)
-            | (TunnelState::Deactivating, StateCommand::TrafficDetected)
-            | (TunnelState::Active, StateCommand::TrafficDetected) => {
-                log::debug!("Traffic detected during active/transition, ignoring");
+            (TunnelState::Activating, StateCommand::TrafficDetected(_))
+            | (TunnelState::Deactivating, StateCommand::TrafficDetected(_))
+            | (TunnelState::Active, StateCommand::TrafficDetected(_)) => {
+                tracing::debug!("Traffic detected during active/transition, ignoring");
                 StateAction::None
             }
 
             // Ignore other combinations
             _ => {
-                log::debug!(
+                tracing::debug!(
                     "No action for state {:?} with command {:?}",
                     self.state,
                     cmd
@@ -172,6 +667,29 @@ impl StateManager {
         }
     }
 
+    /// Hold off traffic-triggered activation for the configured pause
+    fn pause_activation(&mut self) {
+        if !self.pause.is_zero() {
+            self.cooldown_until = Some(Instant::now() + self.pause);
+        }
+    }
+
+    /// Continue the session of a tunnel a previous daemon instance was tracking
+    ///
+    /// `active_for` is how long it has been up and `manual` whether it was brought
+    /// up outside the daemon; applied when the tunnel is found already up
+    /// (`TunnelAlreadyUp`) so min_active, max_session and manual mode carry over.
+    pub fn resume_session(&mut self, active_for: Duration, manual: bool) {
+        self.resumed_active_for = Some(active_for);
+        self.resumed_manual = manual;
+    }
+
+    /// Carry over a cooldown or flap backoff from a previous daemon instance
+    pub fn resume_cooldown(&mut self, remaining: Duration) {
+        let until = Instant::now() + remaining;
+        self.cooldown_until = Some(self.cooldown_until.map_or(until, |t| t.max(until)));
+    }
+
     /// Get current state
     pub fn state(&self) -> TunnelState {
         self.state
@@ -181,18 +699,133 @@ impl StateManager {
     pub fn idle_timeout(&self) -> Duration {
         self.idle_timeout
     }
+
+    /// Get minimum active duration before idle timeout applies
+    pub fn min_active(&self) -> Duration {
+        self.min_active
+    }
+
+    /// Get maximum session duration, if limited
+    pub fn max_session(&self) -> Option<Duration> {
+        self.max_session
+    }
+
+    /// Check whether the active tunnel has exceeded the maximum session duration
+    pub fn session_limit_reached(&self) -> bool {
+        match (self.max_session, self.active_for()) {
+            (Some(max), Some(active_for)) => active_for >= max,
+            _ => false,
+        }
+    }
+
+    /// Get the time until the idle check next needs to run, given how long the tunnel has been idle
+    ///
+    /// This is the earliest point at which the idle timeout (not before min_active has
+    /// elapsed), the idle warning `warning_window` ahead of it, or the session limit
+    /// could fire. Returns None if the tunnel is not active or none of them can fire.
+    pub fn next_idle_check(
+        &self,
+        idle_for: Duration,
+        warning_window: Duration,
+    ) -> Option<Duration> {
+        let active_for = self.active_for()?;
+        let mut next = None;
+
+        if self.idle_timeout_applies() {
+            let timeout_in = self
+                .idle_timeout
+                .saturating_sub(idle_for)
+                .max(self.min_active.saturating_sub(active_for));
+            next = Some(timeout_in);
+            // Once inside the warning window only the timeout itself is left to wait for
+            let warn_in = timeout_in.saturating_sub(warning_window);
+            if !warning_window.is_zero() && !warn_in.is_zero() {
+                next = Some(timeout_in.min(warn_in));
+            }
+        }
+        if let Some(max_session) = self.max_session {
+            let limit_in = max_session.saturating_sub(active_for);
+            next = Some(next.map_or(limit_in, |next: Duration| next.min(limit_in)));
+        }
+        next
+    }
+
+    /// Check whether the current session was brought up outside the daemon
+    pub fn is_manual(&self) -> bool {
+        self.state == TunnelState::Active && self.manual
+    }
+
+    /// Check whether the idle timeout may deactivate the current session
+    pub fn idle_timeout_applies(&self) -> bool {
+        !self.is_manual() || self.manual_idle_timeout
+    }
+
+    /// Get remaining cooldown (post-idle or flap backoff), if re-activation is currently suppressed
+    pub fn cooldown_remaining(&self) -> Option<Duration> {
+        self.cooldown_until
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+            .filter(|d| !d.is_zero())
+    }
+
+    /// Number of the current activation attempt (1 for the first try)
+    pub fn activation_attempt(&self) -> u32 {
+        self.activation_attempts + 1
+    }
+
+    /// Get current flap backoff level (0 when not backing off)
+    pub fn backoff_level(&self) -> u32 {
+        self.backoff_level
+    }
+
+    /// Get number of tunnel restarts triggered by failed health checks
+    pub fn health_restarts(&self) -> u32 {
+        self.health_restarts
+    }
+
+    /// Get how long the tunnel has been in the Active state
+    /// Returns None if the tunnel is not active
+    pub fn active_for(&self) -> Option<Duration> {
+        if self.state != TunnelState::Active {
+            return None;
+        }
+        self.active_since.map(|t| t.elapsed())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A millisecond in event timestamps
+    const MS: u64 = 1_000_000;
+
     #[test]
     fn test_initial_state() {
         let manager = StateManager::new(300);
         assert_eq!(manager.state(), TunnelState::Inactive);
     }
 
+    #[test]
+    fn test_subscribe() {
+        let mut manager = StateManager::new(300);
+        let mut events = manager.subscribe();
+
+        manager.handle_command(StateCommand::StartMonitoring);
+        // Ignored commands are not published
+        manager.handle_command(StateCommand::TunnelDown);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.command, StateCommand::StartMonitoring);
+        assert_eq!(event.from, TunnelState::Inactive);
+        assert_eq!(event.to, TunnelState::Monitoring);
+        assert_eq!(event.action, StateAction::AttachEbpf);
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.command, StateCommand::TrafficDetected(0));
+        assert_eq!(event.action, StateAction::ActivateTunnel);
+        assert!(events.try_recv().is_err());
+    }
+
     #[test]
     fn test_start_monitoring() {
         let mut manager = StateManager::new(300);
@@ -206,7 +839,7 @@ mod tests {
         let mut manager = StateManager::new(300);
         manager.handle_command(StateCommand::StartMonitoring);
 
-        let action = manager.handle_command(StateCommand::TrafficDetected);
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
         assert_eq!(action, StateAction::ActivateTunnel);
         assert_eq!(manager.state(), TunnelState::Activating);
     }
@@ -220,7 +853,7 @@ mod tests {
         assert_eq!(manager.state(), TunnelState::Monitoring);
 
         // Traffic detected
-        manager.handle_command(StateCommand::TrafficDetected);
+        manager.handle_command(StateCommand::TrafficDetected(0));
         assert_eq!(manager.state(), TunnelState::Activating);
 
         // Tunnel up - should detach eBPF
@@ -229,13 +862,40 @@ mod tests {
         assert_eq!(manager.state(), TunnelState::Active);
     }
 
+    #[test]
+    fn test_home_network_reachable() {
+        let mut manager = StateManager::new(300);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+
+        let action = manager.handle_command(StateCommand::HomeNetworkReachable);
+        assert_eq!(action, StateAction::DetachEbpf);
+        assert_eq!(manager.state(), TunnelState::Inactive);
+
+        // Route and address changes don't re-attach while paused
+        let action = manager.handle_command(StateCommand::RetryEbpfAttachment);
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Inactive);
+
+        // Joining a monitored network again resumes monitoring
+        let action = manager.handle_command(StateCommand::StartMonitoring);
+        assert_eq!(action, StateAction::AttachEbpf);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+
+        // Manual activation still works
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::HomeNetworkReachable);
+        let action = manager.handle_command(StateCommand::ForceActivate);
+        assert_eq!(action, StateAction::ActivateTunnel);
+    }
+
     #[test]
     fn test_stop_monitoring_while_active() {
         let mut manager = StateManager::new(300);
 
         // Get to active state
         manager.handle_command(StateCommand::StartMonitoring);
-        manager.handle_command(StateCommand::TrafficDetected);
+        manager.handle_command(StateCommand::TrafficDetected(0));
         manager.handle_command(StateCommand::TunnelUp);
 
         // Stop monitoring should deactivate tunnel
@@ -274,7 +934,7 @@ mod tests {
         let mut manager = StateManager::new(300);
 
         // Traffic detected when not monitoring should be ignored
-        let action = manager.handle_command(StateCommand::TrafficDetected);
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
         assert_eq!(action, StateAction::None);
         assert_eq!(manager.state(), TunnelState::Inactive);
     }
@@ -285,11 +945,11 @@ mod tests {
 
         // Get to active state
         manager.handle_command(StateCommand::StartMonitoring);
-        manager.handle_command(StateCommand::TrafficDetected);
+        manager.handle_command(StateCommand::TrafficDetected(0));
         manager.handle_command(StateCommand::TunnelUp);
 
         // Traffic while active should be ignored (idle tracking in main.rs now)
-        let action = manager.handle_command(StateCommand::TrafficDetected);
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
         assert_eq!(action, StateAction::None);
         assert_eq!(manager.state(), TunnelState::Active);
     }
@@ -300,11 +960,11 @@ mod tests {
 
         // Get to activating state
         manager.handle_command(StateCommand::StartMonitoring);
-        manager.handle_command(StateCommand::TrafficDetected);
+        manager.handle_command(StateCommand::TrafficDetected(0));
         assert_eq!(manager.state(), TunnelState::Activating);
 
         // More traffic while activating should be ignored
-        let action = manager.handle_command(StateCommand::TrafficDetected);
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
         assert_eq!(action, StateAction::None);
         assert_eq!(manager.state(), TunnelState::Activating);
     }
@@ -317,7 +977,7 @@ mod tests {
         manager.state = TunnelState::Deactivating;
 
         // Traffic while deactivating should be ignored
-        let action = manager.handle_command(StateCommand::TrafficDetected);
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
         assert_eq!(action, StateAction::None);
         assert_eq!(manager.state(), TunnelState::Deactivating);
     }
@@ -360,7 +1020,7 @@ mod tests {
 
         // Get to active state
         manager.handle_command(StateCommand::StartMonitoring);
-        manager.handle_command(StateCommand::TrafficDetected);
+        manager.handle_command(StateCommand::TrafficDetected(0));
         manager.handle_command(StateCommand::TunnelUp);
 
         // Manually set to deactivating (in real scenario, main.rs triggers this)
@@ -378,7 +1038,7 @@ mod tests {
 
         // Get to activating state
         manager.handle_command(StateCommand::StartMonitoring);
-        manager.handle_command(StateCommand::TrafficDetected);
+        manager.handle_command(StateCommand::TrafficDetected(0));
         assert_eq!(manager.state(), TunnelState::Activating);
 
         // Disconnect while activating should go back to inactive
@@ -395,7 +1055,7 @@ mod tests {
         manager.handle_command(StateCommand::StartMonitoring);
         assert_eq!(manager.state(), TunnelState::Monitoring);
 
-        manager.handle_command(StateCommand::TrafficDetected);
+        manager.handle_command(StateCommand::TrafficDetected(0));
         assert_eq!(manager.state(), TunnelState::Activating);
 
         // Disconnect before tunnel is up
@@ -425,7 +1085,7 @@ mod tests {
 
         // Get to active state
         manager.handle_command(StateCommand::StartMonitoring);
-        manager.handle_command(StateCommand::TrafficDetected);
+        manager.handle_command(StateCommand::TrafficDetected(0));
         manager.handle_command(StateCommand::TunnelUp);
         assert_eq!(manager.state(), TunnelState::Active);
 
@@ -440,6 +1100,599 @@ mod tests {
         assert_eq!(manager.state(), TunnelState::Monitoring);
     }
 
+    #[test]
+    fn test_idle_timeout_respects_min_active() {
+        let mut manager = StateManager::new(300).with_min_active(3600);
+
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::TunnelUp);
+
+        // Idle timeout within min_active window should keep the tunnel up
+        let action = manager.handle_command(StateCommand::IdleTimeout);
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Active);
+
+        // Disconnecting still deactivates regardless of min_active
+        let action = manager.handle_command(StateCommand::StopMonitoring);
+        assert_eq!(action, StateAction::DeactivateTunnel);
+    }
+
+    #[test]
+    fn test_idle_timeout_after_min_active() {
+        let mut manager = StateManager::new(300).with_min_active(0);
+
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TunnelAlreadyUp);
+        assert!(manager.active_for().is_some());
+
+        let action = manager.handle_command(StateCommand::IdleTimeout);
+        assert_eq!(action, StateAction::DeactivateTunnel);
+        assert_eq!(manager.active_for(), None);
+    }
+
+    #[test]
+    fn test_activation_delay_requires_second_event() {
+        let mut manager = StateManager::new(300).with_activation_delay(10_000);
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        // First event only opens the debounce window
+        let action = manager.handle_command(StateCommand::TrafficDetected(MS));
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+
+        // Second event within the window activates
+        let action = manager.handle_command(StateCommand::TrafficDetected(5 * MS));
+        assert_eq!(action, StateAction::ActivateTunnel);
+        assert_eq!(manager.state(), TunnelState::Activating);
+    }
+
+    #[test]
+    fn test_activation_delay_window_expires() {
+        let mut manager = StateManager::new(300).with_activation_delay(20);
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        manager.handle_command(StateCommand::TrafficDetected(MS));
+
+        // Window expired: this event starts a new window instead of activating
+        let action = manager.handle_command(StateCommand::TrafficDetected(51 * MS));
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+
+        let action = manager.handle_command(StateCommand::TrafficDetected(60 * MS));
+        assert_eq!(action, StateAction::ActivateTunnel);
+    }
+
+    #[test]
+    fn test_activation_delay_uses_event_timestamps() {
+        let mut manager = StateManager::new(300).with_activation_delay(20);
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        // Two packets 10ms apart, the second drained a poll interval later
+        manager.handle_command(StateCommand::TrafficDetected(1_000 * MS));
+        std::thread::sleep(Duration::from_millis(50));
+        let action = manager.handle_command(StateCommand::TrafficDetected(1_010 * MS));
+        assert_eq!(action, StateAction::ActivateTunnel);
+    }
+
+    #[test]
+    fn test_activation_delay_reset_on_stop_monitoring() {
+        let mut manager = StateManager::new(300).with_activation_delay(10_000);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+
+        manager.handle_command(StateCommand::StopMonitoring);
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        // Pending event from the previous network must not count
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
+        assert_eq!(action, StateAction::None);
+    }
+
+    #[test]
+    fn test_session_limit_reached() {
+        let mut manager = StateManager::new(300).with_max_session(Some(0));
+        assert!(!manager.session_limit_reached());
+
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::TunnelUp);
+        assert!(manager.session_limit_reached());
+
+        let action = manager.handle_command(StateCommand::SessionLimitReached);
+        assert_eq!(action, StateAction::DeactivateTunnel);
+        assert_eq!(manager.state(), TunnelState::Deactivating);
+
+        // Returns to monitoring while still on the monitored network
+        let action = manager.handle_command(StateCommand::TunnelDown);
+        assert_eq!(action, StateAction::AttachEbpf);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+    }
+
+    #[test]
+    fn test_session_limit_unlimited() {
+        let mut manager = StateManager::new(300).with_max_session(None);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TunnelAlreadyUp);
+        assert!(!manager.session_limit_reached());
+
+        let mut manager = StateManager::new(300).with_max_session(Some(3600));
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TunnelAlreadyUp);
+        assert!(!manager.session_limit_reached());
+    }
+
+    #[test]
+    fn test_next_idle_check() {
+        let minute = Duration::from_secs(60);
+        let mut manager = StateManager::new(300)
+            .with_min_active(600)
+            .with_max_session(Some(3600));
+        assert_eq!(manager.next_idle_check(Duration::ZERO, minute), None);
+
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TunnelAlreadyUp);
+
+        // Idle timeout is held back until min_active has elapsed
+        let next = manager
+            .next_idle_check(Duration::ZERO, Duration::ZERO)
+            .unwrap();
+        assert!(next > Duration::from_secs(590) && next <= Duration::from_secs(600));
+
+        let mut manager = StateManager::new(300).with_max_session(Some(3600));
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TunnelAlreadyUp);
+        assert_eq!(
+            manager.next_idle_check(Duration::from_secs(100), Duration::ZERO),
+            Some(Duration::from_secs(200))
+        );
+        // Wake up for the warning first, then for the timeout itself
+        assert_eq!(
+            manager.next_idle_check(Duration::from_secs(100), minute),
+            Some(Duration::from_secs(140))
+        );
+        assert_eq!(
+            manager.next_idle_check(Duration::from_secs(250), minute),
+            Some(Duration::from_secs(50))
+        );
+        assert_eq!(
+            manager.next_idle_check(Duration::from_secs(400), minute),
+            Some(Duration::ZERO)
+        );
+
+        // Session limit comes first
+        let mut manager = StateManager::new(3600).with_max_session(Some(0));
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TunnelAlreadyUp);
+        assert_eq!(
+            manager.next_idle_check(Duration::ZERO, minute),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_resume_session() {
+        let mut manager = StateManager::new(300).with_max_session(Some(3600));
+        manager.resume_session(Duration::from_secs(3600), false);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TunnelAlreadyUp);
+        assert!(manager.active_for().unwrap() >= Duration::from_secs(3600));
+        assert!(manager.session_limit_reached());
+
+        // Only applies to the first session
+        manager.handle_command(StateCommand::SessionLimitReached);
+        manager.handle_command(StateCommand::TunnelDown);
+        manager.handle_command(StateCommand::TunnelAlreadyUp);
+        assert!(manager.active_for().unwrap() < Duration::from_secs(1));
+
+        let mut manager = StateManager::new(300);
+        manager.resume_session(Duration::ZERO, true);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TunnelAlreadyUp);
+        assert!(manager.is_manual());
+    }
+
+    #[test]
+    fn test_resume_cooldown() {
+        let mut manager = StateManager::new(300);
+        manager.resume_cooldown(Duration::from_secs(120));
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+        assert!(manager.cooldown_remaining().unwrap() > Duration::from_secs(110));
+    }
+
+    #[test]
+    fn test_tunnel_lost() {
+        let mut manager = StateManager::new(300);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::TunnelUp);
+
+        let action = manager.handle_command(StateCommand::TunnelLost);
+        assert_eq!(action, StateAction::AttachEbpf);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+        assert_eq!(manager.active_for(), None);
+
+        // Only meaningful while active
+        let action = manager.handle_command(StateCommand::TunnelLost);
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+    }
+
+    #[test]
+    fn test_tunnel_up_externally() {
+        let mut manager = StateManager::new(300);
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        let action = manager.handle_command(StateCommand::TunnelUpExternally);
+        assert_eq!(action, StateAction::DetachEbpf);
+        assert_eq!(manager.state(), TunnelState::Active);
+        assert!(manager.is_manual());
+        assert!(!manager.idle_timeout_applies());
+        assert_eq!(
+            manager.next_idle_check(Duration::from_secs(600), Duration::ZERO),
+            None
+        );
+
+        // Not torn down by idle timeout or leaving the network
+        assert_eq!(
+            manager.handle_command(StateCommand::IdleTimeout),
+            StateAction::None
+        );
+        assert_eq!(
+            manager.handle_command(StateCommand::StopMonitoring),
+            StateAction::None
+        );
+        assert_eq!(manager.state(), TunnelState::Active);
+
+        // Brought down the same way, back to the network we're on now
+        let action = manager.handle_command(StateCommand::TunnelLost);
+        assert_eq!(action, StateAction::DetachEbpf);
+        assert_eq!(manager.state(), TunnelState::Inactive);
+        assert!(!manager.is_manual());
+    }
+
+    #[test]
+    fn test_force_activate_while_inactive() {
+        let mut manager = StateManager::new(300);
+
+        let action = manager.handle_command(StateCommand::ForceActivate);
+        assert_eq!(action, StateAction::ActivateTunnel);
+        assert_eq!(manager.state(), TunnelState::Activating);
+
+        manager.handle_command(StateCommand::TunnelUp);
+        assert_eq!(manager.state(), TunnelState::Active);
+        assert!(manager.is_manual());
+        assert!(!manager.idle_timeout_applies());
+
+        // Ended by the user, back to inactive
+        let action = manager.handle_command(StateCommand::ForceDeactivate);
+        assert_eq!(action, StateAction::DeactivateTunnel);
+        manager.handle_command(StateCommand::TunnelDown);
+        assert_eq!(manager.state(), TunnelState::Inactive);
+
+        // Traffic-triggered sessions are not manual
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::TunnelUp);
+        assert!(!manager.is_manual());
+    }
+
+    #[test]
+    fn test_manual_idle_timeout() {
+        let mut manager = StateManager::new(300).with_manual_idle_timeout(true);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TunnelUpExternally);
+        assert!(manager.idle_timeout_applies());
+
+        let action = manager.handle_command(StateCommand::IdleTimeout);
+        assert_eq!(action, StateAction::DeactivateTunnel);
+    }
+
+    #[test]
+    fn test_force_deactivate() {
+        let mut manager = StateManager::new(300).with_pause(900);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::TunnelUp);
+
+        let action = manager.handle_command(StateCommand::ForceDeactivate);
+        assert_eq!(action, StateAction::DeactivateTunnel);
+        manager.handle_command(StateCommand::TunnelDown);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+        assert!(manager.cooldown_remaining().unwrap() > Duration::from_secs(890));
+
+        // Traffic doesn't bring it back, the user still can
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
+        assert_eq!(action, StateAction::None);
+        let action = manager.handle_command(StateCommand::ForceActivate);
+        assert_eq!(action, StateAction::ActivateTunnel);
+    }
+
+    #[test]
+    fn test_force_deactivate_while_monitoring() {
+        let mut manager = StateManager::new(300).with_pause(900);
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        let action = manager.handle_command(StateCommand::ForceDeactivate);
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
+        assert_eq!(action, StateAction::None);
+    }
+
+    #[test]
+    fn test_session_limit_ignored_when_not_active() {
+        let mut manager = StateManager::new(300);
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        let action = manager.handle_command(StateCommand::SessionLimitReached);
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+    }
+
+    #[test]
+    fn test_cooldown_suppresses_reactivation() {
+        let mut manager = StateManager::new(300).with_cooldown(3600);
+
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::TunnelUp);
+        manager.handle_command(StateCommand::IdleTimeout);
+        manager.handle_command(StateCommand::TunnelDown);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+        assert!(manager.cooldown_remaining().is_some());
+
+        // Traffic during cooldown must not activate
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+
+        // Forced activation bypasses cooldown
+        let action = manager.handle_command(StateCommand::ForceActivate);
+        assert_eq!(action, StateAction::ActivateTunnel);
+        assert_eq!(manager.state(), TunnelState::Activating);
+        assert!(manager.cooldown_remaining().is_none());
+    }
+
+    #[test]
+    fn test_cooldown_only_after_idle_deactivation() {
+        let mut manager = StateManager::new(300).with_cooldown(3600);
+
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::TunnelUp);
+        manager.handle_command(StateCommand::SessionLimitReached);
+        manager.handle_command(StateCommand::TunnelDown);
+
+        assert!(manager.cooldown_remaining().is_none());
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
+        assert_eq!(action, StateAction::ActivateTunnel);
+    }
+
+    #[test]
+    fn test_cooldown_expires() {
+        let mut manager = StateManager::new(300).with_cooldown(0);
+
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::TunnelUp);
+        manager.handle_command(StateCommand::IdleTimeout);
+        manager.handle_command(StateCommand::TunnelDown);
+
+        assert!(manager.cooldown_remaining().is_none());
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
+        assert_eq!(action, StateAction::ActivateTunnel);
+    }
+
+    /// Run one full activate/deactivate cycle from Monitoring
+    fn flap(manager: &mut StateManager) {
+        manager.handle_command(StateCommand::ForceActivate);
+        manager.handle_command(StateCommand::TunnelUp);
+        manager.handle_command(StateCommand::SessionLimitReached);
+        manager.handle_command(StateCommand::TunnelDown);
+    }
+
+    #[test]
+    fn test_flap_detection_enters_backoff() {
+        let mut manager = StateManager::new(300).with_flap_detection(2, 600, 60);
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        flap(&mut manager);
+        flap(&mut manager);
+        assert_eq!(manager.backoff_level(), 0);
+        assert!(manager.cooldown_remaining().is_none());
+
+        flap(&mut manager);
+        assert_eq!(manager.backoff_level(), 1);
+        let remaining = manager.cooldown_remaining().unwrap();
+        assert!(remaining > Duration::from_secs(55) && remaining <= Duration::from_secs(60));
+
+        // Traffic is suppressed during backoff
+        let action = manager.handle_command(StateCommand::TrafficDetected(0));
+        assert_eq!(action, StateAction::None);
+    }
+
+    #[test]
+    fn test_flap_backoff_increases_exponentially() {
+        let mut manager = StateManager::new(300).with_flap_detection(1, 600, 60);
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        flap(&mut manager);
+        flap(&mut manager);
+        assert_eq!(manager.backoff_level(), 1);
+
+        flap(&mut manager);
+        assert_eq!(manager.backoff_level(), 2);
+        assert!(manager.cooldown_remaining().unwrap() > Duration::from_secs(115));
+
+        flap(&mut manager);
+        assert_eq!(manager.backoff_level(), 3);
+        assert!(manager.cooldown_remaining().unwrap() > Duration::from_secs(235));
+    }
+
+    #[test]
+    fn test_flap_backoff_resets_after_window() {
+        let mut manager = StateManager::new(300).with_flap_detection(1, 0, 60);
+        manager.handle_command(StateCommand::StartMonitoring);
+
+        // Zero window: activations age out immediately, so nothing counts as flapping
+        flap(&mut manager);
+        std::thread::sleep(Duration::from_millis(5));
+        flap(&mut manager);
+        assert_eq!(manager.backoff_level(), 0);
+    }
+
+    #[test]
+    fn test_flap_detection_disabled_by_default() {
+        let mut manager = StateManager::new(300);
+        manager.handle_command(StateCommand::StartMonitoring);
+        for _ in 0..10 {
+            flap(&mut manager);
+        }
+        assert_eq!(manager.backoff_level(), 0);
+        assert!(manager.cooldown_remaining().is_none());
+    }
+
+    #[test]
+    fn test_activation_retry_with_backoff() {
+        let mut manager = StateManager::new(300).with_activation_retries(3, 2);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+
+        let mut delays = Vec::new();
+        for _ in 0..3 {
+            match manager.handle_command(StateCommand::ActivationFailed) {
+                StateAction::ScheduleActivationRetry(delay) => delays.push(delay.as_secs()),
+                other => panic!("unexpected action {:?}", other),
+            }
+            assert_eq!(manager.state(), TunnelState::Activating);
+            let action = manager.handle_command(StateCommand::RetryActivation);
+            assert_eq!(action, StateAction::ActivateTunnel);
+        }
+        assert_eq!(delays, vec![2, 4, 8]);
+
+        // Retries exhausted: back to monitoring with a hold-off
+        let action = manager.handle_command(StateCommand::ActivationFailed);
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+        assert!(manager.cooldown_remaining().is_some());
+    }
+
+    #[test]
+    fn test_activation_retry_success_resets_attempts() {
+        let mut manager = StateManager::new(300).with_activation_retries(1, 1);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::ActivationFailed);
+        manager.handle_command(StateCommand::RetryActivation);
+        manager.handle_command(StateCommand::TunnelUp);
+        assert_eq!(manager.state(), TunnelState::Active);
+
+        manager.handle_command(StateCommand::SessionLimitReached);
+        manager.handle_command(StateCommand::TunnelDown);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+
+        // Fresh retry budget for the new activation
+        let action = manager.handle_command(StateCommand::ActivationFailed);
+        assert_eq!(
+            action,
+            StateAction::ScheduleActivationRetry(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_activation_failure_without_retries() {
+        let mut manager = StateManager::new(300);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+
+        let action = manager.handle_command(StateCommand::ActivationFailed);
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+    }
+
+    #[test]
+    fn test_stale_activation_retry_ignored() {
+        let mut manager = StateManager::new(300).with_activation_retries(3, 1);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::ActivationFailed);
+
+        // Disconnected before the retry fired
+        manager.handle_command(StateCommand::StopMonitoring);
+        assert_eq!(
+            manager.handle_command(StateCommand::RetryActivation),
+            StateAction::None
+        );
+
+        // A new activation is not disturbed by the stale retry
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        assert_eq!(
+            manager.handle_command(StateCommand::RetryActivation),
+            StateAction::None
+        );
+    }
+
+    #[test]
+    fn test_activation_retry_delay_capped() {
+        let manager = StateManager::new(300).with_activation_retries(10, 10);
+        assert_eq!(manager.activation_retry_delay(1), Duration::from_secs(10));
+        assert_eq!(
+            manager.activation_retry_delay(10),
+            MAX_ACTIVATION_RETRY_DELAY
+        );
+    }
+
+    #[test]
+    fn test_unhealthy_tunnel_restarts() {
+        let mut manager = StateManager::new(300).with_flap_detection(1, 600, 60);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::TunnelUp);
+
+        let action = manager.handle_command(StateCommand::TunnelUnhealthy);
+        assert_eq!(action, StateAction::RestartTunnel);
+        assert_eq!(manager.state(), TunnelState::Activating);
+        assert_eq!(manager.health_restarts(), 1);
+
+        // eBPF is already detached, nothing to do once the tunnel is back
+        let action = manager.handle_command(StateCommand::TunnelUp);
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.state(), TunnelState::Active);
+
+        // Restarts don't count as flaps
+        manager.handle_command(StateCommand::SessionLimitReached);
+        manager.handle_command(StateCommand::TunnelDown);
+        assert_eq!(manager.backoff_level(), 0);
+    }
+
+    #[test]
+    fn test_failed_restart_reattaches_ebpf() {
+        let mut manager = StateManager::new(300);
+        manager.handle_command(StateCommand::StartMonitoring);
+        manager.handle_command(StateCommand::TrafficDetected(0));
+        manager.handle_command(StateCommand::TunnelUp);
+        manager.handle_command(StateCommand::TunnelUnhealthy);
+
+        let action = manager.handle_command(StateCommand::ActivationFailed);
+        assert_eq!(action, StateAction::AttachEbpf);
+        assert_eq!(manager.state(), TunnelState::Monitoring);
+    }
+
+    #[test]
+    fn test_unhealthy_ignored_when_not_active() {
+        let mut manager = StateManager::new(300);
+        manager.handle_command(StateCommand::StartMonitoring);
+        let action = manager.handle_command(StateCommand::TunnelUnhealthy);
+        assert_eq!(action, StateAction::None);
+        assert_eq!(manager.health_restarts(), 0);
+    }
+
     #[test]
     fn test_idle_timeout_ignored_when_not_active() {
         let mut manager = StateManager::new(300);
@@ -456,7 +1709,7 @@ mod tests {
         assert_eq!(manager.state(), TunnelState::Monitoring);
 
         // IdleTimeout when activating should be ignored
-        manager.handle_command(StateCommand::TrafficDetected);
+        manager.handle_command(StateCommand::TrafficDetected(0));
         assert_eq!(manager.state(), TunnelState::Activating);
         let action = manager.handle_command(StateCommand::IdleTimeout);
         assert_eq!(action, StateAction::None);
@@ -1,54 +1,474 @@
 // State file writer for external monitoring
 //!
 //! Writes current daemon state to a file for consumption by external tools
-//! like wg-ondemand-ctl and waybar widgets.
+//! like wg-ondemand-ctl and waybar widgets. The file is left behind if the daemon
+//! dies, so a restarted daemon can read it back to pick up the previous session.
+//! A clean shutdown removes it and saves the same record as the session file
+//! under `/var/lib/wg-ondemand` instead, next to the tunnel it leaves up.
 
-use crate::types::TunnelState;
+use crate::instance::Instance;
+use crate::stats::LatencyStats;
+use crate::types::{TrafficTotals, TunnelState};
 use anyhow::{Context, Result};
 use std::fs;
 use std::path::Path;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-const STATE_FILE: &str = "/run/wg-ondemand/state";
-const STATE_DIR: &str = "/run/wg-ondemand";
+/// State file, in the runtime directory next to the control socket (see
+/// [`Instance::state_file`])
+pub const STATE_FILE: &str = "/run/wg-ondemand/state";
+/// Session saved on shutdown, in the directory of the lifetime statistics (see
+/// [`Instance::session_file`])
+pub const SESSION_FILE: &str = "/var/lib/wg-ondemand/session";
+
+/// Snapshot of daemon state written to the state file
+#[derive(Debug, Clone, Copy)]
+pub struct StateSnapshot<'a> {
+    /// Current tunnel state
+    pub state: TunnelState,
+    /// Current SSID, if connected to a monitored network
+    pub ssid: Option<&'a str>,
+    /// Tunnel traffic for the current session and since daemon start
+    pub traffic: TrafficTotals,
+    /// Time from traffic detection to tunnel up
+    pub activation_latency: LatencyStats,
+    /// Time since last tunnel activity (only while the tunnel is active)
+    pub idle: Option<Duration>,
+    /// Time since the tunnel came up (only while the tunnel is active)
+    pub active_for: Option<Duration>,
+    /// Whether the tunnel was brought up outside the daemon
+    pub manual: bool,
+    /// Idle timeout after which the tunnel is brought down
+    pub idle_timeout: Duration,
+    /// Whether idle deactivation is imminent (within the configured warning window)
+    pub idle_warning: bool,
+    /// Most recent user-facing notice and its Unix timestamp
+    pub notice: Option<(&'a str, u64)>,
+    /// Remaining post-idle cooldown during which traffic won't re-activate the tunnel
+    pub cooldown: Option<Duration>,
+    /// Flap backoff level (0 when not backing off)
+    pub backoff_level: u32,
+    /// Tunnel restarts triggered by failed health checks
+    pub health_restarts: u32,
+    /// Most recent peer handshake (only while the tunnel is active)
+    pub last_handshake: Option<SystemTime>,
+}
+
+impl<'a> StateSnapshot<'a> {
+    /// Create a snapshot with no traffic recorded
+    pub fn new(state: TunnelState, ssid: Option<&'a str>) -> Self {
+        Self {
+            state,
+            ssid,
+            traffic: TrafficTotals::default(),
+            activation_latency: LatencyStats::default(),
+            idle: None,
+            active_for: None,
+            manual: false,
+            idle_timeout: Duration::ZERO,
+            idle_warning: false,
+            notice: None,
+            cooldown: None,
+            backoff_level: 0,
+            health_restarts: 0,
+            last_handshake: None,
+        }
+    }
+}
+
+/// Convert tunnel state to its state file representation
+pub fn state_str(state: TunnelState) -> &'static str {
+    match state {
+        TunnelState::Inactive => "inactive",
+        TunnelState::Monitoring => "monitoring",
+        TunnelState::Activating => "activating",
+        TunnelState::Active => "connected",
+        TunnelState::Deactivating => "deactivating",
+    }
+}
+
+/// Parse a state file representation back into a tunnel state
+fn parse_state_str(value: &str) -> Option<TunnelState> {
+    match value {
+        "inactive" => Some(TunnelState::Inactive),
+        "monitoring" => Some(TunnelState::Monitoring),
+        "activating" => Some(TunnelState::Activating),
+        "connected" => Some(TunnelState::Active),
+        "deactivating" => Some(TunnelState::Deactivating),
+        _ => None,
+    }
+}
+
+/// Format an optional duration as whole milliseconds (empty if not yet measured)
+fn millis_str(duration: Option<Duration>) -> String {
+    duration
+        .map(|d| d.as_millis().to_string())
+        .unwrap_or_default()
+}
+
+/// Format state file contents (KEY=value lines)
+fn format_state(snapshot: &StateSnapshot, timestamp: u64) -> String {
+    let latency = &snapshot.activation_latency;
+    format!(
+        "STATE={}\nSSID={}\nTIMESTAMP={}\n\
+        SESSION_RX_BYTES={}\nSESSION_TX_BYTES={}\n\
+        TOTAL_RX_BYTES={}\nTOTAL_TX_BYTES={}\n\
+        ACTIVATION_LATENCY_LAST_MS={}\nACTIVATION_LATENCY_MIN_MS={}\n\
+        ACTIVATION_LATENCY_AVG_MS={}\nACTIVATION_LATENCY_MAX_MS={}\n\
+        IDLE_SECONDS={}\nIDLE_TIMEOUT={}\nIDLE_WARNING={}\nSESSION_START={}\nMANUAL={}\n\
+        NOTICE={}\nNOTICE_TIMESTAMP={}\nCOOLDOWN_SECONDS={}\nBACKOFF_LEVEL={}\nHEALTH_RESTARTS={}\n\
+        LAST_HANDSHAKE={}\n",
+        state_str(snapshot.state),
+        snapshot.ssid.unwrap_or(""),
+        timestamp,
+        snapshot.traffic.session_rx_bytes,
+        snapshot.traffic.session_tx_bytes,
+        snapshot.traffic.total_rx_bytes,
+        snapshot.traffic.total_tx_bytes,
+        millis_str(latency.last()),
+        millis_str(latency.min()),
+        millis_str(latency.avg()),
+        millis_str(latency.max()),
+        snapshot
+            .idle
+            .map(|d| d.as_secs().to_string())
+            .unwrap_or_default(),
+        snapshot.idle_timeout.as_secs(),
+        u8::from(snapshot.idle_warning),
+        snapshot
+            .active_for
+            .map(|d| timestamp.saturating_sub(d.as_secs()).to_string())
+            .unwrap_or_default(),
+        u8::from(snapshot.manual),
+        // Notices are single-line values
+        snapshot
+            .notice
+            .map(|(msg, _)| msg.replace('\n', " "))
+            .unwrap_or_default(),
+        snapshot
+            .notice
+            .map(|(_, ts)| ts.to_string())
+            .unwrap_or_default(),
+        snapshot.cooldown.map(|d| d.as_secs()).unwrap_or(0),
+        snapshot.backoff_level,
+        snapshot.health_restarts,
+        snapshot
+            .last_handshake
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs().to_string())
+            .unwrap_or_default()
+    )
+}
 
 /// Write current state to state file
-pub fn write_state(state: TunnelState, ssid: Option<&str>) -> Result<()> {
+pub fn write_state(instance: &Instance, snapshot: &StateSnapshot) -> Result<()> {
+    let path = instance.state_file();
     // Create directory if it doesn't exist
-    let state_dir = Path::new(STATE_DIR);
-    if !state_dir.exists() {
+    if let Some(state_dir) = path.parent().filter(|dir| !dir.exists()) {
         fs::create_dir_all(state_dir).context("Failed to create state directory")?;
     }
 
-    // Get current timestamp
-    let timestamp = SystemTime::now()
+    fs::write(path, format_state(snapshot, unix_now())).context("Failed to write state file")?;
+
+    Ok(())
+}
+
+/// Save the session for the next daemon instance on a clean shutdown
+///
+/// Unlike the state file, the session file survives a `systemctl restart`,
+/// which empties the runtime directory.
+pub fn save_session(instance: &Instance, snapshot: &StateSnapshot) -> Result<()> {
+    save_session_to(&instance.session_file(), snapshot, unix_now())
+}
+
+fn save_session_to(path: &Path, snapshot: &StateSnapshot, timestamp: u64) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    }
+    fs::write(path, format_state(snapshot, timestamp)).context("Failed to write session file")
+}
+
+/// Current Unix time in seconds
+fn unix_now() -> u64 {
+    SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .map(|d| d.as_secs())
-        .unwrap_or(0);
+        .unwrap_or(0)
+}
 
-    // Convert state to string
-    let state_str = match state {
-        TunnelState::Inactive => "inactive",
-        TunnelState::Monitoring => "monitoring",
-        TunnelState::Activating => "activating",
-        TunnelState::Active => "connected",
-        TunnelState::Deactivating => "deactivating",
+/// State left behind by a previous daemon instance
+///
+/// Times are Unix timestamps in seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedState {
+    /// Tunnel state when the file was last written
+    pub state: TunnelState,
+    /// SSID the daemon was connected to
+    pub ssid: Option<String>,
+    /// When the file was last written
+    pub timestamp: u64,
+    /// When the tunnel came up (only while the tunnel was active)
+    pub session_start: Option<u64>,
+    /// Last tunnel activity (only while the tunnel was active)
+    pub last_activity: Option<u64>,
+    /// End of the post-idle cooldown or flap backoff
+    pub cooldown_until: Option<u64>,
+    /// Whether the tunnel was brought up outside the daemon
+    pub manual: bool,
+}
+
+/// Parse state file contents written by [`format_state`]
+///
+/// Returns None if the state or timestamp is missing or malformed.
+fn parse_state(contents: &str) -> Option<SavedState> {
+    let value = |key: &str| {
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .filter(|v| !v.is_empty())
     };
+    let number = |key: &str| value(key).and_then(|v| v.parse::<u64>().ok());
 
-    // Write state file
-    let content = format!(
-        "STATE={}\nSSID={}\nTIMESTAMP={}\n",
-        state_str,
-        ssid.unwrap_or(""),
-        timestamp
-    );
+    let timestamp = number("TIMESTAMP")?;
+    Some(SavedState {
+        state: parse_state_str(value("STATE")?)?,
+        ssid: value("SSID").map(String::from),
+        timestamp,
+        session_start: number("SESSION_START"),
+        last_activity: number("IDLE_SECONDS").map(|idle| timestamp.saturating_sub(idle)),
+        cooldown_until: number("COOLDOWN_SECONDS")
+            .filter(|&secs| secs > 0)
+            .map(|secs| timestamp + secs),
+        manual: value("MANUAL") == Some("1"),
+    })
+}
 
-    fs::write(STATE_FILE, content).context("Failed to write state file")?;
+/// Read the state left behind by a previous daemon instance, if any
+///
+/// That is the session file of a clean shutdown or the state file of a
+/// crash, whichever is newer. The session file is removed so it can't be
+/// resumed twice. Must be called before the first [`write_state`], which
+/// overwrites the state file.
+pub fn read_state(instance: &Instance) -> Option<SavedState> {
+    take_saved_state(&instance.session_file(), &instance.state_file())
+}
 
-    Ok(())
+fn take_saved_state(session_file: &Path, state_file: &Path) -> Option<SavedState> {
+    let read = |path: &Path| parse_state(&fs::read_to_string(path).ok()?);
+    let session = read(session_file);
+    if session.is_some() {
+        let _ = fs::remove_file(session_file);
+    }
+    session
+        .into_iter()
+        .chain(read(state_file))
+        .max_by_key(|saved| saved.timestamp)
 }
 
 /// Remove state file on shutdown
-pub fn cleanup() {
-    let _ = fs::remove_file(STATE_FILE);
+pub fn cleanup(instance: &Instance) {
+    let _ = fs::remove_file(instance.state_file());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_state_basic() {
+        let snapshot = StateSnapshot::new(TunnelState::Monitoring, Some("HomeWiFi"));
+        let content = format_state(&snapshot, 1700000000);
+
+        assert!(content.starts_with("STATE=monitoring\nSSID=HomeWiFi\nTIMESTAMP=1700000000\n"));
+        assert!(content.contains("SESSION_RX_BYTES=0\n"));
+    }
+
+    #[test]
+    fn test_format_state_traffic() {
+        let mut snapshot = StateSnapshot::new(TunnelState::Active, None);
+        snapshot.traffic = TrafficTotals {
+            session_rx_bytes: 1024,
+            session_tx_bytes: 2048,
+            total_rx_bytes: 4096,
+            total_tx_bytes: 8192,
+        };
+        let content = format_state(&snapshot, 0);
+
+        assert!(content.contains("STATE=connected\n"));
+        assert!(content.contains("SSID=\n"));
+        assert!(content.contains("SESSION_RX_BYTES=1024\n"));
+        assert!(content.contains("SESSION_TX_BYTES=2048\n"));
+        assert!(content.contains("TOTAL_RX_BYTES=4096\n"));
+        assert!(content.contains("TOTAL_TX_BYTES=8192\n"));
+    }
+
+    #[test]
+    fn test_format_state_activation_latency() {
+        let mut snapshot = StateSnapshot::new(TunnelState::Active, None);
+        assert!(format_state(&snapshot, 0).contains("ACTIVATION_LATENCY_AVG_MS=\n"));
+
+        snapshot
+            .activation_latency
+            .record(Duration::from_millis(800));
+        snapshot
+            .activation_latency
+            .record(Duration::from_millis(1200));
+        let content = format_state(&snapshot, 0);
+
+        assert!(content.contains("ACTIVATION_LATENCY_LAST_MS=1200\n"));
+        assert!(content.contains("ACTIVATION_LATENCY_MIN_MS=800\n"));
+        assert!(content.contains("ACTIVATION_LATENCY_AVG_MS=1000\n"));
+        assert!(content.contains("ACTIVATION_LATENCY_MAX_MS=1200\n"));
+    }
+
+    #[test]
+    fn test_format_state_idle_countdown() {
+        let mut snapshot = StateSnapshot::new(TunnelState::Monitoring, None);
+        snapshot.idle_timeout = Duration::from_secs(300);
+        let content = format_state(&snapshot, 0);
+        assert!(content.contains("IDLE_SECONDS=\n"));
+        assert!(content.contains("IDLE_TIMEOUT=300\n"));
+
+        snapshot.state = TunnelState::Active;
+        snapshot.idle = Some(Duration::from_millis(210_500));
+        let content = format_state(&snapshot, 0);
+        assert!(content.contains("IDLE_SECONDS=210\n"));
+        assert!(content.contains("IDLE_WARNING=0\n"));
+
+        snapshot.idle_warning = true;
+        assert!(format_state(&snapshot, 0).contains("IDLE_WARNING=1\n"));
+    }
+
+    #[test]
+    fn test_format_state_session_start() {
+        let mut snapshot = StateSnapshot::new(TunnelState::Monitoring, None);
+        assert!(format_state(&snapshot, 1000).contains("SESSION_START=\n"));
+
+        snapshot.state = TunnelState::Active;
+        snapshot.active_for = Some(Duration::from_secs(600));
+        assert!(format_state(&snapshot, 1000).contains("SESSION_START=400\nMANUAL=0\n"));
+
+        snapshot.manual = true;
+        assert!(format_state(&snapshot, 1000).contains("MANUAL=1\n"));
+    }
+
+    #[test]
+    fn test_parse_state_roundtrip() {
+        let mut snapshot = StateSnapshot::new(TunnelState::Active, Some("HomeWiFi"));
+        snapshot.active_for = Some(Duration::from_secs(600));
+        snapshot.idle = Some(Duration::from_secs(30));
+        snapshot.cooldown = Some(Duration::from_secs(90));
+
+        assert_eq!(
+            parse_state(&format_state(&snapshot, 1_700_000_000)),
+            Some(SavedState {
+                state: TunnelState::Active,
+                ssid: Some("HomeWiFi".to_string()),
+                timestamp: 1_700_000_000,
+                session_start: Some(1_699_999_400),
+                last_activity: Some(1_699_999_970),
+                cooldown_until: Some(1_700_000_090),
+                manual: false,
+            })
+        );
+
+        let snapshot = StateSnapshot::new(TunnelState::Monitoring, None);
+        let saved = parse_state(&format_state(&snapshot, 1000)).unwrap();
+        assert_eq!(saved.state, TunnelState::Monitoring);
+        assert_eq!(saved.ssid, None);
+        assert_eq!(saved.session_start, None);
+        assert_eq!(saved.last_activity, None);
+        assert_eq!(saved.cooldown_until, None);
+        assert!(!saved.manual);
+    }
+
+    #[test]
+    fn test_parse_state_invalid() {
+        assert_eq!(parse_state(""), None);
+        assert_eq!(parse_state("STATE=bogus\nTIMESTAMP=1\n"), None);
+        assert_eq!(parse_state("STATE=connected\n"), None);
+    }
+
+    #[test]
+    fn test_session_survives_restart() {
+        let dir =
+            std::env::temp_dir().join(format!("wg-ondemand-session-test-{}", std::process::id()));
+        let session_file = dir.join("lib/session");
+        let state_file = dir.join("run/state");
+        fs::create_dir_all(dir.join("run")).unwrap();
+
+        // Running with the tunnel up for 10 minutes
+        let mut snapshot = StateSnapshot::new(TunnelState::Active, Some("HomeWiFi"));
+        snapshot.active_for = Some(Duration::from_secs(600));
+        fs::write(&state_file, format_state(&snapshot, 1_700_000_000)).unwrap();
+
+        // Clean shutdown: the session is saved and the runtime directory emptied
+        snapshot.active_for = Some(Duration::from_secs(660));
+        save_session_to(&session_file, &snapshot, 1_700_000_060).unwrap();
+        fs::remove_file(&state_file).unwrap();
+
+        // Startup resumes the session once
+        let saved = take_saved_state(&session_file, &state_file).unwrap();
+        assert_eq!(saved.state, TunnelState::Active);
+        assert_eq!(saved.ssid.as_deref(), Some("HomeWiFi"));
+        assert_eq!(saved.session_start, Some(1_699_999_400));
+        assert!(!session_file.exists());
+        assert_eq!(take_saved_state(&session_file, &state_file), None);
+
+        // After a crash, the state file is newer than a stale session file
+        save_session_to(&session_file, &snapshot, 1_700_000_060).unwrap();
+        let snapshot = StateSnapshot::new(TunnelState::Monitoring, Some("HomeWiFi"));
+        fs::write(&state_file, format_state(&snapshot, 1_700_001_000)).unwrap();
+        let saved = take_saved_state(&session_file, &state_file).unwrap();
+        assert_eq!(saved.state, TunnelState::Monitoring);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_state_notice() {
+        let mut snapshot = StateSnapshot::new(TunnelState::Monitoring, None);
+        assert!(format_state(&snapshot, 0).contains("NOTICE=\nNOTICE_TIMESTAMP=\n"));
+
+        snapshot.notice = Some(("Session limit\nreached", 1700000000));
+        let content = format_state(&snapshot, 0);
+        assert!(content.contains("NOTICE=Session limit reached\n"));
+        assert!(content.contains("NOTICE_TIMESTAMP=1700000000\n"));
+    }
+
+    #[test]
+    fn test_format_state_cooldown() {
+        let mut snapshot = StateSnapshot::new(TunnelState::Monitoring, None);
+        assert!(format_state(&snapshot, 0).contains("COOLDOWN_SECONDS=0\n"));
+
+        snapshot.cooldown = Some(Duration::from_secs(90));
+        assert!(format_state(&snapshot, 0).contains("COOLDOWN_SECONDS=90\n"));
+    }
+
+    #[test]
+    fn test_format_state_backoff() {
+        let mut snapshot = StateSnapshot::new(TunnelState::Monitoring, None);
+        assert!(format_state(&snapshot, 0).contains("BACKOFF_LEVEL=0\n"));
+
+        snapshot.backoff_level = 2;
+        assert!(format_state(&snapshot, 0).contains("BACKOFF_LEVEL=2\n"));
+    }
+
+    #[test]
+    fn test_format_state_health_restarts() {
+        let mut snapshot = StateSnapshot::new(TunnelState::Active, None);
+        assert!(format_state(&snapshot, 0).contains("HEALTH_RESTARTS=0\n"));
+
+        snapshot.health_restarts = 3;
+        assert!(format_state(&snapshot, 0).contains("HEALTH_RESTARTS=3\n"));
+    }
+
+    #[test]
+    fn test_format_state_last_handshake() {
+        let mut snapshot = StateSnapshot::new(TunnelState::Active, None);
+        assert!(format_state(&snapshot, 0).contains("LAST_HANDSHAKE=\n"));
+
+        snapshot.last_handshake = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        assert!(format_state(&snapshot, 0).contains("LAST_HANDSHAKE=1700000000\n"));
+    }
 }
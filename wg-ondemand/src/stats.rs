@@ -0,0 +1,356 @@
+// Runtime statistics
+
+//! Runtime statistics
+//!
+//! This module aggregates daemon metrics that are not part of the state machine,
+//! such as how long it takes for the tunnel to come up after traffic is detected,
+//! counters for `wg-ondemand-ctl stats`, and lifetime counters that are persisted
+//! across daemon restarts.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// File holding lifetime statistics across daemon restarts (see
+/// [`Instance::stats_file`](crate::instance::Instance::stats_file))
+pub const STATS_FILE: &str = "/var/lib/wg-ondemand/stats";
+
+/// Get the current CLOCK_MONOTONIC time in nanoseconds
+///
+/// This is the same clock as `bpf_ktime_get_ns()`, so the result can be compared
+/// directly with [`TrafficEvent::timestamp`](crate::types::TrafficEvent::timestamp).
+pub fn monotonic_now_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid, writable timespec and CLOCK_MONOTONIC is always available
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    (ts.tv_sec as u64) * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Running min/avg/max of a latency measurement
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    count: u64,
+    total: Duration,
+    min: Option<Duration>,
+    max: Option<Duration>,
+    last: Option<Duration>,
+}
+
+impl LatencyStats {
+    /// Record a new latency sample
+    pub fn record(&mut self, latency: Duration) {
+        self.count += 1;
+        self.total += latency;
+        self.min = Some(self.min.map_or(latency, |m| m.min(latency)));
+        self.max = Some(self.max.map_or(latency, |m| m.max(latency)));
+        self.last = Some(latency);
+    }
+
+    /// Number of samples recorded
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Smallest recorded latency
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    /// Largest recorded latency
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    /// Mean of all recorded latencies
+    pub fn avg(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.total / self.count as u32)
+    }
+
+    /// Most recently recorded latency
+    pub fn last(&self) -> Option<Duration> {
+        self.last
+    }
+}
+
+/// Days since the epoch in local time, for counting per calendar day
+pub fn local_day(unix_secs: u64) -> i64 {
+    let time = unix_secs as libc::time_t;
+    // SAFETY: zeroed memory is a valid `tm`
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call
+    let offset = if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        0
+    } else {
+        tm.tm_gmtoff
+    };
+    (unix_secs as i64 + offset).div_euclid(86_400)
+}
+
+/// Counters of the current daemon run answered by `wg-ondemand-ctl stats`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SessionStats {
+    /// Local day [`activations`](Self::activations) counts
+    day: i64,
+    /// Activations on `day`
+    activations: u64,
+    /// When the current session started (Unix timestamp), None while the tunnel is down
+    pub session_start: Option<u64>,
+    /// Bytes received in the current session
+    pub session_rx_bytes: u64,
+    /// Bytes sent in the current session
+    pub session_tx_bytes: u64,
+    /// Time from traffic detection to tunnel up
+    pub activation_latency: LatencyStats,
+    /// Destination of the traffic that last activated the tunnel
+    pub last_trigger: Option<String>,
+}
+
+/// Stats shared between the main loop and the control socket
+pub type SharedStats = Arc<Mutex<SessionStats>>;
+
+impl SessionStats {
+    /// Count an activation on local day `day`
+    pub fn record_activation(&mut self, day: i64) {
+        if day != self.day {
+            self.day = day;
+            self.activations = 0;
+        }
+        self.activations += 1;
+    }
+
+    /// Activations on local day `day`
+    pub fn activations_on(&self, day: i64) -> u64 {
+        if day == self.day {
+            self.activations
+        } else {
+            0
+        }
+    }
+
+    /// Format for `wg-ondemand-ctl stats` as of `now` (Unix timestamp)
+    pub fn format(&self, now: u64) -> String {
+        let session = match self.session_start {
+            Some(start) => format!(
+                "up {}s, {} bytes received, {} bytes sent",
+                now.saturating_sub(start),
+                self.session_rx_bytes,
+                self.session_tx_bytes
+            ),
+            None => "none (tunnel down)".to_string(),
+        };
+        let latency = match self.activation_latency.avg() {
+            Some(avg) => format!(
+                "{}ms average over {} activations (last {}ms)",
+                avg.as_millis(),
+                self.activation_latency.count(),
+                self.activation_latency
+                    .last()
+                    .unwrap_or_default()
+                    .as_millis()
+            ),
+            None => "not measured yet".to_string(),
+        };
+        format!(
+            "Activations today:   {}\n\
+            Current session:     {}\n\
+            Activation latency:  {}\n\
+            Last trigger:        {}",
+            self.activations_on(local_day(now)),
+            session,
+            latency,
+            self.last_trigger.as_deref().unwrap_or("none")
+        )
+    }
+}
+
+/// Counters accumulated over all daemon runs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LifetimeStats {
+    /// Number of times the tunnel came up
+    pub activations: u64,
+    /// Total time the tunnel was up, in seconds
+    pub active_secs: u64,
+    /// Total bytes received through the tunnel
+    pub rx_bytes: u64,
+    /// Total bytes sent through the tunnel
+    pub tx_bytes: u64,
+}
+
+impl LifetimeStats {
+    /// Format as `KEY=value` lines
+    pub fn format(&self) -> String {
+        format!(
+            "ACTIVATIONS={}\nACTIVE_SECONDS={}\nRX_BYTES={}\nTX_BYTES={}\n",
+            self.activations, self.active_secs, self.rx_bytes, self.tx_bytes
+        )
+    }
+
+    /// Parse `KEY=value` lines written by [`format`](Self::format)
+    ///
+    /// Missing or malformed counters are treated as zero.
+    pub fn parse(contents: &str) -> Self {
+        let number = |key: &str| {
+            contents
+                .lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(0)
+        };
+        Self {
+            activations: number("ACTIVATIONS"),
+            active_secs: number("ACTIVE_SECONDS"),
+            rx_bytes: number("RX_BYTES"),
+            tx_bytes: number("TX_BYTES"),
+        }
+    }
+
+    /// Load statistics from `path`, starting from zero if the file doesn't exist
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(Self::parse(&contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        }
+    }
+
+    /// Write statistics to `path`, replacing it atomically
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory or file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.format()).with_context(|| format!("Failed to write {:?}", tmp))?;
+        fs::rename(&tmp, path).with_context(|| format!("Failed to replace {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_stats_activations() {
+        let mut stats = SessionStats::default();
+        stats.record_activation(19_000);
+        stats.record_activation(19_000);
+        assert_eq!(stats.activations_on(19_000), 2);
+        assert_eq!(stats.activations_on(19_001), 0);
+
+        // A new day starts counting from zero
+        stats.record_activation(19_001);
+        assert_eq!(stats.activations_on(19_001), 1);
+        assert_eq!(stats.activations_on(19_000), 0);
+    }
+
+    #[test]
+    fn test_session_stats_format() {
+        let mut stats = SessionStats::default();
+        let listing = stats.format(1_700_000_000);
+        assert!(listing.contains("Activations today:   0\n"));
+        assert!(listing.contains("Current session:     none (tunnel down)\n"));
+        assert!(listing.contains("Activation latency:  not measured yet\n"));
+        assert!(listing.ends_with("Last trigger:        none"));
+
+        stats.record_activation(local_day(1_700_000_000));
+        stats.session_start = Some(1_699_999_400);
+        stats.session_rx_bytes = 1024;
+        stats.session_tx_bytes = 2048;
+        stats.activation_latency.record(Duration::from_millis(800));
+        stats.activation_latency.record(Duration::from_millis(1200));
+        stats.last_trigger = Some("10.0.0.5:443/tcp".to_string());
+        let listing = stats.format(1_700_000_000);
+        assert!(listing.contains("Activations today:   1\n"));
+        assert!(listing.contains("up 600s, 1024 bytes received, 2048 bytes sent\n"));
+        assert!(listing.contains("1000ms average over 2 activations (last 1200ms)\n"));
+        assert!(listing.ends_with("Last trigger:        10.0.0.5:443/tcp"));
+    }
+
+    #[test]
+    fn test_latency_stats_empty() {
+        let stats = LatencyStats::default();
+        assert_eq!(stats.count(), 0);
+        assert_eq!(stats.min(), None);
+        assert_eq!(stats.avg(), None);
+        assert_eq!(stats.max(), None);
+        assert_eq!(stats.last(), None);
+    }
+
+    #[test]
+    fn test_latency_stats_record() {
+        let mut stats = LatencyStats::default();
+        stats.record(Duration::from_millis(300));
+        stats.record(Duration::from_millis(100));
+        stats.record(Duration::from_millis(500));
+
+        assert_eq!(stats.count(), 3);
+        assert_eq!(stats.min(), Some(Duration::from_millis(100)));
+        assert_eq!(stats.avg(), Some(Duration::from_millis(300)));
+        assert_eq!(stats.max(), Some(Duration::from_millis(500)));
+        assert_eq!(stats.last(), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_lifetime_stats_roundtrip() {
+        let stats = LifetimeStats {
+            activations: 42,
+            active_secs: 86_400,
+            rx_bytes: 1 << 33,
+            tx_bytes: 12_345,
+        };
+        assert_eq!(LifetimeStats::parse(&stats.format()), stats);
+        assert_eq!(
+            LifetimeStats::parse("ACTIVATIONS=3\nRX_BYTES=bad\n"),
+            LifetimeStats {
+                activations: 3,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_lifetime_stats_save_load() {
+        let dir =
+            std::env::temp_dir().join(format!("wg-ondemand-stats-test-{}", std::process::id()));
+        let path = dir.join("stats");
+        assert_eq!(
+            LifetimeStats::load(&path).unwrap(),
+            LifetimeStats::default()
+        );
+
+        let stats = LifetimeStats {
+            activations: 1,
+            active_secs: 60,
+            rx_bytes: 100,
+            tx_bytes: 50,
+        };
+        stats.save(&path).unwrap();
+        assert_eq!(LifetimeStats::load(&path).unwrap(), stats);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_monotonic_now_ns_advances() {
+        let a = monotonic_now_ns();
+        std::thread::sleep(Duration::from_millis(10));
+        let b = monotonic_now_ns();
+        assert!(b - a >= 10_000_000);
+    }
+}
@@ -0,0 +1,115 @@
+// Log output to the system logger
+
+//! Logging to syslog
+//!
+//! On systems with a syslog daemon but no journald (OpenWrt, minimal servers)
+//! stderr is lost, so with `log_target = "syslog"` each log line is sent to the
+//! system logger instead, as `wg-ondemand[pid]` with the daemon facility and
+//! the priority of its level. The C library keeps the `/dev/log` socket open
+//! (opened before the sandbox is set up) and reconnects if the logger restarts.
+
+use std::ffi::CString;
+use std::io;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Identity of the log lines; syslog keeps the pointer
+const IDENT: &std::ffi::CStr = c"wg-ondemand";
+
+/// `MakeWriter` sending each formatted event to syslog
+#[derive(Debug, Clone, Copy)]
+pub struct Syslog;
+
+impl Syslog {
+    /// Connect to the system logger
+    pub fn open() -> Self {
+        // SAFETY: IDENT is a static C string
+        unsafe {
+            libc::openlog(
+                IDENT.as_ptr(),
+                libc::LOG_PID | libc::LOG_NDELAY,
+                libc::LOG_DAEMON,
+            )
+        };
+        Self
+    }
+}
+
+/// Syslog priority of a tracing level
+pub fn priority(level: &Level) -> libc::c_int {
+    match *level {
+        Level::ERROR => libc::LOG_ERR,
+        Level::WARN => libc::LOG_WARNING,
+        Level::INFO => libc::LOG_INFO,
+        Level::DEBUG | Level::TRACE => libc::LOG_DEBUG,
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = SyslogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogWriter::new(libc::LOG_INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        SyslogWriter::new(priority(meta.level()))
+    }
+}
+
+/// Collects one formatted event and logs it when dropped
+pub struct SyslogWriter {
+    priority: libc::c_int,
+    buffer: Vec<u8>,
+}
+
+impl SyslogWriter {
+    fn new(priority: libc::c_int) -> Self {
+        Self {
+            priority,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl io::Write for SyslogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogWriter {
+    fn drop(&mut self) {
+        let mut message = std::mem::take(&mut self.buffer);
+        while message.last().is_some_and(u8::is_ascii_whitespace) {
+            message.pop();
+        }
+        message.retain(|&byte| byte != 0);
+        if message.is_empty() {
+            return;
+        }
+        let Ok(message) = CString::new(message) else {
+            return;
+        };
+        // SAFETY: constant format string with one C string argument
+        unsafe { libc::syslog(self.priority, c"%s".as_ptr(), message.as_ptr()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority() {
+        assert_eq!(priority(&Level::ERROR), libc::LOG_ERR);
+        assert_eq!(priority(&Level::WARN), libc::LOG_WARNING);
+        assert_eq!(priority(&Level::INFO), libc::LOG_INFO);
+        assert_eq!(priority(&Level::TRACE), libc::LOG_DEBUG);
+    }
+}
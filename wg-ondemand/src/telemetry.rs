@@ -0,0 +1,208 @@
+// OpenTelemetry export of traces and metrics
+
+//! OpenTelemetry export
+//!
+//! With the `otel` feature and an `[otel]` section, spans are exported over
+//! OTLP/HTTP to a collector: each `activation` with its `wg_up`,
+//! `add_routes` and `handshake` children, carrying how long after the
+//! triggering traffic it started. Alongside, activations and deactivations are
+//! counted and the trigger-to-tunnel-up latency goes into a histogram.
+//!
+//! The recording functions are no-ops without the feature or the section, so
+//! callers don't need to check either.
+
+use std::time::Duration;
+
+#[cfg(feature = "otel")]
+use crate::types::OtelConfig;
+#[cfg(feature = "otel")]
+use anyhow::{Context, Result};
+#[cfg(feature = "otel")]
+use opentelemetry::{
+    metrics::{Counter, Histogram, MeterProvider},
+    trace::TracerProvider,
+    KeyValue,
+};
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::{
+    metrics::{PeriodicReader, SdkMeterProvider},
+    trace::SdkTracerProvider,
+    Resource,
+};
+#[cfg(feature = "otel")]
+use std::sync::OnceLock;
+#[cfg(feature = "otel")]
+use tracing_subscriber::{filter, registry::LookupSpan, Layer};
+
+/// Instrumentation scope of the spans and metrics
+#[cfg(feature = "otel")]
+const SCOPE: &str = "wg-ondemand";
+
+/// Histogram buckets in seconds, from a warm netlink bring-up to a slow
+/// handshake over a captive network
+#[cfg(feature = "otel")]
+const LATENCY_BOUNDARIES: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0];
+
+#[cfg(feature = "otel")]
+struct Instruments {
+    activations: Counter<u64>,
+    deactivations: Counter<u64>,
+    activation_latency: Histogram<f64>,
+}
+
+/// Set by [`Telemetry::init`]
+#[cfg(feature = "otel")]
+static INSTRUMENTS: OnceLock<Instruments> = OnceLock::new();
+
+/// OTLP endpoint of a signal (`traces`, `metrics`) under the collector's base URL
+pub fn signal_endpoint(base: &str, signal: &str) -> String {
+    format!("{}/v1/{}", base.trim_end_matches('/'), signal)
+}
+
+/// Trace and metric pipelines exporting to the collector
+#[cfg(feature = "otel")]
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+}
+
+#[cfg(feature = "otel")]
+impl Telemetry {
+    /// Set up the exporters; spans are exported through [`Telemetry::layer`]
+    ///
+    /// Exports run on the SDK's own threads, so this is called before the
+    /// daemon starts and its restrictions are applied to all threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an exporter cannot be built, e.g. for an invalid
+    /// endpoint.
+    pub fn init(config: &OtelConfig) -> Result<Self> {
+        let resource = Resource::builder()
+            .with_service_name(config.service_name.clone())
+            .build();
+
+        let spans = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(signal_endpoint(&config.endpoint, "traces"))
+            .build()
+            .context("Failed to build OTLP span exporter")?;
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(spans)
+            .with_resource(resource.clone())
+            .build();
+
+        let metrics = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(signal_endpoint(&config.endpoint, "metrics"))
+            .build()
+            .context("Failed to build OTLP metric exporter")?;
+        let reader = PeriodicReader::builder(metrics)
+            .with_interval(Duration::from_secs(config.metrics_interval_secs))
+            .build();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(resource)
+            .build();
+
+        let meter = meter_provider.meter(SCOPE);
+        let _ = INSTRUMENTS.set(Instruments {
+            activations: meter
+                .u64_counter("wg_ondemand.activations")
+                .with_description("Tunnel activations by result")
+                .build(),
+            deactivations: meter
+                .u64_counter("wg_ondemand.deactivations")
+                .with_description("Tunnel deactivations")
+                .build(),
+            activation_latency: meter
+                .f64_histogram("wg_ondemand.activation.duration")
+                .with_description("Time from the triggering traffic to the tunnel being up")
+                .with_unit("s")
+                .with_boundaries(LATENCY_BOUNDARIES.to_vec())
+                .build(),
+        });
+
+        Ok(Self {
+            tracer_provider,
+            meter_provider,
+        })
+    }
+
+    /// Tracing layer exporting the daemon's spans
+    ///
+    /// Spans and events of other crates are left out, in particular those of
+    /// the HTTP client doing the export.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        tracing_opentelemetry::layer()
+            .with_tracer(self.tracer_provider.tracer(SCOPE))
+            .with_filter(filter::filter_fn(|metadata| {
+                metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+            }))
+    }
+
+    /// Export what is still buffered and stop the exporters
+    ///
+    /// Blocks until the collector answers or the export times out.
+    pub fn shutdown(self) {
+        if let Err(e) = self.tracer_provider.shutdown() {
+            tracing::warn!("Failed to flush spans: {}", e);
+        }
+        if let Err(e) = self.meter_provider.shutdown() {
+            tracing::warn!("Failed to flush metrics: {}", e);
+        }
+    }
+}
+
+/// Count an activation attempt
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+pub fn record_activation(success: bool) {
+    #[cfg(feature = "otel")]
+    if let Some(instruments) = INSTRUMENTS.get() {
+        let result = if success { "success" } else { "failure" };
+        instruments
+            .activations
+            .add(1, &[KeyValue::new("result", result)]);
+    }
+}
+
+/// Record the time from the triggering traffic to the tunnel being up
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+pub fn record_activation_latency(latency: Duration) {
+    #[cfg(feature = "otel")]
+    if let Some(instruments) = INSTRUMENTS.get() {
+        instruments
+            .activation_latency
+            .record(latency.as_secs_f64(), &[]);
+    }
+}
+
+/// Count a deactivation
+pub fn record_deactivation() {
+    #[cfg(feature = "otel")]
+    if let Some(instruments) = INSTRUMENTS.get() {
+        instruments.deactivations.add(1, &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_endpoint() {
+        assert_eq!(
+            signal_endpoint("http://localhost:4318", "traces"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            signal_endpoint("http://collector:4318/", "metrics"),
+            "http://collector:4318/v1/metrics"
+        );
+    }
+}
@@ -0,0 +1,289 @@
+// Traffic detection backends
+
+//! Traffic detection backends
+//!
+//! The eBPF classifier ([`EbpfManager`]) sees every packet leaving the monitored
+//! interfaces and can drop it for the kill switch. Where eBPF can't be loaded,
+//! [`SocketScanner`] polls the socket tables instead (see [`socket_scan`](crate::socket_scan)).
+//! [`TrafficMonitor`] puts both behind the interface the daemon uses; operations
+//! that only make sense for eBPF are no-ops or errors with the scanner.
+
+#[cfg(feature = "ebpf")]
+use crate::ebpf_loader::EbpfManager;
+use crate::error::EbpfError;
+use crate::rtnl;
+use crate::socket_scan::{SocketScanner, MIN_SCAN_INTERVAL_MS};
+use crate::types::{Config, TrafficDetection, TrafficEvent};
+use anyhow::Context;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+/// Source ranges the filter holds (capacity of the eBPF SOURCES map)
+pub const MAX_SOURCE_RANGES: usize = 16;
+
+/// Traffic detection on the monitored interfaces
+pub enum TrafficMonitor {
+    /// TC eBPF classifier
+    #[cfg(feature = "ebpf")]
+    Ebpf(Box<EbpfManager>),
+    /// Socket table scanning, active while "attached" to any interface
+    Sockets {
+        /// The scanner
+        scanner: SocketScanner,
+        /// Interfaces being monitored
+        interfaces: Vec<String>,
+    },
+}
+
+impl TrafficMonitor {
+    /// Load the backend selected by `traffic_detection`
+    ///
+    /// The first of `interfaces` must exist. With `auto`, failing to load eBPF
+    /// (missing privileges, kernel lockdown) falls back to socket scanning, which
+    /// is all builds without the `ebpf` feature have.
+    pub fn load(config: &Config, interfaces: &[String]) -> Result<Self, EbpfError> {
+        let subnets = &config.subnets.ranges;
+        match config.general.traffic_detection {
+            TrafficDetection::Ebpf => Self::ebpf(config, interfaces),
+            TrafficDetection::Sockets => Self::sockets(interfaces, subnets),
+            TrafficDetection::Auto if !cfg!(feature = "ebpf") => Self::sockets(interfaces, subnets),
+            TrafficDetection::Auto => {
+                // Only fall back for eBPF itself, not a missing interface
+                if let Some(primary) = interfaces.first() {
+                    rtnl::ifindex(primary).with_context(|| {
+                        format!("Network interface '{}' does not exist", primary)
+                    })?;
+                }
+                Self::ebpf(config, interfaces).or_else(|e| {
+                    tracing::warn!(
+                        "eBPF unavailable, falling back to socket table scanning: {:#}",
+                        e
+                    );
+                    Self::sockets(interfaces, subnets)
+                })
+            }
+        }
+    }
+
+    #[cfg(feature = "ebpf")]
+    fn ebpf(config: &Config, interfaces: &[String]) -> Result<Self, EbpfError> {
+        let manager = EbpfManager::load(interfaces, &config.subnets.ranges)?.with_tc_filter(
+            config.instance.tc_priority(config.general.tc_priority),
+            config.general.tc_handle.unwrap_or(0),
+        );
+        // Filters of a crashed instance, from before any attachment
+        manager.remove_stale_filters(interfaces)?;
+        Ok(Self::Ebpf(Box::new(manager)))
+    }
+
+    #[cfg(not(feature = "ebpf"))]
+    fn ebpf(_config: &Config, _interfaces: &[String]) -> Result<Self, EbpfError> {
+        Err(anyhow::anyhow!("Built without eBPF support (the `ebpf` feature)").into())
+    }
+
+    fn sockets(interfaces: &[String], subnets: &[String]) -> Result<Self, EbpfError> {
+        if let Some(primary) = interfaces.first() {
+            rtnl::ifindex(primary)
+                .with_context(|| format!("Network interface '{}' does not exist", primary))?;
+        }
+        let scanner = SocketScanner::new(subnets).context("Socket table scanning unavailable")?;
+        tracing::info!("Detecting traffic by scanning socket tables (host traffic only)");
+        Ok(Self::Sockets {
+            scanner,
+            interfaces: Vec::new(),
+        })
+    }
+
+    /// Whether the eBPF classifier is in use
+    pub fn is_ebpf(&self) -> bool {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(_) => true,
+            Self::Sockets { .. } => false,
+        }
+    }
+
+    /// How often to call [`Self::read_events`] given the configured eBPF poll interval
+    pub fn poll_interval(&self, ebpf_poll_interval_ms: u64) -> Duration {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(_) => Duration::from_millis(ebpf_poll_interval_ms),
+            Self::Sockets { .. } => {
+                Duration::from_millis(ebpf_poll_interval_ms.max(MIN_SCAN_INTERVAL_MS))
+            }
+        }
+    }
+
+    /// Start monitoring traffic leaving through `interface`
+    pub fn attach(&mut self, interface: &str) -> Result<(), EbpfError> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.attach(interface),
+            Self::Sockets { interfaces, .. } => {
+                if !interfaces.iter().any(|name| name == interface) {
+                    interfaces.push(interface.to_string());
+                    tracing::info!("Scanning sockets for traffic on {}", interface);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Stop monitoring all interfaces
+    pub fn detach(&mut self) -> Result<(), EbpfError> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.detach(),
+            Self::Sockets {
+                scanner,
+                interfaces,
+            } => {
+                interfaces.clear();
+                scanner.reset();
+                Ok(())
+            }
+        }
+    }
+
+    /// Stop monitoring `interface`
+    pub fn detach_from(&mut self, interface: &str) -> Result<(), EbpfError> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.detach_from(interface),
+            Self::Sockets {
+                scanner,
+                interfaces,
+            } => {
+                interfaces.retain(|name| name != interface);
+                if interfaces.is_empty() {
+                    scanner.reset();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Check if any interface is monitored
+    pub fn is_attached(&self) -> bool {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.is_attached(),
+            Self::Sockets { interfaces, .. } => !interfaces.is_empty(),
+        }
+    }
+
+    /// Check if `interface` is monitored
+    pub fn is_attached_to(&self, interface: &str) -> bool {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.is_attached_to(interface),
+            Self::Sockets { interfaces, .. } => interfaces.iter().any(|name| name == interface),
+        }
+    }
+
+    /// Traffic events since the last call
+    pub fn read_events(&mut self) -> Vec<TrafficEvent> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.read_events(),
+            Self::Sockets {
+                scanner,
+                interfaces,
+            } if !interfaces.is_empty() => scanner.scan(),
+            Self::Sockets { .. } => Vec::new(),
+        }
+    }
+
+    /// See [`EbpfManager::set_kill_switch`]; socket scanning can't drop packets
+    pub fn set_kill_switch(&mut self, enabled: bool) -> Result<(), EbpfError> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.set_kill_switch(enabled),
+            Self::Sockets { .. } if enabled => Err(anyhow::anyhow!(
+                "The kill switch needs eBPF, which is unavailable (see `wg-ondemand doctor`)"
+            )
+            .into()),
+            Self::Sockets { .. } => Ok(()),
+        }
+    }
+
+    /// See [`EbpfManager::set_tunnel`]; sockets are only scanned while the tunnel is down
+    #[cfg_attr(not(feature = "ebpf"), allow(unused_variables))]
+    pub fn set_tunnel(&mut self, ifindex: u32, fwmark: u32) -> Result<(), EbpfError> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.set_tunnel(ifindex, fwmark),
+            Self::Sockets { .. } => Ok(()),
+        }
+    }
+
+    /// See [`EbpfManager::set_source_filter`]; matched against the socket's local address
+    pub fn set_source_filter(&mut self, ranges: Option<&[String]>) -> Result<(), EbpfError> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.set_source_filter(ranges),
+            Self::Sockets { scanner, .. } => {
+                scanner.set_source_filter(ranges);
+                Ok(())
+            }
+        }
+    }
+
+    /// See [`EbpfManager::exclude_destination`]
+    pub fn exclude_destination(&mut self, ip: Ipv4Addr) -> Result<bool, EbpfError> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.exclude_destination(ip),
+            Self::Sockets { scanner, .. } => Ok(scanner.exclude_destination(ip)),
+        }
+    }
+
+    /// See [`EbpfManager::load_counters`]; nothing to load without eBPF
+    pub fn load_counters(&mut self) -> Result<(), EbpfError> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.load_counters(),
+            Self::Sockets { .. } => Ok(()),
+        }
+    }
+
+    /// See [`EbpfManager::attach_counters`]
+    #[cfg_attr(not(feature = "ebpf"), allow(unused_variables))]
+    pub fn attach_counters(&mut self, interface: &str) -> Result<(), EbpfError> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.attach_counters(interface),
+            Self::Sockets { .. } => {
+                Err(anyhow::anyhow!("Byte counters need eBPF, which is unavailable").into())
+            }
+        }
+    }
+
+    /// See [`EbpfManager::detach_counters`]
+    pub fn detach_counters(&mut self) {
+        #[cfg(feature = "ebpf")]
+        if let Self::Ebpf(manager) = self {
+            manager.detach_counters();
+        }
+    }
+
+    /// See [`EbpfManager::counters_attached`]
+    pub fn counters_attached(&self) -> bool {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.counters_attached(),
+            Self::Sockets { .. } => false,
+        }
+    }
+
+    /// See [`EbpfManager::read_counters`]
+    pub fn read_counters(&self) -> Result<(u64, u64), EbpfError> {
+        match self {
+            #[cfg(feature = "ebpf")]
+            Self::Ebpf(manager) => manager.read_counters(),
+            Self::Sockets { .. } => {
+                Err(anyhow::anyhow!("Byte counters need eBPF, which is unavailable").into())
+            }
+        }
+    }
+}
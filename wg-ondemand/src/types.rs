@@ -6,24 +6,14 @@
 //! including the FFI-compatible TrafficEvent structure for eBPF communication,
 //! state machine types, and configuration structures.
 
+use crate::instance::Instance;
 use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
-/// Event structure for eBPF → userspace communication
-/// Must be #[repr(C)] for ABI compatibility with eBPF
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-pub struct TrafficEvent {
-    /// Kernel timestamp in nanoseconds
-    pub timestamp: u64,
-    /// Destination IP in network byte order
-    pub dest_ip: u32,
-    /// Destination port
-    pub dest_port: u16,
-    /// IP protocol (IPPROTO_TCP, IPPROTO_UDP, etc.)
-    pub protocol: u8,
-    /// Padding for alignment
-    pub _padding: u8,
-}
+/// Event structure for eBPF → userspace communication, defined in the crate
+/// shared with the eBPF program
+pub use wg_ondemand_common::TrafficEvent;
 
 /// Tunnel state machine states
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -40,13 +30,53 @@ pub enum TunnelState {
     Deactivating,
 }
 
+/// Tunnel traffic totals accumulated from WireGuard transfer counters
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TrafficTotals {
+    /// Bytes received during the current tunnel session
+    pub session_rx_bytes: u64,
+    /// Bytes sent during the current tunnel session
+    pub session_tx_bytes: u64,
+    /// Bytes received across all sessions since daemon start
+    pub total_rx_bytes: u64,
+    /// Bytes sent across all sessions since daemon start
+    pub total_tx_bytes: u64,
+}
+
 /// Main configuration structure
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     /// General configuration options
     pub general: GeneralConfig,
-    /// Subnet configuration
+    /// Subnet configuration (part of the tunnel sections when those are used)
+    #[serde(default)]
     pub subnets: SubnetConfig,
+    /// Named tunnels from `[tunnel.NAME]` sections; a flat config is loaded as a
+    /// single tunnel named after its `wg_interface`
+    #[serde(default, rename = "tunnel")]
+    pub tunnels: BTreeMap<String, TunnelConfig>,
+    /// Optional connectivity probe run after the tunnel comes up
+    #[serde(default)]
+    pub probe: Option<ProbeConfig>,
+    /// Optional alternate peer endpoints to fail over between
+    #[serde(default)]
+    pub endpoints: Option<EndpointConfig>,
+    /// Optional public IP check identifying the home network
+    #[serde(default)]
+    pub fingerprint: Option<FingerprintConfig>,
+    /// Optional docking station identifying the home network
+    #[serde(default)]
+    pub dock: Option<DockConfig>,
+    /// Optional interface definition for managing the tunnel natively via netlink
+    /// instead of wg-quick
+    #[serde(default)]
+    pub native: Option<NativeTunnelConfig>,
+    /// Optional OTLP export of traces and metrics (needs the `otel` feature)
+    #[serde(default)]
+    pub otel: Option<OtelConfig>,
+    /// Runtime paths and IDs of the selected tunnel, set by the config loader
+    #[serde(skip)]
+    pub instance: Instance,
 }
 
 /// General configuration options
@@ -59,7 +89,8 @@ pub struct GeneralConfig {
     /// SSIDs to exclude from monitoring (blacklist). Takes precedence over target_ssids.
     #[serde(default)]
     pub exclude_ssids: Vec<String>,
-    /// WireGuard interface name
+    /// WireGuard interface name (part of the tunnel sections when those are used)
+    #[serde(default)]
     pub wg_interface: String,
     /// NetworkManager connection name (if using NetworkManager instead of wg-quick)
     #[serde(default)]
@@ -67,12 +98,193 @@ pub struct GeneralConfig {
     /// Network interface to monitor (auto-detected if not specified)
     #[serde(default)]
     pub monitor_interface: Option<String>,
+    /// Several interfaces to monitor at once (e.g. a dock's Ethernet and WiFi),
+    /// in order of preference; replaces `monitor_interface`
+    #[serde(default)]
+    pub monitor_interfaces: Vec<String>,
     /// Idle timeout in seconds before deactivating tunnel
     #[serde(default = "default_idle_timeout")]
     pub idle_timeout: u64,
+    /// Whether traffic activates the tunnel or only manual commands do
+    #[serde(default)]
+    pub mode: TunnelMode,
+    /// Seconds before idle deactivation at which to flag an idle warning (0 disables)
+    #[serde(default = "default_idle_warning_secs")]
+    pub idle_warning_secs: u64,
+    /// Minimum seconds the tunnel stays up after activation before idle timeout applies
+    #[serde(default)]
+    pub min_active_secs: u64,
+    /// Debounce window in milliseconds: require a second traffic event within this window
+    /// before activating (0 activates on the first event)
+    #[serde(default)]
+    pub activation_delay_ms: u64,
+    /// Maximum seconds a tunnel session may last before forced deactivation (unlimited if unset)
+    #[serde(default)]
+    pub max_session_secs: Option<u64>,
+    /// Seconds after an idle deactivation during which traffic does not re-activate the tunnel
+    #[serde(default)]
+    pub cooldown_secs: u64,
+    /// Seconds a forced deactivation (SIGUSR2 or `down`) holds off traffic-triggered activation
+    #[serde(default = "default_pause_secs")]
+    pub pause_secs: u64,
+    /// Number of recent events kept for `wg-ondemand-ctl history` (0 disables)
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+    /// Append a JSON line per finished tunnel session to this file (disabled if unset)
+    #[serde(default)]
+    pub event_log: Option<PathBuf>,
+    /// Rotate the session log before it grows beyond this many bytes (0: no limit)
+    #[serde(default = "default_event_log_max_bytes")]
+    pub event_log_max_bytes: u64,
+    /// Rotated session logs to keep (`<event_log>.1` is the newest)
+    #[serde(default = "default_event_log_keep")]
+    pub event_log_keep: usize,
+    /// Rotate the session log once its first session is this many days old, and
+    /// delete rotated logs not written for as long (0: no age limit)
+    #[serde(default)]
+    pub event_log_max_days: u64,
+    /// Activations within `flap_window_secs` above which the tunnel is considered flapping
+    /// (0 disables flap detection)
+    #[serde(default)]
+    pub flap_threshold: u32,
+    /// Sliding window in seconds for counting activations
+    #[serde(default = "default_flap_window_secs")]
+    pub flap_window_secs: u64,
+    /// Initial backoff in seconds when flapping is detected, doubled on each repeat
+    #[serde(default = "default_flap_backoff_secs")]
+    pub flap_backoff_secs: u64,
+    /// Number of times a failed tunnel bring-up is retried before returning to monitoring
+    #[serde(default = "default_activation_retries")]
+    pub activation_retries: u32,
+    /// Initial delay in seconds between bring-up retries, doubled on each attempt
+    #[serde(default = "default_activation_retry_secs")]
+    pub activation_retry_secs: u64,
+    /// Seconds to wait for a peer handshake after bring-up before treating activation
+    /// as failed (0 skips handshake verification)
+    #[serde(default)]
+    pub handshake_timeout_secs: u64,
+    /// Restart an in-use tunnel whose latest handshake is older than this many seconds
+    /// (0 disables the health check)
+    #[serde(default)]
+    pub handshake_stale_secs: u64,
+    /// Seconds after which hung external commands (nmcli, wg-quick, ip) are killed
+    #[serde(default = "default_command_timeout_secs")]
+    pub command_timeout_secs: u64,
+    /// Rewrite the peer's AllowedIPs and routes to exactly the target subnets on
+    /// activation, so a full-tunnel config only carries split traffic
+    #[serde(default)]
+    pub narrow_allowed_ips: bool,
+    /// Persistent keepalive applied to all peers on activation (0 clears keepalives
+    /// so they don't count as activity; unset leaves the configured value)
+    #[serde(default)]
+    pub persistent_keepalive_secs: Option<u16>,
+    /// MTU set on the tunnel interface after bring-up (unset keeps the backend's value)
+    #[serde(default)]
+    pub mtu: Option<u32>,
+    /// Peers (base64 public keys) whose traffic counts toward idle detection
+    /// (empty counts all peers)
+    #[serde(default)]
+    pub activity_peers: Vec<String>,
+    /// Ignore transfers no larger than the keepalive traffic of a check interval
+    #[serde(default = "default_ignore_keepalives")]
+    pub ignore_keepalives: bool,
+    /// Bytes (rx + tx) a check interval must transfer to count as activity
+    #[serde(default)]
+    pub activity_threshold_bytes: u64,
+    /// Seconds between tunnel health checks; also the assumed interval of the first idle check
+    #[serde(default = "default_idle_check_interval_secs")]
+    pub idle_check_interval_secs: u64,
+    /// Milliseconds between polls of the eBPF traffic event ring buffer
+    #[serde(default = "default_ebpf_poll_interval_ms")]
+    pub ebpf_poll_interval_ms: u64,
+    /// Idle detection heuristic: "bytes" (transfer counters), "handshake"
+    /// (latest handshake timestamp) or "ebpf" (counters on the interface)
+    #[serde(default)]
+    pub idle_detection: IdleDetection,
+    /// Traffic detection backend: "auto", "ebpf" or "sockets"
+    #[serde(default)]
+    pub traffic_detection: TrafficDetection,
+    /// Apply the idle timeout to tunnels brought up outside the daemon
+    #[serde(default)]
+    pub manual_idle_timeout: bool,
+    /// Detect traffic and run the state machine, but only log the route and tunnel
+    /// changes it would make
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Block plaintext traffic while on a monitored network and the tunnel is not
+    /// carrying it: "off", "subnets" (traffic to the target subnets) or "all"
+    #[serde(default)]
+    pub kill_switch: KillSwitchMode,
+    /// Hold traffic to the target subnets in an NFQUEUE while the tunnel comes up
+    /// and release it through the tunnel, instead of letting it leave in plaintext
+    #[serde(default)]
+    pub hold_traffic: bool,
+    /// Network detection backend: "networkmanager" or "connman"
+    #[serde(default)]
+    pub network_backend: NetworkBackend,
+    /// Defer monitoring until NetworkManager reports full internet connectivity
+    /// (ConnMan: the service is online), so a captive portal is cleared before
+    /// the tunnel is brought up
+    #[serde(default = "default_captive_portal_check")]
+    pub captive_portal_check: bool,
+    /// Longest wait (seconds) for NetworkManager to finish activating a monitored
+    /// connection (DHCP, DNS) before monitoring starts anyway
+    #[serde(default = "default_connection_wait_secs")]
+    pub connection_wait_secs: u64,
+    /// Hosts inside the target subnets pinged before monitoring starts; if one
+    /// answers without the tunnel, the subnets are reachable locally and
+    /// monitoring is skipped
+    #[serde(default)]
+    pub local_hosts: Vec<String>,
+    /// Host inside the target subnets pinged before each activation; if it
+    /// answers without the tunnel, activation is skipped (on the home LAN)
+    #[serde(default)]
+    pub presence_probe: Option<String>,
+    /// How monitoring routes are selected: "table" (all lookups) or "fwmark"
+    /// (only packets to the subnets, marked by nftables)
+    #[serde(default)]
+    pub monitor_routing: MonitorRouting,
+    /// Metric of the monitoring routes (kernel default 0 if unset)
+    #[serde(default)]
+    pub route_metric: Option<u32>,
+    /// Monitoring routes used when the interface has no gateway: "none",
+    /// "device" (point-to-point links) or "blackhole" (needs hold_traffic)
+    #[serde(default)]
+    pub gateway_fallback: GatewayFallback,
+    /// Which source addresses may trigger activation
+    #[serde(default)]
+    pub source_filter: SourceFilter,
+    /// Source ranges (CIDR) allowed to trigger with `source_filter = "ranges"`
+    #[serde(default)]
+    pub source_ranges: Vec<String>,
+    /// Priority of the TC filters (lower runs first; kernel-chosen if unset)
+    #[serde(default)]
+    pub tc_priority: Option<u16>,
+    /// Handle of the TC filters at that priority (kernel-chosen if unset)
+    #[serde(default)]
+    pub tc_handle: Option<u32>,
+    /// After startup, drop all capabilities except CAP_NET_ADMIN and those the
+    /// configured features need (CAP_BPF for the eBPF maps, CAP_NET_RAW for pings)
+    #[serde(default)]
+    pub drop_capabilities: bool,
+    /// After startup, switch to this user, keeping only the capabilities above
+    /// (implies `drop_capabilities`; needs the `[native]` tunnel backend)
+    #[serde(default)]
+    pub user: Option<String>,
+    /// After startup, restrict the filesystem access of the daemon and the
+    /// commands it runs with Landlock (where the kernel supports it)
+    #[serde(default)]
+    pub landlock: bool,
+    /// After startup, restrict the daemon and the commands it runs to the
+    /// syscalls they need with a seccomp filter
+    #[serde(default)]
+    pub seccomp: bool,
     /// Log level (trace, debug, info, warn, error)
     #[serde(default = "default_log_level")]
     pub log_level: String,
+    /// Where logs go: "stderr" (the journal under systemd) or "syslog"
+    #[serde(default)]
+    pub log_target: LogTarget,
 }
 
 /// Custom type to handle both single SSID (backward compat) and list of SSIDs
@@ -122,17 +334,368 @@ impl<'de> Deserialize<'de> for SsidList {
 }
 
 /// Subnet configuration
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct SubnetConfig {
     /// Target subnet ranges in CIDR notation (e.g., "192.168.1.0/24")
     pub ranges: Vec<String>,
 }
 
+/// A named tunnel (`[tunnel.NAME]`), overriding the matching `[general]` options
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct TunnelConfig {
+    /// WireGuard interface name
+    pub wg_interface: String,
+    /// NetworkManager connection name (if using NetworkManager instead of wg-quick)
+    #[serde(default)]
+    pub nm_connection: Option<String>,
+    /// Target subnet ranges in CIDR notation
+    pub subnets: Vec<String>,
+    /// Idle timeout in seconds (`general.idle_timeout` if unset)
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+    /// Activation mode (`general.mode` if unset)
+    #[serde(default)]
+    pub mode: Option<TunnelMode>,
+}
+
+/// What the kill switch blocks on monitored networks
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KillSwitchMode {
+    /// Nothing is blocked
+    #[default]
+    Off,
+    /// Traffic to the target subnets is dropped until the tunnel is up
+    Subnets,
+    /// As `Subnets`, and all other traffic not leaving through the tunnel is
+    /// dropped except DHCP, DNS, IPv6 neighbor discovery and the peer endpoints
+    /// (meant for full-tunnel configs)
+    All,
+}
+
+/// Which traffic the monitoring routing table applies to
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MonitorRouting {
+    /// A policy rule sends every lookup through the monitoring table first
+    #[default]
+    Table,
+    /// Only packets marked by an nftables rule on their way to the subnets use it
+    Fwmark,
+}
+
+/// What the monitoring routes point at when the interface has no gateway
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GatewayFallback {
+    /// No monitoring routes; traffic-triggered activation doesn't work
+    #[default]
+    None,
+    /// Routes straight out of the interface, for point-to-point links
+    Device,
+    /// Blackhole routes; the packets are dropped before reaching eBPF, so only
+    /// the traffic hold sees them
+    Blackhole,
+}
+
+/// Which traffic to the subnets may trigger activation, by source address
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceFilter {
+    /// Any source, including forwarded, bridged and container traffic
+    #[default]
+    Any,
+    /// Only the monitored interfaces' own addresses (the host itself)
+    Local,
+    /// Only `source_ranges`
+    Ranges,
+}
+
+/// How the tunnel gets activated
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TunnelMode {
+    /// Traffic to the target subnets activates the tunnel
+    #[default]
+    OnDemand,
+    /// Only `wg-ondemand-ctl up` or SIGUSR1 activates the tunnel; the idle
+    /// timeout still takes it down
+    Manual,
+}
+
+/// Connectivity probe configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProbeConfig {
+    /// Host inside the target subnets to probe (IPv4 address)
+    pub host: String,
+    /// TCP port to connect to; if unset, the host is pinged (ICMP) instead
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Seconds to wait for the probe to succeed
+    #[serde(default = "default_probe_timeout_secs")]
+    pub timeout_secs: u64,
+    /// After activation, find the largest packet size that gets through to the host
+    /// and lower the tunnel MTU to it
+    #[serde(default)]
+    pub path_mtu: bool,
+}
+
+/// External network fingerprint configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct FingerprintConfig {
+    /// Where to look up the public IPv4 address: `stun:host:port`, or an HTTP(S)
+    /// URL returning the address as plain text
+    #[serde(default = "default_fingerprint_url")]
+    pub url: String,
+    /// Public address ranges (CIDR) of the home network, e.g. the ISP's prefixes
+    #[serde(default)]
+    pub home_ranges: Vec<String>,
+    /// Domains whose subdomains the public address resolves to at home
+    #[serde(default)]
+    pub home_reverse_dns: Vec<String>,
+    /// Seconds to wait for the lookup
+    #[serde(default = "default_probe_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// Docking station at home, reached over a wired interface
+#[derive(Debug, Deserialize, Clone)]
+pub struct DockConfig {
+    /// Wired interface of the dock (e.g. its USB Ethernet adapter)
+    pub interface: String,
+    /// MAC address of the home gateway, as seen in the ARP table
+    #[serde(default)]
+    pub gateway_mac: Option<String>,
+    /// Subnet (CIDR) the interface's address must be in
+    #[serde(default)]
+    pub subnet: Option<String>,
+}
+
+/// Peer endpoint failover configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct EndpointConfig {
+    /// Public key (base64) of the peer whose endpoint is managed.
+    /// May be omitted when the tunnel has a single peer.
+    #[serde(default)]
+    pub peer: Option<String>,
+    /// Candidate endpoints as `host:port`, primary first
+    pub addresses: Vec<String>,
+    /// How to choose the endpoint for each activation
+    #[serde(default)]
+    pub selection: EndpointSelection,
+}
+
+/// How tunnel activity is detected for the idle timeout
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleDetection {
+    /// Any change in the transfer counters (subject to the keepalive filter and
+    /// activity threshold) counts as activity
+    #[default]
+    Bytes,
+    /// A handshake renewal counts as activity; WireGuard only renews handshakes
+    /// (every 2 minutes) while traffic flows
+    Handshake,
+    /// eBPF byte counters attached to the WireGuard interface while the tunnel is
+    /// active; keepalives never pass through the interface, so they aren't counted
+    Ebpf,
+}
+
+/// Where the daemon logs
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    /// Standard error, collected by journald under systemd
+    #[default]
+    Stderr,
+    /// The system logger via /dev/log, as `wg-ondemand[pid]` with the daemon facility
+    Syslog,
+}
+
+/// Which network manager is asked for the current network
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkBackend {
+    /// NetworkManager over D-Bus
+    #[default]
+    NetworkManager,
+    /// ConnMan over D-Bus (embedded and automotive systems)
+    Connman,
+}
+
+/// How traffic to the target subnets is detected
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrafficDetection {
+    /// eBPF, falling back to socket table scanning if it can't be loaded
+    #[default]
+    Auto,
+    /// TC eBPF classifier on the monitored interfaces
+    Ebpf,
+    /// Periodic scan of `/proc/net/tcp` and `/proc/net/udp` (host traffic only,
+    /// no kill switch or eBPF idle detection)
+    Sockets,
+}
+
+/// Endpoint selection strategy
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EndpointSelection {
+    /// Start with the primary endpoint and move down the list on failure
+    #[default]
+    Failover,
+    /// Start with the reachable endpoint with the lowest ICMP round-trip time
+    Latency,
+}
+
+/// Interface definition for the native (netlink) tunnel backend
+///
+/// Either `config_file` or the inline `private_key_file`/`addresses`/`peers`
+/// definition must be given.
+#[derive(Debug, Deserialize, Clone)]
+pub struct NativeTunnelConfig {
+    /// wg-quick config file to read the interface and peers from
+    #[serde(default)]
+    pub config_file: Option<PathBuf>,
+    /// File containing the interface private key (base64)
+    #[serde(default)]
+    pub private_key_file: Option<PathBuf>,
+    /// Interface addresses in CIDR notation
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    /// UDP listen port (random if unset)
+    #[serde(default)]
+    pub listen_port: Option<u16>,
+    /// Interface MTU (kernel default if unset)
+    #[serde(default)]
+    pub mtu: Option<u32>,
+    /// Peers of the interface
+    #[serde(default)]
+    pub peers: Vec<NativePeerConfig>,
+}
+
+/// Peer definition for the native tunnel backend
+#[derive(Debug, Deserialize, Clone)]
+pub struct NativePeerConfig {
+    /// Peer public key (base64)
+    pub public_key: String,
+    /// File containing the preshared key (base64), if any
+    #[serde(default)]
+    pub preshared_key_file: Option<PathBuf>,
+    /// Peer endpoint as `host:port` (resolved on every bring-up)
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Networks routed to this peer in CIDR notation
+    pub allowed_ips: Vec<String>,
+    /// Persistent keepalive interval in seconds
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
+}
+
+/// OpenTelemetry export to a collector over OTLP/HTTP
+#[derive(Debug, Deserialize, Clone)]
+pub struct OtelConfig {
+    /// Base URL of the collector; `/v1/traces` and `/v1/metrics` are appended.
+    /// Plain `http://` only, the exporter is built without TLS.
+    #[serde(default = "default_otel_endpoint")]
+    pub endpoint: String,
+    /// `service.name` of the exported resource
+    #[serde(default = "default_otel_service_name")]
+    pub service_name: String,
+    /// How often metrics are exported
+    #[serde(default = "default_otel_metrics_interval_secs")]
+    pub metrics_interval_secs: u64,
+}
+
 // Default values for configuration
 fn default_idle_timeout() -> u64 {
     300 // 5 minutes
 }
 
+fn default_idle_warning_secs() -> u64 {
+    60 // 1 minute
+}
+
+fn default_flap_window_secs() -> u64 {
+    600 // 10 minutes
+}
+
+fn default_flap_backoff_secs() -> u64 {
+    60 // 1 minute
+}
+
+fn default_activation_retries() -> u32 {
+    3
+}
+
+fn default_activation_retry_secs() -> u64 {
+    2
+}
+
+fn default_command_timeout_secs() -> u64 {
+    30
+}
+
+/// Long enough to finish what the tunnel was taken down for
+fn default_pause_secs() -> u64 {
+    900
+}
+
+fn default_history_size() -> usize {
+    crate::history::DEFAULT_HISTORY_SIZE
+}
+
+/// Frequent enough to detect idle timeouts accurately
+fn default_idle_check_interval_secs() -> u64 {
+    60
+}
+
+/// 1 second balances responsiveness with battery efficiency
+/// This reduces CPU wakeups from 864K/day to 86K/day
+fn default_ebpf_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_ignore_keepalives() -> bool {
+    true
+}
+
+fn default_captive_portal_check() -> bool {
+    true
+}
+
+fn default_event_log_max_bytes() -> u64 {
+    1024 * 1024
+}
+
+fn default_event_log_keep() -> usize {
+    5
+}
+
+fn default_connection_wait_secs() -> u64 {
+    30
+}
+
+fn default_fingerprint_url() -> String {
+    "stun:stun.l.google.com:19302".to_string()
+}
+
+fn default_probe_timeout_secs() -> u64 {
+    5
+}
+
+fn default_otel_endpoint() -> String {
+    "http://localhost:4318".to_string()
+}
+
+fn default_otel_service_name() -> String {
+    "wg-ondemand".to_string()
+}
+
+fn default_otel_metrics_interval_secs() -> u64 {
+    60
+}
+
 fn default_log_level() -> String {
     "info".to_string()
 }
@@ -172,6 +735,24 @@ mod tests {
         assert_eq!(mem::size_of::<u8>(), 1); // _padding
     }
 
+    #[test]
+    fn test_traffic_event_destination() {
+        let event = TrafficEvent {
+            timestamp: 0,
+            dest_ip: 0xC0A80105,
+            dest_port: 22,
+            protocol: 6,
+            _padding: 0,
+        };
+        assert_eq!(event.destination(), "192.168.1.5:22/tcp");
+        let other = TrafficEvent {
+            protocol: 47,
+            dest_port: 0,
+            ..event
+        };
+        assert_eq!(other.destination(), "192.168.1.5:0/47");
+    }
+
     #[test]
     fn test_traffic_event_copy_clone() {
         let event = TrafficEvent {
@@ -6,10 +6,184 @@
 //! (bringing up/down), querying tunnel statistics, and tracking activity
 //! for idle timeout detection.
 
+use crate::endpoint::{self, EndpointList, HostnameEndpoint};
+use crate::error::TunnelError;
+use crate::native_tunnel::{self, NativeTunnel};
+use crate::process::{self, DEFAULT_COMMAND_TIMEOUT};
+use crate::rtnl;
+use crate::types::{EndpointConfig, EndpointSelection, IdleDetection, TrafficTotals};
 use anyhow::{Context, Result};
-use std::time::Instant;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::process::Command;
-use wireguard_control::{Backend, Device, InterfaceName};
+use wireguard_control::{Backend, Device, DeviceUpdate, InterfaceName, PeerConfigBuilder};
+
+/// How long to wait for each endpoint's ping when selecting by latency
+const ENDPOINT_RTT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Size of a WireGuard keepalive (a transport message with an empty payload)
+const KEEPALIVE_BYTES: u64 = 32;
+
+/// Size of the largest handshake message (initiation), renewed while keepalives flow
+const HANDSHAKE_BYTES: u64 = 148;
+
+/// How often to poll the device while waiting for a handshake
+const HANDSHAKE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Age after which WireGuard drops a session (`REJECT_AFTER_TIME`); sessions are
+/// renewed every two minutes, so a working peer's handshake is never older
+pub const FRESH_HANDSHAKE_AGE: Duration = Duration::from_secs(180);
+
+/// Check whether a handshake is older than `threshold` (or never happened)
+pub fn handshake_stale(age: Option<Duration>, threshold: Duration) -> bool {
+    !matches!(age, Some(age) if age <= threshold)
+}
+
+/// Upper bound on the bytes a peer with persistent keepalive `keepalive_secs`
+/// transfers per `check_interval` when it carries no real traffic
+///
+/// Covers the keepalives sent in the interval plus one handshake renewal. Peers
+/// without persistent keepalive have no floor.
+pub fn keepalive_floor(check_interval: Duration, keepalive_secs: Option<u16>) -> u64 {
+    match keepalive_secs {
+        Some(keepalive) if keepalive > 0 => {
+            let keepalives = check_interval.as_secs().div_ceil(u64::from(keepalive));
+            keepalives * KEEPALIVE_BYTES + HANDSHAKE_BYTES
+        }
+        _ => 0,
+    }
+}
+
+/// Transfer counters of a single peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PeerTransfer {
+    public_key: String,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    persistent_keepalive: Option<u16>,
+    last_handshake: Option<SystemTime>,
+}
+
+/// Status of a WireGuard peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// Public key (base64)
+    pub public_key: String,
+    /// Endpoint currently in use, if known
+    pub endpoint: Option<SocketAddr>,
+    /// Most recent handshake, None if none has completed yet
+    pub last_handshake: Option<SystemTime>,
+    /// Bytes received from the peer
+    pub rx_bytes: u64,
+    /// Bytes sent to the peer
+    pub tx_bytes: u64,
+    /// Allowed IPs (CIDR)
+    pub allowed_ips: Vec<String>,
+}
+
+impl PeerInfo {
+    /// Time since the last handshake as of `now`
+    pub fn handshake_age(&self, now: SystemTime) -> Option<Duration> {
+        self.last_handshake
+            .map(|t| now.duration_since(t).unwrap_or_default())
+    }
+}
+
+/// Format peers for `wg-ondemand-ctl peers`, one block per peer
+pub fn format_peers(peers: &[PeerInfo], now: SystemTime) -> String {
+    if peers.is_empty() {
+        return "No peers configured".to_string();
+    }
+    peers
+        .iter()
+        .map(|peer| {
+            let endpoint = peer
+                .endpoint
+                .map_or_else(|| "none".to_string(), |e| e.to_string());
+            let handshake = match peer.handshake_age(now) {
+                Some(age) => format!(
+                    "{}s ago ({})",
+                    age.as_secs(),
+                    if age <= FRESH_HANDSHAKE_AGE {
+                        "fresh"
+                    } else {
+                        "stale"
+                    }
+                ),
+                None => "never".to_string(),
+            };
+            format!(
+                "peer {}\n  endpoint: {}\n  allowed ips: {}\n  latest handshake: {}\n  \
+                transfer: {} bytes received, {} bytes sent",
+                peer.public_key,
+                endpoint,
+                peer.allowed_ips.join(", "),
+                handshake,
+                peer.rx_bytes,
+                peer.tx_bytes
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Query the status of each peer of a WireGuard interface (or NetworkManager
+/// connection name, see [`WgController::wg_stats_interface`])
+pub async fn query_peers(interface: &str) -> Result<Vec<PeerInfo>, TunnelError> {
+    let device = device_info(interface).await?;
+    Ok(device
+        .peers
+        .into_iter()
+        .map(|peer| PeerInfo {
+            public_key: peer.config.public_key.to_base64(),
+            endpoint: peer.config.endpoint,
+            last_handshake: peer.stats.last_handshake_time,
+            rx_bytes: peer.stats.rx_bytes,
+            tx_bytes: peer.stats.tx_bytes,
+            allowed_ips: peer
+                .config
+                .allowed_ips
+                .iter()
+                .map(|ip| format!("{}/{}", ip.address, ip.cidr))
+                .collect(),
+        })
+        .collect())
+}
+
+/// Query a WireGuard device via netlink
+///
+/// This is 100x faster than spawning the `wg` process (~20µs vs 200µs)
+async fn device_info(iface: &str) -> Result<Device> {
+    // Parse interface name for wireguard-control
+    let iface_name: InterfaceName = iface
+        .parse()
+        .with_context(|| format!("Invalid interface name: {}", iface))?;
+
+    // Use tokio::task::spawn_blocking for sync netlink call
+    tokio::task::spawn_blocking(move || {
+        Device::get(&iface_name, Backend::Kernel).context("Failed to get WireGuard device info")
+    })
+    .await
+    .context("Netlink task panicked")?
+}
+
+/// Bytes transferred since `last`, treating a decrease as a counter restart
+///
+/// Counters restart from zero if the interface was recreated behind our back, in
+/// which case the new value is the delta.
+fn counter_delta(current: u64, last: u64) -> u64 {
+    if current >= last {
+        current - last
+    } else {
+        current
+    }
+}
+
+/// Check whether the latest handshake happened at or after `since`
+fn handshake_completed(latest: Option<SystemTime>, since: SystemTime) -> bool {
+    latest.is_some_and(|t| t >= since)
+}
 
 /// Validates that a name (interface or connection) is safe to use in shell commands.
 /// Only allows alphanumeric characters, hyphens, and underscores to prevent command injection.
@@ -40,8 +214,8 @@ fn validate_name(name: &str, field_name: &str) -> Result<()> {
 ///
 /// Returns an error if the interface name is empty or contains invalid characters.
 /// Only alphanumeric characters, hyphens, and underscores are allowed.
-pub fn validate_interface_name(name: &str) -> Result<()> {
-    validate_name(name, "Interface name")
+pub fn validate_interface_name(name: &str) -> Result<(), TunnelError> {
+    Ok(validate_name(name, "Interface name")?)
 }
 
 /// Controller for managing WireGuard tunnel state
@@ -51,6 +225,24 @@ pub struct WgController {
     last_rx_bytes: u64,
     last_tx_bytes: u64,
     last_activity: Option<Instant>,
+    traffic: TrafficTotals,
+    command_timeout: Duration,
+    endpoints: Option<EndpointList>,
+    endpoint_peer: Option<String>,
+    endpoint_selection: EndpointSelection,
+    endpoint_selected: bool,
+    hostname_endpoints: Vec<HostnameEndpoint>,
+    native: Option<NativeTunnel>,
+    narrow_allowed_ips: Option<Vec<String>>,
+    persistent_keepalive: Option<u16>,
+    mtu: Option<u32>,
+    activity_peers: Option<HashSet<String>>,
+    last_peer_bytes: HashMap<String, (u64, u64)>,
+    keepalive_check_interval: Option<Duration>,
+    last_peer_sample: Option<Instant>,
+    activity_threshold: u64,
+    idle_detection: IdleDetection,
+    last_seen_handshake: Option<SystemTime>,
 }
 
 impl WgController {
@@ -61,7 +253,7 @@ impl WgController {
     /// Returns an error if the interface name or NetworkManager connection name
     /// contains invalid characters. Only alphanumeric characters, hyphens, and
     /// underscores are allowed to prevent command injection.
-    pub fn new(interface: String, nm_connection: Option<String>) -> Result<Self> {
+    pub fn new(interface: String, nm_connection: Option<String>) -> Result<Self, TunnelError> {
         // Validate interface name
         validate_name(&interface, "Interface name")?;
 
@@ -76,16 +268,360 @@ impl WgController {
             last_rx_bytes: 0,
             last_tx_bytes: 0,
             last_activity: None,
+            traffic: TrafficTotals::default(),
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            endpoints: None,
+            endpoint_peer: None,
+            endpoint_selection: EndpointSelection::Failover,
+            endpoint_selected: false,
+            hostname_endpoints: Vec::new(),
+            native: None,
+            narrow_allowed_ips: None,
+            persistent_keepalive: None,
+            mtu: None,
+            activity_peers: None,
+            last_peer_bytes: HashMap::new(),
+            keepalive_check_interval: None,
+            last_peer_sample: None,
+            activity_threshold: 0,
+            idle_detection: IdleDetection::Bytes,
+            last_seen_handshake: None,
         })
     }
 
+    /// Manage the peer endpoint, failing over between the configured addresses
+    pub fn with_endpoints(mut self, config: EndpointConfig) -> Self {
+        self.endpoints = Some(EndpointList::new(config.addresses));
+        self.endpoint_peer = config.peer;
+        self.endpoint_selection = config.selection;
+        self
+    }
+
+    /// Re-resolve these hostname endpoints on every activation (when no failover list is set)
+    pub fn with_hostname_endpoints(mut self, endpoints: Vec<HostnameEndpoint>) -> Self {
+        self.hostname_endpoints = endpoints;
+        self
+    }
+
+    /// Manage the interface natively via netlink instead of wg-quick
+    pub fn with_native(mut self, native: NativeTunnel) -> Self {
+        self.native = Some(native);
+        self
+    }
+
+    /// Restrict the peer's AllowedIPs and the interface routes to `subnets` on activation
+    pub fn with_narrowed_allowed_ips(mut self, subnets: Vec<String>) -> Self {
+        self.narrow_allowed_ips = Some(subnets);
+        self
+    }
+
+    /// Set the persistent keepalive of all peers on activation (0 disables it)
+    pub fn with_persistent_keepalive(mut self, secs: u16) -> Self {
+        self.persistent_keepalive = Some(secs);
+        self
+    }
+
+    /// Only count traffic of these peers (base64 public keys) as activity
+    ///
+    /// Traffic of other peers still shows up in the traffic totals but doesn't
+    /// keep the tunnel from going idle.
+    pub fn with_activity_peers(mut self, peers: Vec<String>) -> Self {
+        self.activity_peers = Some(peers.into_iter().collect());
+        self
+    }
+
+    /// Don't count keepalive-only traffic as activity
+    ///
+    /// Per-peer deltas up to the keepalive traffic expected since the previous
+    /// [`check_activity`](Self::check_activity) call are ignored; `check_interval`
+    /// is assumed for the first call.
+    pub fn with_keepalive_filter(mut self, check_interval: Duration) -> Self {
+        self.keepalive_check_interval = Some(check_interval);
+        self
+    }
+
+    /// Only count checks that transferred more than `bytes` (rx + tx, beyond
+    /// keepalive traffic) as activity
+    pub fn with_activity_threshold(mut self, bytes: u64) -> Self {
+        self.activity_threshold = bytes;
+        self
+    }
+
+    /// Choose how activity is detected for the idle timeout
+    pub fn with_idle_detection(mut self, idle_detection: IdleDetection) -> Self {
+        self.idle_detection = idle_detection;
+        self
+    }
+
+    /// Set this MTU on the interface after bring-up
+    pub fn with_mtu(mut self, mtu: u32) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Apply the configured MTU, if any
+    pub async fn apply_mtu(&self) -> Result<(), TunnelError> {
+        match self.mtu {
+            Some(mtu) => self.set_mtu(mtu).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Current MTU of the interface
+    pub fn current_mtu(&self) -> Result<u32, TunnelError> {
+        let path = format!("/sys/class/net/{}/mtu", self.interface);
+        std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path))?
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid MTU in {}", path))
+            .map_err(Into::into)
+    }
+
+    /// Change the MTU of the interface
+    pub async fn set_mtu(&self, mtu: u32) -> Result<(), TunnelError> {
+        let output = self
+            .run(
+                "ip",
+                [
+                    "link",
+                    "set",
+                    "dev",
+                    &self.interface,
+                    "mtu",
+                    &mtu.to_string(),
+                ],
+            )
+            .await?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to set MTU {} on {}: {}",
+                mtu,
+                self.interface,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        tracing::info!("MTU of {} set to {}", self.interface, mtu);
+        Ok(())
+    }
+
+    /// Apply the configured persistent keepalive interval to every peer
+    ///
+    /// Keepalives keep NAT mappings open on WiFi networks but also generate
+    /// traffic that the idle tracker counts as activity, so clearing them (0)
+    /// keeps the idle timeout effective.
+    pub async fn apply_keepalive(&self) -> Result<(), TunnelError> {
+        let Some(secs) = self.persistent_keepalive else {
+            return Ok(());
+        };
+
+        let device = self.get_device().await?;
+        let mut update = DeviceUpdate::new();
+        for peer in &device.peers {
+            update = update.add_peer(
+                PeerConfigBuilder::new(&peer.config.public_key)
+                    .set_persistent_keepalive_interval(secs),
+            );
+        }
+        let iface_name = device.name;
+        tokio::task::spawn_blocking(move || update.apply(&iface_name, Backend::Kernel))
+            .await
+            .context("Netlink task panicked")?
+            .context("Failed to set persistent keepalive")?;
+
+        match secs {
+            0 => tracing::info!("Persistent keepalive disabled"),
+            secs => tracing::info!("Persistent keepalive set to {}s", secs),
+        }
+        Ok(())
+    }
+
+    /// Rewrite the peer's AllowedIPs to exactly the configured subnets
+    ///
+    /// Routes added for the original AllowedIPs (including a wg-quick default route
+    /// in its own table) are flushed and replaced by routes for the subnets, so a
+    /// full-tunnel config only carries split traffic. Kernel routes for the
+    /// interface addresses are kept.
+    pub async fn narrow_allowed_ips(&self) -> Result<(), TunnelError> {
+        let Some(subnets) = &self.narrow_allowed_ips else {
+            return Ok(());
+        };
+
+        let allowed = subnets
+            .iter()
+            .map(|cidr| native_tunnel::parse_allowed_ip(cidr))
+            .collect::<Result<Vec<_>>>()?;
+        endpoint::update_peer(
+            &self.interface,
+            self.endpoint_peer.as_deref(),
+            move |builder| {
+                allowed
+                    .iter()
+                    .fold(builder.replace_allowed_ips(), |builder, ip| {
+                        builder.add_allowed_ip(ip.address, ip.cidr)
+                    })
+            },
+        )
+        .await
+        .context("Failed to narrow AllowedIPs")?;
+
+        for family in ["-4", "-6"] {
+            let output = self
+                .run(
+                    "ip",
+                    [
+                        family,
+                        "route",
+                        "flush",
+                        "dev",
+                        &self.interface,
+                        "table",
+                        "all",
+                        "proto",
+                        "boot",
+                    ],
+                )
+                .await?;
+            if !output.status.success() {
+                tracing::debug!(
+                    "ip {} route flush failed: {}",
+                    family,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+        }
+        for subnet in subnets {
+            let output = self
+                .run("ip", ["route", "replace", subnet, "dev", &self.interface])
+                .await?;
+            if !output.status.success() {
+                return Err(anyhow::anyhow!(
+                    "Failed to route {} via {}: {}",
+                    subnet,
+                    self.interface,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+                .into());
+            }
+        }
+
+        tracing::info!("AllowedIPs narrowed to {}", subnets.join(", "));
+        Ok(())
+    }
+
+    /// Point the peer at the endpoint currently selected for failover, resolving
+    /// hostnames afresh so dynamic DNS changes are picked up
+    ///
+    /// With latency-based selection, the first attempt of an activation starts from
+    /// the endpoint with the lowest round-trip time; failover continues from there.
+    /// Without a failover list, hostname endpoints from the wg-quick config are
+    /// re-resolved instead.
+    pub async fn apply_endpoint(&mut self) -> Result<(), TunnelError> {
+        let Some(endpoints) = self.endpoints.as_mut() else {
+            self.refresh_hostname_endpoints().await;
+            return Ok(());
+        };
+
+        if self.endpoint_selection == EndpointSelection::Latency
+            && !self.endpoint_selected
+            && endpoints.has_alternates()
+        {
+            match endpoint::fastest(endpoints.addresses(), ENDPOINT_RTT_TIMEOUT).await {
+                Some((index, rtt)) => {
+                    endpoints.select(index);
+                    tracing::info!(
+                        "Selected endpoint {} (RTT {}ms)",
+                        endpoints.current(),
+                        rtt.as_millis()
+                    );
+                }
+                None => tracing::warn!("No endpoint answered ping, using {}", endpoints.current()),
+            }
+        }
+        self.endpoint_selected = true;
+
+        let endpoints = self.endpoints.as_ref().expect("checked above");
+
+        let address = endpoints.current();
+        let resolved = endpoint::resolve(address).await?;
+        endpoint::set_peer_endpoint(
+            self.wg_stats_interface(),
+            self.endpoint_peer.as_deref(),
+            resolved,
+        )
+        .await?;
+        tracing::info!("Peer endpoint set to {} ({})", address, resolved);
+        Ok(())
+    }
+
+    /// Re-resolve hostname endpoints and update peers whose address changed
+    ///
+    /// Failures are only logged: the interface still has the address resolved at bring-up.
+    async fn refresh_hostname_endpoints(&self) {
+        for peer in &self.hostname_endpoints {
+            let resolved = match endpoint::resolve(&peer.address).await {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    tracing::warn!("{:#}", e);
+                    continue;
+                }
+            };
+            match endpoint::set_peer_endpoint(
+                self.wg_stats_interface(),
+                Some(&peer.public_key),
+                resolved,
+            )
+            .await
+            {
+                Ok(()) => tracing::debug!("Endpoint {} resolved to {}", peer.address, resolved),
+                Err(e) => tracing::warn!("Failed to update endpoint {}: {:#}", peer.address, e),
+            }
+        }
+    }
+
+    /// Fail over to the next configured endpoint after the current one didn't work
+    pub fn endpoint_failed(&mut self) {
+        if let Some(endpoints) = self.endpoints.as_mut().filter(|e| e.has_alternates()) {
+            let failed = endpoints.current().to_string();
+            endpoints.advance();
+            tracing::warn!(
+                "Endpoint {} failed, switching to {}",
+                failed,
+                endpoints.current()
+            );
+        }
+    }
+
+    /// Start the next activation from the primary endpoint again
+    pub fn reset_endpoint(&mut self) {
+        if let Some(endpoints) = self.endpoints.as_mut() {
+            endpoints.reset();
+        }
+        self.endpoint_selected = false;
+    }
+
+    /// Set the timeout after which hung nmcli/wg-quick/ip invocations are killed
+    pub fn with_command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
+
+    /// Run an external command with the configured timeout
+    async fn run<const N: usize>(
+        &self,
+        program: &str,
+        args: [&str; N],
+    ) -> Result<std::process::Output> {
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+        process::run(cmd, self.command_timeout).await
+    }
+
     /// Check if the WireGuard interface is currently up
     pub async fn is_up(&self) -> bool {
         // Check if interface exists using `ip link show`
-        let output = Command::new("ip")
-            .args(["link", "show", &self.interface])
-            .output()
-            .await;
+        let output = self.run("ip", ["link", "show", &self.interface]).await;
 
         match output {
             Ok(output) => output.status.success(),
@@ -93,80 +629,83 @@ impl WgController {
         }
     }
 
-    /// Bring up the WireGuard interface using NetworkManager or wg-quick
-    pub async fn bring_up(&self) -> Result<()> {
+    /// Check whether the interface exists and is administratively up
+    ///
+    /// Reads sysfs rather than spawning `ip`, so it is cheap enough to poll.
+    pub fn link_up(&self) -> bool {
+        std::fs::read_to_string(format!("/sys/class/net/{}/flags", self.interface))
+            .ok()
+            .and_then(|flags| u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok())
+            .is_some_and(|flags| flags & libc::IFF_UP as u32 != 0)
+    }
+
+    /// Bring up the WireGuard interface using NetworkManager, netlink or wg-quick
+    #[tracing::instrument(name = "wg_up", skip_all, fields(interface = %self.interface))]
+    pub async fn bring_up(&self) -> Result<(), TunnelError> {
         if let Some(nm_conn) = &self.nm_connection {
-            log::info!("Bringing up NetworkManager connection: {}", nm_conn);
+            tracing::info!("Bringing up NetworkManager connection: {}", nm_conn);
 
-            let output = Command::new("nmcli")
-                .args(["connection", "up", nm_conn])
-                .output()
-                .await
-                .context("Failed to execute nmcli connection up")?;
+            let output = self.run("nmcli", ["connection", "up", nm_conn]).await?;
 
             if !output.status.success() {
                 let stderr = String::from_utf8_lossy(&output.stderr);
-                anyhow::bail!("nmcli connection up failed: {}", stderr);
+                return Err(anyhow::anyhow!("nmcli connection up failed: {}", stderr).into());
             }
 
-            log::info!("NetworkManager connection {} is up", nm_conn);
+            tracing::info!("NetworkManager connection {} is up", nm_conn);
+        } else if let Some(native) = &self.native {
+            tracing::info!("Creating WireGuard interface: {}", self.interface);
+            native.up().await?;
+            tracing::info!("WireGuard interface {} is up", self.interface);
         } else {
-            log::info!("Bringing up WireGuard interface: {}", self.interface);
+            tracing::info!("Bringing up WireGuard interface: {}", self.interface);
 
-            let output = Command::new("wg-quick")
-                .args(["up", &self.interface])
-                .output()
-                .await
-                .context("Failed to execute wg-quick up")?;
+            let output = self.run("wg-quick", ["up", &self.interface]).await?;
 
             if !output.status.success() {
                 let stderr = String::from_utf8_lossy(&output.stderr);
-                anyhow::bail!("wg-quick up failed: {}", stderr);
+                return Err(anyhow::anyhow!("wg-quick up failed: {}", stderr).into());
             }
 
-            log::info!("WireGuard interface {} is up", self.interface);
+            tracing::info!("WireGuard interface {} is up", self.interface);
         }
         Ok(())
     }
 
-    /// Bring down the WireGuard interface using NetworkManager or wg-quick
-    pub async fn bring_down(&self) -> Result<()> {
+    /// Bring down the WireGuard interface using NetworkManager, netlink or wg-quick
+    pub async fn bring_down(&self) -> Result<(), TunnelError> {
         if let Some(nm_conn) = &self.nm_connection {
-            log::info!("Bringing down NetworkManager connection: {}", nm_conn);
+            tracing::info!("Bringing down NetworkManager connection: {}", nm_conn);
 
-            let output = Command::new("nmcli")
-                .args(["connection", "down", nm_conn])
-                .output()
-                .await
-                .context("Failed to execute nmcli connection down")?;
+            let output = self.run("nmcli", ["connection", "down", nm_conn]).await?;
 
             if !output.status.success() {
                 let stderr = String::from_utf8_lossy(&output.stderr);
                 // Don't fail if connection is already down
                 if !stderr.contains("not an active connection") {
-                    log::warn!("nmcli connection down warning: {}", stderr);
+                    tracing::warn!("nmcli connection down warning: {}", stderr);
                 }
             }
 
-            log::info!("NetworkManager connection {} is down", nm_conn);
+            tracing::info!("NetworkManager connection {} is down", nm_conn);
+        } else if let Some(native) = &self.native {
+            tracing::info!("Removing WireGuard interface: {}", self.interface);
+            native.down().await?;
+            tracing::info!("WireGuard interface {} is down", self.interface);
         } else {
-            log::info!("Bringing down WireGuard interface: {}", self.interface);
+            tracing::info!("Bringing down WireGuard interface: {}", self.interface);
 
-            let output = Command::new("wg-quick")
-                .args(["down", &self.interface])
-                .output()
-                .await
-                .context("Failed to execute wg-quick down")?;
+            let output = self.run("wg-quick", ["down", &self.interface]).await?;
 
             if !output.status.success() {
                 let stderr = String::from_utf8_lossy(&output.stderr);
                 // Don't fail if interface is already down
                 if !stderr.contains("is not a WireGuard interface") {
-                    anyhow::bail!("wg-quick down failed: {}", stderr);
+                    return Err(anyhow::anyhow!("wg-quick down failed: {}", stderr).into());
                 }
             }
 
-            log::info!("WireGuard interface {} is down", self.interface);
+            tracing::info!("WireGuard interface {} is down", self.interface);
         }
         Ok(())
     }
@@ -181,7 +720,7 @@ impl WgController {
     /// When using NetworkManager, this returns the NetworkManager connection name
     /// instead of the actual interface name, because `wg show <name>` works with
     /// NetworkManager connection names.
-    fn wg_stats_interface(&self) -> &str {
+    pub fn wg_stats_interface(&self) -> &str {
         self.nm_connection.as_deref().unwrap_or(&self.interface)
     }
 
@@ -206,60 +745,277 @@ impl WgController {
         (total_rx, total_tx)
     }
 
-    /// Get current transfer statistics from WireGuard using netlink API
-    /// Returns (rx_bytes, tx_bytes) summed across all peers
-    ///
-    /// This is 100x faster than spawning the `wg` process (~20µs vs 200µs)
-    async fn get_transfer_stats(&self) -> Result<(u64, u64)> {
-        let iface = self.wg_stats_interface();
+    /// Index and firewall mark (0 if none) of the WireGuard interface, which tell
+    /// packets already going through the tunnel apart
+    pub async fn tunnel_identity(&self) -> Result<(u32, u32), TunnelError> {
+        let index = rtnl::ifindex(&self.interface)?;
+        let fwmark = self.get_device().await?.fwmark.unwrap_or(0);
+        Ok((index, fwmark))
+    }
 
-        // Parse interface name for wireguard-control
-        let iface_name: InterfaceName = iface
-            .parse()
-            .with_context(|| format!("Invalid interface name: {}", iface))?;
+    /// Endpoints the peers currently use, as resolved by WireGuard
+    pub async fn peer_endpoints(&self) -> Result<Vec<SocketAddr>, TunnelError> {
+        let device = self.get_device().await?;
+        Ok(device
+            .peers
+            .iter()
+            .filter_map(|peer| peer.config.endpoint)
+            .collect())
+    }
 
-        // Use tokio::task::spawn_blocking for sync netlink call
-        let (total_rx, total_tx) = tokio::task::spawn_blocking(move || {
-            let device = Device::get(&iface_name, Backend::Kernel)
-                .context("Failed to get WireGuard device info")?;
+    /// Query the WireGuard device via netlink
+    async fn get_device(&self) -> Result<Device> {
+        device_info(self.wg_stats_interface()).await
+    }
 
-            let mut total_rx = 0u64;
-            let mut total_tx = 0u64;
+    /// Status of each peer, as reported by WireGuard
+    pub async fn peers(&self) -> Result<Vec<PeerInfo>, TunnelError> {
+        query_peers(self.wg_stats_interface()).await
+    }
 
-            for peer in device.peers {
-                total_rx += peer.stats.rx_bytes;
-                total_tx += peer.stats.tx_bytes;
-            }
+    /// Get current per-peer transfer statistics from WireGuard using netlink API
+    async fn get_peer_transfer_stats(&self) -> Result<Vec<PeerTransfer>> {
+        let device = self.get_device().await?;
+
+        Ok(device
+            .peers
+            .into_iter()
+            .map(|peer| PeerTransfer {
+                public_key: peer.config.public_key.to_base64(),
+                rx_bytes: peer.stats.rx_bytes,
+                tx_bytes: peer.stats.tx_bytes,
+                persistent_keepalive: peer.config.persistent_keepalive_interval,
+                last_handshake: peer.stats.last_handshake_time,
+            })
+            .collect())
+    }
 
-            Ok::<(u64, u64), anyhow::Error>((total_rx, total_tx))
-        })
-        .await
-        .context("Netlink task panicked")??;
+    /// Get the most recent handshake time across all peers
+    /// Returns None if no peer has completed a handshake yet
+    pub async fn latest_handshake(&self) -> Result<Option<SystemTime>, TunnelError> {
+        let device = self.get_device().await?;
+        Ok(device
+            .peers
+            .iter()
+            .filter_map(|peer| peer.stats.last_handshake_time)
+            .max())
+    }
 
-        Ok((total_rx, total_tx))
+    /// Get time since the most recent handshake across all peers
+    /// Returns None if no peer has completed a handshake yet
+    pub async fn handshake_age(&self) -> Result<Option<Duration>, TunnelError> {
+        Ok(self
+            .latest_handshake()
+            .await?
+            .map(|t| t.elapsed().unwrap_or_default()))
+    }
+
+    /// Wait until a peer completes a handshake at or after `since`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no handshake is observed within `timeout`, e.g. because
+    /// the endpoint is unreachable.
+    #[tracing::instrument(name = "handshake", skip_all, fields(interface = %self.interface))]
+    pub async fn wait_for_handshake(
+        &self,
+        since: SystemTime,
+        timeout: Duration,
+    ) -> Result<(), TunnelError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match self.latest_handshake().await {
+                Ok(latest) if handshake_completed(latest, since) => {
+                    tracing::info!("WireGuard handshake with peer completed");
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(e) => tracing::debug!("Failed to query handshake state: {}", e),
+            }
+
+            if Instant::now() >= deadline {
+                return Err(anyhow::anyhow!(
+                    "No WireGuard handshake within {}s (endpoint unreachable?)",
+                    timeout.as_secs()
+                )
+                .into());
+            }
+            tokio::time::sleep(HANDSHAKE_POLL_INTERVAL).await;
+        }
     }
 
     /// Check for tunnel activity and update internal state
     /// Returns true if there has been activity since last check
-    pub async fn check_activity(&mut self) -> Result<bool> {
-        let (rx, tx) = self.get_transfer_stats().await?;
+    pub async fn check_activity(&mut self) -> Result<bool, TunnelError> {
+        let peers = self.get_peer_transfer_stats().await?;
+        let (rx, tx) = peers.iter().fold((0, 0), |(rx, tx), peer| {
+            (rx + peer.rx_bytes, tx + peer.tx_bytes)
+        });
+
+        if self.idle_detection == IdleDetection::Handshake {
+            self.record_traffic(rx, tx);
+            return Ok(self.update_handshake(&peers));
+        }
+        if self.activity_peers.is_none()
+            && self.keepalive_check_interval.is_none()
+            && self.activity_threshold == 0
+        {
+            return Ok(self.update_counters(rx, tx));
+        }
+        self.record_traffic(rx, tx);
+        Ok(self.update_peer_counters(&peers))
+    }
+
+    /// Record new transfer counter values, accumulating deltas into traffic totals
+    /// Returns true if the counters changed since the last update
+    fn update_counters(&mut self, rx: u64, tx: u64) -> bool {
+        let has_activity = self.record_traffic(rx, tx);
+        if has_activity {
+            self.last_activity = Some(Instant::now());
+        }
+        has_activity
+    }
+
+    /// Check the counters of the peers that count toward activity
+    /// Returns true if they transferred more than keepalive traffic plus the activity
+    /// threshold since the last update
+    fn update_peer_counters(&mut self, peers: &[PeerTransfer]) -> bool {
+        let mut transferred = 0u64;
+        let now = Instant::now();
+        let since_last = self.last_peer_sample.map(|t| now.duration_since(t));
+        self.last_peer_sample = Some(now);
+
+        for peer in peers {
+            if let Some(watched) = &self.activity_peers {
+                if !watched.contains(&peer.public_key) {
+                    continue;
+                }
+            }
+            let (last_rx, last_tx) = self
+                .last_peer_bytes
+                .get(&peer.public_key)
+                .copied()
+                .unwrap_or((0, 0));
+            let rx_delta = counter_delta(peer.rx_bytes, last_rx);
+            let tx_delta = counter_delta(peer.tx_bytes, last_tx);
+            let floor = self
+                .keepalive_check_interval
+                .map(|interval| {
+                    keepalive_floor(since_last.unwrap_or(interval), peer.persistent_keepalive)
+                })
+                .unwrap_or(0);
+
+            let excess = rx_delta.saturating_sub(floor) + tx_delta.saturating_sub(floor);
+            if excess > 0 {
+                tracing::debug!(
+                    "Traffic from peer {} (delta: rx={} tx={})",
+                    peer.public_key,
+                    rx_delta,
+                    tx_delta
+                );
+            } else if rx_delta > 0 || tx_delta > 0 {
+                tracing::trace!(
+                    "Ignoring keepalive traffic from peer {} (delta: rx={} tx={}, floor {})",
+                    peer.public_key,
+                    rx_delta,
+                    tx_delta,
+                    floor
+                );
+            }
+            transferred += excess;
+            self.last_peer_bytes
+                .insert(peer.public_key.clone(), (peer.rx_bytes, peer.tx_bytes));
+        }
+
+        let has_activity = transferred > self.activity_threshold;
+        if has_activity {
+            self.last_activity = Some(Instant::now());
+        } else if transferred > 0 {
+            tracing::debug!(
+                "Ignoring {} bytes of traffic (activity threshold {})",
+                transferred,
+                self.activity_threshold
+            );
+        }
+        has_activity
+    }
 
-        let has_activity = rx != self.last_rx_bytes || tx != self.last_tx_bytes;
+    /// Record byte counters measured on the interface itself (eBPF)
+    /// Returns true if more than the activity threshold was transferred since the
+    /// last update
+    pub fn observe_counters(&mut self, rx: u64, tx: u64) -> bool {
+        let transferred =
+            counter_delta(rx, self.last_rx_bytes) + counter_delta(tx, self.last_tx_bytes);
+        self.record_traffic(rx, tx);
 
+        let has_activity = transferred > self.activity_threshold;
         if has_activity {
-            log::debug!(
-                "Tunnel activity detected: rx={} tx={} (delta: rx={} tx={})",
+            self.last_activity = Some(Instant::now());
+        }
+        has_activity
+    }
+
+    /// Check whether a counted peer renewed its handshake since the last update
+    ///
+    /// The idle clock starts at the renewal, so an idle tunnel times out
+    /// `idle_timeout` after its last handshake rather than after this check.
+    fn update_handshake(&mut self, peers: &[PeerTransfer]) -> bool {
+        let latest = peers
+            .iter()
+            .filter(|peer| match &self.activity_peers {
+                Some(watched) => watched.contains(&peer.public_key),
+                None => true,
+            })
+            .filter_map(|peer| peer.last_handshake)
+            .max();
+
+        let renewed = latest.is_some() && latest > self.last_seen_handshake;
+        if renewed {
+            self.last_seen_handshake = latest;
+            let age = latest.and_then(|t| t.elapsed().ok()).unwrap_or_default();
+            let at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+            tracing::debug!("Handshake renewed {}s ago", age.as_secs());
+            // Never move the idle clock backwards (e.g. before the tunnel came up)
+            if !matches!(self.last_activity, Some(last) if last >= at) {
+                self.last_activity = Some(at);
+            }
+        }
+        renewed
+    }
+
+    /// Accumulate transfer counter deltas into traffic totals
+    /// Returns true if the counters changed since the last update
+    fn record_traffic(&mut self, rx: u64, tx: u64) -> bool {
+        let changed = rx != self.last_rx_bytes || tx != self.last_tx_bytes;
+
+        if changed {
+            let rx_delta = counter_delta(rx, self.last_rx_bytes);
+            let tx_delta = counter_delta(tx, self.last_tx_bytes);
+
+            tracing::debug!(
+                "Tunnel traffic: rx={} tx={} (delta: rx={} tx={})",
                 rx,
                 tx,
-                rx.saturating_sub(self.last_rx_bytes),
-                tx.saturating_sub(self.last_tx_bytes)
+                rx_delta,
+                tx_delta
             );
-            self.last_activity = Some(Instant::now());
+
+            self.traffic.session_rx_bytes += rx_delta;
+            self.traffic.session_tx_bytes += tx_delta;
+            self.traffic.total_rx_bytes += rx_delta;
+            self.traffic.total_tx_bytes += tx_delta;
+
             self.last_rx_bytes = rx;
             self.last_tx_bytes = tx;
         }
 
-        Ok(has_activity)
+        changed
+    }
+
+    /// Get traffic totals for the current session and since daemon start
+    pub fn traffic(&self) -> TrafficTotals {
+        self.traffic
     }
 
     /// Get the duration since last tunnel activity
@@ -268,18 +1024,36 @@ impl WgController {
         self.last_activity.map(|t| t.elapsed())
     }
 
-    /// Reset activity tracking (call when tunnel is brought up)
+    /// Treat the tunnel as active now without a counter change (e.g. user asked to keep it up)
+    pub fn mark_activity(&mut self) {
+        self.last_activity = Some(Instant::now());
+    }
+
+    /// Continue idle tracking of a tunnel a previous daemon instance brought up
+    ///
+    /// `idle_for` is the time since the activity it last saw. Call after a first
+    /// [`check_activity`](Self::check_activity) so the counters have a baseline.
+    pub fn resume_activity(&mut self, idle_for: std::time::Duration) {
+        let now = Instant::now();
+        self.last_activity = Some(now.checked_sub(idle_for).unwrap_or(now));
+    }
+
+    /// Reset activity tracking and start a new traffic session (call when tunnel is brought up)
     pub fn reset_activity(&mut self) {
         self.last_rx_bytes = 0;
         self.last_tx_bytes = 0;
+        self.last_peer_bytes.clear();
+        self.last_peer_sample = None;
+        self.last_seen_handshake = None;
         self.last_activity = Some(Instant::now());
+        self.traffic.session_rx_bytes = 0;
+        self.traffic.session_tx_bytes = 0;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Duration;
 
     #[test]
     fn test_wg_controller_creation() {
@@ -448,6 +1222,250 @@ mod tests {
         assert!(duration < Duration::from_millis(100));
     }
 
+    #[test]
+    fn test_resume_activity() {
+        let mut controller = WgController::new("wg0".to_string(), None).unwrap();
+        controller.mark_activity();
+        controller.resume_activity(Duration::from_secs(120));
+        assert!(controller.idle_duration().unwrap() >= Duration::from_secs(120));
+    }
+
+    #[test]
+    fn test_mark_activity_resets_idle() {
+        let mut controller = WgController::new("wg0".to_string(), None).unwrap();
+        controller.last_activity = Some(Instant::now() - Duration::from_secs(600));
+
+        controller.mark_activity();
+
+        assert!(controller.idle_duration().unwrap() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_update_counters_accumulates_traffic() {
+        let mut controller = WgController::new("wg0".to_string(), None).unwrap();
+
+        assert!(controller.update_counters(1000, 500));
+        assert!(controller.update_counters(1500, 700));
+        // No change in counters is not activity
+        assert!(!controller.update_counters(1500, 700));
+
+        let traffic = controller.traffic();
+        assert_eq!(traffic.session_rx_bytes, 1500);
+        assert_eq!(traffic.session_tx_bytes, 700);
+        assert_eq!(traffic.total_rx_bytes, 1500);
+        assert_eq!(traffic.total_tx_bytes, 700);
+    }
+
+    #[test]
+    fn test_update_peer_counters_ignores_other_peers() {
+        let mut controller = WgController::new("wg0".to_string(), None)
+            .unwrap()
+            .with_activity_peers(vec!["home".to_string()]);
+        let peers = |home: u64, chatty: u64| {
+            vec![
+                PeerTransfer {
+                    public_key: "home".to_string(),
+                    rx_bytes: home,
+                    tx_bytes: 50,
+                    persistent_keepalive: None,
+                    last_handshake: None,
+                },
+                PeerTransfer {
+                    public_key: "chatty".to_string(),
+                    rx_bytes: chatty,
+                    tx_bytes: chatty,
+                    persistent_keepalive: None,
+                    last_handshake: None,
+                },
+            ]
+        };
+
+        assert!(controller.update_peer_counters(&peers(100, 1000)));
+        // Only the chatty peer moves
+        assert!(!controller.update_peer_counters(&peers(100, 5000)));
+        assert!(controller.update_peer_counters(&peers(180, 5000)));
+    }
+
+    #[test]
+    fn test_keepalive_floor() {
+        let minute = Duration::from_secs(60);
+        // 3 keepalives of 32 bytes plus a handshake initiation
+        assert_eq!(keepalive_floor(minute, Some(25)), 3 * 32 + 148);
+        assert_eq!(keepalive_floor(minute, Some(0)), 0);
+        assert_eq!(keepalive_floor(minute, None), 0);
+    }
+
+    #[test]
+    fn test_update_peer_counters_ignores_keepalives() {
+        let mut controller = WgController::new("wg0".to_string(), None)
+            .unwrap()
+            .with_keepalive_filter(Duration::from_secs(60));
+        let peer = |rx: u64, tx: u64| {
+            vec![PeerTransfer {
+                public_key: "home".to_string(),
+                rx_bytes: rx,
+                tx_bytes: tx,
+                persistent_keepalive: Some(25),
+                last_handshake: None,
+            }]
+        };
+
+        assert!(controller.update_peer_counters(&peer(10_000, 10_000)));
+        // Keepalives only (plus a handshake response)
+        assert!(!controller.update_peer_counters(&peer(10_000 + 92, 10_000 + 96)));
+        assert!(!controller.update_peer_counters(&peer(10_092, 10_096 + 64)));
+        // Real traffic
+        assert!(controller.update_peer_counters(&peer(11_500, 10_160)));
+
+        // The floor grows with the time since the previous check
+        controller.last_peer_sample = Some(Instant::now() - Duration::from_secs(300));
+        assert!(!controller.update_peer_counters(&peer(11_500 + 12 * 32, 10_160 + 12 * 32)));
+    }
+
+    #[test]
+    fn test_update_peer_counters_threshold() {
+        let mut controller = WgController::new("wg0".to_string(), None)
+            .unwrap()
+            .with_activity_threshold(1000);
+        let peer = |rx: u64, tx: u64| {
+            vec![PeerTransfer {
+                public_key: "home".to_string(),
+                rx_bytes: rx,
+                tx_bytes: tx,
+                persistent_keepalive: None,
+                last_handshake: None,
+            }]
+        };
+
+        assert!(controller.update_peer_counters(&peer(5000, 5000)));
+        // A DNS retry or two stays below the threshold
+        assert!(!controller.update_peer_counters(&peer(5300, 5300)));
+        assert!(controller.update_peer_counters(&peer(6000, 5700)));
+    }
+
+    #[test]
+    fn test_update_handshake() {
+        let mut controller = WgController::new("wg0".to_string(), None)
+            .unwrap()
+            .with_idle_detection(IdleDetection::Handshake);
+        let peer = |handshake: Option<SystemTime>| {
+            vec![PeerTransfer {
+                public_key: "home".to_string(),
+                rx_bytes: 0,
+                tx_bytes: 0,
+                persistent_keepalive: None,
+                last_handshake: handshake,
+            }]
+        };
+        let renewal = SystemTime::now() - Duration::from_secs(30);
+
+        assert!(!controller.update_handshake(&peer(None)));
+        assert!(controller.update_handshake(&peer(Some(renewal))));
+        // Idle time counts from the renewal
+        let idle = controller.idle_duration().unwrap();
+        assert!(idle >= Duration::from_secs(30) && idle < Duration::from_secs(31));
+
+        // Same handshake again is not activity
+        assert!(!controller.update_handshake(&peer(Some(renewal))));
+        assert!(controller.update_handshake(&peer(Some(SystemTime::now()))));
+    }
+
+    #[test]
+    fn test_observe_counters() {
+        let mut controller = WgController::new("wg0".to_string(), None)
+            .unwrap()
+            .with_activity_threshold(100);
+
+        assert!(controller.observe_counters(400, 300));
+        assert!(!controller.observe_counters(450, 320));
+        assert!(controller.observe_counters(600, 320));
+        // Counters restart from zero when re-attached
+        assert!(controller.observe_counters(500, 0));
+
+        let traffic = controller.traffic();
+        assert_eq!(traffic.session_rx_bytes, 1100);
+        assert_eq!(traffic.session_tx_bytes, 320);
+    }
+
+    #[test]
+    fn test_reset_activity_starts_new_session() {
+        let mut controller = WgController::new("wg0".to_string(), None).unwrap();
+        controller.update_counters(1000, 500);
+
+        controller.reset_activity();
+        controller.update_counters(200, 100);
+
+        let traffic = controller.traffic();
+        assert_eq!(traffic.session_rx_bytes, 200);
+        assert_eq!(traffic.session_tx_bytes, 100);
+        assert_eq!(traffic.total_rx_bytes, 1200);
+        assert_eq!(traffic.total_tx_bytes, 600);
+    }
+
+    #[test]
+    fn test_update_counters_handles_counter_reset() {
+        let mut controller = WgController::new("wg0".to_string(), None).unwrap();
+        controller.update_counters(1000, 1000);
+
+        // Interface recreated: counters restart below the previous values
+        controller.update_counters(100, 50);
+
+        let traffic = controller.traffic();
+        assert_eq!(traffic.session_rx_bytes, 1100);
+        assert_eq!(traffic.session_tx_bytes, 1050);
+    }
+
+    #[test]
+    fn test_handshake_completed() {
+        let since = SystemTime::now();
+        assert!(!handshake_completed(None, since));
+        assert!(!handshake_completed(
+            Some(since - Duration::from_secs(120)),
+            since
+        ));
+        assert!(handshake_completed(Some(since), since));
+        assert!(handshake_completed(
+            Some(since + Duration::from_secs(1)),
+            since
+        ));
+    }
+
+    #[test]
+    fn test_handshake_stale() {
+        let threshold = Duration::from_secs(300);
+        assert!(handshake_stale(None, threshold));
+        assert!(!handshake_stale(Some(Duration::from_secs(110)), threshold));
+        assert!(handshake_stale(Some(Duration::from_secs(301)), threshold));
+    }
+
+    #[test]
+    fn test_format_peers() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut peer = PeerInfo {
+            public_key: "cGVlcg==".to_string(),
+            endpoint: Some("203.0.113.1:51820".parse().unwrap()),
+            last_handshake: Some(now - Duration::from_secs(42)),
+            rx_bytes: 1024,
+            tx_bytes: 2048,
+            allowed_ips: vec!["10.0.0.0/24".to_string(), "10.1.0.0/16".to_string()],
+        };
+        assert_eq!(
+            format_peers(std::slice::from_ref(&peer), now),
+            "peer cGVlcg==\n  endpoint: 203.0.113.1:51820\n  allowed ips: 10.0.0.0/24, 10.1.0.0/16\n  \
+            latest handshake: 42s ago (fresh)\n  transfer: 1024 bytes received, 2048 bytes sent"
+        );
+
+        peer.last_handshake = Some(now - Duration::from_secs(600));
+        assert!(format_peers(std::slice::from_ref(&peer), now).contains("600s ago (stale)"));
+        peer.last_handshake = None;
+        peer.endpoint = None;
+        let listing = format_peers(&[peer.clone(), peer], now);
+        assert!(listing.contains("endpoint: none\n"));
+        assert!(listing.contains("latest handshake: never\n"));
+        assert_eq!(listing.matches("peer ").count(), 2);
+        assert_eq!(format_peers(&[], now), "No peers configured");
+    }
+
     // Note: Actual up/down tests would require root privileges and WireGuard setup
     // These should be integration tests run in a proper environment
 }
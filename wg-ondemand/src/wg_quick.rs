@@ -0,0 +1,303 @@
+// wg-quick configuration file parsing
+
+//! wg-quick config parser
+//!
+//! Reads `/etc/wireguard/<iface>.conf` so that the native backend, AllowedIPs
+//! checks and endpoint handling can work from the user's existing config file.
+//! Only the keys relevant to the daemon are kept; wg-quick specific hooks such
+//! as `PostUp` or `Table` are ignored.
+
+use crate::config::parse_cidr;
+use crate::types::Config;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Directory holding wg-quick interface configs
+pub const CONFIG_DIR: &str = "/etc/wireguard";
+
+/// Path of the wg-quick config for `interface`
+pub fn config_path(interface: &str) -> PathBuf {
+    Path::new(CONFIG_DIR).join(format!("{}.conf", interface))
+}
+
+/// Path of the wg-quick config the tunnel is set up from, if any
+///
+/// That is `native.config_file` for the native backend, or the interface's config
+/// in [`CONFIG_DIR`] for wg-quick. Inline `[native]` definitions and
+/// NetworkManager connections have none.
+pub fn config_in_use(config: &Config) -> Option<PathBuf> {
+    match &config.native {
+        Some(native) => native.config_file.clone(),
+        None if config.general.nm_connection.is_none() => {
+            Some(config_path(&config.general.wg_interface))
+        }
+        None => None,
+    }
+}
+
+/// `[Interface]` section of a wg-quick config
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WgQuickInterface {
+    /// Interface private key (base64)
+    pub private_key: Option<String>,
+    /// Interface addresses in CIDR notation
+    pub addresses: Vec<String>,
+    /// DNS servers and search domains
+    pub dns: Vec<String>,
+    /// UDP listen port
+    pub listen_port: Option<u16>,
+    /// Interface MTU
+    pub mtu: Option<u32>,
+}
+
+/// `[Peer]` section of a wg-quick config
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WgQuickPeer {
+    /// Peer public key (base64)
+    pub public_key: String,
+    /// Preshared key (base64)
+    pub preshared_key: Option<String>,
+    /// Endpoint as `host:port`
+    pub endpoint: Option<String>,
+    /// Networks routed to this peer in CIDR notation
+    pub allowed_ips: Vec<String>,
+    /// Persistent keepalive interval in seconds
+    pub persistent_keepalive: Option<u16>,
+}
+
+/// Parsed wg-quick config
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WgQuickConfig {
+    /// The `[Interface]` section
+    pub interface: WgQuickInterface,
+    /// All `[Peer]` sections, in file order
+    pub peers: Vec<WgQuickPeer>,
+}
+
+impl WgQuickConfig {
+    /// Target subnets that no peer's AllowedIPs cover (IPv4 only)
+    ///
+    /// Traffic to these subnets would not be sent through the tunnel.
+    pub fn uncovered_subnets(&self, subnets: &[String]) -> Vec<String> {
+        let allowed: Vec<(u32, u32)> = self
+            .peers
+            .iter()
+            .flat_map(|p| &p.allowed_ips)
+            .filter_map(|cidr| parse_cidr(cidr).ok())
+            .collect();
+
+        subnets
+            .iter()
+            .filter(|subnet| match parse_cidr(subnet) {
+                // Covered if an allowed range is at most as specific and contains it
+                Ok((network, mask)) => !allowed
+                    .iter()
+                    .any(|&(a_net, a_mask)| a_mask & mask == a_mask && network & a_mask == a_net),
+                Err(_) => false,
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Split a comma-separated value list
+fn split_list(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+}
+
+/// Parse the contents of a wg-quick config
+///
+/// # Errors
+///
+/// Returns an error (with the line number) for lines outside a section,
+/// malformed lines, invalid numbers, or peers without a public key.
+pub fn parse(contents: &str) -> Result<WgQuickConfig> {
+    enum Section {
+        None,
+        Interface,
+        Peer,
+        Other,
+    }
+
+    let mut config = WgQuickConfig::default();
+    let mut section = Section::None;
+
+    for (index, line) in contents.lines().enumerate() {
+        let lineno = index + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            section = match line.to_ascii_lowercase().as_str() {
+                "[interface]" => Section::Interface,
+                "[peer]" => {
+                    config.peers.push(WgQuickPeer::default());
+                    Section::Peer
+                }
+                _ => Section::Other,
+            };
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .with_context(|| format!("line {}: expected `Key = Value`", lineno))?;
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+
+        match section {
+            Section::None => anyhow::bail!("line {}: `{}` outside of a section", lineno, key),
+            Section::Other => {}
+            Section::Interface => {
+                let interface = &mut config.interface;
+                match key.as_str() {
+                    "privatekey" => interface.private_key = Some(value.to_string()),
+                    "address" => interface.addresses.extend(split_list(value)),
+                    "dns" => interface.dns.extend(split_list(value)),
+                    "listenport" => {
+                        interface.listen_port = Some(
+                            value
+                                .parse()
+                                .with_context(|| format!("line {}: invalid ListenPort", lineno))?,
+                        )
+                    }
+                    "mtu" => {
+                        interface.mtu = Some(
+                            value
+                                .parse()
+                                .with_context(|| format!("line {}: invalid MTU", lineno))?,
+                        )
+                    }
+                    _ => {}
+                }
+            }
+            Section::Peer => {
+                // A peer section was pushed when its header was seen
+                let peer = config.peers.last_mut().expect("peer section");
+                match key.as_str() {
+                    "publickey" => peer.public_key = value.to_string(),
+                    "presharedkey" => peer.preshared_key = Some(value.to_string()),
+                    "endpoint" => peer.endpoint = Some(value.to_string()),
+                    "allowedips" => peer.allowed_ips.extend(split_list(value)),
+                    "persistentkeepalive" => {
+                        peer.persistent_keepalive = match value {
+                            "off" => None,
+                            _ => Some(value.parse().with_context(|| {
+                                format!("line {}: invalid PersistentKeepalive", lineno)
+                            })?),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if let Some(index) = config.peers.iter().position(|p| p.public_key.is_empty()) {
+        anyhow::bail!("[Peer] #{} has no PublicKey", index + 1);
+    }
+
+    Ok(config)
+}
+
+/// Read and parse a wg-quick config file
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or parsed.
+pub fn load(path: &Path) -> Result<WgQuickConfig> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    parse(&contents).with_context(|| format!("Failed to parse {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+[Interface]
+PrivateKey = cHJpdmF0ZQ==
+Address = 10.0.0.2/32, fd00::2/128
+DNS = 192.168.1.1, home.lan
+ListenPort = 51820
+MTU = 1420
+PostUp = echo up  # ignored
+
+[Peer]
+PublicKey = aG9tZQ==
+PresharedKey = cHNr
+Endpoint = home.example.com:51820  # dynamic DNS
+AllowedIPs = 192.168.1.0/24,
+AllowedIPs = 10.10.0.0/16
+PersistentKeepalive = 25
+
+[peer]
+PublicKey = b2ZmaWNl
+PersistentKeepalive = off
+";
+
+    #[test]
+    fn test_parse() {
+        let config = parse(SAMPLE).unwrap();
+        assert_eq!(
+            config.interface,
+            WgQuickInterface {
+                private_key: Some("cHJpdmF0ZQ==".to_string()),
+                addresses: vec!["10.0.0.2/32".to_string(), "fd00::2/128".to_string()],
+                dns: vec!["192.168.1.1".to_string(), "home.lan".to_string()],
+                listen_port: Some(51820),
+                mtu: Some(1420),
+            }
+        );
+        assert_eq!(config.peers.len(), 2);
+        assert_eq!(
+            config.peers[0],
+            WgQuickPeer {
+                public_key: "aG9tZQ==".to_string(),
+                preshared_key: Some("cHNr".to_string()),
+                endpoint: Some("home.example.com:51820".to_string()),
+                allowed_ips: vec!["192.168.1.0/24".to_string(), "10.10.0.0/16".to_string()],
+                persistent_keepalive: Some(25),
+            }
+        );
+        assert_eq!(config.peers[1].persistent_keepalive, None);
+        assert!(config.peers[1].endpoint.is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("PrivateKey = abc\n").is_err());
+        assert!(parse("[Interface]\nAddress\n").is_err());
+        assert!(parse("[Interface]\nMTU = big\n").is_err());
+        assert!(parse("[Peer]\nEndpoint = a:1\n").is_err());
+        assert!(parse("[Interface]\n[Custom]\nFoo = bar\n").is_ok());
+    }
+
+    #[test]
+    fn test_uncovered_subnets() {
+        let config = parse(SAMPLE).unwrap();
+        let subnets = vec![
+            "192.168.1.0/24".to_string(),
+            "192.168.1.128/25".to_string(),
+            "10.10.5.0/24".to_string(),
+            "192.168.0.0/16".to_string(),
+            "172.16.0.0/12".to_string(),
+        ];
+        assert_eq!(
+            config.uncovered_subnets(&subnets),
+            vec!["192.168.0.0/16".to_string(), "172.16.0.0/12".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_config_path() {
+        assert_eq!(config_path("wg0"), PathBuf::from("/etc/wireguard/wg0.conf"));
+    }
+}
@@ -0,0 +1,461 @@
+//! End-to-end test in network namespaces
+//!
+//! Two namespaces are joined by a veth pair: the client runs the daemon, the
+//! server runs a real WireGuard peer and owns the target address. Traffic to
+//! the target must bring the tunnel up (with a completed handshake), and the
+//! tunnel must go down again after the idle timeout. Everything is removed
+//! afterwards, also on failure.
+
+use crate::{build_ebpf, is_root, workspace_root, DAEMON_ENV};
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+const CLIENT_NS: &str = "wgod-client";
+const SERVER_NS: &str = "wgod-server";
+const CLIENT_VETH: &str = "wgod-veth0";
+const SERVER_VETH: &str = "wgod-veth1";
+const CLIENT_ADDRESS: &str = "192.0.2.1/24";
+const SERVER_ADDRESS: &str = "192.0.2.2/24";
+const SERVER_IP: &str = "192.0.2.2";
+
+/// WireGuard interface on both sides
+const WG_INTERFACE: &str = "wgod-wg";
+const WG_PORT: u16 = 51820;
+const CLIENT_TUNNEL_ADDRESS: &str = "10.99.0.2/32";
+const SERVER_TUNNEL_ADDRESS: &str = "10.99.0.1/24";
+const SERVER_TUNNEL_IP: &str = "10.99.0.1";
+
+/// Only reachable through the tunnel once it is up
+const TARGET_SUBNET: &str = "203.0.113.0/24";
+const TARGET_IP: &str = "203.0.113.1";
+
+const IDLE_TIMEOUT_SECS: u64 = 10;
+const IDLE_CHECK_INTERVAL_SECS: u64 = 2;
+
+/// How long to wait for a state change other than the idle timeout
+const STATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tools needed on the host
+const REQUIRED_TOOLS: [&str; 3] = ["ip", "wg", "ping"];
+
+/// Give the daemon fresh `/run` and `/var/lib` so its state file, control
+/// socket and stats don't touch the host's (`ip netns exec` already runs it in
+/// a private mount namespace)
+const ISOLATE_DAEMON: &str =
+    r#"mount -t tmpfs tmpfs /run && mount -t tmpfs tmpfs /var/lib && exec "$0" "$@""#;
+
+pub fn run() -> Result<()> {
+    for tool in REQUIRED_TOOLS {
+        which(tool).with_context(|| format!("`{}` is required for the integration test", tool))?;
+    }
+
+    // The daemon always embeds the release build of the eBPF object
+    build_ebpf(true)?;
+    let status = Command::new("cargo")
+        .args([
+            "build",
+            "--package",
+            "wg-ondemand",
+            "--example",
+            "netns-daemon",
+        ])
+        .status()
+        .context("Failed to build the test daemon")?;
+    if !status.success() {
+        anyhow::bail!("Test daemon build failed");
+    }
+    let daemon = workspace_root().join("target/debug/examples/netns-daemon");
+
+    let dir = workspace_root().join("target/integration-test");
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let mut network = Network::default();
+    network.setup(&dir)?;
+    let config = network.write_config(&dir)?;
+
+    let log = dir.join("daemon.log");
+    let result = network
+        .start_daemon(&daemon, &config, &log)
+        .and_then(|states| test_tunnel(&states));
+    drop(network);
+
+    match result {
+        Ok(()) => {
+            println!("Integration test passed");
+            Ok(())
+        }
+        Err(e) => Err(e.context(format!(
+            "Integration test failed, daemon log: {}",
+            log.display()
+        ))),
+    }
+}
+
+/// The assertions, against the daemon's state transitions
+fn test_tunnel(states: &Receiver<String>) -> Result<()> {
+    expect_state(states, "inactive", "monitoring", STATE_TIMEOUT)?;
+    println!("Daemon is monitoring, sending traffic to {}", TARGET_IP);
+
+    ping(1);
+    expect_state(states, "monitoring", "activating", STATE_TIMEOUT)?;
+    expect_state(states, "activating", "connected", STATE_TIMEOUT)?;
+
+    // Packets through the tunnel make the peers complete a handshake
+    ping(3);
+    let handshakes = output(privileged("ip").args([
+        "netns",
+        "exec",
+        SERVER_NS,
+        "wg",
+        "show",
+        WG_INTERFACE,
+        "latest-handshakes",
+    ]))?;
+    let handshake = handshakes
+        .split_whitespace()
+        .nth(1)
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or(0);
+    if handshake == 0 {
+        anyhow::bail!("The server saw no handshake from the daemon's tunnel");
+    }
+    println!("Tunnel is up and the peers completed a handshake");
+
+    let idle =
+        Duration::from_secs(IDLE_TIMEOUT_SECS + 2 * IDLE_CHECK_INTERVAL_SECS) + STATE_TIMEOUT;
+    expect_state(states, "connected", "deactivating", idle)?;
+    expect_state(states, "deactivating", "monitoring", STATE_TIMEOUT)?;
+
+    let link = privileged("ip")
+        .args(["-n", CLIENT_NS, "link", "show", WG_INTERFACE])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .context("Failed to run ip")?;
+    if link.success() {
+        anyhow::bail!("{} still exists after the tunnel idled down", WG_INTERFACE);
+    }
+    println!("Tunnel went down after the idle timeout");
+    Ok(())
+}
+
+/// Wait for the daemon to report a transition from `from` to `to`
+fn expect_state(states: &Receiver<String>, from: &str, to: &str, timeout: Duration) -> Result<()> {
+    let expected = format!("STATE {} {}", from, to);
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match states.recv_timeout(remaining) {
+            Ok(line) if line == expected => return Ok(()),
+            Ok(line) => println!("Daemon: {}", line),
+            Err(RecvTimeoutError::Timeout) => {
+                anyhow::bail!("No transition from {} to {} within {:?}", from, to, timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                anyhow::bail!("Daemon exited while waiting for {} -> {}", from, to)
+            }
+        }
+    }
+}
+
+/// Send `count` pings to the target from the client; whether they are answered
+/// doesn't matter
+fn ping(count: u32) {
+    let _ = privileged("ip")
+        .args([
+            "netns", "exec", CLIENT_NS, "ping", "-q", "-i", "0.5", "-W", "1", "-c",
+        ])
+        .arg(count.to_string())
+        .arg(TARGET_IP)
+        .stdout(Stdio::null())
+        .status();
+}
+
+/// The namespaces, and the daemon running in them
+#[derive(Default)]
+struct Network {
+    namespaces: Vec<&'static str>,
+    daemon: Option<Child>,
+}
+
+impl Network {
+    fn setup(&mut self, dir: &Path) -> Result<()> {
+        for ns in [CLIENT_NS, SERVER_NS] {
+            ip(&["netns", "add", ns])?;
+            self.namespaces.push(ns);
+            ip(&["-n", ns, "link", "set", "lo", "up"])?;
+        }
+
+        ip(&[
+            "link",
+            "add",
+            CLIENT_VETH,
+            "netns",
+            CLIENT_NS,
+            "type",
+            "veth",
+            "peer",
+            "name",
+            SERVER_VETH,
+            "netns",
+            SERVER_NS,
+        ])?;
+        for (ns, veth, address) in [
+            (CLIENT_NS, CLIENT_VETH, CLIENT_ADDRESS),
+            (SERVER_NS, SERVER_VETH, SERVER_ADDRESS),
+        ] {
+            ip(&["-n", ns, "addr", "add", address, "dev", veth])?;
+            ip(&["-n", ns, "link", "set", veth, "up"])?;
+        }
+        ip(&["-n", CLIENT_NS, "route", "add", "default", "via", SERVER_IP])?;
+
+        // The WireGuard peer, which also owns the target
+        let server_key = dir.join("server.key");
+        write_key(&server_key)?;
+        let client_public = write_key(&dir.join("client.key"))?;
+        ip(&[
+            "-n",
+            SERVER_NS,
+            "link",
+            "add",
+            WG_INTERFACE,
+            "type",
+            "wireguard",
+        ])?;
+        let status = privileged("ip")
+            .args([
+                "netns",
+                "exec",
+                SERVER_NS,
+                "wg",
+                "set",
+                WG_INTERFACE,
+                "private-key",
+            ])
+            .arg(&server_key)
+            .args(["listen-port", &WG_PORT.to_string(), "peer", &client_public])
+            .args(["allowed-ips", CLIENT_TUNNEL_ADDRESS])
+            .status()
+            .context("Failed to run wg")?;
+        if !status.success() {
+            anyhow::bail!("Failed to configure the WireGuard peer");
+        }
+        ip(&[
+            "-n",
+            SERVER_NS,
+            "addr",
+            "add",
+            SERVER_TUNNEL_ADDRESS,
+            "dev",
+            WG_INTERFACE,
+        ])?;
+        ip(&["-n", SERVER_NS, "link", "set", WG_INTERFACE, "up"])?;
+        ip(&[
+            "-n",
+            SERVER_NS,
+            "addr",
+            "add",
+            &format!("{}/32", TARGET_IP),
+            "dev",
+            "lo",
+        ])?;
+
+        println!(
+            "Network namespaces {} and {} are set up",
+            CLIENT_NS, SERVER_NS
+        );
+        Ok(())
+    }
+
+    /// Write the daemon config using the keys from [`Self::setup`]
+    fn write_config(&self, dir: &Path) -> Result<PathBuf> {
+        let server_public = std::fs::read_to_string(dir.join("server.key.pub"))
+            .context("Failed to read the server's public key")?;
+        let config = format!(
+            r#"[general]
+wg_interface = "{wg}"
+monitor_interface = "{veth}"
+target_ssids = []
+idle_timeout = {idle}
+idle_check_interval_secs = {interval}
+idle_warning_secs = 0
+captive_portal_check = false
+traffic_detection = "ebpf"
+log_level = "debug"
+
+[subnets]
+ranges = ["{target}"]
+
+[native]
+private_key_file = "{key}"
+addresses = ["{address}"]
+
+[[native.peers]]
+public_key = "{public}"
+endpoint = "{server}:{port}"
+allowed_ips = ["{target}", "{server_tunnel}/32"]
+"#,
+            wg = WG_INTERFACE,
+            veth = CLIENT_VETH,
+            idle = IDLE_TIMEOUT_SECS,
+            interval = IDLE_CHECK_INTERVAL_SECS,
+            target = TARGET_SUBNET,
+            key = dir.join("client.key").display(),
+            address = CLIENT_TUNNEL_ADDRESS,
+            public = server_public.trim(),
+            server = SERVER_IP,
+            port = WG_PORT,
+            server_tunnel = SERVER_TUNNEL_IP,
+        );
+        let path = dir.join("wg-ondemand.toml");
+        std::fs::write(&path, config)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Start the daemon in the client namespace; its state transitions are
+    /// sent to the returned channel
+    fn start_daemon(
+        &mut self,
+        daemon: &Path,
+        config: &Path,
+        log: &Path,
+    ) -> Result<Receiver<String>> {
+        let log_file = std::fs::File::create(log)
+            .with_context(|| format!("Failed to create {}", log.display()))?;
+        let mut child = privileged("ip")
+            .args(["netns", "exec", CLIENT_NS, "sh", "-c", ISOLATE_DAEMON])
+            .arg(daemon)
+            .arg(config)
+            .stdout(Stdio::piped())
+            .stderr(log_file)
+            .spawn()
+            .context("Failed to start the daemon")?;
+
+        let stdout = child.stdout.take().context("Daemon stdout not captured")?;
+        self.daemon = Some(child);
+
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    /// Stop the daemon, giving it the chance to remove its tunnel
+    fn stop_daemon(&mut self) {
+        let Some(mut child) = self.daemon.take() else {
+            return;
+        };
+        for signal in ["-TERM", "-KILL"] {
+            signal_namespace(CLIENT_NS, signal);
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while Instant::now() < deadline {
+                if let Ok(Some(_)) = child.try_wait() {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        self.stop_daemon();
+        // Deleting a namespace also deletes the interfaces in it
+        for ns in self.namespaces.drain(..).rev() {
+            if let Err(e) = ip(&["netns", "del", ns]) {
+                eprintln!("Failed to delete network namespace {}: {:#}", ns, e);
+            }
+        }
+    }
+}
+
+/// Send `signal` to every process in the network namespace `ns`
+fn signal_namespace(ns: &str, signal: &str) {
+    let Ok(pids) = output(privileged("ip").args(["netns", "pids", ns])) else {
+        return;
+    };
+    let pids: Vec<&str> = pids.split_whitespace().collect();
+    if !pids.is_empty() {
+        let _ = privileged("kill").arg(signal).args(pids).status();
+    }
+}
+
+/// Generate a WireGuard key into `path` (mode 0600) and its public key into
+/// `path.pub`; returns the public key
+fn write_key(path: &Path) -> Result<String> {
+    let private = output(Command::new("wg").arg("genkey"))?;
+    std::fs::write(path, &private)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict {}", path.display()))?;
+
+    let key =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let public = output(Command::new("wg").arg("pubkey").stdin(key))?
+        .trim()
+        .to_string();
+    let public_path = path.with_extension("key.pub");
+    std::fs::write(&public_path, &public)
+        .with_context(|| format!("Failed to write {}", public_path.display()))?;
+    Ok(public)
+}
+
+/// `program` with sudo in front unless already root
+fn privileged(program: &str) -> Command {
+    if is_root() {
+        Command::new(program)
+    } else {
+        let mut sudo = Command::new("sudo");
+        sudo.arg(format!("--preserve-env={}", DAEMON_ENV))
+            .arg(program);
+        sudo
+    }
+}
+
+/// Run `ip` with root privileges
+fn ip(args: &[&str]) -> Result<()> {
+    let status = privileged("ip")
+        .args(args)
+        .status()
+        .context("Failed to run ip")?;
+    if !status.success() {
+        anyhow::bail!("`ip {}` failed", args.join(" "));
+    }
+    Ok(())
+}
+
+/// Run a command and return its standard output
+fn output(cmd: &mut Command) -> Result<String> {
+    let output = cmd
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run {:?}", cmd.get_program()))?;
+    if !output.status.success() {
+        anyhow::bail!("{:?} failed", cmd.get_program());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Whether `program` is on the PATH
+fn which(program: &str) -> Result<()> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(["/usr/sbin".into(), "/sbin".into()])
+        .any(|dir| dir.join(program).is_file())
+        .then_some(())
+        .context("Not found")
+}
@@ -1,7 +1,16 @@
 use anyhow::{Context, Result};
 use clap::Parser;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+mod integration_test;
+mod package;
+
+/// Environment variables passed through sudo to the daemon
+const DAEMON_ENV: &str = "RUST_LOG,RUST_BACKTRACE";
+
 #[derive(Parser)]
 enum Args {
     /// Build the eBPF program
@@ -10,6 +19,27 @@ enum Args {
         #[clap(long)]
         release: bool,
     },
+    /// Build the eBPF program and the daemon, then run the daemon as root
+    Run {
+        /// Build the daemon in release mode
+        #[clap(long)]
+        release: bool,
+        /// Arguments for the daemon (after `--`)
+        #[clap(last = true)]
+        args: Vec<String>,
+    },
+    /// Run the daemon against a real WireGuard peer in network namespaces
+    IntegrationTest,
+    /// Build release binaries and assemble distributable packages
+    #[clap(group(clap::ArgGroup::new("format").required(true).multiple(true)))]
+    Package {
+        /// Build a Debian package (needs dpkg-deb)
+        #[clap(long, group = "format")]
+        deb: bool,
+        /// Build an RPM package (needs rpmbuild)
+        #[clap(long, group = "format")]
+        rpm: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -17,6 +47,9 @@ fn main() -> Result<()> {
 
     match args {
         Args::BuildEbpf { release } => build_ebpf(release),
+        Args::Run { release, args } => run(release, &args),
+        Args::IntegrationTest => integration_test::run(),
+        Args::Package { deb, rpm } => package::run(deb, rpm),
     }
 }
 
@@ -46,3 +79,58 @@ fn build_ebpf(release: bool) -> Result<()> {
     println!("eBPF program built successfully");
     Ok(())
 }
+
+fn build_daemon(release: bool) -> Result<()> {
+    let mut cmd = Command::new("cargo");
+    cmd.args(["build", "--package", "wg-ondemand"]);
+
+    if release {
+        cmd.arg("--release");
+    }
+
+    let status = cmd.status().context("Failed to build daemon")?;
+
+    if !status.success() {
+        anyhow::bail!("Daemon build failed");
+    }
+    Ok(())
+}
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("xtask is inside the workspace")
+        .to_path_buf()
+}
+
+fn is_root() -> bool {
+    std::fs::metadata("/proc/self")
+        .map(|meta| meta.uid() == 0)
+        .unwrap_or(false)
+}
+
+fn run(release: bool, args: &[String]) -> Result<()> {
+    // The daemon always embeds the release build of the eBPF object
+    build_ebpf(true)?;
+    build_daemon(release)?;
+
+    let profile = if release { "release" } else { "debug" };
+    let daemon = workspace_root()
+        .join("target")
+        .join(profile)
+        .join("wg-ondemand");
+
+    let mut cmd = if is_root() {
+        Command::new(&daemon)
+    } else {
+        let mut sudo = Command::new("sudo");
+        sudo.arg(format!("--preserve-env={}", DAEMON_ENV))
+            .arg(&daemon);
+        sudo
+    };
+    cmd.args(args);
+
+    // Replace this process so Ctrl+C and SIGTERM reach the daemon directly
+    let err = cmd.exec();
+    Err(err).with_context(|| format!("Failed to run {}", daemon.display()))
+}
@@ -0,0 +1,307 @@
+//! Distributable packages
+//!
+//! The release binaries and the files around them (systemd unit, default
+//! config, tmpfiles entry for the state directory, D-Bus policy, completions)
+//! are staged under `target/package` at their install paths, then wrapped with
+//! `dpkg-deb` or `rpmbuild`. The version is the one in `wg-ondemand.spec`, as
+//! for `scripts/build-rpm.sh`.
+
+use crate::{build_daemon, build_ebpf, workspace_root};
+use anyhow::{Context, Result};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const NAME: &str = "wg-ondemand";
+const SUMMARY: &str = "Automatic WireGuard VPN activation on-demand";
+const DESCRIPTION: &str =
+    "A lightweight daemon that automatically activates your WireGuard VPN tunnel
+only when accessing specific networks, saving mobile data and battery life.";
+const HOMEPAGE: &str = "https://github.com/vly/wg-ondemand";
+const MAINTAINER: &str = "wg-ondemand developers <noreply@github.com>";
+const DEB_DEPENDS: &str = "wireguard-tools, network-manager, systemd";
+const RPM_REQUIRES: &str = "wireguard-tools NetworkManager systemd";
+
+/// Installed path of the default config, kept on upgrades
+const CONFIG_FILE: &str = "etc/wg-ondemand/config.toml";
+
+/// Files copied from the workspace: (source, installed path, mode)
+const FILES: [(&str, &str, u32); 9] = [
+    ("target/release/wg-ondemand", "usr/bin/wg-ondemand", 0o755),
+    ("scripts/wg-ondemand-ctl", "usr/bin/wg-ondemand-ctl", 0o755),
+    ("scripts/setup-tc.sh", "usr/bin/wg-ondemand-setup-tc", 0o755),
+    ("config/wg-ondemand.toml", CONFIG_FILE, 0o644),
+    (
+        "systemd/wg-ondemand.tmpfiles",
+        "usr/lib/tmpfiles.d/wg-ondemand.conf",
+        0o644,
+    ),
+    (
+        "dbus/io.github.vly.WgOndemand.conf",
+        "usr/share/dbus-1/system.d/io.github.vly.WgOndemand.conf",
+        0o644,
+    ),
+    ("README.md", "usr/share/doc/wg-ondemand/README.md", 0o644),
+    (
+        "CHANGELOG.md",
+        "usr/share/doc/wg-ondemand/CHANGELOG.md",
+        0o644,
+    ),
+    ("LICENSE", "usr/share/doc/wg-ondemand/LICENSE", 0o644),
+];
+
+/// Installed with the binary paths changed from `/usr/local/bin`, where
+/// `scripts/install.sh` puts them, to `/usr/bin`
+const UNIT: (&str, &str) = (
+    "systemd/wg-ondemand.service",
+    "usr/lib/systemd/system/wg-ondemand.service",
+);
+
+/// Directories owned by the package
+const DIRS: [&str; 2] = ["etc/wg-ondemand", "usr/share/doc/wg-ondemand"];
+
+/// Maintainer scripts shared by both formats; PRERM only runs on removal
+const POSTINST: &str = "if [ -d /run/systemd/system ]; then
+    systemd-tmpfiles --create wg-ondemand.conf >/dev/null 2>&1 || true
+    systemctl daemon-reload >/dev/null 2>&1 || true
+fi";
+const PRERM: &str = "if [ -d /run/systemd/system ]; then
+    systemctl disable --now wg-ondemand.service >/dev/null 2>&1 || true
+fi";
+const POSTRM: &str = "if [ -d /run/systemd/system ]; then
+    systemctl daemon-reload >/dev/null 2>&1 || true
+fi";
+
+pub fn run(deb: bool, rpm: bool) -> Result<()> {
+    // The daemon embeds the release build of the eBPF object
+    build_ebpf(true)?;
+    build_daemon(true)?;
+
+    let version = spec_version()?;
+    let dir = workspace_root().join("target/package");
+    if deb {
+        let package = build_deb(&dir, &version)?;
+        println!("Built {}", package.display());
+    }
+    if rpm {
+        let package = build_rpm(&dir, &version)?;
+        println!("Built {}", package.display());
+    }
+    Ok(())
+}
+
+/// `Version:` from the RPM spec
+fn spec_version() -> Result<String> {
+    let path = workspace_root().join("wg-ondemand.spec");
+    let spec = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    spec.lines()
+        .find_map(|line| line.strip_prefix("Version:"))
+        .map(|version| version.trim().to_string())
+        .context("No Version in wg-ondemand.spec")
+}
+
+/// Copy the package contents under `root`; returns the installed paths
+/// (relative to `/`)
+fn stage(root: &Path) -> Result<Vec<String>> {
+    if root.exists() {
+        std::fs::remove_dir_all(root)
+            .with_context(|| format!("Failed to clean {}", root.display()))?;
+    }
+    let workspace = workspace_root();
+    let mut installed = Vec::new();
+
+    for (source, dest, mode) in FILES {
+        let contents = std::fs::read(workspace.join(source))
+            .with_context(|| format!("Failed to read {}", source))?;
+        install(root, dest, &contents, mode)?;
+        installed.push(dest.to_string());
+    }
+
+    let (source, dest) = UNIT;
+    let unit = std::fs::read_to_string(workspace.join(source))
+        .with_context(|| format!("Failed to read {}", source))?
+        .replace("/usr/local/bin/", "/usr/bin/");
+    install(root, dest, unit.as_bytes(), 0o644)?;
+    installed.push(dest.to_string());
+
+    let daemon = root.join("usr/bin/wg-ondemand");
+    for ctl in [false, true] {
+        let name = if ctl { "wg-ondemand-ctl" } else { NAME };
+        for (shell, dest) in [
+            (
+                "bash",
+                format!("usr/share/bash-completion/completions/{}", name),
+            ),
+            ("zsh", format!("usr/share/zsh/site-functions/_{}", name)),
+            (
+                "fish",
+                format!("usr/share/fish/vendor_completions.d/{}.fish", name),
+            ),
+        ] {
+            let mut cmd = Command::new(&daemon);
+            cmd.args(["completions", shell]);
+            if ctl {
+                cmd.arg("--ctl");
+            }
+            let output = cmd
+                .output()
+                .with_context(|| format!("Failed to generate {} completions", shell))?;
+            if !output.status.success() {
+                anyhow::bail!("Generating {} completions for {} failed", shell, name);
+            }
+            install(root, &dest, &output.stdout, 0o644)?;
+            installed.push(dest);
+        }
+    }
+
+    Ok(installed)
+}
+
+/// Write `contents` to `root/dest` with `mode`
+fn install(root: &Path, dest: &str, contents: &[u8], mode: u32) -> Result<()> {
+    let path = root.join(dest);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::write(&path, contents)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set the mode of {}", path.display()))
+}
+
+fn build_deb(dir: &Path, version: &str) -> Result<PathBuf> {
+    let root = dir.join("deb");
+    stage(&root)?;
+
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => arch,
+    };
+    let description = DESCRIPTION
+        .lines()
+        .map(|line| format!(" {}", line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let control = format!(
+        "Package: {NAME}
+Version: {version}
+Section: net
+Priority: optional
+Architecture: {arch}
+Depends: {DEB_DEPENDS}
+Maintainer: {MAINTAINER}
+Homepage: {HOMEPAGE}
+Description: {SUMMARY}
+{description}
+"
+    );
+    install(&root, "DEBIAN/control", control.as_bytes(), 0o644)?;
+    install(
+        &root,
+        "DEBIAN/conffiles",
+        format!("/{}\n", CONFIG_FILE).as_bytes(),
+        0o644,
+    )?;
+    for (name, script) in [
+        ("postinst", POSTINST),
+        (
+            "prerm",
+            &format!("if [ \"$1\" = remove ]; then\n{}\nfi", PRERM),
+        ),
+        ("postrm", POSTRM),
+    ] {
+        let script = format!("#!/bin/sh\nset -e\n\n{}\n\nexit 0\n", script);
+        install(&root, &format!("DEBIAN/{}", name), script.as_bytes(), 0o755)?;
+    }
+
+    let package = dir.join(format!("{}_{}_{}.deb", NAME, version, arch));
+    let status = Command::new("dpkg-deb")
+        .args(["--build", "--root-owner-group"])
+        .arg(&root)
+        .arg(&package)
+        .status()
+        .context("Failed to run dpkg-deb (is it installed?)")?;
+    if !status.success() {
+        anyhow::bail!("dpkg-deb failed");
+    }
+    Ok(package)
+}
+
+fn build_rpm(dir: &Path, version: &str) -> Result<PathBuf> {
+    let rpmbuild = dir.join("rpmbuild");
+    let root = rpmbuild.join("root");
+    let installed = stage(&root)?;
+
+    let files = installed
+        .iter()
+        .map(|path| {
+            if path == CONFIG_FILE {
+                format!("%config(noreplace) /{}", path)
+            } else {
+                format!("/{}", path)
+            }
+        })
+        .chain(DIRS.iter().map(|dir| format!("%dir /{}", dir)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    // Plain release binaries, without a debuginfo package
+    let spec = format!(
+        "Name: {NAME}
+Version: {version}
+Release: 1
+Summary: {SUMMARY}
+License: MIT
+URL: {HOMEPAGE}
+Requires: {RPM_REQUIRES}
+
+%global debug_package %{{nil}}
+
+%description
+{DESCRIPTION}
+
+%install
+cp -a {root}/. %{{buildroot}}/
+
+%post
+{POSTINST}
+
+%preun
+if [ $1 -eq 0 ]; then
+{PRERM}
+fi
+
+%postun
+{POSTRM}
+
+%files
+{files}
+",
+        root = root.display(),
+    );
+    let spec_path = rpmbuild.join("wg-ondemand.spec");
+    std::fs::write(&spec_path, spec)
+        .with_context(|| format!("Failed to write {}", spec_path.display()))?;
+
+    let status = Command::new("rpmbuild")
+        .arg("-bb")
+        .arg("--define")
+        .arg(format!("_topdir {}", rpmbuild.display()))
+        .arg("--define")
+        .arg(format!("_rpmdir {}", dir.display()))
+        .args([
+            "--define",
+            "_build_name_fmt %%{NAME}-%%{VERSION}-%%{RELEASE}.%%{ARCH}.rpm",
+        ])
+        .arg(&spec_path)
+        .status()
+        .context("Failed to run rpmbuild (is it installed?)")?;
+    if !status.success() {
+        anyhow::bail!("rpmbuild failed");
+    }
+
+    let arch = std::env::consts::ARCH;
+    Ok(dir.join(format!("{}-{}-1.{}.rpm", NAME, version, arch)))
+}